@@ -0,0 +1,23 @@
+// Picks the game database compiled into the crate. The NES 2.0 XML database
+// is too big to keep in the repository: set NES20DB to the path of a
+// downloaded nes20db.xml to embed it, the small subset in src/rom is used
+// otherwise.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SUBSET: &str = "src/rom/nes20db.xml";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=NES20DB");
+    let source = match env::var_os("NES20DB") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(SUBSET),
+    };
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let output = Path::new(&env::var_os("OUT_DIR").unwrap()).join("nes20db.xml");
+    if let Err(e) = fs::copy(&source, &output) {
+        panic!("Could not read the game database {}: {}", source.display(), e);
+    }
+}
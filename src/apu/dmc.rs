@@ -0,0 +1,148 @@
+// Delta modulation channel $4010-$4013
+// https://wiki.nesdev.com/w/index.php/APU_DMC
+use rom::Region;
+
+// Timer periods in CPU cycles
+const NTSC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Debug)]
+pub struct DMC {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    pub irq: bool,
+    looped: bool,
+    timer: u16,
+    timer_period: u16,
+    pub level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    // Memory reader
+    current_address: u16,
+    pub bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    // Output unit
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl DMC {
+    pub fn new(region: Region) -> DMC {
+        let rates = if region == Region::PAL { &PAL_RATES } else { &NTSC_RATES };
+        DMC {
+            rates,
+            irq_enabled: false,
+            irq: false,
+            looped: false,
+            timer: 0,
+            timer_period: rates[0],
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x03 {
+            // IL-- RRRR
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looped = value & 0x40 != 0;
+                self.timer_period = self.rates[(value & 0x0f) as usize];
+            },
+            // -DDD DDDD
+            1 => self.level = value & 0x7f,
+            // Sample address = %11AAAAAA.AA000000
+            2 => self.sample_address = 0xc000 | (value as u16) << 6,
+            // Sample length = %LLLL.LLLL0001
+            _ => self.sample_length = (value as u16) << 4 | 1,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Address the memory reader wants to fetch, if its buffer is empty
+    pub fn request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill(&mut self, byte: u8) {
+        self.sample_buffer = Some(byte);
+        // The address wraps around to $8000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looped {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
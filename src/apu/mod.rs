@@ -0,0 +1,311 @@
+// Audio processing unit of the 2A03, registers $4000-$4017
+// https://wiki.nesdev.com/w/index.php/APU
+pub mod dmc;
+pub mod noise;
+pub mod pulse;
+pub mod triangle;
+pub mod units;
+
+use apu::dmc::DMC;
+use apu::noise::Noise;
+use apu::pulse::Pulse;
+use apu::triangle::Triangle;
+use rom::Region;
+
+use std::f32::consts::PI;
+
+// Frame counter steps in CPU cycles: quarter frames, the last one also
+// ends the sequence
+const NTSC_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33252, 41565];
+
+// Cutoff of the high-pass filter on the console's audio output
+const HIGH_PASS_HZ: f32 = 37.0;
+
+#[derive(Debug)]
+pub struct APU {
+    pub pulse_1: Pulse,
+    pub pulse_2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: DMC,
+    // Output of the sound chip on the cartridge, on the same scale as `output`
+    pub expansion: f32,
+    frame_steps: &'static [u32; 5],
+    // Five step sequence, no frame IRQ
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    // Pulse and noise timers tick every other CPU cycle
+    odd_cycle: bool,
+    // Downsampling from the CPU clock to the output rate
+    cpu_clock: u64,
+    sample_rate: u64,
+    sample_clock: u64,
+    sample_sum: f32,
+    sample_count: u32,
+    filter_alpha: f32,
+    filter_input: f32,
+    filter_output: f32,
+    samples: Vec<f32>,
+}
+
+impl APU {
+    pub fn new(region: Region, sample_rate: u32) -> APU {
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_HZ);
+        let dt = 1.0 / sample_rate as f32;
+        APU {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(region),
+            dmc: DMC::new(region),
+            expansion: 0.0,
+            frame_steps: if region == Region::PAL { &PAL_STEPS } else { &NTSC_STEPS },
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            cpu_clock: region.cpu_clock() as u64,
+            sample_rate: sample_rate as u64,
+            sample_clock: 0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filter_alpha: rc / (rc + dt),
+            filter_input: 0.0,
+            filter_output: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    // Only $4015 is readable
+    pub fn read_register(&mut self, address: u16) -> Option<u8> {
+        if address != 0x4015 {
+            return None;
+        }
+        // IF-D NT21
+        let status = (self.dmc.irq as u8) << 7 |
+                     (self.frame_irq as u8) << 6 |
+                     ((self.dmc.bytes_remaining > 0) as u8) << 4 |
+                     (self.noise.length.is_active() as u8) << 3 |
+                     (self.triangle.length.is_active() as u8) << 2 |
+                     (self.pulse_2.length.is_active() as u8) << 1 |
+                     self.pulse_1.length.is_active() as u8;
+        self.frame_irq = false;
+        Some(status)
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse_1.write(address, value),
+            0x4004..=0x4007 => self.pulse_2.write(address, value),
+            0x4008..=0x400b => self.triangle.write(address, value),
+            0x400c..=0x400f => self.noise.write(address, value),
+            0x4010..=0x4013 => self.dmc.write(address, value),
+            // ---D NT21
+            0x4015 => {
+                self.pulse_1.length.set_enabled(value & 0x01 != 0);
+                self.pulse_2.length.set_enabled(value & 0x02 != 0);
+                self.triangle.length.set_enabled(value & 0x04 != 0);
+                self.noise.length.set_enabled(value & 0x08 != 0);
+                self.dmc.set_enabled(value & 0x10 != 0);
+            },
+            // MI-- ----
+            0x4017 => {
+                self.five_step = value & 0x80 != 0;
+                self.irq_inhibit = value & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // The DMC reads samples from CPU memory, the bus has to serve these
+    // requests with `dmc_fill`
+    pub fn dmc_request(&self) -> Option<u16> {
+        self.dmc.request()
+    }
+
+    pub fn dmc_fill(&mut self, byte: u8) {
+        self.dmc.fill(byte);
+    }
+
+    // Advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+        self.clock_frame_counter();
+
+        self.sample_sum += self.output();
+        self.sample_count += 1;
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= self.cpu_clock {
+            self.sample_clock -= self.cpu_clock;
+            let sample = self.sample_sum / self.sample_count as f32;
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+            self.filter_output = self.filter_alpha * (self.filter_output + sample - self.filter_input);
+            self.filter_input = sample;
+            self.samples.push(self.filter_output);
+        }
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let steps = self.frame_steps;
+        let cycle = self.frame_cycle;
+        if cycle == steps[0] || cycle == steps[2] {
+            self.clock_quarter_frame();
+        } else if cycle == steps[1] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        } else if cycle == steps[3] && !self.five_step {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            if !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+            self.frame_cycle = 0;
+        } else if cycle == steps[4] {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.length.clock();
+        self.pulse_2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_sweep();
+    }
+
+    // Nonlinear mixer, the result is in 0.0..1.0 plus expansion audio
+    // https://wiki.nesdev.com/w/index.php/APU_Mixer
+    pub fn output(&self) -> f32 {
+        let pulse_out = APU::pulse_level(self.pulse_1.output() + self.pulse_2.output());
+
+        let tnd = self.triangle.output() as f32 / 8227.0 +
+                  self.noise.output() as f32 / 12241.0 +
+                  self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out + self.expansion
+    }
+
+    // Mixer curve of the pulse channels, `pulse` is the sum of both
+    // outputs, 0-30. Expansion chips with pulses mix them the same way.
+    pub fn pulse_level(pulse: u8) -> f32 {
+        if pulse == 0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse as f32 + 100.0)
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    // Samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        ::std::mem::take(&mut self.samples)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use apu::*;
+
+    #[test]
+    fn reading_status() {
+        let mut apu = APU::new(Region::NTSC, 44100);
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_register(0x4015), Some(0x01));
+        assert_eq!(apu.read_register(0x4000), None);
+
+        apu.write_register(0x4015, 0x00);
+        assert_eq!(apu.read_register(0x4015), Some(0x00));
+    }
+
+    #[test]
+    fn raising_frame_irq() {
+        let mut apu = APU::new(Region::NTSC, 44100);
+        for _ in 0..29829 {
+            apu.tick();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read_register(0x4015), Some(0x40));
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0x40);
+        for _ in 0..29829 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn producing_samples() {
+        let mut apu = APU::new(Region::NTSC, 44100);
+        apu.write_register(0x4015, 0x01);
+        // 50% duty, constant volume 15, ~440 Hz
+        apu.write_register(0x4000, 0xbf);
+        apu.write_register(0x4002, 0xfd);
+        apu.write_register(0x4003, 0x08);
+        // One second
+        for _ in 0..1_789_773 {
+            apu.tick();
+        }
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 44100);
+        assert!(samples.iter().any(|&s| s > 0.05));
+        assert!(samples.iter().any(|&s| s < -0.05));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn fetching_dmc_samples() {
+        let mut apu = APU::new(Region::NTSC, 44100);
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.dmc_request(), Some(0xc040));
+        apu.dmc_fill(0xff);
+        assert_eq!(apu.dmc_request(), None);
+        assert!(apu.irq());
+    }
+}
@@ -0,0 +1,79 @@
+// Pseudo-random noise channel $400C-$400F
+// https://wiki.nesdev.com/w/index.php/APU_Noise
+use apu::units::{Envelope, LengthCounter};
+use rom::Region;
+
+// Timer periods in CPU cycles
+const NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Debug)]
+pub struct Noise {
+    periods: &'static [u16; 16],
+    timer: u16,
+    timer_period: u16,
+    // Short (93 step) sequence
+    mode: bool,
+    shift: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Noise {
+        Noise {
+            periods: if region == Region::PAL { &PAL_PERIODS } else { &NTSC_PERIODS },
+            timer: 0,
+            timer_period: NTSC_PERIODS[0],
+            mode: false,
+            // Loaded with 1 on power up
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x03 {
+            // --lc vvvv
+            0 => {
+                self.length.halted = value & 0x20 != 0;
+                self.envelope.write(value);
+            },
+            1 => {},
+            // M--- PPPP
+            2 => {
+                self.mode = value & 0x80 != 0;
+                self.timer_period = self.periods[(value & 0x0f) as usize];
+            },
+            // LLLL L---
+            _ => {
+                self.length.load(value);
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.shift & 0x01 != 0 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
@@ -0,0 +1,120 @@
+// Square wave channels $4000-$4007
+// https://wiki.nesdev.com/w/index.php/APU_Pulse
+use apu::units::{Envelope, LengthCounter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Debug, Default)]
+pub struct Pulse {
+    // Pulse 1 negates the sweep change with one's complement
+    first: bool,
+    // Expansion chips (MMC5) have pulses without the sweep unit and its muting
+    sweepless: bool,
+    duty: u8,
+    sequence: u8,
+    timer: u16,
+    timer_period: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(first: bool) -> Pulse {
+        Pulse {
+            first,
+            ..Default::default()
+        }
+    }
+
+    pub fn without_sweep() -> Pulse {
+        Pulse {
+            sweepless: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x03 {
+            // DDlc vvvv
+            0 => {
+                self.duty = value >> 6;
+                self.length.halted = value & 0x20 != 0;
+                self.envelope.write(value);
+            },
+            // EPPP NSSS
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            },
+            // Timer low
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            // LLLL Lttt
+            _ => {
+                self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.sequence = 0;
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // Clocked every APU cycle (two CPU cycles)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.timer_period + change
+        } else if self.first {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    fn is_muted(&self) -> bool {
+        !self.sweepless && (self.timer_period < 8 || self.sweep_target() > 0x7ff)
+    }
+
+    // Clocked every half frame
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.is_muted() ||
+           DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
@@ -0,0 +1,71 @@
+// Triangle wave channel $4008-$400B
+// https://wiki.nesdev.com/w/index.php/APU_Triangle
+use apu::units::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Default)]
+pub struct Triangle {
+    sequence: u8,
+    timer: u16,
+    timer_period: u16,
+    pub length: LengthCounter,
+    linear_counter: u8,
+    linear_period: u8,
+    linear_reload: bool,
+    // Also halts the length counter
+    control: bool,
+}
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x03 {
+            // CRRR RRRR
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.length.halted = self.control;
+                self.linear_period = value & 0x7f;
+            },
+            1 => {},
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            // LLLL Lttt
+            _ => {
+                self.timer_period = (self.timer_period & 0xff) | ((value as u16 & 0x07) << 8);
+                self.length.load(value);
+                self.linear_reload = true;
+            },
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.sequence = (self.sequence + 1) & 0x1f;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked every quarter frame
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    // The sequencer just stops when silenced, so the level is held
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence as usize]
+    }
+}
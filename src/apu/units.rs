@@ -0,0 +1,126 @@
+// Building blocks shared by the APU channels
+// https://wiki.nesdev.com/w/index.php/APU_Envelope
+// https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug, Default)]
+pub struct Envelope {
+    pub start: bool,
+    pub looped: bool,
+    pub constant: bool,
+    // Constant volume or the divider period
+    pub volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // --lc vvvv of $4000/$4004/$400C
+    pub fn write(&mut self, value: u8) {
+        self.looped = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0f;
+    }
+
+    // Clocked by the frame counter every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looped {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halted: bool,
+    pub counter: u8,
+}
+
+impl LengthCounter {
+    // Upper 5 bits of the fourth channel register
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // Clocked by the frame counter every half frame
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use apu::units::*;
+
+    #[test]
+    fn decaying_envelope() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0000_0000);
+        envelope.start = true;
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..15 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 0);
+
+        envelope.write(0b0011_0111);
+        assert_eq!(envelope.output(), 7);
+    }
+
+    #[test]
+    fn counting_length() {
+        let mut length = LengthCounter::default();
+        length.load(0b0000_1000);
+        assert!(!length.is_active());
+
+        length.set_enabled(true);
+        length.load(0b0000_1000);
+        assert_eq!(length.counter, 254);
+        length.clock();
+        assert_eq!(length.counter, 253);
+        length.set_enabled(false);
+        assert!(!length.is_active());
+    }
+}
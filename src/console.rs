@@ -0,0 +1,472 @@
+// The console: CPU, internal RAM, APU and the cartridge wired together
+// https://wiki.nesdev.com/w/index.php/CPU_memory_map
+use apu::APU;
+use cpu::bus::Bus;
+use cpu::cpu::CPU;
+use mapper::{self, Mapper, MapperError};
+use png;
+use ppu::palette::Palette;
+use ppu::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use ram::RAM;
+use rom::ROM;
+use save::SaveFile;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug)]
+pub enum ConsoleError {
+    // The cartridge's board isn't emulated
+    MapperError(MapperError),
+    // The save file exists but couldn't be read
+    SaveError(io::Error),
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConsoleError::MapperError(ref err) => write!(f, "{}", err),
+            ConsoleError::SaveError(ref err) => write!(f, "Could not read the save: {}", err),
+        }
+    }
+}
+
+impl Error for ConsoleError {}
+
+impl From<MapperError> for ConsoleError {
+    fn from(error: MapperError) -> ConsoleError {
+        ConsoleError::MapperError(error)
+    }
+}
+
+impl From<io::Error> for ConsoleError {
+    fn from(error: io::Error) -> ConsoleError {
+        ConsoleError::SaveError(error)
+    }
+}
+
+pub struct SystemBus {
+    pub ram: RAM,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub mapper: Box<dyn Mapper>,
+    // Last value on the data bus, returned by reads nothing responds to
+    open_bus: u8,
+}
+
+impl SystemBus {
+    pub fn new(rom: &ROM) -> Result<SystemBus, MapperError> {
+        Ok(SystemBus {
+            ram: RAM::new(),
+            ppu: PPU::new(rom.region()),
+            apu: APU::new(rom.region(), SAMPLE_RATE),
+            mapper: mapper::create(rom)?,
+            open_bus: 0,
+        })
+    }
+}
+
+impl SystemBus {
+    // Copies a page to OAM through $2004, the CPU is halted for 513 cycles
+    // (514 on odd ones, the count isn't kept so it's always 513)
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for offset in 0..0x100 {
+            let value = self.read_byte(start + offset);
+            self.ppu.write_register(0x2004, value, &mut *self.mapper);
+        }
+        self.tick(513);
+    }
+}
+
+impl Bus for SystemBus {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        let value = match address {
+            0x0000..=0x1fff => Some(self.ram.read_byte(address)),
+            0x2000..=0x3fff => Some(self.ppu.read_register(address, &mut *self.mapper)),
+            0x4015 => self.apu.read_register(address),
+            0x4020..=0xffff => self.mapper.cpu_read(address),
+            _ => None,
+        };
+        self.open_bus = value.unwrap_or(self.open_bus);
+        self.open_bus
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        self.open_bus = byte;
+        match address {
+            0x4014 => self.oam_dma(byte),
+            0x0000..=0x1fff => self.ram.write(address, byte),
+            0x2000..=0x3fff => {
+                self.ppu.write_register(address, byte, &mut *self.mapper);
+                self.mapper.cpu_write(address & 0x2007, byte);
+            },
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(address, byte),
+            0x4020..=0xffff => self.mapper.cpu_write(address, byte),
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.mapper.notify_cpu_cycle();
+            self.ppu.tick(&mut *self.mapper);
+            self.apu.expansion = self.mapper.audio_output();
+            self.apu.tick();
+            if let Some(address) = self.apu.dmc_request() {
+                let byte = self.read_byte(address);
+                self.apu.dmc_fill(byte);
+            }
+        }
+    }
+
+    fn irq(&mut self) -> bool {
+        self.apu.irq() || self.mapper.irq()
+    }
+
+    fn nmi(&mut self) -> bool {
+        self.ppu.nmi()
+    }
+}
+
+// Rows and columns at the edges of the picture a TV doesn't show
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    // The 8 lines at the top and the bottom most TVs hide
+    pub fn tv() -> Overscan {
+        Overscan { top: 8, bottom: 8, left: 0, right: 0 }
+    }
+}
+
+// A picture of 256x240 pixels, a row after another
+pub struct Frame<'a> {
+    // 6-bit colors with the emphasis bits, see `PPU::frame_buffer`
+    pub pixels: &'a [u16],
+    // Through the palette, 4 bytes a pixel
+    pub rgba: &'a [u8],
+}
+
+pub struct Console {
+    pub cpu: CPU<SystemBus>,
+    // Colors of the PPU's pixels
+    palette: Palette,
+    // The last frame in colors
+    rgba: Vec<u8>,
+    save_file: Option<SaveFile>,
+    cpu_clock: u64,
+    // Emulated CPU cycles between writes of the save, 0 for never
+    save_interval: u64,
+    cycles_since_save: u64,
+}
+
+impl Console {
+    // Powers the console up with the cartridge inserted
+    pub fn new(rom: &ROM) -> Result<Console, MapperError> {
+        let mut console = Console {
+            cpu: CPU::new(SystemBus::new(rom)?),
+            palette: Palette::for_rom(rom),
+            rgba: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+            save_file: None,
+            cpu_clock: rom.region().cpu_clock() as u64,
+            save_interval: 0,
+            cycles_since_save: 0,
+        };
+        console.cpu.reset();
+        Ok(console)
+    }
+
+    // Powers up with the battery-backed memory kept in <rom>.sav next to
+    // the ROM file, or in <entry>.sav for games loaded from an archive, see
+    // `SaveFile::for_rom`
+    pub fn with_save<P: AsRef<Path>>(rom: &ROM, rom_path: P) -> Result<Console, ConsoleError> {
+        let mut console = Console::new(rom)?;
+        console.attach_save_file(SaveFile::for_rom(rom_path, rom.archive_entry.as_deref()))?;
+        Ok(console)
+    }
+
+    // The reset button
+    pub fn reset(&mut self) {
+        self.cpu.bus.mapper.notify_reset();
+        self.cpu.reset();
+    }
+
+    // Sets the cartridge's dipswitches, multicarts read them to pick the menu
+    pub fn set_dipswitch(&mut self, value: u8) {
+        self.cpu.bus.mapper.set_dipswitch(value);
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // A .pal file instead of the built-in colors
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    // Off shows every sprite instead of 8 a line, see `PPU::set_sprite_limit`
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.cpu.bus.ppu.set_sprite_limit(limit);
+    }
+
+    // Keeps the cartridge's battery-backed memory in the file, loading it
+    // now if it exists. Does nothing for boards without a battery.
+    pub fn attach_save_file(&mut self, mut save_file: SaveFile) -> io::Result<()> {
+        if self.cpu.bus.mapper.save_ram().is_none() {
+            return Ok(());
+        }
+        if let Some(data) = save_file.read()? {
+            self.cpu.bus.mapper.load_save_ram(&data);
+        }
+        self.save_file = Some(save_file);
+        Ok(())
+    }
+
+    // Writes the save every `interval` of emulated time as well, None
+    // only writes it on `flush_save` and when the console is dropped
+    pub fn set_save_interval(&mut self, interval: Option<Duration>) {
+        self.save_interval = interval.map_or(0, |interval| {
+            (interval.as_secs_f64() * self.cpu_clock as f64) as u64
+        });
+        self.cycles_since_save = 0;
+    }
+
+    // Writes the battery-backed memory if it changed since the last write
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        self.cycles_since_save = 0;
+        match (self.save_file.as_mut(), self.cpu.bus.mapper.save_ram()) {
+            (Some(save_file), Some(data)) => save_file.write(data).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    // Runs until the PPU has drawn a whole picture
+    pub fn run_frame(&mut self) -> Frame<'_> {
+        while !self.cpu.bus.ppu.frame_finished() {
+            self.step();
+        }
+        self.palette.to_rgba(self.cpu.bus.ppu.frame_buffer(), &mut self.rgba);
+        self.frame()
+    }
+
+    // The picture of the last `run_frame`
+    pub fn frame(&self) -> Frame<'_> {
+        Frame {
+            pixels: self.cpu.bus.ppu.frame_buffer(),
+            rgba: &self.rgba,
+        }
+    }
+
+    // Writes the picture of the last `run_frame` to a PNG file, without
+    // the overscan. Fails with `InvalidInput` when nothing is left of it.
+    pub fn screenshot<P: AsRef<Path>>(&self, path: P, overscan: Overscan) -> io::Result<()> {
+        let width = SCREEN_WIDTH.saturating_sub(overscan.left.saturating_add(overscan.right));
+        let height = SCREEN_HEIGHT.saturating_sub(overscan.top.saturating_add(overscan.bottom));
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the overscan covers the whole picture"));
+        }
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        let left = overscan.left;
+        for row in self.rgba.chunks(SCREEN_WIDTH * 4).skip(overscan.top).take(height) {
+            rgba.extend_from_slice(&row[left * 4..(left + width) * 4]);
+        }
+        png::write(path, width, height, &rgba)
+    }
+
+    // Executes one CPU instruction, returns the cycles taken
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.cpu.step();
+        if self.save_interval > 0 {
+            self.cycles_since_save += cycles as u64;
+            if self.cycles_since_save >= self.save_interval {
+                // A failed write leaves the save dirty, the next flush
+                // retries and reports the error
+                let _ = self.flush_save();
+            }
+        }
+        cycles
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use console::*;
+    use cpu::bus::Bus;
+    use cpu::instructions;
+    use rom::LoadOptions;
+    use utils::test_dir;
+
+    use zip::write::{FileOptions, ZipWriter};
+
+    use std::io::Write;
+
+    use std::fs;
+
+    #[test]
+    fn booting_super_mario_bros() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/Super Mario Bros (E).nes");
+        let rom = ROM::load(path).unwrap();
+        let mut console = Console::new(&rom).unwrap();
+        // Reset vector
+        assert_eq!(console.cpu.pc_reg, 0x8000);
+
+        for _ in 0..10_000 {
+            console.step();
+        }
+        // The init code has set up the stack and waits for vblank in ROM
+        assert_eq!(console.cpu.sp_reg, 0xff);
+        assert!(console.cpu.pc_reg >= 0x8000);
+        assert_eq!(console.cpu.p_reg.get_interrupt_flag(), 1);
+    }
+
+    #[test]
+    fn keeping_saves() {
+        // NROM with battery-backed PRG RAM
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        let dir = test_dir("keeping_saves");
+        let save_path = dir.join("game.sav");
+
+        let mut console = Console::new(&rom).unwrap();
+        console.attach_save_file(SaveFile::new(&save_path)).unwrap();
+        console.set_save_interval(Some(Duration::from_millis(1)));
+        console.cpu.bus.write(0x6000, 0x42);
+        // Written after a millisecond of emulated time
+        for _ in 0..2000 {
+            console.step();
+        }
+        assert_eq!(fs::read(&save_path).unwrap()[0], 0x42);
+
+        console.cpu.bus.write(0x6001, 0x43);
+        console.flush_save().unwrap();
+        assert_eq!(fs::read(&save_path).unwrap()[1], 0x43);
+        console.cpu.bus.write(0x6002, 0x44);
+        drop(console);
+
+        let mut console = Console::new(&rom).unwrap();
+        console.attach_save_file(SaveFile::new(&save_path)).unwrap();
+        assert_eq!(console.cpu.bus.read_byte(0x6002), 0x44);
+        assert_eq!(fs::read(&save_path).unwrap().len(), 0x2000);
+        drop(console);
+
+        // Next to the ROM file
+        let rom_path = dir.join("game.nes");
+        fs::write(&rom_path, &data).unwrap();
+        let rom = ROM::load(rom_path.to_str().unwrap()).unwrap();
+        let mut console = Console::with_save(&rom, &rom_path).unwrap();
+        assert_eq!(console.cpu.bus.read_byte(0x6002), 0x44);
+        console.cpu.bus.write(0x6003, 0x45);
+        drop(console);
+        assert_eq!(fs::read(&save_path).unwrap()[3], 0x45);
+
+        // A save that can't be read isn't overwritten
+        fs::remove_file(&save_path).unwrap();
+        fs::create_dir(&save_path).unwrap();
+        assert!(matches!(Console::with_save(&rom, &rom_path), Err(ConsoleError::SaveError(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeping_saves_of_archived_games() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        let dir = test_dir("keeping_saves_of_archived_games");
+        let zip_path = dir.join("games.zip");
+        let mut writer = ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        for name in &["First.nes", "Second.nes"] {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(&data).unwrap();
+        }
+        writer.finish().unwrap();
+
+        for (name, value) in &[("First.nes", 0x11), ("Second.nes", 0x22)] {
+            let options = LoadOptions { archive_entry: Some(name.to_string()), ..LoadOptions::default() };
+            let rom = ROM::load_with_options(zip_path.to_str().unwrap(), &options).unwrap();
+            let mut console = Console::with_save(&rom, &zip_path).unwrap();
+            console.cpu.bus.write(0x6000, *value);
+        }
+        assert_eq!(fs::read(dir.join("First.sav")).unwrap()[0], 0x11);
+        assert_eq!(fs::read(dir.join("Second.sav")).unwrap()[0], 0x22);
+        assert!(!dir.join("games.sav").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn storing_to_ppu_registers() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        let mut console = Console::new(&ROM::from_bytes(&data, &LoadOptions::default()).unwrap()).unwrap();
+        // $2400 through the mirrors of $2006 and $2007
+        for &(address, value) in &[(0x2006, 0x24), (0x200e, 0x00), (0x3ff7, 0x42)] {
+            console.cpu.a_reg = value;
+            instructions::sta(&mut console.cpu, address);
+        }
+        console.cpu.bus.write(0x2006, 0x24);
+        console.cpu.bus.write(0x2006, 0x00);
+        console.cpu.bus.read_byte(0x2007);
+        assert_eq!(console.cpu.bus.read_byte(0x2007), 0x42);
+    }
+
+    #[test]
+    fn copying_sprites_with_dma() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        let mut console = Console::new(&ROM::from_bytes(&data, &LoadOptions::default()).unwrap()).unwrap();
+        console.cpu.bus.write(0x0201, 0x55);
+        console.cpu.bus.write(0x02ff, 0xaa);
+        console.cpu.bus.write(0x4014, 0x02);
+        console.cpu.bus.write(0x2003, 0x01);
+        assert_eq!(console.cpu.bus.read_byte(0x2004), 0x55);
+        console.cpu.bus.write(0x2003, 0xff);
+        assert_eq!(console.cpu.bus.read_byte(0x2004), 0xaa);
+    }
+
+    #[test]
+    fn running_frames() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/Super Mario Bros (E).nes");
+        let mut console = Console::new(&ROM::load(path).unwrap()).unwrap();
+        for _ in 0..60 {
+            console.run_frame();
+        }
+        // The title screen on a blue sky
+        let frame = console.run_frame();
+        assert_eq!(frame.pixels[0], 0x22);
+        assert_eq!(frame.rgba[0..4], [0x92, 0x90, 0xff, 0xff]);
+        let mut colors = frame.pixels.to_vec();
+        colors.sort();
+        colors.dedup();
+        assert!(colors.len() > 8);
+        // Sprite 0 for the status bar split, copied by OAM DMA
+        assert_eq!(console.cpu.bus.ppu.oam[1], 0xff);
+
+        let dir = test_dir("running_frames");
+        let path = dir.join("title.png");
+        console.screenshot(&path, Overscan::tv()).unwrap();
+        let png = fs::read(&path).unwrap();
+        // 256x224
+        assert_eq!(png[16..24], [0, 0, 1, 0, 0, 0, 0, 224]);
+        let overscan = Overscan { left: 128, right: 128, ..Overscan::default() };
+        let error = console.screenshot(&path, overscan).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
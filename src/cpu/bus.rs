@@ -0,0 +1,27 @@
+// Everything the CPU sees through its address and data lines: RAM,
+// memory-mapped registers and the cartridge
+pub trait Bus {
+    fn read_byte(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, byte: u8);
+
+    fn read_word(&mut self, address: u16) -> u16 {
+        let lower_byte = self.read_byte(address) as u16;
+        let upper_byte = self.read_byte(address.wrapping_add(1)) as u16;
+        upper_byte << 8 | lower_byte
+    }
+
+    // Called after every instruction with the number of CPU cycles it took,
+    // so that the rest of the hardware can catch up
+    fn tick(&mut self, _cycles: u32) {}
+
+    // Level of the /IRQ line
+    fn irq(&mut self) -> bool {
+        false
+    }
+
+    // True once for every falling edge of /NMI
+    fn nmi(&mut self) -> bool {
+        false
+    }
+}
@@ -1,10 +1,14 @@
 use utils;
+use cpu::bus::Bus;
 use cpu::opcode::*;
 use cpu::instructions::*;
-use ram;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
 
 #[derive(Debug)]
-pub struct CPU {
+pub struct CPU<B: Bus> {
     // Accumulator register
     pub a_reg: u8,
     // Index registers
@@ -16,7 +20,11 @@ pub struct CPU {
     pub sp_reg: u8,
     // Program counter
     pub pc_reg: u16,
-    pub ram: ram::RAM,
+    pub bus: B,
+    // Cycles elapsed since power up
+    pub cycles: u64,
+    // Set by indexed addressing when the effective address is on another page
+    page_crossed: bool,
 }
 
 // 7  bit  0
@@ -36,12 +44,14 @@ pub struct CPU {
 // +--------- Negative: Set to bit 7 of the last operation
 
 #[derive(Debug)]
-enum Status {
+pub enum Status {
     CarryFlag = 0,
     ZeroFlag = 1,
     InterruptFlag = 2,
     DecimalMode = 3,
     Breakpoint = 4,
+    // Always reads back as 1
+    Unused = 5,
     OverflowFlag = 6,
     NegativeFlag = 7,
 }
@@ -51,6 +61,12 @@ pub struct StatusRegister {
     pub data: u8,
 }
 
+impl Default for StatusRegister {
+    fn default() -> StatusRegister {
+        StatusRegister::new()
+    }
+}
+
 impl StatusRegister {
     pub fn new() -> StatusRegister {
         StatusRegister {
@@ -83,7 +99,7 @@ impl StatusRegister {
         utils::get_bit(&self.data, Status::OverflowFlag as u8)
     }
 
-    pub fn get_negative_flag(&mut self) -> u8 {
+    pub fn get_negative_flag(&self) -> u8 {
         utils::get_bit(&self.data, Status::NegativeFlag as u8)
     }
 
@@ -114,142 +130,280 @@ impl StatusRegister {
     pub fn set_negative_flag(&mut self, value: bool) {
         utils::set_bit(&mut self.data, Status::NegativeFlag as u8, value);
     }
+
+    pub fn set_zero_and_negative(&mut self, value: u8) {
+        self.set_zero_flag(value == 0x0);
+        self.set_negative_flag(value & 0x80 == 0x80);
+    }
+
+    // The B flag doesn't exist in the register, it only appears in the copy
+    // pushed on the stack: set by PHP and BRK, clear for /IRQ and /NMI
+    pub fn to_stack(&self, breakpoint: bool) -> u8 {
+        let mut data = self.data;
+        utils::set_bit(&mut data, Status::Breakpoint as u8, breakpoint);
+        utils::set_bit(&mut data, Status::Unused as u8, true);
+        data
+    }
+
+    pub fn from_stack(&mut self, data: u8) {
+        self.data = data;
+        utils::set_bit(&mut self.data, Status::Breakpoint as u8, false);
+        utils::set_bit(&mut self.data, Status::Unused as u8, true);
+    }
 } 
 
-impl CPU {
+impl<B: Bus> CPU<B> {
     // Power up
-    pub fn new(ram: ram::RAM) -> CPU {
+    pub fn new(bus: B) -> CPU<B> {
         CPU {
             a_reg: 0x0,
             x_reg: 0x0,
             y_reg: 0x0,
             p_reg: StatusRegister::new(),
             sp_reg: 0xfd,
-            pc_reg: 0x79,
-            ram: ram,
+            pc_reg: 0x0,
+            bus,
+            cycles: 0,
+            page_crossed: false,
         }
     }
 
+    // Jumps to the reset vector. Called on power up as well, the stack
+    // pointer ends up at $FD then.
     pub fn reset(&mut self) {
-        self.sp_reg -= 3; 
+        self.sp_reg = self.sp_reg.wrapping_sub(3);
         self.p_reg.set_interrupt_flag(true);
+        self.pc_reg = self.bus.read_word(RESET_VECTOR);
+        self.cycles += 7;
+        self.bus.tick(7);
+    }
+
+    // Executes one instruction, or enters an interrupt handler.
+    // Returns the number of cycles taken.
+    pub fn step(&mut self) -> u32 {
+        let cycles = if self.bus.nmi() {
+            self.interrupt(NMI_VECTOR)
+        } else if self.bus.irq() && self.p_reg.get_interrupt_flag() == 0 {
+            self.interrupt(IRQ_VECTOR)
+        } else {
+            self.execute()
+        };
+        self.cycles += cycles as u64;
+        self.bus.tick(cycles);
+        cycles
     }
 
-    pub fn step(&mut self) {
+    fn execute(&mut self) -> u32 {
         let opcodes = &MAP;
         // Fetching opcodes from programm counter register.
-        let address = self.pc_reg as u8;
-        let opcode = opcodes.get(&address).unwrap();
-        println!("{:?}", opcode);
-
-        let fetched_address = self.choose_addressing_mode(opcode) as u8;
+        let code = self.bus.read_byte(self.pc_reg);
+        self.pc_reg = self.pc_reg.wrapping_add(1);
+        let opcode = match opcodes.get(&code) {
+            Some(opcode) => opcode,
+            // Unofficial opcodes aren't supported, skip them as one byte NOPs
+            None => return 2,
+        };
+
+        self.page_crossed = false;
+        let fetched_address = self.choose_addressing_mode(opcode);
         // Decoding and executing instruction.
-        self.execute_instruction(fetched_address, opcode);
+        let extra_cycles = self.execute_instruction(fetched_address, opcode);
+
+        let page_cycle = match opcode.label {
+            Label::ADC | Label::AND | Label::CMP | Label::EOR | Label::LDA |
+            Label::LDX | Label::LDY | Label::ORA | Label::SBC => self.page_crossed as u32,
+            _ => 0,
+        };
+        opcode.cycles as u32 + page_cycle + extra_cycles
     }
 
+    fn interrupt(&mut self, vector: u16) -> u32 {
+        let pc = self.pc_reg;
+        let p = self.p_reg.to_stack(false);
+        self.push_word(pc);
+        self.push(p);
+        self.p_reg.set_interrupt_flag(true);
+        self.pc_reg = self.bus.read_word(vector);
+        7
+    }
+
+    // Returns the effective address of the operand
     pub fn choose_addressing_mode(&mut self, opcode: &OpCode) -> u16 {
         match opcode.mode {
             AddressingMode::Implicit => 0x0000,
-            AddressingMode::Accumulator => self.fetching_accumulator() as u16,
-            AddressingMode::Immediate => self.fetch_immidiate() as u16,
+            AddressingMode::Accumulator => 0x0000,
+            AddressingMode::Immediate => self.fetch_immediate(),
             AddressingMode::ZeroPage => self.fetch_zero_page(),
             AddressingMode::ZeroPageX => self.fetch_zero_page_x(),
             AddressingMode::ZeroPageY => self.fetch_zero_page_y(),
             AddressingMode::Absolute => self.fetch_absolute(),
             AddressingMode::AbsoluteX => self.fetch_absolute_x(),
             AddressingMode::AbsoluteY => self.fetch_absolute_y(),
-            AddressingMode::Relative => self.fetch_relative() as u16,
+            AddressingMode::Relative => self.fetch_relative(),
+            AddressingMode::Indirect => self.fetch_indirect(),
             AddressingMode::IndirectX => self.fetch_indirect_x(),
             AddressingMode::IndirectY => self.fetch_indirect_y(),
-            _ => 0x0
         } 
     }
 
-    pub fn execute_instruction(&mut self, address: u8, opcode: &OpCode) {
+    // Returns extra cycles taken by branches
+    pub fn execute_instruction(&mut self, address: u16, opcode: &OpCode) -> u32 {
+        let accumulator = opcode.mode == AddressingMode::Accumulator;
         match opcode.label {
-            Label::ADC => adc(self, address as u8),
-            Label::LDA => lda(self, address as u8),
-            _ => println!("Other instructions, {:?}", opcode)
+            Label::ADC => adc(self, address),
+            Label::AND => and(self, address),
+            Label::ASL if accumulator => asl_accumulator(self),
+            Label::ASL => asl(self, address),
+            Label::BCC => return bcc(self, address),
+            Label::BCS => return bcs(self, address),
+            Label::BEQ => return beq(self, address),
+            Label::BIT => bit(self, address),
+            Label::BMI => return bmi(self, address),
+            Label::BNE => return bne(self, address),
+            Label::BPL => return bpl(self, address),
+            Label::BRK => brk(self, address),
+            Label::BVC => return bvc(self, address),
+            Label::BVS => return bvs(self, address),
+            Label::CLC => clc(self, address),
+            Label::CLD => cld(self, address),
+            Label::CLI => cli(self, address),
+            Label::CLV => clv(self, address),
+            Label::CMP => cmp(self, address),
+            Label::CPX => cpx(self, address),
+            Label::CPY => cpy(self, address),
+            Label::DEC => dec(self, address),
+            Label::DEX => dex(self, address),
+            Label::DEY => dey(self, address),
+            Label::EOR => eor(self, address),
+            Label::INC => inc(self, address),
+            Label::INX => inx(self, address),
+            Label::INY => iny(self, address),
+            Label::JMP => jmp(self, address),
+            Label::JSR => jsr(self, address),
+            Label::LDA => lda(self, address),
+            Label::LDX => ldx(self, address),
+            Label::LDY => ldy(self, address),
+            Label::LSR if accumulator => lsr_accumulator(self),
+            Label::LSR => lsr(self, address),
+            Label::NOP => nop(self, address),
+            Label::ORA => ora(self, address),
+            Label::PHA => pha(self, address),
+            Label::PHP => php(self, address),
+            Label::PLA => pla(self, address),
+            Label::PLP => plp(self, address),
+            Label::ROL if accumulator => rol_accumulator(self),
+            Label::ROL => rol(self, address),
+            Label::ROR if accumulator => ror_accumulator(self),
+            Label::ROR => ror(self, address),
+            Label::RTI => rti(self, address),
+            Label::RTS => rts(self, address),
+            Label::SBC => sbc(self, address),
+            Label::SEC => sec(self, address),
+            Label::SED => sed(self, address),
+            Label::SEI => sei(self, address),
+            Label::STA => sta(self, address),
+            Label::STX => stx(self, address),
+            Label::STY => sty(self, address),
+            Label::TAX => tax(self, address),
+            Label::TAY => tay(self, address),
+            Label::TSX => tsx(self, address),
+            Label::TXA => txa(self, address),
+            Label::TXS => txs(self, address),
+            Label::TYA => tya(self, address),
         }
+        0
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bus.read_byte(self.pc_reg);
+        self.pc_reg = self.pc_reg.wrapping_add(1);
+        byte
     }
 
-    pub fn fetching_accumulator(&mut self) -> u8 {
-        self.a_reg
+    fn next_word(&mut self) -> u16 {
+        let word = self.bus.read_word(self.pc_reg);
+        self.pc_reg = self.pc_reg.wrapping_add(2);
+        word
+    }
+
+    // Reads a pointer from the zero page, wrapping around $FF
+    fn read_zero_page_word(&mut self, address: u8) -> u16 {
+        let lower_byte = self.bus.read_byte(address as u16) as u16;
+        let upper_byte = self.bus.read_byte(address.wrapping_add(1) as u16) as u16;
+        upper_byte << 8 | lower_byte
+    }
+
+    fn indexed(&mut self, base: u16, index: u8) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        self.page_crossed = base & 0xff00 != address & 0xff00;
+        address
     }
 
-    pub fn fetch_immidiate(&mut self) -> u8 {
-        let address = self.ram.read_byte(self.pc_reg);
-        self.pc_reg += 1;
+    pub fn fetch_immediate(&mut self) -> u16 {
+        let address = self.pc_reg;
+        self.pc_reg = self.pc_reg.wrapping_add(1);
         address
     }
 
     pub fn fetch_zero_page(&mut self) -> u16 {
-        let address = self.ram.read_byte(self.pc_reg);
-        self.pc_reg += 1;
-        address as u16
+        self.next_byte() as u16
     }
 
     pub fn fetch_zero_page_x(&mut self) -> u16 {
-        let address = self.ram.read_byte(self.pc_reg);
-        self.pc_reg += 1;
-        (address as u16 + self.x_reg as u16) & 0xff as u16
+        self.next_byte().wrapping_add(self.x_reg) as u16
     }
 
     pub fn fetch_zero_page_y(&mut self) -> u16 {
-        let address = self.ram.read_byte(self.pc_reg);
-        self.pc_reg += 1;
-        (address as u16 + self.y_reg as u16) & 0xff as u16
+        self.next_byte().wrapping_add(self.y_reg) as u16
     }
 
-    pub fn fetch_relative(&mut self) -> u8 {
-        let address = self.ram.read_byte(self.pc_reg);
-        self.pc_reg += 1;
-        if address > 0x80 {
-            address + self.pc_reg as u8
-        } else {
-            address + (0x100 - self.pc_reg) as u8
-        }
+    // Branch target, the offset is signed and relative to the next instruction
+    pub fn fetch_relative(&mut self) -> u16 {
+        let offset = self.next_byte() as i8;
+        self.pc_reg.wrapping_add(offset as u16)
     }
 
     pub fn fetch_absolute(&mut self) -> u16 {
-        let address = self.ram.read_word(self.pc_reg);
-        self.pc_reg += 2;
-        address & 0xffff
+        self.next_word()
     }
 
     pub fn fetch_absolute_x(&mut self) -> u16 {
-        let address = self.ram.read_word(self.pc_reg);
-        self.pc_reg += 2;
-        (address + self.x_reg as u16) & 0xffff
+        let base = self.next_word();
+        let x = self.x_reg;
+        self.indexed(base, x)
     }
     
     pub fn fetch_absolute_y(&mut self) -> u16 {
-        let address = self.ram.read_word(self.pc_reg);
-        self.pc_reg += 2;
-        (address + self.y_reg as u16) & 0xffff
+        let base = self.next_word();
+        let y = self.y_reg;
+        self.indexed(base, y)
     }
 
-    pub fn fetch_indirect_x(&mut self) -> u16 {
-        let address = self.ram.read_byte(self.pc_reg);
-        let tmp = ((address + self.x_reg) & 0xff) as u16;
-        let result = self.ram.read_word(tmp);
-        self.pc_reg += 1;
+    // JMP ($xxFF) takes the upper byte from $xx00, not from the next page
+    pub fn fetch_indirect(&mut self) -> u16 {
+        let pointer = self.next_word();
+        let lower_byte = self.bus.read_byte(pointer) as u16;
+        let upper_address = (pointer & 0xff00) | (pointer.wrapping_add(1) & 0x00ff);
+        let upper_byte = self.bus.read_byte(upper_address) as u16;
+        upper_byte << 8 | lower_byte
+    }
 
-        (self.ram.read_word(result) + self.ram.read_word(result + 1) * 0x100)
+    pub fn fetch_indirect_x(&mut self) -> u16 {
+        let pointer = self.next_byte().wrapping_add(self.x_reg);
+        self.read_zero_page_word(pointer)
     }
 
     pub fn fetch_indirect_y(&mut self) -> u16 {
-        let address = self.ram.read_word(self.pc_reg);
-        let base = ((self.ram.read_word(address) + self.ram.read_word(address + 1)) & 0x00ff) * 0x100;
-        self.pc_reg += 1;
-
-        (base as u16 + self.y_reg as u16) & 0xffff
+        let pointer = self.next_byte();
+        let base = self.read_zero_page_word(pointer);
+        let y = self.y_reg;
+        self.indexed(base, y)
     }
 
     // Stack starts with 0x100 untill 0x1FF.
     pub fn push(&mut self, byte: u8) {
-        self.ram.write(0x100 | self.sp_reg as u16, byte);
-        self.sp_reg -= 1u8;
+        self.bus.write(0x100 | self.sp_reg as u16, byte);
+        self.sp_reg = self.sp_reg.wrapping_sub(1);
     }
 
     pub fn push_word(&mut self, word: u16) {
@@ -258,15 +412,83 @@ impl CPU {
     }
 
     pub fn pull(&mut self) -> u8 {
-        self.sp_reg += 1u8;
-        let byte = self.ram.read_byte(0x100 | self.sp_reg as u16);
-        byte
+        self.sp_reg = self.sp_reg.wrapping_add(1);
+        self.bus.read_byte(0x100 | self.sp_reg as u16)
     }
 
     pub fn pull_word(&mut self) -> u16 {
         let low = self.pull() as u16;
         let high = self.pull() as u16;
-        let word = high << 0x8 | low;
-        word
+        high << 0x8 | low
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cpu::cpu::*;
+    use ram::RAM;
+
+    // Loads the program at $0200 and runs `steps` instructions
+    fn run(program: &[u8], steps: usize) -> CPU<RAM> {
+        let mut ram = RAM::new();
+        ram.data[0x200..0x200 + program.len()].copy_from_slice(program);
+        let mut cpu = CPU::new(ram);
+        cpu.pc_reg = 0x200;
+        for _ in 0..steps {
+            cpu.step();
+        }
+        cpu
+    }
+
+    #[test]
+    fn adding_and_subtracting() {
+        // LDA #$50; CLC; ADC #$50
+        let cpu = run(&[0xa9, 0x50, 0x18, 0x69, 0x50], 3);
+        assert_eq!(cpu.a_reg, 0xa0);
+        assert_eq!(cpu.p_reg.get_overflow_flag(), 1);
+        assert_eq!(cpu.p_reg.get_negative_flag(), 1);
+        assert_eq!(cpu.p_reg.get_carry_flag(), 0);
+
+        // LDA #$10; SEC; SBC #$20
+        let cpu = run(&[0xa9, 0x10, 0x38, 0xe9, 0x20], 3);
+        assert_eq!(cpu.a_reg, 0xf0);
+        assert_eq!(cpu.p_reg.get_carry_flag(), 0);
+        assert_eq!(cpu.p_reg.get_zero_flag(), 0);
+    }
+
+    #[test]
+    fn storing_and_shifting_memory() {
+        // LDA #$81; STA $10; ASL $10; LDX $10
+        let cpu = run(&[0xa9, 0x81, 0x85, 0x10, 0x06, 0x10, 0xa6, 0x10], 4);
+        assert_eq!(cpu.bus.data[0x10], 0x02);
+        assert_eq!(cpu.x_reg, 0x02);
+        assert_eq!(cpu.p_reg.get_carry_flag(), 1);
+    }
+
+    #[test]
+    fn looping_with_branches() {
+        // LDX #$03; DEX; BNE -3
+        let mut cpu = run(&[0xa2, 0x03, 0xca, 0xd0, 0xfd], 1);
+        let mut cycles = 0;
+        for _ in 0..6 {
+            cycles += cpu.step();
+        }
+        assert_eq!(cpu.x_reg, 0);
+        assert_eq!(cpu.p_reg.get_zero_flag(), 1);
+        assert_eq!(cpu.pc_reg, 0x205);
+        // Two taken branches, one not taken
+        assert_eq!(cycles, 3 * 2 + 3 + 3 + 2);
+    }
+
+    #[test]
+    fn calling_subroutines() {
+        // JSR $0210; LDY #$01 ... $0210: LDA #$42; RTS
+        let mut program = vec![0xea; 0x14];
+        program[..5].copy_from_slice(&[0x20, 0x10, 0x02, 0xa0, 0x01]);
+        program[0x10..0x13].copy_from_slice(&[0xa9, 0x42, 0x60]);
+        let cpu = run(&program, 4);
+        assert_eq!(cpu.a_reg, 0x42);
+        assert_eq!(cpu.y_reg, 0x01);
+        assert_eq!(cpu.sp_reg, 0xfd);
     }
 }
@@ -1,156 +1,126 @@
 // Explanation of instructions: http://obelisk.me.uk/6502/reference.html
+use cpu::bus::Bus;
 use cpu::cpu;
-use utils::get_bit;
 
 // --------------- Load and Store operations ---------------
-pub fn lda(cpu: &mut cpu::CPU, address: u8)  {
-    println!("LDA performed");
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn lda<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     cpu.a_reg = memory;
-
-    if cpu.a_reg == 0x0 {
-        cpu.p_reg.set_zero_flag(false);
-    }
-
-    if get_bit(&cpu.a_reg, 7) == 1 {
-        cpu.p_reg.set_negative_flag(true);
-    }
+    cpu.p_reg.set_zero_and_negative(memory);
 }
 
-pub fn ldx(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn ldx<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     cpu.x_reg = memory;
-
-    if cpu.x_reg == 0x0 {
-        cpu.p_reg.set_zero_flag(false);
-    }
-
-    if get_bit(&cpu.x_reg, 7) == 1 {
-        cpu.p_reg.set_negative_flag(true);
-    } 
+    cpu.p_reg.set_zero_and_negative(memory);
 }
 
-pub fn ldy(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn ldy<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     cpu.y_reg = memory;
-
-    if cpu.y_reg == 0x0 {
-        cpu.p_reg.set_zero_flag(false);
-    }
-
-    if get_bit(&cpu.y_reg, 7) == 1 {
-        cpu.p_reg.set_negative_flag(true);
-    } 
+    cpu.p_reg.set_zero_and_negative(memory);
 }
 
-pub fn sta(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    cpu.a_reg = memory;
+pub fn sta<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let accum = cpu.a_reg;
+    cpu.bus.write(address, accum);
 }
 
-pub fn stx(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    cpu.x_reg = memory;
+pub fn stx<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let x = cpu.x_reg;
+    cpu.bus.write(address, x);
 }
 
-pub fn sty(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    cpu.y_reg = memory;
+pub fn sty<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let y = cpu.y_reg;
+    cpu.bus.write(address, y);
 }
 
 // --------------- Register Transfers ---------------
-pub fn tax(cpu: &mut cpu::CPU, address: u8) {
+pub fn tax<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.x_reg = cpu.a_reg;
     let x = cpu.x_reg;
-
-    cpu.p_reg.set_zero_flag(x == 0x0);
-    cpu.p_reg.set_negative_flag(x & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(x);
 }
 
-pub fn tay(cpu: &mut cpu::CPU, address: u8) {
+pub fn tay<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.y_reg = cpu.a_reg;
     let y = cpu.y_reg;
-
-    cpu.p_reg.set_zero_flag(y == 0x0);
-    cpu.p_reg.set_negative_flag(y & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(y);
 }
 
-pub fn txa(cpu: &mut cpu::CPU, address: u8) {
+pub fn txa<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.a_reg = cpu.x_reg;
     let accum = cpu.a_reg;
-
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(accum & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(accum);
 }
 
-pub fn tya(cpu: &mut cpu::CPU, address: u8) {
+pub fn tya<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.a_reg = cpu.y_reg;
     let accum = cpu.a_reg;
-
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(accum & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(accum);
 }
 
 // --------------- Stack Operations ---------------
-pub fn tsx(cpu: &mut cpu::CPU, address: u8) {
+pub fn tsx<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.x_reg = cpu.sp_reg;
     let x = cpu.x_reg;
-
-    cpu.p_reg.set_zero_flag(x == 0x0);
-    cpu.p_reg.set_negative_flag(x & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(x);
 }
 
-pub fn txs(cpu: &mut cpu::CPU, address: u8) {
+pub fn txs<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.sp_reg = cpu.x_reg;
 }
 
-pub fn pha(cpu: &mut cpu::CPU, address: u8) {
+pub fn pha<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     let accum = cpu.a_reg;
     cpu.push(accum);
 }
 
-pub fn php(cpu: &mut cpu::CPU, address: u8) {
-    let status = cpu.p_reg.data;
+pub fn php<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let status = cpu.p_reg.to_stack(true);
     cpu.push(status);
 }
 
-pub fn pla(cpu: &mut cpu::CPU, address: u8) {
+pub fn pla<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.a_reg = cpu.pull();
     let accum = cpu.a_reg;
-
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(accum & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(accum);
 }
 
-pub fn plp(cpu: &mut cpu::CPU, address: u8) {
-    cpu.p_reg.data = cpu.pull(); 
+pub fn plp<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let status = cpu.pull();
+    cpu.p_reg.from_stack(status);
 }
 
 // --------------- Logical --------------- 
-pub fn and(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn and<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     let accum = cpu.a_reg & memory; 
     cpu.a_reg = accum;
-
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(accum & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(accum);
 }
 
-pub fn eor(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn eor<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     let accum = cpu.a_reg ^ memory;
     cpu.a_reg = accum;
+    cpu.p_reg.set_zero_and_negative(accum);
+}
 
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(accum & 0x80 == 0x80);
+pub fn ora<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    let accum = cpu.a_reg | memory;
+    cpu.a_reg = accum;
+    cpu.p_reg.set_zero_and_negative(accum);
 }
 
-pub fn nop(cpu: &mut cpu::CPU, address: u8) {
+pub fn nop<B: Bus>(_cpu: &mut cpu::CPU<B>, _address: u16) {
     // Empty instruction. This one just increment program counter.
 }
 
-pub fn bit(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn bit<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     let accum = cpu.a_reg;
     let m6 = memory & 0x40; 
     let m7 = memory & 0x80;
@@ -161,214 +131,278 @@ pub fn bit(cpu: &mut cpu::CPU, address: u8) {
 }
 
 // --------------- Arithmetic operations ---------------
-pub fn adc(cpu: &mut cpu::CPU, address: u8) {
-    println!("ADC performed");
-    let memory = cpu.ram.read_byte(address as u16);
-    let carry = cpu.p_reg.get_carry_flag() as u8;
+// The NES CPU has no decimal mode, the D flag is ignored
+fn add_with_carry<B: Bus>(cpu: &mut cpu::CPU<B>, memory: u8) {
+    let carry = cpu.p_reg.get_carry_flag();
     let accum = cpu.a_reg;
-    cpu.a_reg = accum + memory + carry;
 
     let result = accum as u16 + memory as u16 + carry as u16;
+    let result_byte = result as u8;
+    cpu.a_reg = result_byte;
+
     cpu.p_reg.set_carry_flag(result > 0xff);
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_overflow_flag((accum ^ memory) & (accum ^ result as u8) & 0x80 != 0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    cpu.p_reg.set_overflow_flag((accum ^ result_byte) & (memory ^ result_byte) & 0x80 != 0);
+    cpu.p_reg.set_zero_and_negative(result_byte);
 }
 
-pub fn sbc(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    let carry = 1u8 - cpu.p_reg.get_carry_flag();
-    let accum = cpu.a_reg;
-    cpu.a_reg = accum + memory + carry;
-    
-    let result = accum as u16 + memory as u16 + carry as u16;
-    cpu.p_reg.set_carry_flag(result > 0xff);
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_overflow_flag((accum ^ memory) & (accum ^ result as u8) & 0x80 != 0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn adc<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    add_with_carry(cpu, memory);
 }
 
-pub fn cpm(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    let accum = cpu.a_reg;
-    let result = accum - memory;
+// A - M - (1 - C) is the same as A + !M + C
+pub fn sbc<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    add_with_carry(cpu, !memory);
+}
 
-    cpu.p_reg.set_carry_flag(accum >= memory);
-    cpu.p_reg.set_zero_flag(accum == memory);
+fn compare<B: Bus>(cpu: &mut cpu::CPU<B>, register: u8, memory: u8) {
+    let result = register.wrapping_sub(memory);
+
+    cpu.p_reg.set_carry_flag(register >= memory);
+    cpu.p_reg.set_zero_flag(register == memory);
     cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
 }
 
-pub fn cpx(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
-    let x = cpu.x_reg;
-    let result = x - memory;
+pub fn cmp<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    let accum = cpu.a_reg;
+    compare(cpu, accum, memory);
+}
 
-    cpu.p_reg.set_carry_flag(x >= memory);
-    cpu.p_reg.set_zero_flag(x == memory);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn cpx<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    let x = cpu.x_reg;
+    compare(cpu, x, memory);
 }
 
-pub fn cpy(cpu: &mut cpu::CPU, address: u8) {
-    let memory = cpu.ram.read_byte(address as u16);
+pub fn cpy<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
     let y = cpu.y_reg;
-    let result = y - memory;
-
-    cpu.p_reg.set_carry_flag(y >= memory);
-    cpu.p_reg.set_zero_flag(y == memory);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    compare(cpu, y, memory);
 }
 
 // --------------- Increments & Decrements ---------------
-pub fn inc(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.ram.read_byte(address as u16) + 1u8;
-    cpu.ram.write(address as u16, result);
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn inc<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let result = memory.wrapping_add(1);
+    cpu.bus.write(address, result);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
-pub fn inx(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.x_reg + 1u8;
+pub fn inx<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let result = cpu.x_reg.wrapping_add(1);
     cpu.x_reg = result;
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
-pub fn iny(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.y_reg + 1u8;
+pub fn iny<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let result = cpu.y_reg.wrapping_add(1);
     cpu.y_reg = result;
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
-pub fn dec(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.ram.read_byte(address as u16) - 1u8;
-    cpu.ram.write(address as u16, result);
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn dec<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let result = memory.wrapping_sub(1);
+    cpu.bus.write(address, result);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
-pub fn dex(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.x_reg - 1u8;
+pub fn dex<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let result = cpu.x_reg.wrapping_sub(1);
     cpu.x_reg = result;
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
-pub fn dey(cpu: &mut cpu::CPU, address: u8) {
-    let result = cpu.y_reg - 1u8;
+pub fn dey<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let result = cpu.y_reg.wrapping_sub(1);
     cpu.y_reg = result;
-
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(result);
 }
 
 // --------------- Shifts ---------------
-pub fn asl(cpu: &mut cpu::CPU, address: u8) {
-    let old_accum = cpu.a_reg;
-    let memory = cpu.ram.read_byte(address as u16);
-    let result = memory * 0x2;
-    cpu.a_reg = result;
-
-    cpu.p_reg.set_carry_flag(old_accum & 0x80 == 0x80);
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+// Shifts and rotations work either on the accumulator or on memory,
+// the helpers return the result and update the flags.
+// Read-modify-write instructions write the unmodified value back first,
+// some mappers see both writes.
+fn shift_left<B: Bus>(cpu: &mut cpu::CPU<B>, value: u8, carry_in: u8) -> u8 {
+    let result = value << 1 | carry_in;
+    cpu.p_reg.set_carry_flag(value & 0x80 == 0x80);
+    cpu.p_reg.set_zero_and_negative(result);
+    result
+}
+
+fn shift_right<B: Bus>(cpu: &mut cpu::CPU<B>, value: u8, carry_in: u8) -> u8 {
+    let result = value >> 1 | carry_in << 7;
+    cpu.p_reg.set_carry_flag(value & 0x1 == 0x1);
+    cpu.p_reg.set_zero_and_negative(result);
+    result
 }
 
-pub fn lsr(cpu: &mut cpu::CPU, address: u8) {
-    let old_accum = cpu.a_reg;
-    let memory = cpu.ram.read_byte(address as u16);
-    let result = memory / 0x2;
-    cpu.a_reg = result;
+pub fn asl<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let result = shift_left(cpu, memory, 0);
+    cpu.bus.write(address, result);
+}
 
-    cpu.p_reg.set_carry_flag(old_accum & 0x80 == 0x80);
-    cpu.p_reg.set_zero_flag(result == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn asl_accumulator<B: Bus>(cpu: &mut cpu::CPU<B>) {
+    let accum = cpu.a_reg;
+    cpu.a_reg = shift_left(cpu, accum, 0);
+}
+
+pub fn lsr<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let result = shift_right(cpu, memory, 0);
+    cpu.bus.write(address, result);
 }
 
-pub fn rol(cpu: &mut cpu::CPU, address: u8) {
+pub fn lsr_accumulator<B: Bus>(cpu: &mut cpu::CPU<B>) {
     let accum = cpu.a_reg;
-    let mut result = accum * 0x2;
-    let old_carry = accum & 0x80 == 0x80;
-    result |= cpu.p_reg.get_carry_flag();
-    cpu.a_reg = result;
+    cpu.a_reg = shift_right(cpu, accum, 0);
+}
 
-    cpu.p_reg.set_carry_flag(old_carry);
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn rol<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let carry = cpu.p_reg.get_carry_flag();
+    let result = shift_left(cpu, memory, carry);
+    cpu.bus.write(address, result);
 }
 
-pub fn ror(cpu: &mut cpu::CPU, address: u8) {
+pub fn rol_accumulator<B: Bus>(cpu: &mut cpu::CPU<B>) {
     let accum = cpu.a_reg;
-    let mut result = accum / 2u8;
-    let old_carry = accum & 0x1 == 0x1;
-    result |= cpu.p_reg.get_carry_flag();
-    cpu.a_reg = result;
+    let carry = cpu.p_reg.get_carry_flag();
+    cpu.a_reg = shift_left(cpu, accum, carry);
+}
 
-    cpu.p_reg.set_carry_flag(old_carry);
-    cpu.p_reg.set_zero_flag(accum == 0x0);
-    cpu.p_reg.set_negative_flag(result & 0x80 == 0x80);
+pub fn ror<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let memory = cpu.bus.read_byte(address);
+    cpu.bus.write(address, memory);
+    let carry = cpu.p_reg.get_carry_flag();
+    let result = shift_right(cpu, memory, carry);
+    cpu.bus.write(address, result);
+}
+
+pub fn ror_accumulator<B: Bus>(cpu: &mut cpu::CPU<B>) {
+    let accum = cpu.a_reg;
+    let carry = cpu.p_reg.get_carry_flag();
+    cpu.a_reg = shift_right(cpu, accum, carry);
 }
 
 // --------------- Jumps & Calls ---------------
-pub fn jmp(cpu: &mut cpu::CPU, address: u8) {
-    cpu.pc_reg = address as u16; 
+pub fn jmp<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    cpu.pc_reg = address; 
+}
+
+// The return address pushed is the last byte of the JSR instruction
+pub fn jsr<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) {
+    let result = cpu.pc_reg.wrapping_sub(1);
+    cpu.push_word(result);
+    cpu.pc_reg = address;
+}
+
+pub fn rts<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    cpu.pc_reg = cpu.pull_word().wrapping_add(1);
+}
+
+// --------------- Branches ---------------
+// A taken branch costs one more cycle, two more if it crosses a page
+fn branch<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16, condition: bool) -> u32 {
+    if !condition {
+        return 0;
+    }
+    let page_crossed = cpu.pc_reg & 0xff00 != address & 0xff00;
+    cpu.pc_reg = address;
+    1 + page_crossed as u32
+}
+
+pub fn bcc<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_carry_flag() == 0;
+    branch(cpu, address, condition)
 }
 
-pub fn jsr(cpu: &mut cpu::CPU, address: u8) {
-    let result = address - 0x1;
-    cpu.push(result);
-    cpu.pc_reg = result as u16;
+pub fn bcs<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_carry_flag() == 1;
+    branch(cpu, address, condition)
 }
 
-pub fn rts(cpu: &mut cpu::CPU, address: u8) {
-    cpu.pc_reg = (cpu.pull() - 0x1) as u16;
+pub fn beq<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_zero_flag() == 1;
+    branch(cpu, address, condition)
+}
+
+pub fn bmi<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_negative_flag() == 1;
+    branch(cpu, address, condition)
+}
+
+pub fn bne<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_zero_flag() == 0;
+    branch(cpu, address, condition)
+}
+
+pub fn bpl<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_negative_flag() == 0;
+    branch(cpu, address, condition)
+}
+
+pub fn bvc<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_overflow_flag() == 0;
+    branch(cpu, address, condition)
+}
+
+pub fn bvs<B: Bus>(cpu: &mut cpu::CPU<B>, address: u16) -> u32 {
+    let condition = cpu.p_reg.get_overflow_flag() == 1;
+    branch(cpu, address, condition)
 }
 
 // --------------- Status Flag Changes ---------------
-pub fn clc(cpu: &mut cpu::CPU, address: u8) {
+pub fn clc<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_carry_flag(false);
 }
 
-pub fn cld(cpu: &mut cpu::CPU, address: u8) {
+pub fn cld<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_decimal_flag(false);
 }
 
-pub fn cli(cpu: &mut cpu::CPU, address: u8) {
+pub fn cli<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_interrupt_flag(false);
 }
 
-pub fn clv(cpu: &mut cpu::CPU, address: u8) {
+pub fn clv<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_overflow_flag(false);
 }
 
-pub fn sec(cpu: &mut cpu::CPU, address: u8) {
+pub fn sec<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_carry_flag(true);
 }
 
-pub fn sed(cpu: &mut cpu::CPU, address: u8) {
+pub fn sed<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_decimal_flag(true);
 }
 
-pub fn sei(cpu: &mut cpu::CPU, address: u8) {
+pub fn sei<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
     cpu.p_reg.set_interrupt_flag(true);
 }
 
 // --------------- System Functions ---------------
-pub fn brk(cpu: &mut cpu::CPU, address: u8) {
-    let pc = cpu.pc_reg;
-    let p = cpu.p_reg.data;
+// BRK skips the byte after the opcode
+pub fn brk<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let pc = cpu.pc_reg.wrapping_add(1);
+    let p = cpu.p_reg.to_stack(true);
     cpu.push_word(pc);
     cpu.push(p);
-    cpu.pc_reg = cpu.ram.read_word(0xfffe);
-    cpu.p_reg.set_breakpoint(true);
+    cpu.p_reg.set_interrupt_flag(true);
+    cpu.pc_reg = cpu.bus.read_word(0xfffe);
 }
 
-pub fn rti(cpu: &mut cpu::CPU, address: u8) {
-    cpu.p_reg.data = cpu.pull();
+pub fn rti<B: Bus>(cpu: &mut cpu::CPU<B>, _address: u16) {
+    let status = cpu.pull();
+    cpu.p_reg.from_stack(status);
     cpu.pc_reg = cpu.pull_word(); 
 }
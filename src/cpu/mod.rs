@@ -1,3 +1,5 @@
+pub mod bus;
+#[allow(clippy::module_inception)]
 pub mod cpu;
 pub mod opcode;
 pub mod instructions;
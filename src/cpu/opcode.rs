@@ -8,7 +8,7 @@ pub struct OpCode {
     pub cycles: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Label {
     ADC,
     AND,
@@ -68,7 +68,7 @@ pub enum Label {
     TYA,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressingMode {
     Implicit,
     Accumulator,
@@ -135,7 +135,7 @@ lazy_static! {
         opcodes.insert(0xd1, OpCode{label: Label::CMP, mode: AddressingMode::IndirectY, size: 2, cycles: 5});
         opcodes.insert(0xe0, OpCode{label: Label::CPX, mode: AddressingMode::Immediate, size: 2, cycles: 2});
         opcodes.insert(0xe4, OpCode{label: Label::CPX, mode: AddressingMode::ZeroPage, size: 2, cycles: 3});
-        opcodes.insert(0xec, OpCode{label: Label::CPX, mode: AddressingMode::Absolute, size: 2, cycles: 4});
+        opcodes.insert(0xec, OpCode{label: Label::CPX, mode: AddressingMode::Absolute, size: 3, cycles: 4});
         opcodes.insert(0xc0, OpCode{label: Label::CPY, mode: AddressingMode::Immediate, size: 2, cycles: 2});
         opcodes.insert(0xc4, OpCode{label: Label::CPY, mode: AddressingMode::ZeroPage, size: 2, cycles: 3});
         opcodes.insert(0xcc, OpCode{label: Label::CPY, mode: AddressingMode::Absolute, size: 3, cycles: 4});
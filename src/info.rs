@@ -0,0 +1,238 @@
+// Detailed reports on ROM, disk and music files for the `info` command.
+// Each report is a flat list of fields, printed as text or as JSON.
+use rom::{archive, mapper_name, Format, LoadOptions, ROMReadError, ROM};
+use rom::database::Hashes;
+use rom::fds::DiskImage;
+use rom::nsf::NSF;
+
+use serde_json::{Map, Value};
+
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Report {
+    pub path: String,
+    // Fields in the order they are printed, or why the file couldn't be read
+    pub fields: Result<Map<String, Value>, ROMReadError>,
+}
+
+impl Report {
+    pub fn new(path: &str) -> Report {
+        Report {
+            path: path.to_string(),
+            fields: inspect(path),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.fields.is_ok()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("file".to_string(), self.path.clone().into());
+        match self.fields {
+            Ok(ref fields) => object.extend(fields.clone()),
+            Err(ref error) => {
+                object.insert("error".to_string(), error.to_string().into());
+            },
+        }
+        Value::Object(object)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.path)?;
+        match self.fields {
+            Ok(ref fields) => write_fields(f, fields, 1),
+            Err(ref error) => writeln!(f, "  Error: {}", error),
+        }
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter, fields: &Map<String, Value>, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    for (key, value) in fields {
+        let label = label(key);
+        match *value {
+            Value::Object(ref object) => {
+                writeln!(f, "{}{}:", indent, label)?;
+                write_fields(f, object, depth + 1)?;
+            },
+            Value::Array(ref items) => {
+                let items: Vec<String> = items.iter().map(text).collect();
+                writeln!(f, "{}{}: {}", indent, label, items.join(", "))?;
+            },
+            _ => writeln!(f, "{}{}: {}", indent, label, text(value))?,
+        }
+    }
+    Ok(())
+}
+
+// "prg_rom_size" -> "PRG ROM size"
+fn label(key: &str) -> String {
+    let words: Vec<String> = key.split('_').enumerate().map(|(i, word)| {
+        match word {
+            "prg" | "chr" | "rom" | "ram" | "nvram" | "crc32" | "sha1" => word.to_uppercase(),
+            _ if i == 0 => word[..1].to_uppercase() + &word[1..],
+            _ => word.to_string(),
+        }
+    }).collect();
+    words.join(" ")
+}
+
+fn text(value: &Value) -> String {
+    match *value {
+        Value::Null => "none".to_string(),
+        Value::Bool(true) => "yes".to_string(),
+        Value::Bool(false) => "no".to_string(),
+        Value::String(ref string) => string.clone(),
+        ref other => other.to_string(),
+    }
+}
+
+pub fn inspect(path: &str) -> Result<Map<String, Value>, ROMReadError> {
+    let image = archive::read_image(path, None)?;
+    let format = match Format::detect(&image.data) {
+        Some(format) => format,
+        None => return Err(ROMReadError::FormatError),
+    };
+
+    let mut fields = Map::new();
+    if let Some(entry) = image.entry_name {
+        fields.insert("archive_entry".to_string(), entry.into());
+    }
+    fields.insert("format".to_string(), format.name().into());
+    match format {
+        Format::INES | Format::NES2 | Format::UNIF => cartridge_fields(&image.data, &mut fields)?,
+        Format::FDS => disk_fields(&image.data, &mut fields)?,
+        Format::NSF | Format::NSFE => music_fields(&image.data, &mut fields)?,
+    }
+    Ok(fields)
+}
+
+fn cartridge_fields(data: &[u8], fields: &mut Map<String, Value>) -> Result<(), ROMReadError> {
+    let options = LoadOptions {
+        auto_patch: false,
+        ..Default::default()
+    };
+    let rom = ROM::from_bytes(data, &options)?;
+    let header = &rom.header;
+    let board = match (&rom.board, &rom.game) {
+        (Some(board), _) => Some(board.clone()),
+        (_, Some(game)) => Some(game.board_name()),
+        _ => mapper_name(rom.mapper()).map(|name| name.to_string()),
+    };
+
+    fields.insert("mapper".to_string(), rom.mapper().into());
+    fields.insert("submapper".to_string(), rom.submapper().into());
+    fields.insert("board".to_string(), board.into());
+    fields.insert("prg_rom_size".to_string(), rom.prg_rom.len().into());
+    fields.insert("chr_rom_size".to_string(), rom.chr_rom.len().into());
+    fields.insert("prg_ram_size".to_string(), header.prg_ram_bytes().into());
+    fields.insert("prg_nvram_size".to_string(), header.prg_nvram_bytes().into());
+    fields.insert("chr_ram_size".to_string(), header.chr_ram_bytes().into());
+    fields.insert("chr_nvram_size".to_string(), header.chr_nvram_bytes().into());
+    fields.insert("mirroring".to_string(), rom.mirroring.name().into());
+    fields.insert("battery".to_string(), rom.has_battery().into());
+    fields.insert("trainer".to_string(), header.has_trainer().into());
+    fields.insert("bus_conflicts".to_string(), rom.has_bus_conflicts().into());
+    fields.insert("region".to_string(), format!("{:?}", rom.region()).into());
+    hash_fields(&rom.hashes, fields);
+    fields.insert("header_fixed".to_string(), rom.header_fixed.into());
+
+    let game = rom.game.as_ref().map(|game| {
+        let mut object = Map::new();
+        object.insert("title".to_string(), game.title.clone().into());
+        object.insert("board".to_string(), game.board.clone().into());
+        Value::Object(object)
+    });
+    fields.insert("database_match".to_string(), game.into());
+    Ok(())
+}
+
+fn disk_fields(data: &[u8], fields: &mut Map<String, Value>) -> Result<(), ROMReadError> {
+    let disk = DiskImage::from_bytes(data)?;
+    fields.insert("sides".to_string(), disk.sides.len().into());
+    for (i, side) in disk.sides.iter().enumerate() {
+        let mut object = Map::new();
+        object.insert("game".to_string(), side.info.game_name().into());
+        object.insert("disk".to_string(), side.info.disk_number.into());
+        let letter = if side.info.side_number == 0 { "A" } else { "B" };
+        object.insert("side".to_string(), letter.into());
+        object.insert("files".to_string(), side.files.len().into());
+        fields.insert(format!("side_{}", i), Value::Object(object));
+    }
+    hash_fields(&Hashes::compute(data, &[]), fields);
+    Ok(())
+}
+
+fn music_fields(data: &[u8], fields: &mut Map<String, Value>) -> Result<(), ROMReadError> {
+    let nsf = NSF::from_bytes(data)?;
+    fields.insert("title".to_string(), nsf.title.clone().into());
+    fields.insert("artist".to_string(), nsf.artist.clone().into());
+    fields.insert("copyright".to_string(), nsf.copyright.clone().into());
+    fields.insert("songs".to_string(), nsf.songs.into());
+    fields.insert("starting_song".to_string(), (nsf.starting_song + 1).into());
+    fields.insert("load_address".to_string(), format!("${:04X}", nsf.load_address).into());
+    fields.insert("init_address".to_string(), format!("${:04X}", nsf.init_address).into());
+    fields.insert("play_address".to_string(), format!("${:04X}", nsf.play_address).into());
+    fields.insert("bankswitched".to_string(), nsf.uses_bankswitching().into());
+    fields.insert("region".to_string(), format!("{:?}", nsf.region).into());
+    fields.insert("expansion_audio".to_string(), nsf.chips.names().into());
+    hash_fields(&Hashes::compute(data, &[]), fields);
+    Ok(())
+}
+
+fn hash_fields(hashes: &Hashes, fields: &mut Map<String, Value>) {
+    fields.insert("crc32".to_string(), format!("{:08X}", hashes.crc32).into());
+    fields.insert("sha1".to_string(), hashes.sha1_hex().into());
+}
+
+#[cfg(test)]
+mod test {
+    use info::*;
+
+    #[test]
+    fn reporting_cartridge() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/Super Mario Bros (E).nes");
+        let report = Report::new(path);
+        let fields = report.fields.as_ref().unwrap();
+        assert_eq!(fields["format"], "iNES");
+        assert_eq!(fields["mapper"], 0);
+        assert_eq!(fields["board"], "NES-NROM-256");
+        assert_eq!(fields["prg_rom_size"], 32768);
+        assert_eq!(fields["chr_rom_size"], 8192);
+        assert_eq!(fields["chr_ram_size"], 0);
+        assert_eq!(fields["mirroring"], "Vertical");
+        assert_eq!(fields["region"], "PAL");
+        assert_eq!(fields["crc32"], "9A2DB086");
+        assert_eq!(fields["database_match"]["title"], "Super Mario Bros. (Europe)");
+
+        let json = report.to_json();
+        assert_eq!(json["file"], path);
+        let keys: Vec<&String> = json.as_object().unwrap().keys().take(3).collect();
+        assert_eq!(keys, vec!["file", "format", "mapper"]);
+
+        let text = report.to_string();
+        assert!(text.contains("  PRG ROM size: 32768\n"));
+        assert!(text.contains("  Battery: no\n"));
+        assert!(text.contains("  Database match:\n    Title: Super Mario Bros. (Europe)\n"));
+    }
+
+    #[test]
+    fn reporting_errors() {
+        let report = Report::new("/nonexistent/file.nes");
+        assert!(!report.is_ok());
+        assert!(report.to_json()["error"].is_string());
+        assert!(report.to_string().contains("Error: IoError"));
+    }
+
+    #[test]
+    fn labeling_fields() {
+        assert_eq!(label("prg_nvram_size"), "PRG NVRAM size");
+        assert_eq!(label("database_match"), "Database match");
+        assert_eq!(label("crc32"), "CRC32");
+    }
+}
@@ -0,0 +1,23 @@
+// Acronym names (ROM, CPU, RAM, opcode labels) follow the hardware documentation
+#![allow(clippy::upper_case_acronyms)]
+
+#[macro_use]
+extern crate lazy_static;
+extern crate crc32fast;
+extern crate sha1_smol;
+extern crate flate2;
+extern crate zip;
+extern crate serde_json;
+
+pub mod rom;
+pub mod utils;
+pub mod cpu;
+pub mod ram;
+pub mod apu;
+pub mod ppu;
+pub mod nsf_player;
+pub mod info;
+pub mod mapper;
+pub mod console;
+pub mod save;
+pub mod png;
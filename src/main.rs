@@ -1,25 +1,176 @@
-#[macro_use]
-extern crate lazy_static;
+extern crate rusty_nes;
+extern crate serde_json;
 
-mod rom;
-mod utils;
-mod cpu;
-mod ram;
+use rusty_nes::console::{Console, Overscan};
+use rusty_nes::info::Report;
+use rusty_nes::ppu::palette::Palette;
+use rusty_nes::rom::{archive, validate, ROM};
+use rusty_nes::rom::validate::Severity;
 
-use rom::ROM;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage: rusty_nes <rom>
+       rusty_nes <rom> --screenshot <png> [--frames <n>] [--crop] [--palette <pal>]
+       rusty_nes info [--json] <file>...
+       rusty_nes lint [--fix] <file>...";
 
 fn main() {
-    let path = "/home/evgeniy/Development/rusty_nes/\
-                roms/Super Mario Bros (E).nes";  
-    let rom = match ROM::load(path) {
-        Ok(r) => r, 
-        Err(e) => panic!("Error: {}", e),
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.first().map(|arg| arg.as_str()) {
+        Some("info") => info(&args[1..]),
+        Some("lint") => lint(&args[1..]),
+        Some(path) if !path.starts_with('-') && args.len() > 1 => screenshot(path, &args[1..]),
+        Some(path) if !path.starts_with('-') => show(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        },
+    };
+    process::exit(code);
+}
+
+fn show(path: &str) -> i32 {
+    match ROM::load(path) {
+        Ok(rom) => {
+            println!("{}", rom);
+            0
+        },
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        },
+    }
+}
+
+// Runs the ROM for a number of frames (60 by default) and writes the
+// last one to a PNG file. --crop leaves the TV's overscan out. Games with
+// a battery load and keep their save in <rom>.sav.
+fn screenshot(path: &str, args: &[String]) -> i32 {
+    let mut output = None;
+    let mut frames: u32 = 60;
+    let mut overscan = Overscan::default();
+    let mut palette = None;
+    let usage = || {
+        eprintln!("{}", USAGE);
+        2
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--crop" => overscan = Overscan::tv(),
+            "--screenshot" | "--palette" | "--frames" => {
+                let value = match args.next() {
+                    Some(value) => value,
+                    None => return usage(),
+                };
+                match arg.as_str() {
+                    "--screenshot" => output = Some(value),
+                    "--palette" => palette = Some(value),
+                    _ => match value.parse() {
+                        Ok(n) => frames = n,
+                        Err(_) => return usage(),
+                    },
+                }
+            },
+            _ => return usage(),
+        }
+    }
+    let output = match output {
+        Some(output) => output,
+        None => return usage(),
     };
-    println!("{}", rom);
 
-    let mut ram = ram::RAM::new();
-    ram.data[0] = 0x79;
-    ram.data[1] = 0xad;
-    let mut cpu = cpu::cpu::CPU::new(ram);
-    cpu.step();
+    let result = ROM::load(path)
+        .map_err(|e| e.to_string())
+        .and_then(|rom| Console::with_save(&rom, path).map_err(|e| e.to_string()))
+        .and_then(|mut console| {
+            if let Some(palette) = palette {
+                console.set_palette(Palette::load(palette).map_err(|e| e.to_string())?);
+            }
+            for _ in 0..frames {
+                console.run_frame();
+            }
+            console.screenshot(output, overscan).map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        },
+    }
+}
+
+// Prints a report for every file, fails if any of them couldn't be read
+fn info(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let reports: Vec<Report> = paths.iter().map(|path| Report::new(path)).collect();
+    if json {
+        let values: Vec<serde_json::Value> = reports.iter().map(Report::to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&values).unwrap());
+    } else {
+        for report in &reports {
+            print!("{}", report);
+        }
+    }
+    if reports.iter().all(Report::is_ok) { 0 } else { 1 }
+}
+
+// Prints the header problems of every file. With --fix the corrected image
+// is written next to the original as <name>.fixed.nes. Fails on errors.
+fn lint(args: &[String]) -> i32 {
+    let fix = args.iter().any(|arg| arg == "--fix");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--fix").collect();
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let mut code = 0;
+    for path in paths {
+        println!("{}", path);
+        let data = match archive::read_image(path, None) {
+            Ok(image) => image.data,
+            Err(e) => {
+                println!("  error: {}", e);
+                code = 1;
+                continue;
+            },
+        };
+        let findings = validate::validate(&data);
+        if findings.is_empty() {
+            println!("  ok");
+        }
+        for finding in &findings {
+            println!("  {}", finding);
+            if finding.severity == Severity::Error {
+                code = 1;
+            }
+        }
+
+        if fix && findings.iter().any(|finding| finding.fix.is_some()) {
+            let output = Path::new(path).with_extension("fixed.nes");
+            let result = validate::fix(&data)
+                .map_err(|e| e.to_string())
+                .and_then(|rom| fs::write(&output, rom.to_bytes()).map_err(|e| e.to_string()));
+            match result {
+                Ok(()) => println!("  fixed: {}", output.display()),
+                Err(e) => {
+                    println!("  could not fix: {}", e);
+                    code = 1;
+                },
+            }
+        }
+    }
+    code
 }
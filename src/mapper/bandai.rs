@@ -0,0 +1,222 @@
+// Mappers 16 and 159: Bandai FCG-1/FCG-2 and LZ93D50
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_016
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_159
+//
+// Registers at $6000-$7FFF (FCG, submapper 4) or $8000-$FFFF (LZ93D50,
+// submapper 5 and mapper 159), mirrored every 16 bytes:
+//   $x0-$x7 1 KB CHR banks   $x8 16 KB PRG bank at $8000, the last one fixed
+//   $x9 mirroring            $xA-$xC IRQ
+//   $xD EEPROM lines, read back at $6000-$7FFF
+// iNES mapper 16 doesn't say which chip, it gets both register ranges.
+// The LZ93D50 boards save to a 24C02 EEPROM (24C01 for mapper 159).
+use mapper::banks::Banks;
+use mapper::eeprom::{self, Eeprom};
+use mapper::{chr_banks, copy_save, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct Bandai {
+    // $8000-$FFFF in two 16 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    eeprom: Option<Eeprom>,
+    // Register ranges
+    fcg_registers: bool,
+    lz93d50_registers: bool,
+
+    // The LZ93D50 loads the counter from a latch, the FCG writes it directly
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Bandai {
+    pub fn new(rom: &ROM) -> Bandai {
+        let (fcg_registers, lz93d50_registers) = match (rom.mapper(), rom.submapper()) {
+            (16, 4) => (true, false),
+            (16, 5) | (159, _) => (false, true),
+            _ => (true, true),
+        };
+        let eeprom = match (rom.mapper(), rom.submapper()) {
+            (159, _) => Some(Eeprom::new(eeprom::Chip::C24C01)),
+            (16, 4) => None,
+            // NES 2.0 tells the EEPROM size, or that there is none
+            _ if rom.header.is_nes2() => match rom.header.prg_nvram_bytes() {
+                128 => Some(Eeprom::new(eeprom::Chip::C24C01)),
+                256 => Some(Eeprom::new(eeprom::Chip::C24C02)),
+                _ => None,
+            },
+            _ => Some(Eeprom::new(eeprom::Chip::C24C02)),
+        };
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x4000, false);
+        prg_rom.map(1, -1);
+        Bandai {
+            prg_rom,
+            chr: chr_banks(rom, 0x0400),
+            mirroring: MirroringType::Vertical,
+            eeprom,
+            fcg_registers,
+            lz93d50_registers,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    pub fn eeprom(&self) -> Option<&Eeprom> {
+        self.eeprom.as_ref()
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x00..=0x07 => self.chr.map(register as usize, value as isize),
+            0x08 => self.prg_rom.map(0, (value & 0x0f) as isize),
+            // ------MM: mirroring (0: vertical, 1: horizontal, 2: one-screen A, 3: one-screen B)
+            0x09 => {
+                self.mirroring = match value & 0x03 {
+                    0 => MirroringType::Vertical,
+                    1 => MirroringType::Horizontal,
+                    2 => MirroringType::SingleScreenA,
+                    _ => MirroringType::SingleScreenB,
+                };
+            },
+            // -------E: IRQ enable, writing acknowledges
+            0x0a => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_pending = false;
+                if self.lz93d50_registers {
+                    self.irq_counter = self.irq_latch;
+                }
+            },
+            0x0b | 0x0c => {
+                let shift = (register - 0x0b) * 8;
+                let target = if self.lz93d50_registers { &mut self.irq_latch } else { &mut self.irq_counter };
+                *target = (*target & !(0xff << shift)) | (value as u16) << shift;
+            },
+            // RDC-----
+            // ||+----- SCL
+            // |+------ SDA
+            // +------- EEPROM direction (1: reading, SDA released)
+            0x0d => {
+                if let Some(ref mut eeprom) = self.eeprom {
+                    let scl = value & 0x20 != 0;
+                    let sda = value & 0x40 != 0 || value & 0x80 != 0;
+                    eeprom.set_lines(scl, sda);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // ---E----: EEPROM data, the rest is open bus
+            0x6000..=0x7fff => self.eeprom.as_ref().map(|eeprom| (eeprom.output() as u8) << 4),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if self.fcg_registers => self.write_register(address & 0x0f, value),
+            0x8000..=0xffff if self.lz93d50_registers => self.write_register(address & 0x0f, value),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // Fires when the counter is 0, then wraps
+    fn notify_cpu_cycle(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    // The EEPROM keeps its contents without a battery
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.eeprom.as_ref().map(|eeprom| eeprom.data())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        if let Some(ref mut eeprom) = self.eeprom {
+            copy_save(eeprom.data_mut(), data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::bandai::*;
+    use mapper::eeprom::test::{send, start, stop};
+    use mapper::test;
+
+    fn make_rom(mapper: u16) -> ROM {
+        test::make_rom(mapper, 0, 0x4000, 16, 0x400, 256)
+    }
+
+    #[test]
+    fn switching_banks_and_counting() {
+        let mut bandai = Bandai::new(&make_rom(16));
+        assert_eq!(bandai.cpu_read(0xc000), Some(15));
+        bandai.cpu_write(0x8008, 3);
+        bandai.cpu_write(0x6017, 200);
+        bandai.cpu_write(0x8019, 1);
+        assert_eq!(bandai.cpu_read(0x8000), Some(3));
+        assert_eq!(bandai.ppu_read(0x1c00), 200);
+        assert_eq!(bandai.mirroring(), MirroringType::Horizontal);
+
+        bandai.cpu_write(0x800b, 2);
+        bandai.cpu_write(0x800c, 0);
+        bandai.cpu_write(0x800a, 1);
+        for _ in 0..2 {
+            bandai.notify_cpu_cycle();
+            assert!(!bandai.irq());
+        }
+        bandai.notify_cpu_cycle();
+        assert!(bandai.irq());
+        bandai.cpu_write(0x800a, 0);
+        assert!(!bandai.irq());
+    }
+
+    #[test]
+    fn saving_to_the_eeprom() {
+        let mut bandai = Bandai::new(&make_rom(159));
+        bandai.load_save_ram(&[0x55; 128]);
+        let eeprom = bandai.eeprom.as_mut().unwrap();
+        assert_eq!(eeprom.chip(), eeprom::Chip::C24C01);
+        start(eeprom);
+        assert!(send(eeprom, 0x03, true));
+        assert!(send(eeprom, 0x42, true));
+        stop(eeprom);
+        assert_eq!(bandai.save_ram().unwrap()[2..5], [0x55, 0x42, 0x55]);
+
+        // SDA read back through $6000 with SCL high and the direction set to reading
+        bandai.cpu_write(0x800d, 0xa0);
+        assert_eq!(bandai.cpu_read(0x6000), Some(0x10));
+    }
+}
@@ -0,0 +1,144 @@
+// PRG and CHR memory split into switchable banks. The address space the
+// mapper exposes is divided into equal windows, each pointing at a bank.
+#[derive(Debug)]
+pub struct Banks {
+    data: Vec<u8>,
+    window_size: usize,
+    // Offset into `data` of the bank mapped into each window
+    offsets: Vec<usize>,
+    // Where RAM starts in `data`, boards with both CHR ROM and CHR RAM
+    // have the RAM after the ROM
+    ram_start: usize,
+}
+
+impl Banks {
+    // `size` is the size of the whole address range, e.g. 32 KB for PRG ROM
+    pub fn new(data: Vec<u8>, size: usize, window_size: usize, writable: bool) -> Banks {
+        let ram_start = if writable { 0 } else { data.len() };
+        let mut banks = Banks {
+            data,
+            window_size,
+            offsets: vec![0; size / window_size],
+            ram_start,
+        };
+        // Identity mapping, wrapping around small memories
+        for window in 0..banks.offsets.len() {
+            banks.map(window, window as isize);
+        }
+        banks
+    }
+
+    // ROM followed by `ram_size` bytes of RAM, the RAM banks numbered
+    // after the ROM ones
+    pub fn with_ram(mut rom: Vec<u8>, ram_size: usize, size: usize, window_size: usize) -> Banks {
+        let ram_start = rom.len();
+        rom.resize(ram_start + ram_size, 0);
+        let mut banks = Banks::new(rom, size, window_size, true);
+        banks.ram_start = ram_start;
+        banks
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.data.len() / self.window_size
+    }
+
+    pub fn window_count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // Maps a bank into a window. Negative numbers count from the last bank,
+    // numbers past the end wrap around like unconnected address lines.
+    pub fn map(&mut self, window: usize, bank: isize) {
+        let count = self.bank_count().max(1) as isize;
+        let bank = bank.rem_euclid(count) as usize;
+        self.offsets[window] = bank * self.window_size;
+    }
+
+    // Maps consecutive banks into a range of windows, for switching
+    // bigger blocks in a memory split into smaller windows
+    pub fn map_range(&mut self, first_window: usize, windows: usize, first_bank: isize) {
+        for i in 0..windows {
+            self.map(first_window + i, first_bank + i as isize);
+        }
+    }
+
+    fn index(&self, address: usize) -> usize {
+        let window = (address / self.window_size) % self.offsets.len();
+        // Memories smaller than a window repeat inside it
+        (self.offsets[window] + address % self.window_size) % self.data.len()
+    }
+
+    // `address` is relative to the start of the range
+    pub fn read(&self, address: usize) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[self.index(address)]
+    }
+
+    // Writes to ROM are ignored
+    pub fn write(&mut self, address: usize, value: u8) {
+        if self.data.is_empty() {
+            return;
+        }
+        let index = self.index(address);
+        if index >= self.ram_start {
+            self.data[index] = value;
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::banks::*;
+
+    #[test]
+    fn switching_banks() {
+        let data: Vec<u8> = (0..4).flat_map(|bank| vec![bank as u8; 0x1000]).collect();
+        let mut banks = Banks::new(data, 0x2000, 0x1000, false);
+        assert_eq!(banks.bank_count(), 4);
+        assert_eq!(banks.read(0x1fff), 1);
+
+        banks.map(0, -1);
+        banks.map(1, 6);
+        assert_eq!(banks.read(0x0000), 3);
+        assert_eq!(banks.read(0x1000), 2);
+
+        banks.map_range(0, 2, 1);
+        assert_eq!((banks.read(0x0000), banks.read(0x1000)), (1, 2));
+
+        banks.write(0x0000, 0xff);
+        assert_eq!(banks.read(0x0000), 1);
+    }
+
+    #[test]
+    fn mirroring_small_memory() {
+        // 16 KB of PRG ROM in a 32 KB range
+        let mut data = vec![0; 0x4000];
+        data[0] = 0x42;
+        let mut banks = Banks::new(data, 0x8000, 0x8000, true);
+        assert_eq!(banks.read(0x4000), 0x42);
+        banks.write(0x4000, 0x24);
+        assert_eq!(banks.read(0x0000), 0x24);
+    }
+
+    #[test]
+    fn mixing_rom_and_ram() {
+        let mut banks = Banks::with_ram(vec![0x11; 0x2000], 0x2000, 0x2000, 0x1000);
+        assert_eq!(banks.bank_count(), 4);
+        banks.map(0, 1);
+        banks.map(1, 2);
+        banks.write(0x0000, 0x42);
+        banks.write(0x1000, 0x42);
+        assert_eq!(banks.read(0x0000), 0x11);
+        assert_eq!(banks.read(0x1000), 0x42);
+    }
+}
@@ -0,0 +1,325 @@
+// Boards made of discrete logic: a latch on the data bus selects the banks.
+// https://wiki.nesdev.com/w/index.php/Category:Discrete_logic_mappers
+//
+// Most of them don't disable the ROM during writes, so the value latched is
+// the written value ANDed with the ROM byte at that address (bus conflict).
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    // Mapper 2: 16 KB at $8000, last bank fixed at $C000
+    UxROM,
+    // Mapper 3: 8 KB CHR
+    CNROM,
+    // Mapper 7: 32 KB PRG, single-screen mirroring
+    AxROM,
+    // Mapper 11: 32 KB PRG in the low bits, 8 KB CHR in the high bits
+    ColorDreams,
+    // Mapper 13: 16 KB CHR RAM, the second 4 KB switchable
+    CPROM,
+    // Mapper 34 with CHR RAM: 32 KB PRG
+    BNROM,
+    // Mapper 34 with CHR ROM: registers at $7FFD-$7FFF, 4 KB CHR banks
+    NINA001,
+    // Mapper 66: 32 KB PRG in the high bits, 8 KB CHR in the low bits
+    GxROM,
+    // Mapper 71: UxROM-like with the register at $C000, Fire Hawk
+    // (submapper 1) switches single-screen mirroring at $8000
+    Camerica,
+}
+
+impl Board {
+    pub fn from_rom(rom: &ROM) -> Option<Board> {
+        let board = match rom.mapper() {
+            2 => Board::UxROM,
+            3 => Board::CNROM,
+            7 => Board::AxROM,
+            11 => Board::ColorDreams,
+            13 => Board::CPROM,
+            // NES 2.0 submapper 1 is NINA-001 and 2 is BNROM,
+            // iNES files only tell them apart by CHR ROM
+            34 => match rom.submapper() {
+                1 => Board::NINA001,
+                2 => Board::BNROM,
+                _ if rom.chr_rom.len() > 0x2000 => Board::NINA001,
+                _ => Board::BNROM,
+            },
+            66 => Board::GxROM,
+            71 => Board::Camerica,
+            _ => return None,
+        };
+        Some(board)
+    }
+}
+
+#[derive(Debug)]
+pub struct Discrete {
+    board: Board,
+    // $8000-$FFFF in two 16 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in two 4 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    bus_conflicts: bool,
+    battery: bool,
+    // Fire Hawk's mirroring register
+    camerica_mirroring: bool,
+}
+
+impl Discrete {
+    pub fn new(rom: &ROM, board: Board) -> Discrete {
+        let chr = match board {
+            Board::CPROM => Banks::new(vec![0; 0x4000], 0x2000, 0x1000, true),
+            _ => chr_banks(rom, 0x1000),
+        };
+        let prg_ram = match board {
+            Board::NINA001 => vec![0; 0x2000],
+            _ => prg_ram(rom),
+        };
+        let mirroring = match board {
+            Board::AxROM => MirroringType::SingleScreenA,
+            _ => rom.mirroring,
+        };
+
+        let mut discrete = Discrete {
+            board,
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x4000, false),
+            prg_ram,
+            chr,
+            mirroring,
+            bus_conflicts: rom.has_bus_conflicts(),
+            battery: rom.has_battery(),
+            camerica_mirroring: board == Board::Camerica && rom.submapper() == 1,
+        };
+        match board {
+            Board::UxROM | Board::Camerica => discrete.prg_rom.map(1, -1),
+            Board::CPROM => discrete.chr.map(1, 0),
+            _ => {},
+        }
+        discrete
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn map_prg_32k(&mut self, bank: u8) {
+        self.prg_rom.map_range(0, 2, bank as isize * 2);
+    }
+
+    fn map_chr_8k(&mut self, bank: u8) {
+        self.chr.map_range(0, 2, bank as isize * 2);
+    }
+
+    // A write to the latch at $8000-$FFFF
+    fn latch(&mut self, address: u16, value: u8) {
+        match self.board {
+            Board::UxROM => self.prg_rom.map(0, value as isize),
+            Board::CNROM => self.map_chr_8k(value),
+            Board::AxROM => {
+                self.map_prg_32k(value & 0x07);
+                self.mirroring = if value & 0x10 == 0 {
+                    MirroringType::SingleScreenA
+                } else {
+                    MirroringType::SingleScreenB
+                };
+            },
+            Board::ColorDreams => {
+                self.map_prg_32k(value & 0x03);
+                self.map_chr_8k(value >> 4);
+            },
+            Board::CPROM => self.chr.map(1, (value & 0x03) as isize),
+            Board::BNROM => self.map_prg_32k(value),
+            Board::NINA001 => {},
+            Board::GxROM => {
+                self.map_prg_32k((value >> 4) & 0x03);
+                self.map_chr_8k(value & 0x03);
+            },
+            Board::Camerica => match address {
+                0x8000..=0x9fff if self.camerica_mirroring => {
+                    self.mirroring = if value & 0x10 == 0 {
+                        MirroringType::SingleScreenA
+                    } else {
+                        MirroringType::SingleScreenB
+                    };
+                },
+                0xc000..=0xffff => self.prg_rom.map(0, value as isize),
+                _ => {},
+            },
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+                // NINA-001 registers sit on top of the RAM
+                if self.board == Board::NINA001 {
+                    match address {
+                        0x7ffd => self.map_prg_32k(value & 0x01),
+                        0x7ffe => self.chr.map(0, (value & 0x0f) as isize),
+                        0x7fff => self.chr.map(1, (value & 0x0f) as isize),
+                        _ => {},
+                    }
+                }
+            },
+            0x8000..=0xffff => {
+                let value = if self.bus_conflicts {
+                    value & self.prg_rom.read(address as usize - 0x8000)
+                } else {
+                    value
+                };
+                self.latch(address, value);
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::discrete::*;
+    use mapper::test;
+
+    // 16 KB PRG banks and 4 KB CHR banks
+    fn make_rom(mapper: u16, submapper: u8, prg_banks: usize, chr_banks: usize) -> ROM {
+        test::make_rom(mapper, submapper, 0x4000, prg_banks, 0x1000, chr_banks)
+    }
+
+    fn create(rom: &ROM) -> Discrete {
+        Discrete::new(rom, Board::from_rom(rom).unwrap())
+    }
+
+    #[test]
+    fn switching_uxrom_and_cnrom() {
+        let mut uxrom = create(&make_rom(2, 0, 8, 0));
+        assert_eq!(uxrom.board(), Board::UxROM);
+        assert_eq!(uxrom.cpu_read(0xc000), Some(7));
+        uxrom.cpu_write(0x8001, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(5));
+        assert_eq!(uxrom.cpu_read(0xc000), Some(7));
+
+        let mut cnrom = create(&make_rom(3, 0, 2, 8));
+        cnrom.cpu_write(0x8001, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 4);
+        assert_eq!(cnrom.ppu_read(0x1000), 5);
+    }
+
+    #[test]
+    fn switching_axrom_mirroring() {
+        let mut axrom = create(&make_rom(7, 0, 8, 0));
+        assert_eq!(axrom.mirroring(), MirroringType::SingleScreenA);
+        axrom.cpu_write(0x8001, 0x12);
+        assert_eq!(axrom.cpu_read(0x8000), Some(4));
+        assert_eq!(axrom.cpu_read(0xc000), Some(5));
+        assert_eq!(axrom.mirroring(), MirroringType::SingleScreenB);
+    }
+
+    #[test]
+    fn switching_gxrom_and_color_dreams() {
+        let mut gxrom = create(&make_rom(66, 0, 8, 8));
+        gxrom.cpu_write(0x8001, 0x21);
+        assert_eq!(gxrom.cpu_read(0x8000), Some(4));
+        assert_eq!(gxrom.ppu_read(0x0000), 2);
+
+        let mut color_dreams = create(&make_rom(11, 0, 8, 8));
+        assert_eq!(color_dreams.board(), Board::ColorDreams);
+        color_dreams.cpu_write(0x8001, 0x21);
+        assert_eq!(color_dreams.cpu_read(0x8000), Some(2));
+        assert_eq!(color_dreams.ppu_read(0x1000), 5);
+    }
+
+    #[test]
+    fn switching_mapper_34_and_cprom() {
+        let mut bnrom = create(&make_rom(34, 0, 8, 0));
+        assert_eq!(bnrom.board(), Board::BNROM);
+        bnrom.cpu_write(0x8001, 3);
+        assert_eq!(bnrom.cpu_read(0x8000), Some(6));
+
+        let mut nina = create(&make_rom(34, 0, 4, 8));
+        assert_eq!(nina.board(), Board::NINA001);
+        nina.cpu_write(0x7ffd, 1);
+        nina.cpu_write(0x7fff, 6);
+        assert_eq!(nina.cpu_read(0x8000), Some(2));
+        assert_eq!(nina.ppu_read(0x1000), 6);
+        assert_eq!(nina.cpu_read(0x7fff), Some(6));
+
+        let mut cprom = create(&make_rom(13, 0, 2, 0));
+        cprom.ppu_write(0x0000, 0x11);
+        cprom.cpu_write(0x8001, 2);
+        cprom.ppu_write(0x1000, 0x22);
+        cprom.cpu_write(0x8001, 0);
+        assert_eq!(cprom.ppu_read(0x1000), 0x11);
+        cprom.cpu_write(0x8001, 2);
+        assert_eq!(cprom.ppu_read(0x1000), 0x22);
+    }
+
+    #[test]
+    fn switching_camerica() {
+        let mut camerica = create(&make_rom(71, 0, 8, 0));
+        camerica.cpu_write(0x8001, 0x10);
+        camerica.cpu_write(0xc001, 3);
+        assert_eq!(camerica.cpu_read(0x8000), Some(3));
+        assert_eq!(camerica.cpu_read(0xc000), Some(7));
+        assert_eq!(camerica.mirroring(), MirroringType::Horizontal);
+    }
+
+    #[test]
+    fn emulating_bus_conflicts() {
+        // The first ROM byte of bank 0 is 0, the next one $FF. iNES 1.0
+        // sets bit 5 of flags 10.
+        let mut rom = make_rom(2, 0, 8, 0);
+        rom.header.flags_10 = 0x20;
+        let mut uxrom = create(&rom);
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(0));
+        uxrom.cpu_write(0x8001, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(5));
+
+        // NES 2.0 submapper 2
+        let mut uxrom = create(&make_rom(2, 2, 8, 0));
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(0));
+
+        let mut uxrom = create(&make_rom(2, 0, 8, 0));
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), Some(5));
+    }
+}
@@ -0,0 +1,274 @@
+// Serial EEPROMs on Bandai boards: 24C01 (128 bytes) and 24C02 (256 bytes)
+// https://wiki.nesdev.com/w/index.php/Bandai_FCG_board#Serial_EEPROM
+//
+// The mapper drives SCL and SDA from a register and reads SDA back. Bytes
+// are clocked in on rising SCL edges and acknowledged on a ninth clock.
+//   24C01 (X24C01): start, 7-bit word address and R/W, then data, all
+//                   least significant bit first
+//   24C02: start, device address $A0/$A1, word address, then data, most
+//          significant bit first. A repeated start switches to reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    C24C01,
+    C24C02,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Standby,
+    // Device address (24C02), or word address and R/W (24C01)
+    Control,
+    // Word address (24C02)
+    Address,
+    Write,
+    Read,
+}
+
+#[derive(Debug)]
+pub struct Eeprom {
+    chip: Chip,
+    data: Vec<u8>,
+    phase: Phase,
+    address: u8,
+    shift: u8,
+    // Clocks of the current byte, the ninth one is the acknowledge
+    bits: u8,
+    // Pulling SDA low to acknowledge the byte received
+    ack: bool,
+    scl: bool,
+    sda: bool,
+    // SDA as driven by the EEPROM, released (high) most of the time
+    output: bool,
+}
+
+impl Eeprom {
+    pub fn new(chip: Chip) -> Eeprom {
+        let size = match chip {
+            Chip::C24C01 => 128,
+            Chip::C24C02 => 256,
+        };
+        Eeprom {
+            chip,
+            data: vec![0; size],
+            phase: Phase::Standby,
+            address: 0,
+            shift: 0,
+            bits: 0,
+            ack: false,
+            scl: false,
+            sda: false,
+            output: true,
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    // State of SDA as seen by the mapper
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    // SCL and SDA as driven by the mapper, a released SDA is high
+    pub fn set_lines(&mut self, scl: bool, sda: bool) {
+        if scl && self.scl && sda != self.sda {
+            // SDA falling while SCL is high starts a command, rising stops it
+            if sda {
+                self.phase = Phase::Standby;
+            } else {
+                self.phase = Phase::Control;
+                self.bits = 0;
+            }
+            self.ack = false;
+            self.output = true;
+        } else if scl && !self.scl {
+            self.clock(sda);
+        } else if !scl && self.scl {
+            self.output = self.next_output();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn lsb_first(&self) -> bool {
+        self.chip == Chip::C24C01
+    }
+
+    fn mask(&self) -> u8 {
+        (self.data.len() - 1) as u8
+    }
+
+    fn next_output(&self) -> bool {
+        match self.phase {
+            _ if self.ack => false,
+            Phase::Read if self.bits < 8 => {
+                let bit = if self.lsb_first() { self.bits } else { 7 - self.bits };
+                self.shift & (1 << bit) != 0
+            },
+            _ => true,
+        }
+    }
+
+    fn clock(&mut self, sda: bool) {
+        if self.ack {
+            // End of the acknowledge clock
+            self.ack = false;
+            self.bits = 0;
+            if self.phase == Phase::Read {
+                self.shift = self.data[self.address as usize];
+            }
+            return;
+        }
+        match self.phase {
+            Phase::Standby => {},
+            Phase::Read if self.bits < 8 => self.bits += 1,
+            // The mapper acknowledges to read on
+            Phase::Read => {
+                if sda {
+                    self.phase = Phase::Standby;
+                } else {
+                    self.address = self.address.wrapping_add(1) & self.mask();
+                    self.shift = self.data[self.address as usize];
+                    self.bits = 0;
+                }
+            },
+            _ => {
+                self.shift = if self.lsb_first() {
+                    self.shift >> 1 | (sda as u8) << 7
+                } else {
+                    self.shift << 1 | sda as u8
+                };
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.receive();
+                }
+            },
+        }
+    }
+
+    // A whole byte clocked in
+    fn receive(&mut self) {
+        let byte = self.shift;
+        self.ack = true;
+        self.phase = match (self.phase, self.chip) {
+            (Phase::Control, Chip::C24C01) => {
+                self.address = byte & 0x7f;
+                if byte & 0x80 != 0 { Phase::Read } else { Phase::Write }
+            },
+            (Phase::Control, Chip::C24C02) if byte & 0xf0 != 0xa0 => {
+                self.ack = false;
+                Phase::Standby
+            },
+            (Phase::Control, Chip::C24C02) => {
+                if byte & 0x01 != 0 { Phase::Read } else { Phase::Address }
+            },
+            (Phase::Address, _) => {
+                self.address = byte;
+                Phase::Write
+            },
+            (phase, _) => {
+                self.data[self.address as usize] = byte;
+                // The address wraps inside a page: 4 bytes on the 24C01, 8 on the 24C02
+                let page = if self.chip == Chip::C24C01 { 0x03 } else { 0x07 };
+                self.address = (self.address & !page) | (self.address.wrapping_add(1) & page);
+                phase
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use mapper::eeprom::*;
+
+    pub fn start(eeprom: &mut Eeprom) {
+        eeprom.set_lines(false, true);
+        eeprom.set_lines(true, true);
+        eeprom.set_lines(true, false);
+        eeprom.set_lines(false, false);
+    }
+
+    pub fn stop(eeprom: &mut Eeprom) {
+        eeprom.set_lines(false, false);
+        eeprom.set_lines(true, false);
+        eeprom.set_lines(true, true);
+    }
+
+    // Sends a byte, returns whether it was acknowledged
+    pub fn send(eeprom: &mut Eeprom, byte: u8, lsb_first: bool) -> bool {
+        for i in 0..8 {
+            let bit = if lsb_first { i } else { 7 - i };
+            let sda = byte & (1 << bit) != 0;
+            eeprom.set_lines(false, sda);
+            eeprom.set_lines(true, sda);
+        }
+        eeprom.set_lines(false, true);
+        let ack = !eeprom.output();
+        eeprom.set_lines(true, true);
+        ack
+    }
+
+    // Reads a byte and acknowledges it unless it's the last one
+    pub fn receive(eeprom: &mut Eeprom, lsb_first: bool, last: bool) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            eeprom.set_lines(false, true);
+            let bit = if lsb_first { i } else { 7 - i };
+            byte |= (eeprom.output() as u8) << bit;
+            eeprom.set_lines(true, true);
+        }
+        eeprom.set_lines(false, last);
+        eeprom.set_lines(true, last);
+        byte
+    }
+
+    #[test]
+    fn writing_and_reading_24c02() {
+        let mut eeprom = Eeprom::new(Chip::C24C02);
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xa0, false));
+        assert!(send(&mut eeprom, 0x10, false));
+        assert!(send(&mut eeprom, 0x12, false));
+        assert!(send(&mut eeprom, 0x34, false));
+        stop(&mut eeprom);
+        assert_eq!(&eeprom.data()[0x10..0x12], &[0x12, 0x34]);
+
+        // Random read: set the address, then a repeated start
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xa0, false));
+        assert!(send(&mut eeprom, 0x10, false));
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xa1, false));
+        assert_eq!(receive(&mut eeprom, false, false), 0x12);
+        assert_eq!(receive(&mut eeprom, false, true), 0x34);
+        stop(&mut eeprom);
+
+        // Other devices on the bus
+        start(&mut eeprom);
+        assert!(!send(&mut eeprom, 0x40, false));
+    }
+
+    #[test]
+    fn writing_and_reading_24c01() {
+        let mut eeprom = Eeprom::new(Chip::C24C01);
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0x05, true));
+        assert!(send(&mut eeprom, 0x81, true));
+        stop(&mut eeprom);
+        assert_eq!(eeprom.data()[5], 0x81);
+
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0x85, true));
+        assert_eq!(receive(&mut eeprom, true, true), 0x81);
+        stop(&mut eeprom);
+    }
+}
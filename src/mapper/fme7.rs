@@ -0,0 +1,429 @@
+// Mapper 69: Sunsoft FME-7 and 5B
+// https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+//
+//   $8000 command   $A000 parameter
+//   $C000 audio address   $E000 audio data (5B only)
+// Commands:
+//   $0-$7 1 KB CHR banks      $8 $6000 bank
+//   $9-$B 8 KB PRG banks      $C mirroring
+//   $D IRQ control            $E, $F IRQ counter low and high
+use apu::APU;
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// Tone and noise timers run at the CPU clock divided by 16
+const AUDIO_DIVIDER: u8 = 16;
+
+// One square wave channel
+#[derive(Debug, Default)]
+struct Tone {
+    period: u16,
+    timer: u16,
+    high: bool,
+}
+
+impl Tone {
+    fn clock(&mut self) {
+        self.timer += 1;
+        if self.timer >= self.period.max(1) {
+            self.timer = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+// The 5B's AY-3-8910 compatible sound: three square channels with a shared
+// noise generator and envelope. Registers:
+//   $0-$5 12-bit tone periods    $6 noise period
+//   $7 --NNNTTT noise and tone disables for channels C, B, A
+//   $8-$A ---EVVVV envelope mode, volume
+//   $B, $C envelope period       $D envelope shape
+#[derive(Debug)]
+pub struct Audio {
+    address: u8,
+    tones: [Tone; 3],
+    noise_period: u8,
+    noise_timer: u8,
+    // 17-bit LFSR
+    noise: u32,
+    mixer: u8,
+    volumes: [u8; 3],
+    envelope_period: u16,
+    envelope_timer: u32,
+    // 76543210
+    // --------
+    // ----CAAH: continue, attack, alternate, hold
+    envelope_shape: u8,
+    // 0-31
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_rising: bool,
+    divider: u8,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            address: 0,
+            tones: Default::default(),
+            noise_period: 0,
+            noise_timer: 0,
+            noise: 1,
+            mixer: 0xff,
+            volumes: [0; 3],
+            envelope_period: 0,
+            envelope_timer: 0,
+            envelope_shape: 0,
+            envelope_step: 31,
+            envelope_holding: true,
+            envelope_rising: false,
+            divider: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.address {
+            register @ 0x0..=0x5 => {
+                let tone = &mut self.tones[register as usize >> 1];
+                tone.period = if register & 0x01 == 0 {
+                    (tone.period & 0xf00) | value as u16
+                } else {
+                    (tone.period & 0x0ff) | (value as u16 & 0x0f) << 8
+                };
+            },
+            0x6 => self.noise_period = value & 0x1f,
+            0x7 => self.mixer = value,
+            register @ 0x8..=0xa => self.volumes[register as usize - 0x8] = value & 0x1f,
+            0xb => self.envelope_period = (self.envelope_period & 0xff00) | value as u16,
+            0xc => self.envelope_period = (self.envelope_period & 0x00ff) | (value as u16) << 8,
+            0xd => {
+                self.envelope_shape = value & 0x0f;
+                self.envelope_rising = value & 0x04 != 0;
+                self.envelope_step = 0;
+                self.envelope_timer = 0;
+                self.envelope_holding = false;
+            },
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self) {
+        self.clock_envelope();
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+        // The noise runs at half the tone rate
+        self.noise_timer += 1;
+        if self.noise_timer >= self.noise_period.max(1) * 2 {
+            self.noise_timer = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 0x01;
+            self.noise = (self.noise >> 1) | feedback << 16;
+        }
+    }
+
+    // 32 steps per 256 * period CPU cycles
+    fn clock_envelope(&mut self) {
+        self.envelope_timer += 1;
+        if self.envelope_timer < 8 * self.envelope_period.max(1) as u32 {
+            return;
+        }
+        self.envelope_timer = 0;
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        // End of a ramp
+        let shape = self.envelope_shape;
+        if shape & 0x08 == 0 {
+            // Down to silence and stay there
+            self.envelope_rising = false;
+            self.envelope_holding = true;
+        } else if shape & 0x01 != 0 {
+            self.envelope_holding = true;
+            if shape & 0x02 != 0 {
+                self.envelope_rising = !self.envelope_rising;
+            }
+        } else {
+            self.envelope_step = 0;
+            if shape & 0x02 != 0 {
+                self.envelope_rising = !self.envelope_rising;
+            }
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_rising { self.envelope_step } else { 31 - self.envelope_step }
+    }
+
+    // Channels are 3 dB a volume step, 1.5 dB an envelope step
+    fn channel_output(&self, channel: usize) -> f32 {
+        let tone_off = self.mixer & (0x01 << channel) != 0;
+        let noise_off = self.mixer & (0x08 << channel) != 0;
+        let high = (tone_off || self.tones[channel].high) && (noise_off || self.noise & 0x01 != 0);
+        if !high {
+            return 0.0;
+        }
+        let volume = self.volumes[channel];
+        let level = if volume & 0x10 != 0 {
+            self.envelope_level()
+        } else {
+            (volume & 0x0f) * 2 + 1
+        };
+        if level <= 1 {
+            0.0
+        } else {
+            10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let sum: f32 = (0..3).map(|channel| self.channel_output(channel)).sum();
+        sum * APU::pulse_level(15)
+    }
+}
+
+#[derive(Debug)]
+pub struct FME7 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // ROM banks also appear at $6000
+    prg_rom_low: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    battery: bool,
+    pub audio: Audio,
+
+    command: u8,
+    // 76543210
+    // --------
+    // ERBBBBBB
+    // ||++++++- ROM bank
+    // |+------- RAM instead of ROM
+    // +-------- RAM enable
+    low_bank: u8,
+    mirroring: MirroringType,
+    // 76543210
+    // --------
+    // C------I: counting enable, IRQ enable
+    irq_control: u8,
+    irq_counter: u16,
+    irq_pending: bool,
+}
+
+impl FME7 {
+    pub fn new(rom: &ROM) -> FME7 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        FME7 {
+            prg_rom,
+            prg_rom_low: Banks::new(rom.prg_rom.clone(), 0x2000, 0x2000, false),
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x0400),
+            battery: rom.has_battery(),
+            audio: Audio::new(),
+            command: 0,
+            low_bank: 0,
+            mirroring: rom.mirroring,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            command @ 0x0..=0x7 => self.chr.map(command as usize, value as isize),
+            0x8 => {
+                self.low_bank = value;
+                self.prg_rom_low.map(0, (value & 0x3f) as isize);
+            },
+            command @ 0x9..=0xb => self.prg_rom.map(command as usize - 0x9, (value & 0x3f) as isize),
+            0xc => {
+                self.mirroring = match value & 0x03 {
+                    0 => MirroringType::Vertical,
+                    1 => MirroringType::Horizontal,
+                    2 => MirroringType::SingleScreenA,
+                    _ => MirroringType::SingleScreenB,
+                };
+            },
+            0xd => {
+                self.irq_control = value;
+                self.irq_pending = false;
+            },
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (value as u16) << 8,
+        }
+    }
+
+    fn ram_selected(&self) -> bool {
+        self.low_bank & 0x40 != 0
+    }
+}
+
+impl Mapper for FME7 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.ram_selected() => Some(self.prg_rom_low.read(address as usize - 0x6000)),
+            0x6000..=0x7fff if self.low_bank & 0x80 != 0 && !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if self.ram_selected() && self.low_bank & 0x80 != 0 && !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            0x8000..=0x9fff => self.command = value & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(value),
+            0xc000..=0xdfff => self.audio.address = value,
+            0xe000..=0xffff => self.audio.write(value),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // Counts down, the IRQ fires when it wraps from $0000 to $FFFF
+    fn notify_cpu_cycle(&mut self) {
+        self.audio.tick();
+        if self.irq_control & 0x80 == 0 {
+            return;
+        }
+        let (counter, wrapped) = self.irq_counter.overflowing_sub(1);
+        self.irq_counter = counter;
+        if wrapped && self.irq_control & 0x01 != 0 {
+            self.irq_pending = true;
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::fme7::*;
+    use mapper::test::make_rom;
+
+    fn make_fme7() -> FME7 {
+        let mut rom = make_rom(69, 0, 0x2000, 16, 0x400, 32);
+        rom.header.flags_6 |= 0x02;
+        FME7::new(&rom)
+    }
+
+    fn command(fme7: &mut FME7, command: u8, value: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xa000, value);
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut fme7 = make_fme7();
+        assert_eq!(fme7.cpu_read(0xe000), Some(15));
+        command(&mut fme7, 0x9, 3);
+        command(&mut fme7, 0xa, 4);
+        command(&mut fme7, 0xb, 5);
+        assert_eq!(fme7.cpu_read(0x8000), Some(3));
+        assert_eq!(fme7.cpu_read(0xa000), Some(4));
+        assert_eq!(fme7.cpu_read(0xc000), Some(5));
+        command(&mut fme7, 0x7, 20);
+        assert_eq!(fme7.ppu_read(0x1c00), 20);
+        command(&mut fme7, 0xc, 0x01);
+        assert_eq!(fme7.mirroring(), MirroringType::Horizontal);
+
+        // ROM at $6000, then enabled RAM
+        command(&mut fme7, 0x8, 6);
+        assert_eq!(fme7.cpu_read(0x6000), Some(6));
+        command(&mut fme7, 0x8, 0xc0);
+        fme7.cpu_write(0x6000, 0x12);
+        assert_eq!(fme7.cpu_read(0x6000), Some(0x12));
+        command(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.cpu_read(0x6000), None);
+    }
+
+    #[test]
+    fn counting_cycles() {
+        let mut fme7 = make_fme7();
+        command(&mut fme7, 0xe, 0x01);
+        command(&mut fme7, 0xf, 0x00);
+        command(&mut fme7, 0xd, 0x81);
+        fme7.notify_cpu_cycle();
+        assert!(!fme7.irq());
+        fme7.notify_cpu_cycle();
+        assert!(fme7.irq());
+        command(&mut fme7, 0xd, 0x00);
+        assert!(!fme7.irq());
+    }
+
+    #[test]
+    fn playing_tones() {
+        let mut fme7 = make_fme7();
+        assert_eq!(fme7.audio_output(), 0.0);
+        // Channel A, period 2, full volume, tone only
+        for &(register, value) in [(0x0, 2), (0x7, 0x3e), (0x8, 0x0f)].iter() {
+            fme7.cpu_write(0xc000, register);
+            fme7.cpu_write(0xe000, value);
+        }
+        let mut changes = 0;
+        let mut last = fme7.audio_output();
+        for _ in 0..16 * 2 * 4 {
+            fme7.notify_cpu_cycle();
+            if fme7.audio_output() != last {
+                changes += 1;
+                last = fme7.audio_output();
+            }
+        }
+        assert_eq!(changes, 4);
+        assert!(fme7.audio.channel_output(0) <= 1.0);
+
+        // The envelope rising from silence
+        fme7.cpu_write(0xc000, 0x8);
+        fme7.cpu_write(0xe000, 0x10);
+        fme7.cpu_write(0xc000, 0xd);
+        fme7.cpu_write(0xe000, 0x0d);
+        assert_eq!(fme7.audio.envelope_level(), 0);
+        for _ in 0..8 * 31 {
+            fme7.audio.clock_envelope();
+        }
+        assert_eq!(fme7.audio.envelope_level(), 31);
+    }
+}
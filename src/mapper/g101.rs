@@ -0,0 +1,123 @@
+// Mapper 32: Irem G-101
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_032
+//
+//   $8000 8 KB PRG bank at $8000 or $C000   $9000 PRG mode, mirroring
+//   $A000 8 KB PRG bank at $A000            $B000-$B007 1 KB CHR banks
+// Submapper 1 (Major League) is wired for one-screen mirroring and
+// ignores $9000.
+use mapper::banks::Banks;
+use mapper::{chr_banks, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct G101 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    fixed_mirroring: bool,
+
+    prg_bank: u8,
+    // The switchable bank at $C000 and the second to last at $8000
+    prg_swap: bool,
+}
+
+impl G101 {
+    pub fn new(rom: &ROM) -> G101 {
+        let fixed_mirroring = rom.submapper() == 1;
+        let mut g101 = G101 {
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false),
+            chr: chr_banks(rom, 0x0400),
+            mirroring: if fixed_mirroring { MirroringType::SingleScreenA } else { rom.mirroring },
+            fixed_mirroring,
+            prg_bank: 0,
+            prg_swap: false,
+        };
+        g101.update_prg();
+        g101.prg_rom.map(3, -1);
+        g101
+    }
+
+    fn update_prg(&mut self) {
+        let (switchable, fixed) = if self.prg_swap { (2, 0) } else { (0, 2) };
+        self.prg_rom.map(switchable, self.prg_bank as isize);
+        self.prg_rom.map(fixed, -2);
+    }
+}
+
+impl Mapper for G101 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address & 0xf000 {
+            0x8000 => {
+                self.prg_bank = value & 0x1f;
+                self.update_prg();
+            },
+            // ------PM
+            //       |+- Mirroring (0: vertical, 1: horizontal)
+            //       +-- PRG mode
+            0x9000 if !self.fixed_mirroring => {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Vertical
+                } else {
+                    MirroringType::Horizontal
+                };
+                self.prg_swap = value & 0x02 != 0;
+                self.update_prg();
+            },
+            0xa000 => self.prg_rom.map(1, (value & 0x1f) as isize),
+            0xb000 => self.chr.map(address as usize & 0x07, value as isize),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::g101::*;
+    use mapper::test::make_rom;
+
+    #[test]
+    fn switching_banks() {
+        let mut g101 = G101::new(&make_rom(32, 0, 0x2000, 16, 0x400, 32));
+        assert_eq!(g101.cpu_read(0xc000), Some(14));
+        assert_eq!(g101.cpu_read(0xe000), Some(15));
+
+        g101.cpu_write(0x8000, 3);
+        g101.cpu_write(0xa000, 4);
+        assert_eq!(g101.cpu_read(0x8000), Some(3));
+        assert_eq!(g101.cpu_read(0xa000), Some(4));
+        g101.cpu_write(0x9000, 0x03);
+        assert_eq!(g101.cpu_read(0x8000), Some(14));
+        assert_eq!(g101.cpu_read(0xc000), Some(3));
+        assert_eq!(g101.mirroring(), MirroringType::Horizontal);
+
+        g101.cpu_write(0xb005, 17);
+        assert_eq!(g101.ppu_read(0x1400), 17);
+
+        // Major League
+        let mut g101 = G101::new(&make_rom(32, 1, 0x2000, 16, 0x400, 32));
+        g101.cpu_write(0x9000, 0x03);
+        assert_eq!(g101.mirroring(), MirroringType::SingleScreenA);
+        assert_eq!(g101.cpu_read(0xc000), Some(14));
+    }
+}
@@ -0,0 +1,138 @@
+// Mapper 65: Irem H3001
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_065
+//
+//   $8000, $A000, $C000 8 KB PRG banks, the last one fixed at $E000
+//   $9001 mirroring   $9003-$9006 IRQ
+//   $B000-$B007 1 KB CHR banks
+use mapper::banks::Banks;
+use mapper::{chr_banks, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct H3001 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+
+    // A 16-bit counter going down every CPU cycle, stopping at 0
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl H3001 {
+    pub fn new(rom: &ROM) -> H3001 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        H3001 {
+            prg_rom,
+            chr: chr_banks(rom, 0x0400),
+            mirroring: rom.mirroring,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+}
+
+impl Mapper for H3001 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000 => self.prg_rom.map(0, value as isize),
+            // M-------: mirroring (0: vertical, 1: horizontal)
+            0x9001 => {
+                self.mirroring = if value & 0x80 == 0 {
+                    MirroringType::Vertical
+                } else {
+                    MirroringType::Horizontal
+                };
+            },
+            // E-------: IRQ enable, writing acknowledges
+            0x9003 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0x9004 => {
+                self.irq_counter = self.irq_latch;
+                self.irq_pending = false;
+            },
+            0x9005 => self.irq_latch = (self.irq_latch & 0x00ff) | (value as u16) << 8,
+            0x9006 => self.irq_latch = (self.irq_latch & 0xff00) | value as u16,
+            0xa000 => self.prg_rom.map(1, value as isize),
+            0xb000..=0xb007 => self.chr.map(address as usize & 0x07, value as isize),
+            0xc000 => self.prg_rom.map(2, value as isize),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        if self.irq_enabled && self.irq_counter > 0 {
+            self.irq_counter -= 1;
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::h3001::*;
+    use mapper::test::make_rom;
+
+    #[test]
+    fn switching_banks_and_counting() {
+        let mut h3001 = H3001::new(&make_rom(65, 0, 0x2000, 16, 0x400, 32));
+        assert_eq!(h3001.cpu_read(0xe000), Some(15));
+        h3001.cpu_write(0x8000, 3);
+        h3001.cpu_write(0xa000, 4);
+        h3001.cpu_write(0xc000, 5);
+        assert_eq!(h3001.cpu_read(0x8000), Some(3));
+        assert_eq!(h3001.cpu_read(0xa000), Some(4));
+        assert_eq!(h3001.cpu_read(0xc000), Some(5));
+        h3001.cpu_write(0xb007, 9);
+        assert_eq!(h3001.ppu_read(0x1c00), 9);
+        h3001.cpu_write(0x9001, 0x80);
+        assert_eq!(h3001.mirroring(), MirroringType::Horizontal);
+
+        h3001.cpu_write(0x9005, 0x00);
+        h3001.cpu_write(0x9006, 0x02);
+        h3001.cpu_write(0x9004, 0);
+        h3001.cpu_write(0x9003, 0x80);
+        h3001.notify_cpu_cycle();
+        assert!(!h3001.irq());
+        h3001.notify_cpu_cycle();
+        assert!(h3001.irq());
+        // Stays at 0
+        h3001.cpu_write(0x9003, 0x80);
+        h3001.notify_cpu_cycle();
+        assert!(!h3001.irq());
+    }
+}
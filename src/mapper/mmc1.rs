@@ -0,0 +1,396 @@
+// Mapper 1: MMC1 (SxROM boards)
+// https://wiki.nesdev.com/w/index.php/MMC1
+//
+// Registers are loaded serially through a 5-bit shift register, one bit per
+// write to $8000-$FFFF. The fifth write copies the value into the register
+// selected by address bits 13-14:
+//   $8000 control, $A000 CHR bank 0, $C000 CHR bank 1, $E000 PRG bank
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// Shift register after a reset, the 1 reaches bit 0 on the fifth write
+const SHIFT_RESET: u8 = 0x10;
+
+// Boards wiring the spare CHR bank bits to bigger PRG ROM or RAM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    // SNROM, SLROM, SKROM... nothing extra
+    Standard,
+    // 512 KB PRG ROM, CHR bit 4 selects the 256 KB half
+    SUROM,
+    // 16 KB PRG RAM, CHR bit 3 selects the 8 KB RAM bank
+    SOROM,
+    // 512 KB PRG ROM and 32 KB PRG RAM, CHR bits 2-3 select the RAM bank
+    SXROM,
+    // SEROM, SHROM: 32 KB PRG ROM not affected by the PRG registers
+    SEROM,
+}
+
+impl Board {
+    // NES 2.0 submapper 5 is SEROM, 1, 2 and 4 are the deprecated SUROM,
+    // SOROM and SXROM submappers. Otherwise the board is told by the sizes.
+    pub fn detect(rom: &ROM) -> Board {
+        match rom.submapper() {
+            1 => return Board::SUROM,
+            2 => return Board::SOROM,
+            4 => return Board::SXROM,
+            5 => return Board::SEROM,
+            _ => {},
+        }
+        let ram = rom.header.prg_ram_bytes() + rom.header.prg_nvram_bytes();
+        if ram >= 0x8000 {
+            Board::SXROM
+        } else if ram >= 0x4000 {
+            Board::SOROM
+        } else if rom.prg_rom.len() > 0x40000 {
+            Board::SUROM
+        } else {
+            Board::Standard
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MMC1 {
+    board: Board,
+    // $8000-$FFFF in two 16 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in two 4 KB windows
+    chr: Banks,
+    battery: bool,
+
+    shift: u8,
+    // 43210
+    // -----
+    // CPPMM
+    // |||++- Mirroring (0: one-screen A, 1: one-screen B, 2: vertical, 3: horizontal)
+    // |++--- PRG mode (0, 1: 32 KB; 2: first bank fixed at $8000; 3: last bank fixed at $C000)
+    // +----- CHR mode (0: 8 KB; 1: two 4 KB banks)
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    // 43210
+    // -----
+    // RPPPP
+    // |++++- 16 KB PRG bank
+    // +----- PRG RAM disable
+    prg_bank: u8,
+    // The CHR register holding the outer PRG ROM and RAM bits, in 4 KB mode
+    // the last written one wins
+    last_chr_register: u8,
+
+    // CPU cycles since power on, and when the last serial write happened
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl MMC1 {
+    pub fn new(rom: &ROM) -> MMC1 {
+        let mut mmc1 = MMC1 {
+            board: Board::detect(rom),
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x4000, false),
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x1000),
+            battery: rom.has_battery(),
+            shift: SHIFT_RESET,
+            // Power on in PRG mode 3 so the reset vector is in the last bank
+            control: 0x0c,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            last_chr_register: 0,
+            cycle: 0,
+            last_write: None,
+        };
+        mmc1.update_banks();
+        mmc1
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn outer_bits(&self) -> u8 {
+        if self.last_chr_register == 1 && self.control & 0x10 != 0 {
+            self.chr_bank_1
+        } else {
+            self.chr_bank_0
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        let bank = match self.board {
+            Board::SOROM => (self.outer_bits() >> 3) & 0x01,
+            Board::SXROM => (self.outer_bits() >> 2) & 0x03,
+            _ => 0,
+        } as usize;
+        (bank * 0x2000 + address as usize - 0x6000) % self.prg_ram.len()
+    }
+
+    fn update_banks(&mut self) {
+        let outer = match self.board {
+            Board::SUROM | Board::SXROM => (self.outer_bits() & 0x10) as isize,
+            _ => 0,
+        };
+        let bank = (self.prg_bank & 0x0f) as isize;
+        if self.board == Board::SEROM {
+            self.prg_rom.map_range(0, 2, 0);
+        } else {
+            match (self.control >> 2) & 0x03 {
+                0 | 1 => self.prg_rom.map_range(0, 2, outer | (bank & !1)),
+                2 => {
+                    self.prg_rom.map(0, outer);
+                    self.prg_rom.map(1, outer | bank);
+                },
+                _ => {
+                    self.prg_rom.map(0, outer | bank);
+                    self.prg_rom.map(1, outer | 0x0f);
+                },
+            }
+        }
+
+        if self.control & 0x10 == 0 {
+            self.chr.map_range(0, 2, (self.chr_bank_0 & !1) as isize);
+        } else {
+            self.chr.map(0, self.chr_bank_0 as isize);
+            self.chr.map(1, self.chr_bank_1 as isize);
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address & 0x6000 {
+            0x0000 => self.control = value,
+            0x2000 => {
+                self.chr_bank_0 = value;
+                self.last_chr_register = 0;
+            },
+            0x4000 => {
+                self.chr_bank_1 = value;
+                self.last_chr_register = 1;
+            },
+            _ => self.prg_bank = value,
+        }
+        self.update_banks();
+    }
+
+    fn serial_write(&mut self, address: u16, value: u8) {
+        // Writes on consecutive cycles (the dummy write of read-modify-write
+        // instructions) are ignored, only the first one counts
+        let consecutive = self.last_write.is_some_and(|last| self.cycle - last <= 1);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+
+        if value & 0x80 != 0 {
+            self.shift = SHIFT_RESET;
+            self.control |= 0x0c;
+            self.update_banks();
+            return;
+        }
+        let full = self.shift & 0x01 != 0;
+        self.shift = (self.shift >> 1) | ((value & 0x01) << 4);
+        if full {
+            let value = self.shift;
+            self.shift = SHIFT_RESET;
+            self.write_register(address, value);
+        }
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                Some(self.prg_ram[self.prg_ram_index(address)])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let index = self.prg_ram_index(address);
+                self.prg_ram[index] = value;
+            },
+            0x8000..=0xffff => self.serial_write(address, value),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        match self.control & 0x03 {
+            0 => MirroringType::SingleScreenA,
+            1 => MirroringType::SingleScreenB,
+            2 => MirroringType::Vertical,
+            _ => MirroringType::Horizontal,
+        }
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::mmc1::*;
+    use mapper::test;
+
+    // 16 KB PRG banks and 4 KB CHR banks, a battery for `prg_ram` KB of
+    // PRG RAM unless it's 0
+    fn make_rom(prg_banks: usize, chr_banks: usize, prg_ram: usize) -> ROM {
+        let mut rom = test::make_rom(1, 0, 0x4000, prg_banks, 0x1000, chr_banks);
+        if prg_ram > 0 {
+            // iNES 1.0 byte 8, in 8 KB units
+            rom.header.flags_6 |= 0x02;
+            rom.header.prg_ram_size = (prg_ram / 8) as u8;
+        }
+        rom
+    }
+
+    // Loads a register bit by bit, letting cycles pass between writes
+    fn load(mmc1: &mut MMC1, address: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.notify_cpu_cycle();
+            mmc1.notify_cpu_cycle();
+            mmc1.cpu_write(address, (value >> i) & 0x01);
+        }
+    }
+
+    #[test]
+    fn switching_prg_banks() {
+        let rom = make_rom(8, 8, 0);
+        let mut mmc1 = MMC1::new(&rom);
+        assert_eq!(mmc1.board(), Board::Standard);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(7));
+
+        load(&mut mmc1, 0xe000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(3));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(7));
+
+        // Mode 2: first bank fixed, vertical mirroring
+        load(&mut mmc1, 0x8000, 0x0a);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(3));
+        assert_eq!(mmc1.mirroring(), MirroringType::Vertical);
+
+        // 32 KB mode ignores the low bit
+        load(&mut mmc1, 0x8000, 0x03);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(2));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(3));
+        assert_eq!(mmc1.mirroring(), MirroringType::Horizontal);
+    }
+
+    #[test]
+    fn switching_chr_banks() {
+        let rom = make_rom(2, 8, 0);
+        let mut mmc1 = MMC1::new(&rom);
+        load(&mut mmc1, 0xa000, 5);
+        load(&mut mmc1, 0xc000, 6);
+        // 8 KB mode uses CHR bank 0 without the low bit
+        assert_eq!(mmc1.ppu_read(0x0000), 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5);
+
+        load(&mut mmc1, 0x8000, 0x1c);
+        assert_eq!(mmc1.ppu_read(0x0000), 5);
+        assert_eq!(mmc1.ppu_read(0x1000), 6);
+    }
+
+    #[test]
+    fn resetting_and_ignoring_writes() {
+        let rom = make_rom(8, 8, 0);
+        let mut mmc1 = MMC1::new(&rom);
+        load(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.cpu_read(0xc000), Some(1));
+
+        // A write with bit 7 set clears the shift register and sets mode 3
+        mmc1.notify_cpu_cycle();
+        mmc1.notify_cpu_cycle();
+        mmc1.cpu_write(0xe000, 0x01);
+        mmc1.notify_cpu_cycle();
+        mmc1.notify_cpu_cycle();
+        mmc1.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc1.cpu_read(0xc000), Some(7));
+        load(&mut mmc1, 0xe000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(2));
+
+        // The second of two back to back writes is dropped
+        mmc1.notify_cpu_cycle();
+        mmc1.notify_cpu_cycle();
+        mmc1.cpu_write(0xe000, 0x01);
+        mmc1.cpu_write(0xe000, 0x00);
+        for _ in 0..4 {
+            mmc1.notify_cpu_cycle();
+            mmc1.notify_cpu_cycle();
+            mmc1.cpu_write(0xe000, 0x00);
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn enabling_prg_ram() {
+        let rom = make_rom(2, 2, 8);
+        let mut mmc1 = MMC1::new(&rom);
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x42));
+
+        load(&mut mmc1, 0xe000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), None);
+        mmc1.cpu_write(0x6000, 0x00);
+        load(&mut mmc1, 0xe000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x42));
+        assert_eq!(mmc1.save_ram().unwrap()[0], 0x42);
+    }
+
+    #[test]
+    fn detecting_boards() {
+        // SUROM: the second 256 KB is selected through CHR bank 0
+        let rom = make_rom(32, 0, 0);
+        let mut mmc1 = MMC1::new(&rom);
+        assert_eq!(mmc1.board(), Board::SUROM);
+        assert_eq!(mmc1.cpu_read(0xc000), Some(15));
+        load(&mut mmc1, 0xa000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(16));
+        assert_eq!(mmc1.cpu_read(0xc000), Some(31));
+
+        // SOROM: two 8 KB RAM banks
+        let rom = make_rom(8, 0, 16);
+        let mut mmc1 = MMC1::new(&rom);
+        assert_eq!(mmc1.board(), Board::SOROM);
+        mmc1.cpu_write(0x6000, 0x11);
+        load(&mut mmc1, 0xa000, 0x08);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x00));
+        mmc1.cpu_write(0x6000, 0x22);
+        load(&mut mmc1, 0xa000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x11));
+
+        let rom = make_rom(32, 0, 32);
+        assert_eq!(MMC1::new(&rom).board(), Board::SXROM);
+    }
+}
@@ -0,0 +1,220 @@
+// Mapper 9: MMC2 (PxROM, Punch-Out!!) and mapper 10: MMC4 (FxROM, Fire Emblem)
+// https://wiki.nesdev.com/w/index.php/MMC2
+// https://wiki.nesdev.com/w/index.php/MMC4
+//
+// Each 4 KB CHR half has two banks and a latch choosing between them. The
+// latches flip when the PPU fetches the patterns of tiles $FD or $FE, the
+// fetch itself still sees the old bank.
+//   $A000 PRG bank
+//   $B000 CHR $0000 bank for latch $FD   $C000 for latch $FE
+//   $D000 CHR $1000 bank for latch $FD   $E000 for latch $FE
+//   $F000 mirroring (0: vertical, 1: horizontal)
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    // 8 KB switchable at $8000, the last three fixed. Latch 0 only flips
+    // on the exact addresses $0FD8 and $0FE8.
+    MMC2,
+    // 16 KB switchable at $8000, the last one fixed, PRG RAM
+    MMC4,
+}
+
+#[derive(Debug)]
+pub struct MMC2 {
+    chip: Chip,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in two 4 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    battery: bool,
+
+    // CHR banks for latch $FD and $FE, per half
+    chr_banks: [[u8; 2]; 2],
+    // false: $FD, true: $FE
+    latches: [bool; 2],
+}
+
+impl MMC2 {
+    pub fn new(rom: &ROM, chip: Chip) -> MMC2 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map_range(0, 4, -4);
+        prg_rom.map(0, 0);
+        if chip == Chip::MMC4 {
+            prg_rom.map(1, 1);
+        }
+        let mut mmc2 = MMC2 {
+            chip,
+            prg_rom,
+            prg_ram: match chip {
+                Chip::MMC2 => Vec::new(),
+                Chip::MMC4 => prg_ram(rom),
+            },
+            chr: chr_banks(rom, 0x1000),
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            chr_banks: [[0, 0], [0, 0]],
+            latches: [true, true],
+        };
+        mmc2.update_chr();
+        mmc2
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    fn update_chr(&mut self) {
+        for half in 0..2 {
+            let bank = self.chr_banks[half][self.latches[half] as usize];
+            self.chr.map(half, bank as isize);
+        }
+    }
+
+    // Called after every pattern fetch
+    fn update_latches(&mut self, address: u16) {
+        // MMC2 compares all address bits for latch 0
+        let mask = if self.chip == Chip::MMC2 && address < 0x1000 { 0x0fff } else { 0x0ff8 };
+        let latch = match address & mask {
+            0x0fd8 => Some(false),
+            0x0fe8 => Some(true),
+            _ => None,
+        };
+        if let Some(latch) = latch {
+            self.latches[(address >> 12) as usize & 0x01] = latch;
+            self.update_chr();
+        }
+    }
+}
+
+impl Mapper for MMC2 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            0xa000..=0xafff => match self.chip {
+                Chip::MMC2 => self.prg_rom.map(0, (value & 0x0f) as isize),
+                Chip::MMC4 => self.prg_rom.map_range(0, 2, (value & 0x0f) as isize * 2),
+            },
+            0xb000..=0xefff => {
+                let register = (address as usize - 0xb000) >> 12;
+                self.chr_banks[register / 2][register % 2] = value & 0x1f;
+                self.update_chr();
+            },
+            0xf000..=0xffff => {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Vertical
+                } else {
+                    MirroringType::Horizontal
+                };
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let value = self.chr.read(address as usize);
+        self.update_latches(address);
+        value
+    }
+
+    fn ppu_peek(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::mmc2::*;
+    use mapper::test;
+
+    fn make_rom(mapper: u16) -> ROM {
+        test::make_rom(mapper, 0, 0x2000, 16, 0x1000, 16)
+    }
+
+    #[test]
+    fn switching_prg_banks() {
+        let mut mmc2 = MMC2::new(&make_rom(9), Chip::MMC2);
+        assert_eq!(mmc2.cpu_read(0xa000), Some(13));
+        assert_eq!(mmc2.cpu_read(0xe000), Some(15));
+        mmc2.cpu_write(0xa000, 5);
+        assert_eq!(mmc2.cpu_read(0x8000), Some(5));
+        assert_eq!(mmc2.cpu_read(0x6000), None);
+
+        let mut mmc4 = MMC2::new(&make_rom(10), Chip::MMC4);
+        mmc4.cpu_write(0xa000, 3);
+        assert_eq!(mmc4.cpu_read(0x8000), Some(6));
+        assert_eq!(mmc4.cpu_read(0xa000), Some(7));
+        assert_eq!(mmc4.cpu_read(0xc000), Some(14));
+        mmc4.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc4.cpu_read(0x6000), Some(0x12));
+    }
+
+    #[test]
+    fn flipping_latches() {
+        let mut mmc2 = MMC2::new(&make_rom(9), Chip::MMC2);
+        mmc2.cpu_write(0xb000, 1);
+        mmc2.cpu_write(0xc000, 2);
+        mmc2.cpu_write(0xd000, 3);
+        mmc2.cpu_write(0xe000, 4);
+        assert_eq!(mmc2.ppu_read(0x0000), 2);
+        assert_eq!(mmc2.ppu_read(0x1000), 4);
+
+        // Fetching the pattern of tile $FD selects the $FD bank
+        mmc2.ppu_read(0x0fd8);
+        assert_eq!(mmc2.ppu_read(0x0000), 1);
+        // Only $0FD8 itself flips latch 0 on MMC2
+        mmc2.ppu_read(0x0fe9);
+        assert_eq!(mmc2.ppu_read(0x0000), 1);
+        mmc2.ppu_read(0x0fe8);
+        assert_eq!(mmc2.ppu_read(0x0000), 2);
+        // Latch 1 reacts to the whole $1FD8-$1FDF range
+        mmc2.ppu_read(0x1fdf);
+        assert_eq!(mmc2.ppu_read(0x1000), 3);
+        assert_eq!(mmc2.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    fn flipping_mmc4_latches() {
+        let mut mmc4 = MMC2::new(&make_rom(10), Chip::MMC4);
+        mmc4.cpu_write(0xb000, 1);
+        mmc4.cpu_write(0xc000, 2);
+        mmc4.ppu_read(0x0fdd);
+        assert_eq!(mmc4.ppu_read(0x0000), 1);
+        mmc4.cpu_write(0xf000, 1);
+        assert_eq!(mmc4.mirroring(), MirroringType::Horizontal);
+    }
+}
@@ -0,0 +1,363 @@
+// Mapper 4: MMC3 (TxROM boards)
+// https://wiki.nesdev.com/w/index.php/MMC3
+//
+// Registers, even and odd addresses in each 8 KB range:
+//   $8000 bank select   $8001 bank data
+//   $A000 mirroring     $A001 PRG RAM protect
+//   $C000 IRQ latch     $C001 IRQ reload
+//   $E000 IRQ disable   $E001 IRQ enable
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// A12 has to stay low this many CPU cycles before a rise clocks the counter,
+// filters out the short drops between sprite pattern fetches
+const A12_LOW_CYCLES: u64 = 3;
+
+// The two ways MMC3 revisions decide when to raise the IRQ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IrqBehavior {
+    // MMC3B/MMC3C ("new"): on every clock leaving the counter at 0,
+    // a latch of 0 fires on each scanline
+    Sharp,
+    // MMC3A ("old"): only when the counter is decremented to 0 or
+    // reloaded by a $C001 write, a latch of 0 never fires on its own
+    NEC,
+}
+
+#[derive(Debug)]
+pub struct MMC3 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    battery: bool,
+    four_screen: bool,
+
+    // 76543210
+    // --------
+    // CPxxxRRR
+    // ||   +++- Register written by $8001 (R0-R7)
+    // |+------- PRG mode (0: $8000 switchable, 1: $C000 switchable)
+    // +-------- CHR inversion (0: 2 KB banks at $0000, 1: at $1000)
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: MirroringType,
+    // 76543210
+    // --------
+    // EWxxxxxx
+    // |+------- Deny writes
+    // +-------- Enable PRG RAM
+    ram_protect: u8,
+
+    irq_behavior: IrqBehavior,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    // CPU cycles since power on, and since when A12 has been low
+    cycle: u64,
+    a12_low_since: Option<u64>,
+}
+
+impl MMC3 {
+    // NES 2.0 submapper 4 marks boards with the NEC chip
+    pub fn new(rom: &ROM) -> MMC3 {
+        let irq_behavior = if rom.submapper() == 4 { IrqBehavior::NEC } else { IrqBehavior::Sharp };
+        let mut mmc3 = MMC3 {
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false),
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x0400),
+            battery: rom.has_battery(),
+            four_screen: rom.mirroring == MirroringType::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.mirroring,
+            ram_protect: 0x80,
+            irq_behavior,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            cycle: 0,
+            a12_low_since: Some(0),
+        };
+        mmc3.update_banks();
+        mmc3
+    }
+
+    pub fn irq_behavior(&self) -> IrqBehavior {
+        self.irq_behavior
+    }
+
+    pub fn set_irq_behavior(&mut self, behavior: IrqBehavior) {
+        self.irq_behavior = behavior;
+    }
+
+    fn update_banks(&mut self) {
+        let r = |i: usize| self.registers[i] as isize;
+        let (r0, r1, r6, r7) = (r(0), r(1), r(6) & 0x3f, r(7) & 0x3f);
+        let (r2, r3, r4, r5) = (r(2), r(3), r(4), r(5));
+
+        if self.bank_select & 0x40 == 0 {
+            self.prg_rom.map(0, r6);
+            self.prg_rom.map(2, -2);
+        } else {
+            self.prg_rom.map(0, -2);
+            self.prg_rom.map(2, r6);
+        }
+        self.prg_rom.map(1, r7);
+        self.prg_rom.map(3, -1);
+
+        // 2 KB banks ignore the low bit
+        let (two_kb, one_kb) = if self.bank_select & 0x80 == 0 { (0, 4) } else { (4, 0) };
+        self.chr.map_range(two_kb, 2, r0 & !1);
+        self.chr.map_range(two_kb + 2, 2, r1 & !1);
+        for (i, &bank) in [r2, r3, r4, r5].iter().enumerate() {
+            self.chr.map(one_kb + i, bank);
+        }
+    }
+
+    fn prg_ram_index(&self, address: u16) -> Option<usize> {
+        if self.ram_protect & 0x80 == 0 || self.prg_ram.is_empty() {
+            return None;
+        }
+        Some((address as usize - 0x6000) % self.prg_ram.len())
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match (address & 0xe000, address & 0x01) {
+            (0x8000, 0) => {
+                self.bank_select = value;
+                self.update_banks();
+            },
+            (0x8000, _) => {
+                self.registers[(self.bank_select & 0x07) as usize] = value;
+                self.update_banks();
+            },
+            (0xa000, 0) => if !self.four_screen {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Vertical
+                } else {
+                    MirroringType::Horizontal
+                };
+            },
+            (0xa000, _) => self.ram_protect = value,
+            (0xc000, 0) => self.irq_latch = value,
+            (0xc000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            (_, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            (_, _) => self.irq_enabled = true,
+        }
+    }
+
+    // One filtered rising edge of A12, normally once per scanline
+    fn clock_counter(&mut self) {
+        let previous = self.irq_counter;
+        let reloaded = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+
+        let fire = match self.irq_behavior {
+            IrqBehavior::Sharp => self.irq_counter == 0,
+            IrqBehavior::NEC => self.irq_counter == 0 && (previous > 0 || reloaded),
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff => self.prg_ram_index(address).map(|index| self.prg_ram[index]),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if self.ram_protect & 0x40 == 0 => {
+                if let Some(index) = self.prg_ram_index(address) {
+                    self.prg_ram[index] = value;
+                }
+            },
+            0x8000..=0xffff => self.write_register(address, value),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_ppu_address(&mut self, address: u16) {
+        let high = address & 0x1000 != 0;
+        match (high, self.a12_low_since) {
+            (true, Some(since)) => {
+                self.a12_low_since = None;
+                if self.cycle - since >= A12_LOW_CYCLES {
+                    self.clock_counter();
+                }
+            },
+            (false, None) => self.a12_low_since = Some(self.cycle),
+            _ => {},
+        }
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::mmc3::*;
+    use mapper::test;
+
+    fn make_rom(submapper: u8) -> ROM {
+        let mut rom = test::make_rom(4, submapper, 0x2000, 8, 0x400, 8);
+        rom.header.flags_6 |= 0x02;
+        rom
+    }
+
+    // A scanline as the PPU fetches it with background patterns at $0000
+    // and sprite patterns at $1000
+    fn scanline(mmc3: &mut MMC3) {
+        for _ in 0..100 {
+            mmc3.notify_cpu_cycle();
+            mmc3.notify_ppu_address(0x0000);
+        }
+        for _ in 0..8 {
+            mmc3.notify_ppu_address(0x1000);
+            mmc3.notify_ppu_address(0x2000);
+            mmc3.notify_cpu_cycle();
+            mmc3.notify_ppu_address(0x1000);
+        }
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut mmc3 = MMC3::new(&make_rom(0));
+        assert_eq!(mmc3.cpu_read(0xe000), Some(7));
+        assert_eq!(mmc3.cpu_read(0xc000), Some(6));
+
+        mmc3.cpu_write(0x8000, 0x06);
+        mmc3.cpu_write(0x8001, 0x03);
+        mmc3.cpu_write(0x8000, 0x07);
+        mmc3.cpu_write(0x8001, 0x04);
+        assert_eq!(mmc3.cpu_read(0x8000), Some(3));
+        assert_eq!(mmc3.cpu_read(0xa000), Some(4));
+
+        // PRG mode 1 swaps $8000 and $C000
+        mmc3.cpu_write(0x8000, 0x40);
+        assert_eq!(mmc3.cpu_read(0x8000), Some(6));
+        assert_eq!(mmc3.cpu_read(0xc000), Some(3));
+
+        mmc3.cpu_write(0x8001, 0x03);
+        mmc3.cpu_write(0x8000, 0x02);
+        mmc3.cpu_write(0x8001, 0x05);
+        assert_eq!(mmc3.ppu_read(0x0000), 2);
+        assert_eq!(mmc3.ppu_read(0x0400), 3);
+        assert_eq!(mmc3.ppu_read(0x1000), 5);
+
+        // CHR inversion moves the 2 KB banks to $1000
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc3.ppu_read(0x0000), 5);
+        assert_eq!(mmc3.ppu_read(0x1000), 2);
+        assert_eq!(mmc3.ppu_read(0x1400), 3);
+
+        mmc3.cpu_write(0xa000, 0x01);
+        assert_eq!(mmc3.mirroring(), MirroringType::Horizontal);
+    }
+
+    #[test]
+    fn protecting_prg_ram() {
+        let mut mmc3 = MMC3::new(&make_rom(0));
+        mmc3.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc3.cpu_read(0x6000), Some(0x12));
+        mmc3.cpu_write(0xa001, 0xc0);
+        mmc3.cpu_write(0x6000, 0x34);
+        assert_eq!(mmc3.cpu_read(0x6000), Some(0x12));
+        mmc3.cpu_write(0xa001, 0x00);
+        assert_eq!(mmc3.cpu_read(0x6000), None);
+        assert_eq!(mmc3.save_ram().unwrap()[0], 0x12);
+    }
+
+    #[test]
+    fn counting_scanlines() {
+        let mut mmc3 = MMC3::new(&make_rom(0));
+        assert_eq!(mmc3.irq_behavior(), IrqBehavior::Sharp);
+        mmc3.cpu_write(0xc000, 3);
+        mmc3.cpu_write(0xc001, 0);
+        mmc3.cpu_write(0xe001, 0);
+
+        // Reload, then three decrements
+        for _ in 0..3 {
+            scanline(&mut mmc3);
+            assert!(!mmc3.irq());
+        }
+        scanline(&mut mmc3);
+        assert!(mmc3.irq());
+
+        mmc3.cpu_write(0xe000, 0);
+        assert!(!mmc3.irq());
+        mmc3.cpu_write(0xe001, 0);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn firing_with_a_zero_latch() {
+        let mut sharp = MMC3::new(&make_rom(0));
+        let mut nec = MMC3::new(&make_rom(4));
+        assert_eq!(nec.irq_behavior(), IrqBehavior::NEC);
+        for mmc3 in [&mut sharp, &mut nec].iter_mut() {
+            mmc3.cpu_write(0xe001, 0);
+            scanline(mmc3);
+        }
+        // Only the Sharp chip fires when the counter stays at 0
+        assert!(sharp.irq());
+        assert!(!nec.irq());
+
+        // Both fire after a reload
+        nec.cpu_write(0xc001, 0);
+        scanline(&mut nec);
+        assert!(nec.irq());
+    }
+}
@@ -0,0 +1,871 @@
+// Mapper 5: MMC5 (ExROM boards)
+// https://wiki.nesdev.com/w/index.php/MMC5
+//
+// MMC5 follows the PPU by watching its fetches: three reads in a row from
+// the same nametable address start a scanline (the dummy fetches at dots
+// 337 and 339 and the first fetch of the next line), and the fetches after
+// that are counted to tell background tiles from sprites. Nothing read for
+// a few CPU cycles means the PPU stopped rendering.
+use apu::pulse::Pulse;
+use apu::APU;
+use mapper::{chr_memory, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// CPU cycles without PPU reads after which the frame is over
+const IDLE_CYCLES: u8 = 3;
+// Audio length counters and envelopes are clocked at 240 Hz
+const AUDIO_FRAME_CYCLES: u32 = 7457;
+
+// Fetches of a scanline counted from its first nametable read: 32 tiles
+// of four reads, 8 sprites of four, then the first two tiles of the next line
+const SPRITE_FETCHES: u32 = 128;
+const NEXT_LINE_FETCHES: u32 = 160;
+
+// Two pulses like the APU's without sweep, and an 8-bit PCM channel
+#[derive(Debug)]
+pub struct Audio {
+    pub pulse_1: Pulse,
+    pub pulse_2: Pulse,
+    pub pcm: u8,
+    // PCM is taken from CPU reads of $8000-$BFFF instead of $5011 writes
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    frame_cycle: u32,
+    odd_cycle: bool,
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            pulse_1: Pulse::without_sweep(),
+            pulse_2: Pulse::without_sweep(),
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+        }
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 => {
+                let value = (self.pcm_irq as u8) << 7 | self.pcm_read_mode as u8;
+                self.pcm_irq = false;
+                Some(value)
+            },
+            0x5015 => Some((self.pulse_2.length.is_active() as u8) << 1 |
+                           self.pulse_1.length.is_active() as u8),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5003 => self.pulse_1.write(address, value),
+            0x5004..=0x5007 => self.pulse_2.write(address, value),
+            // I------M
+            0x5010 => {
+                self.pcm_read_mode = value & 0x01 != 0;
+                self.pcm_irq_enabled = value & 0x80 != 0;
+            },
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulse_1.length.set_enabled(value & 0x01 != 0);
+                self.pulse_2.length.set_enabled(value & 0x02 != 0);
+            },
+            _ => {},
+        }
+    }
+
+    // A CPU read of $8000-$BFFF, in read mode a 0 raises the IRQ
+    fn snoop_read(&mut self, value: u8) {
+        if self.pcm_read_mode {
+            if value == 0 {
+                self.pcm_irq = true;
+            } else {
+                self.pcm = value;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        self.frame_cycle += 1;
+        if self.frame_cycle == AUDIO_FRAME_CYCLES {
+            self.frame_cycle = 0;
+            for pulse in [&mut self.pulse_1, &mut self.pulse_2].iter_mut() {
+                pulse.envelope.clock();
+                pulse.length.clock();
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+
+    // The pulses mix like the APU's, PCM about as loud as the DMC
+    fn output(&self) -> f32 {
+        let pulse = APU::pulse_level(self.pulse_1.output() + self.pulse_2.output());
+        let pcm = if self.pcm == 0 {
+            0.0
+        } else {
+            159.79 / (22638.0 / (self.pcm >> 1).max(1) as f32 + 100.0)
+        };
+        pulse + pcm
+    }
+}
+
+// What a PPU read is for, worked out from its position in the scanline
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fetch {
+    // Background tile in this column, 0-33
+    Background(u8),
+    Sprite,
+    // CPU access through $2007, or the dummy fetches at the end of a line
+    Other,
+}
+
+#[derive(Debug)]
+pub struct MMC5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    // CHR ROM followed by CHR RAM
+    chr: Vec<u8>,
+    chr_ram_start: usize,
+    exram: [u8; 0x400],
+    battery: bool,
+    pub audio: Audio,
+
+    // $5100: 0: 32 KB, 1: 16 KB x2, 2: 16 KB + 8 KB x2, 3: 8 KB x4
+    prg_mode: u8,
+    // $5101: 0: 8 KB, 1: 4 KB, 2: 2 KB, 3: 1 KB
+    chr_mode: u8,
+    // $5102, $5103: PRG RAM is writable only with 2 and 1 in them
+    ram_protect: [u8; 2],
+    // $5104: 0: nametable, 1: extended attributes, 2: RAM, 3: read-only RAM
+    exram_mode: u8,
+    // $5105
+    // 76543210
+    // --------
+    // DDCCBBAA: source of each nametable (0: CIRAM A, 1: CIRAM B, 2: ExRAM, 3: fill)
+    nametables: u8,
+    fill_tile: u8,
+    fill_color: u8,
+    // $5113: 8 KB PRG RAM bank at $6000
+    prg_ram_bank: u8,
+    // $5114-$5117, bit 7 selects ROM over RAM
+    prg_banks: [u8; 4],
+    // $5120-$5127 (set A, sprites) and $5128-$512B (set B, background),
+    // with the upper bits of $5130 latched on write
+    chr_a: [u16; 8],
+    chr_b: [u16; 4],
+    chr_upper: u8,
+    // Set written last, used outside 8x16 sprite rendering
+    last_set_b: bool,
+
+    // $5200
+    // 76543210
+    // --------
+    // ERxTTTTT
+    // ||  +++++- Tile column where the split starts or ends
+    // |+-------- 0: split on the left, 1: on the right
+    // +--------- Enable
+    split_control: u8,
+    split_scroll: u8,
+    split_page: u8,
+    // Tile and row of the split region fetched last
+    split_tile: u8,
+    split_y: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // Snooped $2000 and $2001
+    sprites_8x16: bool,
+    rendering: bool,
+
+    // Scanline detection and fetch counting
+    last_nametable_address: u16,
+    nametable_repeats: u8,
+    fetch: u32,
+    idle_cycles: u8,
+    // ExRAM byte of the tile being fetched in extended attribute mode
+    extended_tile: u8,
+}
+
+impl MMC5 {
+    pub fn new(rom: &ROM) -> MMC5 {
+        MMC5 {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr: chr_memory(rom),
+            chr_ram_start: rom.chr_rom.len(),
+            exram: [0; 0x400],
+            battery: rom.has_battery(),
+            audio: Audio::new(),
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0, 0],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_color: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0xff; 4],
+            chr_a: [0; 8],
+            chr_b: [0; 4],
+            chr_upper: 0,
+            last_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_page: 0,
+            split_tile: 0,
+            split_y: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            sprites_8x16: false,
+            rendering: false,
+            last_nametable_address: 0,
+            nametable_repeats: 0,
+            fetch: 0,
+            idle_cycles: 0,
+            extended_tile: 0,
+        }
+    }
+
+    pub fn in_frame(&self) -> bool {
+        self.in_frame
+    }
+
+    // ROM or RAM and the 8 KB bank in one of the windows at $8000-$FFFF
+    fn prg_window(&self, window: usize) -> (bool, usize) {
+        let (register, size) = match (self.prg_mode, window) {
+            (0, _) => (3, 4),
+            (1, 0..=1) => (1, 2),
+            (1, _) => (3, 2),
+            (2, 0..=1) => (1, 2),
+            (2, _) => (window, 1),
+            (_, _) => (window, 1),
+        };
+        let value = self.prg_banks[register] as usize;
+        // The last window is always ROM
+        let rom = value & 0x80 != 0 || register == 3;
+        let bank = (value & 0x7f & !(size - 1)) + window % size;
+        (rom, bank)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.ram_protect == [0x02, 0x01]
+    }
+
+    fn prg_ram_index(&self, bank: usize, address: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some((bank * 0x2000 + (address as usize & 0x1fff)) % self.prg_ram.len())
+    }
+
+    fn chr_offset_a(&self, slot: usize) -> usize {
+        let regs = &self.chr_a;
+        match self.chr_mode {
+            0 => regs[7] as usize * 0x2000 + slot * 0x400,
+            1 => regs[slot / 4 * 4 + 3] as usize * 0x1000 + (slot % 4) * 0x400,
+            2 => regs[slot / 2 * 2 + 1] as usize * 0x800 + (slot % 2) * 0x400,
+            _ => regs[slot] as usize * 0x400,
+        }
+    }
+
+    // Set B only has 4 KB worth of registers, repeated in both halves
+    fn chr_offset_b(&self, slot: usize) -> usize {
+        let regs = &self.chr_b;
+        match self.chr_mode {
+            0 => regs[3] as usize * 0x2000 + slot * 0x400,
+            1 => regs[3] as usize * 0x1000 + (slot % 4) * 0x400,
+            2 => regs[(slot % 4) / 2 * 2 + 1] as usize * 0x800 + (slot % 2) * 0x400,
+            _ => regs[slot % 4] as usize * 0x400,
+        }
+    }
+
+    fn chr_byte(&self, offset: usize) -> u8 {
+        if self.chr.is_empty() {
+            return 0;
+        }
+        self.chr[offset % self.chr.len()]
+    }
+
+    fn split_enabled(&self) -> bool {
+        self.split_control & 0x80 != 0 && self.exram_mode <= 1
+    }
+
+    fn in_split(&self, column: u8) -> bool {
+        let threshold = self.split_control & 0x1f;
+        if self.split_control & 0x40 == 0 {
+            column < threshold
+        } else {
+            column >= threshold
+        }
+    }
+
+    // Classifies the current read and advances the count
+    fn next_fetch(&mut self) -> Fetch {
+        self.idle_cycles = 0;
+        if !self.in_frame {
+            return Fetch::Other;
+        }
+        let fetch = self.fetch;
+        self.fetch += 1;
+        match fetch {
+            0..=127 => Fetch::Background((fetch / 4 + 2) as u8),
+            SPRITE_FETCHES..=159 => Fetch::Sprite,
+            NEXT_LINE_FETCHES..=167 => Fetch::Background(((fetch - NEXT_LINE_FETCHES) / 4) as u8),
+            _ => Fetch::Other,
+        }
+    }
+
+    // Row of the split region, the last two tiles fetched on a line
+    // belong to the next one
+    fn update_split_y(&mut self) {
+        let line = self.scanline as u16 + (self.fetch > NEXT_LINE_FETCHES) as u16;
+        self.split_y = ((self.split_scroll as u16 + line) % 240) as u8;
+    }
+
+    fn new_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        }
+        self.fetch = 0;
+    }
+
+    fn end_frame(&mut self) {
+        self.in_frame = false;
+        self.last_nametable_address = 0;
+        self.nametable_repeats = 0;
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5015 => self.audio.write(address, value),
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 | 0x5103 => self.ram_protect[address as usize - 0x5102] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_color = value & 0x03,
+            0x5113 => self.prg_ram_bank = value & 0x07,
+            0x5114..=0x5117 => self.prg_banks[address as usize - 0x5114] = value,
+            0x5120..=0x5127 => {
+                self.chr_a[address as usize - 0x5120] = (self.chr_upper as u16) << 8 | value as u16;
+                self.last_set_b = false;
+            },
+            0x5128..=0x512b => {
+                self.chr_b[address as usize - 0x5128] = (self.chr_upper as u16) << 8 | value as u16;
+                self.last_set_b = true;
+            },
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_page = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            // Modes 0 and 1 only take writes during rendering, 3 is read-only
+            0x5c00..=0x5fff => match self.exram_mode {
+                0 | 1 => self.exram[address as usize - 0x5c00] = if self.in_frame { value } else { 0 },
+                2 => self.exram[address as usize - 0x5c00] = value,
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    fn read_register(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x5010 | 0x5015 => self.audio.read(address),
+            // PI------
+            0x5204 => {
+                let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                Some(status)
+            },
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5c00..=0x5fff if self.exram_mode >= 2 => Some(self.exram[address as usize - 0x5c00]),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for MMC5 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x5000..=0x5fff => self.read_register(address),
+            0x6000..=0x7fff => {
+                let bank = self.prg_ram_bank as usize;
+                self.prg_ram_index(bank, address).map(|index| self.prg_ram[index])
+            },
+            0x8000..=0xffff => {
+                // The NMI vector is read when vblank starts
+                if address == 0xfffa || address == 0xfffb {
+                    self.end_frame();
+                }
+                let (rom, bank) = self.prg_window((address as usize - 0x8000) / 0x2000);
+                let value = if rom {
+                    let index = bank * 0x2000 + (address as usize & 0x1fff);
+                    self.prg_rom[index % self.prg_rom.len()]
+                } else {
+                    let index = self.prg_ram_index(bank, address)?;
+                    self.prg_ram[index]
+                };
+                if address < 0xc000 {
+                    self.audio.snoop_read(value);
+                }
+                Some(value)
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x2000 => self.sprites_8x16 = value & 0x20 != 0,
+            0x2001 => {
+                self.rendering = value & 0x18 != 0;
+                if !self.rendering {
+                    self.end_frame();
+                }
+            },
+            0x5000..=0x5fff => self.write_register(address, value),
+            0x6000..=0x7fff if self.prg_ram_writable() => {
+                let bank = self.prg_ram_bank as usize;
+                if let Some(index) = self.prg_ram_index(bank, address) {
+                    self.prg_ram[index] = value;
+                }
+            },
+            0x8000..=0xdfff if self.prg_ram_writable() => {
+                let (rom, bank) = self.prg_window((address as usize - 0x8000) / 0x2000);
+                if !rom {
+                    if let Some(index) = self.prg_ram_index(bank, address) {
+                        self.prg_ram[index] = value;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.nametable_repeats = 0;
+        self.last_nametable_address = 0;
+        let fetch = self.next_fetch();
+        let slot = (address as usize >> 10) & 0x07;
+
+        if let Fetch::Background(column) = fetch {
+            if self.split_enabled() && self.in_split(column) {
+                // Split patterns come from their own 4 KB page, at the
+                // split's fine scroll
+                let offset = self.split_page as usize * 0x1000 +
+                             self.split_tile as usize * 16 +
+                             (address as usize & 0x08) +
+                             (self.split_y & 0x07) as usize;
+                return self.chr_byte(offset);
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.extended_tile & 0x3f) as usize | (self.chr_upper as usize) << 6;
+                return self.chr_byte(bank * 0x1000 + (address as usize & 0x0fff));
+            }
+        }
+
+        let use_b = match fetch {
+            _ if !self.sprites_8x16 => self.last_set_b,
+            Fetch::Sprite => false,
+            Fetch::Background(_) => true,
+            Fetch::Other => self.last_set_b,
+        };
+        let offset = if use_b { self.chr_offset_b(slot) } else { self.chr_offset_a(slot) };
+        self.chr_byte(offset + (address as usize & 0x3ff))
+    }
+
+    // Sprite patterns without counting the fetch
+    fn ppu_peek(&mut self, address: u16) -> u8 {
+        let slot = (address as usize >> 10) & 0x07;
+        let use_b = !self.sprites_8x16 && self.last_set_b;
+        let offset = if use_b { self.chr_offset_b(slot) } else { self.chr_offset_a(slot) };
+        self.chr_byte(offset + (address as usize & 0x3ff))
+    }
+
+    // Through the last register set written, as $2007 accesses read
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr.is_empty() {
+            return;
+        }
+        let slot = (address as usize >> 10) & 0x07;
+        let bank_offset = if self.last_set_b { self.chr_offset_b(slot) } else { self.chr_offset_a(slot) };
+        let offset = (bank_offset + (address as usize & 0x3ff)) % self.chr.len();
+        if offset >= self.chr_ram_start {
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        match self.nametables {
+            0x44 => MirroringType::Vertical,
+            0x50 => MirroringType::Horizontal,
+            0x00 => MirroringType::SingleScreenA,
+            0x55 => MirroringType::SingleScreenB,
+            _ => MirroringType::MapperControlled,
+        }
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        // Scanline detection
+        if address == self.last_nametable_address {
+            self.nametable_repeats += 1;
+            if self.nametable_repeats == 2 {
+                self.new_scanline();
+            }
+        } else {
+            self.last_nametable_address = address;
+            self.nametable_repeats = 0;
+        }
+
+        let offset = address as usize & 0x3ff;
+        let attribute = offset >= 0x3c0;
+        if let Fetch::Background(column) = self.next_fetch() {
+            if self.split_enabled() && self.in_split(column) {
+                self.update_split_y();
+                let row = (self.split_y / 8) as usize;
+                let column = column as usize & 0x1f;
+                if !attribute {
+                    self.split_tile = self.exram[row * 32 + column];
+                    return Some(self.split_tile);
+                }
+                let byte = self.exram[0x3c0 + row / 4 * 8 + column / 4];
+                let shift = (row & 0x02) << 1 | (column & 0x02);
+                return Some(byte >> shift & 0x03);
+            }
+            if self.exram_mode == 1 && attribute {
+                return Some((self.extended_tile >> 6) * 0x55);
+            }
+            if self.exram_mode == 1 {
+                self.extended_tile = self.exram[offset];
+            }
+        }
+
+        match (self.nametables >> ((nametable_index(address)) * 2)) & 0x03 {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if attribute => Some(self.fill_color * 0x55),
+            3 => Some(self.fill_tile),
+            _ => None,
+        }
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        match (self.nametables >> (nametable_index(address) * 2)) & 0x03 {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[address as usize & 0x3ff] = value;
+                }
+                true
+            },
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn nametable_page(&self, table: usize) -> usize {
+        (self.nametables >> (table * 2)) as usize & 0x01
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.audio.tick();
+        if self.in_frame {
+            self.idle_cycles += 1;
+            if self.idle_cycles >= IDLE_CYCLES {
+                self.end_frame();
+            }
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+// Which of the four nametables an address in $2000-$2FFF is in
+fn nametable_index(address: u16) -> usize {
+    (address as usize >> 10) & 0x03
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::mmc5::*;
+    use mapper::test::make_rom;
+
+    // With 64 KB of battery-backed PRG RAM
+    fn make_mmc5() -> MMC5 {
+        let mut rom = make_rom(5, 0, 0x2000, 16, 0x400, 64);
+        rom.header.flags_6 |= 0x02;
+        rom.header.prg_ram_size = 8;
+        MMC5::new(&rom)
+    }
+
+    // The fetches of one rendered scanline, from dot 1 to dot 1 of the next
+    // line. Returns what the background and sprite pattern fetches read.
+    fn scanline(mmc5: &mut MMC5) -> (Vec<u8>, Vec<u8>) {
+        let mut background = Vec::new();
+        let mut sprites = Vec::new();
+        for tile in 0..32 {
+            mmc5.nametable_read(0x2000 + 2 + tile);
+            mmc5.nametable_read(0x23c0);
+            background.push(mmc5.ppu_read(0x0000));
+            mmc5.ppu_read(0x0008);
+        }
+        for _ in 0..8 {
+            mmc5.nametable_read(0x2000);
+            mmc5.nametable_read(0x2000);
+            sprites.push(mmc5.ppu_read(0x1000));
+            mmc5.ppu_read(0x1008);
+        }
+        for tile in 0..2 {
+            mmc5.nametable_read(0x2000 + tile);
+            mmc5.nametable_read(0x23c0);
+            mmc5.ppu_read(0x0000);
+            mmc5.ppu_read(0x0008);
+        }
+        mmc5.nametable_read(0x2002);
+        mmc5.nametable_read(0x2002);
+        mmc5.notify_cpu_cycle();
+        (background, sprites)
+    }
+
+    // Dummy fetches of the pre-render line, the next read starts scanline 0
+    fn start_frame(mmc5: &mut MMC5) {
+        mmc5.nametable_read(0x2002);
+        mmc5.nametable_read(0x2002);
+    }
+
+    #[test]
+    fn switching_prg_banks() {
+        let mut mmc5 = make_mmc5();
+        // Mode 3 at power on, $5117 = $FF
+        assert_eq!(mmc5.cpu_read(0xe000), Some(15));
+        mmc5.write_register(0x5114, 0x83);
+        mmc5.write_register(0x5115, 0x85);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(3));
+        assert_eq!(mmc5.cpu_read(0xa000), Some(5));
+
+        // 16 KB banks ignore the low bit
+        mmc5.write_register(0x5100, 1);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(4));
+        assert_eq!(mmc5.cpu_read(0xa000), Some(5));
+        assert_eq!(mmc5.cpu_read(0xc000), Some(14));
+
+        mmc5.write_register(0x5100, 0);
+        mmc5.write_register(0x5117, 0x05);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(4));
+        assert_eq!(mmc5.cpu_read(0xe000), Some(7));
+    }
+
+    #[test]
+    fn protecting_prg_ram() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc5.cpu_read(0x6000), Some(0x00));
+        mmc5.cpu_write(0x5102, 0x02);
+        mmc5.cpu_write(0x5103, 0x01);
+        mmc5.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc5.cpu_read(0x6000), Some(0x12));
+
+        // RAM mapped into $8000
+        mmc5.cpu_write(0x5114, 0x00);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(0x12));
+        mmc5.cpu_write(0x8001, 0x34);
+        assert_eq!(mmc5.cpu_read(0x6001), Some(0x34));
+    }
+
+    #[test]
+    fn multiplying() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), Some((20000 & 0xff) as u8));
+        assert_eq!(mmc5.cpu_read(0x5206), Some((20000 >> 8) as u8));
+    }
+
+    #[test]
+    fn using_exram_and_fill_mode() {
+        let mut mmc5 = make_mmc5();
+        // Nametable mode, writable only while rendering
+        mmc5.cpu_write(0x5c00, 0x12);
+        mmc5.cpu_write(0x5105, 0xe4);
+        assert_eq!(mmc5.nametable_read(0x2800), Some(0x00));
+        assert_eq!(mmc5.cpu_read(0x5c00), None);
+
+        mmc5.cpu_write(0x5104, 0x02);
+        mmc5.cpu_write(0x5c00, 0x12);
+        assert_eq!(mmc5.cpu_read(0x5c00), Some(0x12));
+        // Read-only mode
+        mmc5.cpu_write(0x5104, 0x03);
+        mmc5.cpu_write(0x5c00, 0x34);
+        assert_eq!(mmc5.cpu_read(0x5c00), Some(0x12));
+
+        mmc5.cpu_write(0x5104, 0x00);
+        assert_eq!(mmc5.nametable_read(0x2800), Some(0x12));
+        assert!(mmc5.nametable_write(0x2801, 0x56));
+        assert_eq!(mmc5.exram[1], 0x56);
+
+        // Fill mode in the fourth nametable
+        mmc5.cpu_write(0x5106, 0x20);
+        mmc5.cpu_write(0x5107, 0x02);
+        assert_eq!(mmc5.nametable_read(0x2c00), Some(0x20));
+        assert_eq!(mmc5.nametable_read(0x2fc0), Some(0xaa));
+
+        // CIRAM for the first two
+        assert_eq!(mmc5.nametable_read(0x2400), None);
+        assert!(!mmc5.nametable_write(0x2400, 0));
+        assert_eq!(mmc5.nametable_page(0), 0);
+        assert_eq!(mmc5.nametable_page(1), 1);
+        assert_eq!(mmc5.mirroring(), MirroringType::MapperControlled);
+        mmc5.cpu_write(0x5105, 0x50);
+        assert_eq!(mmc5.mirroring(), MirroringType::Horizontal);
+    }
+
+    #[test]
+    fn counting_scanlines() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x5203, 2);
+        mmc5.cpu_write(0x5204, 0x80);
+        start_frame(&mut mmc5);
+        scanline(&mut mmc5);
+        assert!(mmc5.in_frame());
+        assert!(!mmc5.irq());
+        scanline(&mut mmc5);
+        assert!(!mmc5.irq());
+        scanline(&mut mmc5);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0xc0));
+        assert!(!mmc5.irq());
+
+        // No PPU reads in vblank
+        for _ in 0..3 {
+            mmc5.notify_cpu_cycle();
+        }
+        assert!(!mmc5.in_frame());
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0x00));
+    }
+
+    #[test]
+    fn selecting_chr_sets() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x5101, 0x03);
+        mmc5.cpu_write(0x5124, 10);
+        mmc5.cpu_write(0x5128, 20);
+        // Outside rendering, the set written last
+        assert_eq!(mmc5.ppu_read(0x0000), 20);
+        assert_eq!(mmc5.ppu_read(0x1000), 20);
+
+        // 8x16 sprites: A for sprites, B for the background
+        mmc5.cpu_write(0x2000, 0x20);
+        start_frame(&mut mmc5);
+        let (background, sprites) = scanline(&mut mmc5);
+        assert!(background.iter().all(|&b| b == 20));
+        assert!(sprites.iter().all(|&b| b == 10));
+    }
+
+    #[test]
+    fn fetching_extended_attributes() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x5104, 0x02);
+        // Tile column 3 uses 4 KB bank 2 and palette 3
+        mmc5.cpu_write(0x5c03, 0xc2);
+        mmc5.cpu_write(0x5104, 0x01);
+        start_frame(&mut mmc5);
+        mmc5.nametable_read(0x2002);
+        assert_eq!(mmc5.nametable_read(0x23c0), Some(0x00));
+        mmc5.ppu_read(0x0000);
+        mmc5.ppu_read(0x0008);
+        mmc5.nametable_read(0x2003);
+        assert_eq!(mmc5.nametable_read(0x23c0), Some(0xff));
+        assert_eq!(mmc5.ppu_read(0x0000), 8);
+    }
+
+    #[test]
+    fn splitting_the_screen() {
+        let mut mmc5 = make_mmc5();
+        mmc5.cpu_write(0x5104, 0x02);
+        mmc5.cpu_write(0x5c02, 0x40);
+        mmc5.cpu_write(0x5104, 0x00);
+        mmc5.cpu_write(0x5101, 0x03);
+        mmc5.cpu_write(0x512b, 5);
+        // Left split up to column 3, patterns from 4 KB page 1
+        mmc5.cpu_write(0x5200, 0x83);
+        mmc5.cpu_write(0x5202, 0x01);
+        start_frame(&mut mmc5);
+        assert_eq!(mmc5.nametable_read(0x2002), Some(0x40));
+        mmc5.nametable_read(0x23c0);
+        // Tile $40 of page 1 is 1 KB bank 5
+        assert_eq!(mmc5.ppu_read(0x0000), 5);
+    }
+
+    #[test]
+    fn playing_audio() {
+        let mut mmc5 = make_mmc5();
+        assert_eq!(mmc5.audio_output(), 0.0);
+        mmc5.cpu_write(0x5015, 0x01);
+        mmc5.cpu_write(0x5000, 0xbf);
+        mmc5.cpu_write(0x5002, 0x04);
+        mmc5.cpu_write(0x5003, 0x08);
+        assert_eq!(mmc5.cpu_read(0x5015), Some(0x01));
+        let mut levels = Vec::new();
+        for _ in 0..64 {
+            mmc5.notify_cpu_cycle();
+            levels.push(mmc5.audio_output());
+        }
+        assert!(levels.iter().any(|&level| level > 0.0));
+
+        mmc5.cpu_write(0x5011, 0x80);
+        assert!(mmc5.audio_output() > 0.0);
+    }
+}
@@ -0,0 +1,296 @@
+// Cartridge boards: how PRG and CHR memory is mapped into the CPU
+// ($4020-$FFFF) and PPU ($0000-$1FFF) address spaces
+// https://wiki.nesdev.com/w/index.php/Mapper
+pub mod bandai;
+pub mod banks;
+pub mod discrete;
+pub mod eeprom;
+pub mod fme7;
+pub mod g101;
+pub mod h3001;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod multicart;
+pub mod n163;
+pub mod nrom;
+pub mod opll;
+pub mod tc0190;
+pub mod vrc1;
+pub mod vrc2;
+pub mod vrc3;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+pub mod x1005;
+pub mod x1017;
+
+use mapper::bandai::Bandai;
+use mapper::discrete::Discrete;
+use mapper::fme7::FME7;
+use mapper::g101::G101;
+use mapper::h3001::H3001;
+use mapper::mmc1::MMC1;
+use mapper::mmc2::MMC2;
+use mapper::mmc3::MMC3;
+use mapper::mmc5::MMC5;
+use mapper::multicart::Multicart;
+use mapper::n163::N163;
+use mapper::nrom::NROM;
+use mapper::tc0190::TC0190;
+use mapper::vrc1::VRC1;
+use mapper::vrc2::VRC2;
+use mapper::vrc3::VRC3;
+use mapper::vrc6::VRC6;
+use mapper::vrc7::VRC7;
+use mapper::x1005::X1005;
+use mapper::x1017::X1017;
+use mapper::banks::Banks;
+use rom::{MirroringType, ROM};
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MapperError {
+    // No implementation for the mapper number in the header
+    Unsupported(u16),
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for MapperError {}
+
+pub trait Mapper {
+    // None when nothing on the cartridge drives the data bus (open bus)
+    fn cpu_read(&mut self, address: u16) -> Option<u8>;
+    // The cartridge sees writes to the PPU registers ($2000-$3FFF) too,
+    // MMC5 snoops the sprite size and rendering state there
+    fn cpu_write(&mut self, address: u16, value: u8);
+
+    // Pattern tables, $0000-$1FFF. The PPU goes through here on every
+    // fetch, boards like MMC2 switch banks depending on what is fetched.
+    fn ppu_read(&mut self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    // Pattern reads the real PPU never makes, for the sprites past the
+    // eighth on a line when the sprite limit is off. Boards reacting to
+    // fetches must not see them.
+    fn ppu_peek(&mut self, address: u16) -> u8 {
+        self.ppu_read(address)
+    }
+
+    fn mirroring(&self) -> MirroringType;
+
+    // Nametable fetches, $2000-$2FFF. Boards with their own nametable
+    // memory answer here, None reads CIRAM.
+    fn nametable_read(&mut self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    // Returns true when the board took the write instead of CIRAM
+    fn nametable_write(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
+
+    // CIRAM page (0: A, 1: B) behind each of the four nametables
+    fn nametable_page(&self, table: usize) -> usize {
+        ciram_page(self.mirroring(), table)
+    }
+
+    // Level of the board's sound chip, on the scale of `APU::output`
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // State of the /IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Called by the PPU once per rendered scanline
+    fn notify_scanline(&mut self) {}
+
+    // Called with every address the PPU puts on its bus, for boards
+    // watching the address lines (A12 for MMC3)
+    fn notify_ppu_address(&mut self, _address: u16) {}
+
+    // Called on every CPU cycle
+    fn notify_cpu_cycle(&mut self) {}
+
+    // Battery-backed memory to persist, None for boards without a battery
+    fn save_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn load_save_ram(&mut self, _data: &[u8]) {}
+
+    // Called when the reset button is pressed, some multicarts switch
+    // games this way
+    fn notify_reset(&mut self) {}
+
+    // Position of the board's dipswitches, multicart menus read it
+    fn set_dipswitch(&mut self, _value: u8) {}
+}
+
+pub fn is_supported(mapper: u16) -> bool {
+    matches!(mapper, 0..=5 | 7 | 9 | 10 | 11 | 13 | 15 | 16 | 19 | 21..=26 | 32 | 33 | 34 | 48 | 59 |
+                     65 | 66 | 69 | 71 | 73 | 75 | 80 | 82 | 85 | 159 | 225..=233)
+}
+
+// Builds the board for the mapper number in the ROM header, or for the
+// board name of UNIF images
+pub fn create(rom: &ROM) -> Result<Box<dyn Mapper>, MapperError> {
+    if let Some(board) = multicart::Board::from_rom(rom) {
+        return Ok(Box::new(Multicart::new(rom, board)));
+    }
+    if let Some(board) = discrete::Board::from_rom(rom) {
+        return Ok(Box::new(Discrete::new(rom, board)));
+    }
+    match rom.mapper() {
+        0 => Ok(Box::new(NROM::new(rom))),
+        1 => Ok(Box::new(MMC1::new(rom))),
+        4 => Ok(Box::new(MMC3::new(rom))),
+        5 => Ok(Box::new(MMC5::new(rom))),
+        9 => Ok(Box::new(MMC2::new(rom, mmc2::Chip::MMC2))),
+        10 => Ok(Box::new(MMC2::new(rom, mmc2::Chip::MMC4))),
+        16 | 159 => Ok(Box::new(Bandai::new(rom))),
+        19 => Ok(Box::new(N163::new(rom))),
+        21 | 22 | 23 | 25 => Ok(Box::new(VRC2::new(rom))),
+        24 | 26 => Ok(Box::new(VRC6::new(rom))),
+        32 => Ok(Box::new(G101::new(rom))),
+        33 | 48 => Ok(Box::new(TC0190::new(rom))),
+        65 => Ok(Box::new(H3001::new(rom))),
+        69 => Ok(Box::new(FME7::new(rom))),
+        73 => Ok(Box::new(VRC3::new(rom))),
+        75 => Ok(Box::new(VRC1::new(rom))),
+        80 => Ok(Box::new(X1005::new(rom))),
+        82 => Ok(Box::new(X1017::new(rom))),
+        85 => Ok(Box::new(VRC7::new(rom))),
+        mapper => Err(MapperError::Unsupported(mapper)),
+    }
+}
+
+// PRG RAM at $6000-$7FFF as declared by the header, volatile and
+// battery-backed parts together
+fn prg_ram(rom: &ROM) -> Vec<u8> {
+    vec![0; rom.header.prg_ram_bytes() + rom.header.prg_nvram_bytes()]
+}
+
+// CHR RAM as declared by the header, 8 KB for boards without CHR ROM
+// when the header doesn't say
+fn chr_ram_size(rom: &ROM) -> usize {
+    let size = rom.header.chr_ram_bytes() + rom.header.chr_nvram_bytes();
+    if size == 0 && rom.chr_rom.is_empty() { 0x2000 } else { size }
+}
+
+// CHR ROM followed by CHR RAM
+fn chr_memory(rom: &ROM) -> Vec<u8> {
+    let mut chr = rom.chr_rom.clone();
+    chr.resize(rom.chr_rom.len() + chr_ram_size(rom), 0);
+    chr
+}
+
+// $0000-$1FFF in windows of `window_size`, writes go to the CHR RAM
+fn chr_banks(rom: &ROM, window_size: usize) -> Banks {
+    Banks::with_ram(rom.chr_rom.clone(), chr_ram_size(rom), 0x2000, window_size)
+}
+
+// CIRAM page for each nametable, four-screen boards don't use CIRAM
+fn ciram_page(mirroring: MirroringType, table: usize) -> usize {
+    match mirroring {
+        MirroringType::Horizontal => (table >> 1) & 0x01,
+        MirroringType::SingleScreenA => 0,
+        MirroringType::SingleScreenB => 1,
+        _ => table & 0x01,
+    }
+}
+
+// Loads a save file into RAM, ignoring size mismatches
+fn copy_save(ram: &mut [u8], data: &[u8]) {
+    let size = ram.len().min(data.len());
+    ram[..size].copy_from_slice(&data[..size]);
+}
+
+#[cfg(test)]
+pub mod test {
+    use mapper::*;
+    use rom::LoadOptions;
+
+    // Image where every PRG and CHR bank starts with its number. The rest
+    // of PRG ROM is $FF, so bus conflicts keep the written value. The
+    // header is iNES 1.0 unless the submapper or mapper number needs NES
+    // 2.0, either way with 8 KB of PRG RAM, and 8 KB of CHR RAM without
+    // CHR banks.
+    pub fn make_rom(mapper: u16, submapper: u8, prg_bank_size: usize, prg_banks: usize,
+                    chr_bank_size: usize, chr_banks: usize) -> ROM {
+        let prg_rom = prg_bank_size * prg_banks;
+        let chr_rom = chr_bank_size * chr_banks;
+        assert!(prg_rom.is_multiple_of(0x4000) && chr_rom.is_multiple_of(0x2000));
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, (prg_rom / 0x4000) as u8, (chr_rom / 0x2000) as u8,
+                            (mapper as u8 & 0x0f) << 4, mapper as u8 & 0xf0, 0, 0, 0, 0, 0, 0, 0, 0];
+        if mapper > 0xff || submapper != 0 {
+            data[7] |= 0x08;
+            data[8] = submapper << 4 | (mapper >> 8) as u8;
+            data[10] = 0x07;
+            data[11] = if chr_banks == 0 { 0x07 } else { 0 };
+        }
+        for bank in 0..prg_banks {
+            let mut prg = vec![0xff; prg_bank_size];
+            prg[0] = bank as u8;
+            data.extend(prg);
+        }
+        for bank in 0..chr_banks {
+            let mut chr = vec![0; chr_bank_size];
+            chr[0] = bank as u8;
+            data.extend(chr);
+        }
+        ROM::from_bytes(&data, &LoadOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn creating_mappers() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        let mapper = create(&rom).unwrap();
+        assert_eq!(mapper.mirroring(), MirroringType::Vertical);
+        assert!(is_supported(0));
+
+        data[6] = 0x60;
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        match create(&rom) {
+            Err(MapperError::Unsupported(6)) => {},
+            _ => panic!("mapper 6 shouldn't be supported"),
+        }
+    }
+
+    #[test]
+    fn allocating_chr_ram() {
+        // UNROM without CHR ROM
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 2, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 32 * 1024, 0);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        let mut mapper = create(&rom).unwrap();
+        mapper.ppu_write(0x1fff, 0x42);
+        assert_eq!(mapper.ppu_read(0x1fff), 0x42);
+
+        // NES 2.0 CNROM with 8 KB of CHR ROM and 32 KB of CHR RAM after it
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x30, 0x08, 0, 0, 0, 0x09, 0, 0, 0, 0];
+        data.resize(16 + 16 * 1024, 0);
+        data.extend(vec![0x11; 8 * 1024]);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(chr_memory(&rom).len(), 40 * 1024);
+        let mut mapper = create(&rom).unwrap();
+        mapper.ppu_write(0x0000, 0x42);
+        assert_eq!(mapper.ppu_read(0x0000), 0x11);
+        mapper.cpu_write(0x8000, 4);
+        mapper.ppu_write(0x0000, 0x42);
+        assert_eq!(mapper.ppu_read(0x0000), 0x42);
+    }
+}
@@ -0,0 +1,506 @@
+// Pirate multicarts: a latch selects the game (outer bank) and sometimes
+// the bank inside it (inner bank), in NROM or UNROM layouts
+// https://wiki.nesdev.com/w/index.php/Category:Multicart_mappers
+//
+// Every board decodes the latched address and value into a `Layout`, the
+// outer and inner parts are put together with `combine`. Some menus read
+// a dipswitch to pick the game count shown, others switch games when the
+// reset button is pressed.
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::unif::short_board_name;
+use rom::{MirroringType, ROM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    // Mapper 15: 100-in-1 Contra Function 16, four PRG modes
+    Mapper15,
+    // Mapper 59, UNIF BMC-D1038: the dipswitch replaces ROM reads
+    D1038,
+    // Mapper 225: 64-in-1 and friends, 4 nibbles of RAM at $5800
+    Mapper225,
+    // Mapper 226, UNIF BMC-Ghostbusters63in1: two registers
+    Mapper226,
+    // Mapper 227: 1200-in-1, NROM and UNROM layouts
+    Mapper227,
+    // Mapper 228: Action 52, three PRG chips
+    Mapper228,
+    // Mapper 229: 31-in-1
+    Mapper229,
+    // Mapper 230: 22-in-1, Contra until the first reset
+    Mapper230,
+    // Mapper 231: 20-in-1
+    Mapper231,
+    // Mapper 232: Camerica Quattro, outer and inner UNROM registers
+    Mapper232,
+    // Mapper 233, UNIF BMC-42in1ResetSwitch: the reset button picks the half
+    Mapper233,
+}
+
+// UNIF names of the boards, as `short_board_name` gives them
+const UNIF_BOARDS: &[(&str, Board)] = &[
+    ("D1038", Board::D1038),
+    ("T3H53", Board::D1038),
+    ("Ghostbusters63in1", Board::Mapper226),
+    ("CAMERICA-BF9096", Board::Mapper232),
+    ("CAMERICA-ALGQ", Board::Mapper232),
+    ("42in1ResetSwitch", Board::Mapper233),
+];
+
+impl Board {
+    // The UNIF board name wins over the mapper number
+    pub fn from_rom(rom: &ROM) -> Option<Board> {
+        if let Some(ref name) = rom.board {
+            let short_name = short_board_name(name);
+            let board = UNIF_BOARDS.iter()
+                .find(|&&(unif, _)| unif.eq_ignore_ascii_case(short_name))
+                .map(|&(_, board)| board);
+            if board.is_some() {
+                return board;
+            }
+        }
+        let board = match rom.mapper() {
+            15 => Board::Mapper15,
+            59 => Board::D1038,
+            225 => Board::Mapper225,
+            226 => Board::Mapper226,
+            227 => Board::Mapper227,
+            228 => Board::Mapper228,
+            229 => Board::Mapper229,
+            230 => Board::Mapper230,
+            231 => Board::Mapper231,
+            232 => Board::Mapper232,
+            233 => Board::Mapper233,
+            _ => return None,
+        };
+        Some(board)
+    }
+}
+
+// PRG banks selected by the latch
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prg {
+    // 32 KB at $8000, in 32 KB units
+    Nrom256(usize),
+    // 16 KB mirrored at $8000 and $C000, in 16 KB units
+    Nrom128(usize),
+    // 16 KB at $8000 and $C000, in 16 KB units
+    Unrom(usize, usize),
+    // 8 KB mirrored four times, in 8 KB units
+    Nrom64(usize),
+}
+
+#[derive(Debug)]
+struct Layout {
+    prg: Prg,
+    // 8 KB CHR bank
+    chr: usize,
+    mirroring: MirroringType,
+    // CHR RAM write protected
+    chr_protected: bool,
+    // Reads from $8000-$FFFF return the dipswitch
+    dipswitch_read: bool,
+}
+
+impl Layout {
+    fn new(prg: Prg, chr: usize, mirroring: MirroringType) -> Layout {
+        Layout { prg, chr, mirroring, chr_protected: false, dipswitch_read: false }
+    }
+}
+
+// Outer bank above the `bits` low bits of the inner one
+fn combine(outer: usize, inner: usize, bits: u32) -> usize {
+    outer << bits | (inner & ((1 << bits) - 1))
+}
+
+fn mirroring(horizontal: bool) -> MirroringType {
+    if horizontal { MirroringType::Horizontal } else { MirroringType::Vertical }
+}
+
+// Last write to $8000-$FFFF
+#[derive(Debug, Default, Clone, Copy)]
+struct Latch {
+    address: u16,
+    value: u8,
+    // Boards with two registers keep both values
+    registers: [u8; 2],
+    // Flipped by the reset button
+    reset_toggle: bool,
+}
+
+#[derive(Debug)]
+pub struct Multicart {
+    board: Board,
+    submapper: u8,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in one 8 KB window
+    chr: Banks,
+    prg_ram: Vec<u8>,
+    // 4-bit RAM of mappers 225 and 228
+    nibbles: [u8; 4],
+    // Mirroring for boards which can't switch it
+    fixed_mirroring: MirroringType,
+    battery: bool,
+
+    latch: Latch,
+    dipswitch: u8,
+    layout: Layout,
+}
+
+impl Multicart {
+    pub fn new(rom: &ROM, board: Board) -> Multicart {
+        let mut multicart = Multicart {
+            board,
+            submapper: rom.submapper(),
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false),
+            chr: chr_banks(rom, 0x2000),
+            prg_ram: if board == Board::Mapper15 { prg_ram(rom) } else { Vec::new() },
+            nibbles: [0; 4],
+            fixed_mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            latch: Latch::default(),
+            dipswitch: 0,
+            layout: Layout::new(Prg::Nrom256(0), 0, rom.mirroring),
+        };
+        multicart.update();
+        multicart
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn decode(&self) -> Layout {
+        let address = self.latch.address as usize;
+        let value = self.latch.value as usize;
+        let bit = |n: u32| address & (1 << n) != 0;
+        match self.board {
+            // Address: ------------MM, value: SMPPPPPP
+            Board::Mapper15 => {
+                let bank = value & 0x3f;
+                let prg = match address & 0x03 {
+                    0 => Prg::Unrom(bank, bank | 1),
+                    1 => Prg::Unrom(bank, bank | 7),
+                    2 => Prg::Nrom64(bank << 1 | value >> 7),
+                    _ => Prg::Nrom128(bank),
+                };
+                let mut layout = Layout::new(prg, 0, mirroring(value & 0x40 != 0));
+                layout.chr_protected = matches!(address & 0x03, 0 | 3);
+                layout
+            },
+            // Address: -------LOPPPMCCC
+            Board::D1038 => {
+                let bank = (address >> 4) & 0x07;
+                let prg = if bit(7) { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                let mut layout = Layout::new(prg, address & 0x07, mirroring(bit(3)));
+                layout.dipswitch_read = bit(8);
+                layout
+            },
+            // Address: -HMOPPPPPPCCCCCC
+            Board::Mapper225 => {
+                let high = (address >> 14) & 0x01;
+                let bank = combine(high, address >> 6, 6);
+                let prg = if bit(12) { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                Layout::new(prg, combine(high, address, 6), mirroring(bit(13)))
+            },
+            // $8000: PMOPPPPP, $8001: ------WH
+            Board::Mapper226 => {
+                let [low, high] = self.latch.registers;
+                let bank = combine(((high & 0x01) << 1 | low >> 7) as usize, low as usize, 5);
+                let prg = if low & 0x20 != 0 { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                let mut layout = Layout::new(prg, 0, mirroring(low & 0x40 == 0));
+                layout.chr_protected = high & 0x02 != 0;
+                layout
+            },
+            // Address: ------LHOPPPPPMS
+            Board::Mapper227 => {
+                let bank = combine((address >> 8) & 0x01, address >> 2, 5);
+                let prg = match (bit(7), bit(0)) {
+                    (true, true) => Prg::Nrom256(bank >> 1),
+                    (true, false) => Prg::Nrom128(bank),
+                    (false, size_32k) => {
+                        let low = if size_32k { bank & !0x01 } else { bank };
+                        let high = if bit(9) { bank | 0x07 } else { bank & 0x38 };
+                        Prg::Unrom(low, high)
+                    },
+                };
+                let mut layout = Layout::new(prg, 0, mirroring(bit(1)));
+                layout.chr_protected = bit(7);
+                layout
+            },
+            // Address: --MCCPPPPPO-HHHH, value: ------LL
+            Board::Mapper228 => {
+                // Chip 2 is missing, chip 3 follows chip 1 in the image
+                let chip = match (address >> 11) & 0x03 {
+                    3 => 2,
+                    chip => chip,
+                };
+                let bank = combine(chip, address >> 6, 5);
+                let prg = if bit(5) { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                Layout::new(prg, combine(address & 0x0f, value, 2), mirroring(bit(13)))
+            },
+            // Address: ----------MBBBBB, bank 0 is the 32 KB menu
+            Board::Mapper229 => {
+                let bank = address & 0x1f;
+                let prg = if bank == 0 { Prg::Nrom256(0) } else { Prg::Nrom128(bank) };
+                Layout::new(prg, bank, mirroring(bit(5)))
+            },
+            // Value: -MOPPPPP, UNROM in the first 128 KB until reset
+            Board::Mapper230 => {
+                if !self.latch.reset_toggle {
+                    return Layout::new(Prg::Unrom(value & 0x07, 7), 0, MirroringType::Vertical);
+                }
+                let bank = (value & 0x1f) + 8;
+                let prg = if value & 0x20 != 0 { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                Layout::new(prg, 0, mirroring(value & 0x40 != 0))
+            },
+            // Address: --------M-LPPPP-
+            Board::Mapper231 => {
+                let bank = address & 0x1e;
+                Layout::new(Prg::Unrom(bank, bank | (address >> 5) & 0x01), 0, mirroring(bit(7)))
+            },
+            // $8000-$BFFF: ---OO---, $C000-$FFFF: ------II
+            Board::Mapper232 => {
+                let [outer, inner] = self.latch.registers;
+                let mut outer = ((outer >> 3) & 0x03) as usize;
+                // Aladdin Deck Enhancer wires the outer bits the other way
+                if self.submapper == 1 {
+                    outer = (outer & 0x01) << 1 | outer >> 1;
+                }
+                let prg = Prg::Unrom(combine(outer, inner as usize, 2), combine(outer, 3, 2));
+                Layout::new(prg, 0, self.fixed_mirroring)
+            },
+            // Value: MMOPPPPP, the reset toggle selects the half
+            Board::Mapper233 => {
+                let bank = combine(self.latch.reset_toggle as usize, value, 5);
+                let prg = if value & 0x20 != 0 { Prg::Nrom128(bank) } else { Prg::Nrom256(bank >> 1) };
+                let mirroring = match value >> 6 {
+                    0 => MirroringType::SingleScreenA,
+                    1 => MirroringType::Vertical,
+                    2 => MirroringType::Horizontal,
+                    _ => MirroringType::SingleScreenB,
+                };
+                Layout::new(prg, 0, mirroring)
+            },
+        }
+    }
+
+    fn update(&mut self) {
+        self.layout = self.decode();
+        match self.layout.prg {
+            Prg::Nrom256(bank) => self.prg_rom.map_range(0, 4, bank as isize * 4),
+            Prg::Nrom128(bank) => {
+                self.prg_rom.map_range(0, 2, bank as isize * 2);
+                self.prg_rom.map_range(2, 2, bank as isize * 2);
+            },
+            Prg::Unrom(low, high) => {
+                self.prg_rom.map_range(0, 2, low as isize * 2);
+                self.prg_rom.map_range(2, 2, high as isize * 2);
+            },
+            Prg::Nrom64(bank) => {
+                for window in 0..4 {
+                    self.prg_rom.map(window, bank as isize);
+                }
+            },
+        }
+        self.chr.map(0, self.layout.chr as isize);
+    }
+
+    // RAM nibbles and their addresses
+    fn nibble_index(&self, address: u16) -> Option<usize> {
+        match (self.board, address) {
+            (Board::Mapper225, 0x5800..=0x5fff) | (Board::Mapper228, 0x4020..=0x5fff) => {
+                Some(address as usize & 0x03)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for Multicart {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        if let Some(index) = self.nibble_index(address) {
+            return Some(self.nibbles[index]);
+        }
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            },
+            0x8000..=0xffff if self.layout.dipswitch_read => Some(self.dipswitch),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let Some(index) = self.nibble_index(address) {
+            self.nibbles[index] = value & 0x0f;
+            return;
+        }
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let size = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % size] = value;
+            },
+            0x8000..=0xffff => {
+                let register = match self.board {
+                    Board::Mapper226 => address as usize & 0x01,
+                    Board::Mapper232 => (address as usize >> 14) & 0x01,
+                    _ => 0,
+                };
+                self.latch.address = address;
+                self.latch.value = value;
+                self.latch.registers[register] = value;
+                self.update();
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if !self.layout.chr_protected {
+            self.chr.write(address as usize, value);
+        }
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.layout.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery && !self.prg_ram.is_empty() { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+
+    // The latch is cleared by reset, the toggle flips
+    fn notify_reset(&mut self) {
+        self.latch = Latch {
+            reset_toggle: !self.latch.reset_toggle,
+            ..Latch::default()
+        };
+        self.update();
+    }
+
+    fn set_dipswitch(&mut self, value: u8) {
+        self.dipswitch = value;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::multicart::*;
+    use rom::unif::test::make_unif;
+    use mapper::test;
+    use rom::LoadOptions;
+
+    // 16 KB PRG banks and 8 KB CHR banks
+    fn make_rom(mapper: u16, prg_banks: usize, chr_banks: usize) -> ROM {
+        test::make_rom(mapper, 0, 0x4000, prg_banks, 0x2000, chr_banks)
+    }
+
+    fn create(rom: &ROM) -> Multicart {
+        Multicart::new(rom, Board::from_rom(rom).unwrap())
+    }
+
+    #[test]
+    fn switching_modes() {
+        let mut multicart = create(&make_rom(15, 64, 0));
+        assert_eq!(multicart.cpu_read(0xc000), Some(1));
+        // UNROM
+        multicart.cpu_write(0x8001, 0x0a);
+        assert_eq!(multicart.cpu_read(0x8000), Some(10));
+        assert_eq!(multicart.cpu_read(0xc000), Some(15));
+        assert_eq!(multicart.mirroring(), MirroringType::Vertical);
+        // 8 KB: the second half of bank 10 everywhere, filled with $FF
+        multicart.cpu_write(0x8002, 0xca);
+        assert_eq!(multicart.cpu_read(0x8000), Some(0xff));
+        assert_eq!(multicart.cpu_read(0xe000), Some(0xff));
+        assert_eq!(multicart.mirroring(), MirroringType::Horizontal);
+
+        // CHR RAM is protected in the NROM modes
+        multicart.ppu_write(0x0000, 0x12);
+        assert_eq!(multicart.ppu_read(0x0000), 0x12);
+        multicart.cpu_write(0x8003, 0x05);
+        assert_eq!(multicart.cpu_read(0xc000), Some(5));
+        multicart.ppu_write(0x0000, 0x34);
+        assert_eq!(multicart.ppu_read(0x0000), 0x12);
+    }
+
+    #[test]
+    fn combining_outer_and_inner_banks() {
+        assert_eq!(combine(2, 0x17, 3), 0x17);
+
+        let mut multicart = create(&make_rom(225, 128, 128));
+        assert_eq!(multicart.board(), Board::Mapper225);
+        // High bit, NROM-128 bank 3, CHR bank 5
+        multicart.cpu_write(0xf0c5, 0);
+        assert_eq!(multicart.cpu_read(0x8000), Some(67));
+        assert_eq!(multicart.cpu_read(0xc000), Some(67));
+        assert_eq!(multicart.ppu_read(0x0000), 69);
+        assert_eq!(multicart.mirroring(), MirroringType::Horizontal);
+        multicart.cpu_write(0x5801, 0xff);
+        assert_eq!(multicart.cpu_read(0x5805), Some(0x0f));
+
+        let data = make_unif("CAMERICA-ALGQ", &[0; 0x40000], &[]);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(create(&rom).board(), Board::Mapper232);
+        let mut multicart = create(&make_rom(232, 16, 0));
+        multicart.cpu_write(0x8000, 0x18);
+        multicart.cpu_write(0xc000, 0x01);
+        assert_eq!(multicart.cpu_read(0x8000), Some(13));
+        assert_eq!(multicart.cpu_read(0xc000), Some(15));
+
+        let mut multicart = create(&make_rom(228, 96, 16));
+        // Chip 3, page 2 as 32 KB, CHR 1 << 2 | 3
+        multicart.cpu_write(0x9881, 0x03);
+        assert_eq!(multicart.cpu_read(0x8000), Some(66));
+        assert_eq!(multicart.cpu_read(0xc000), Some(67));
+        assert_eq!(multicart.ppu_read(0x0000), 7);
+    }
+
+    #[test]
+    fn pressing_reset() {
+        let mut multicart = create(&make_rom(230, 32, 0));
+        multicart.cpu_write(0x8000, 0x05);
+        assert_eq!(multicart.cpu_read(0x8000), Some(5));
+        assert_eq!(multicart.cpu_read(0xc000), Some(7));
+        multicart.notify_reset();
+        multicart.cpu_write(0x8000, 0x22);
+        assert_eq!(multicart.cpu_read(0x8000), Some(10));
+        assert_eq!(multicart.cpu_read(0xc000), Some(10));
+
+        let data = make_unif("BMC-42in1ResetSwitch", &[0; 0x80000], &[]);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        let mut multicart = create(&rom);
+        assert_eq!(multicart.board(), Board::Mapper233);
+        multicart.notify_reset();
+        multicart.cpu_write(0x8000, 0x41);
+        assert_eq!(multicart.mirroring(), MirroringType::Vertical);
+        assert_eq!(multicart.layout.prg, Prg::Nrom256(16));
+    }
+
+    #[test]
+    fn reading_the_dipswitch() {
+        let data = make_unif("BMC-T3H53", &[0; 0x20000], &[0; 0x10000]);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(create(&rom).board(), Board::D1038);
+
+        let mut multicart = create(&make_rom(59, 8, 8));
+        assert_eq!(multicart.board(), Board::D1038);
+        multicart.set_dipswitch(0x02);
+        multicart.cpu_write(0x80d3, 0);
+        assert_eq!(multicart.cpu_read(0x8000), Some(5));
+        assert_eq!(multicart.ppu_read(0x0000), 3);
+        assert_eq!(multicart.mirroring(), MirroringType::Vertical);
+        multicart.cpu_write(0x8100, 0);
+        assert_eq!(multicart.cpu_read(0x8000), Some(0x02));
+    }
+}
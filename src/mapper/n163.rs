@@ -0,0 +1,385 @@
+// Mapper 19: Namco 163
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+//
+//   $4800 internal RAM data port   $F800 internal RAM address, PRG RAM protect
+//   $5000, $5800 IRQ counter       $E000, $E800, $F000 8 KB PRG banks
+//   $8000-$BFFF 1 KB CHR banks     $C000-$DFFF nametables
+// CHR and nametable values of $E0 and up select CIRAM pages instead of
+// CHR ROM. In the pattern tables this isn't supported, those windows keep
+// reading CHR ROM.
+use apu::APU;
+use mapper::banks::Banks;
+use mapper::{chr_banks, chr_memory, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// Each enabled channel gets its turn every 15 CPU cycles
+const CHANNEL_CYCLES: u8 = 15;
+
+// Up to 8 wavetable channels, registers and waveforms share the 128 bytes
+// of internal RAM. Channel 7 is at $78-$7F, channel 0 at $40-$47:
+//   +0, +2, +4 (low 2 bits): 18-bit frequency
+//   +1, +3, +5: 24-bit phase
+//   +4 (high 6 bits): wave length, 256 - 4 * L samples
+//   +6: wave address in 4-bit samples, low nibble first
+//   +7: volume, bits 4-6 of $7F are the channel count - 1
+#[derive(Debug)]
+pub struct Audio {
+    pub ram: [u8; 128],
+    // 0-7 from the highest enabled channel down
+    current: usize,
+    cycles: u8,
+    outputs: [i16; 8],
+}
+
+impl Audio {
+    fn new() -> Audio {
+        Audio {
+            ram: [0; 128],
+            current: 0,
+            cycles: 0,
+            outputs: [0; 8],
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 0x07) as usize + 1
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CHANNEL_CYCLES {
+            return;
+        }
+        self.cycles = 0;
+        let count = self.channel_count();
+        self.current = (self.current + 1) % count;
+        let channel = 7 - self.current;
+        self.outputs[channel] = self.update_channel(channel);
+    }
+
+    fn update_channel(&mut self, channel: usize) -> i16 {
+        let base = 0x40 + channel * 8;
+        let r = |offset: usize| self.ram[base + offset] as u32;
+        let frequency = (r(4) & 0x03) << 16 | r(2) << 8 | r(0);
+        let length = 256 - (r(4) & 0xfc);
+        let phase = (r(5) << 16 | r(3) << 8 | r(1)) + frequency;
+        let phase = phase % (length << 16);
+        let (address, volume) = (r(6), r(7) & 0x0f);
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let sample_index = ((phase >> 16) + address) as usize & 0xff;
+        let byte = self.ram[sample_index >> 1];
+        let sample = if sample_index & 0x01 == 0 { byte & 0x0f } else { byte >> 4 };
+        (sample as i16 - 8) * volume as i16
+    }
+
+    // The channels are played one after the other, heard as their average
+    fn output(&self) -> f32 {
+        let count = self.channel_count();
+        let sum: i16 = self.outputs[8 - count..].iter().sum();
+        sum as f32 / count as f32 * APU::pulse_level(15) / 64.0
+    }
+}
+
+#[derive(Debug)]
+pub struct N163 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows, and the CHR ROM banks used as
+    // nametables
+    chr: Banks,
+    nametable_chr: Banks,
+    battery: bool,
+    pub audio: Audio,
+
+    // 76543210
+    // --------
+    // IAAAAAAA: auto-increment, internal RAM address
+    ram_address: u8,
+    // $F800 0100PPPP: writes allowed, each P protects 2 KB of PRG RAM
+    ram_protect: u8,
+    nametables: [u8; 4],
+    sound_disabled: bool,
+    // 15 bits and the enable bit
+    irq_counter: u16,
+    irq_pending: bool,
+}
+
+impl N163 {
+    pub fn new(rom: &ROM) -> N163 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        N163 {
+            prg_rom,
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x0400),
+            nametable_chr: Banks::new(chr_memory(rom), 0x1000, 0x0400, false),
+            battery: rom.has_battery(),
+            audio: Audio::new(),
+            ram_address: 0,
+            ram_protect: 0,
+            nametables: [0xe0, 0xe1, 0xe0, 0xe1],
+            sound_disabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let chunk = (address as usize - 0x6000) >> 11;
+        self.ram_protect & 0xf0 == 0x40 && self.ram_protect & (1 << chunk) == 0
+    }
+
+    fn increment_address(&mut self) {
+        if self.ram_address & 0x80 != 0 {
+            self.ram_address = 0x80 | (self.ram_address.wrapping_add(1) & 0x7f);
+        }
+    }
+
+    fn is_ciram(&self, table: usize) -> bool {
+        self.nametables[table] >= 0xe0
+    }
+}
+
+impl Mapper for N163 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4fff => {
+                let value = self.audio.ram[(self.ram_address & 0x7f) as usize];
+                self.increment_address();
+                Some(value)
+            },
+            0x5000..=0x57ff => Some(self.irq_counter as u8),
+            0x5800..=0x5fff => Some((self.irq_counter >> 8) as u8),
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800..=0x4fff => {
+                self.audio.ram[(self.ram_address & 0x7f) as usize] = value;
+                self.increment_address();
+            },
+            // Writing the counter acknowledges the IRQ
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0xff00) | value as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (value as u16) << 8;
+                self.irq_pending = false;
+            },
+            0x6000..=0x7fff if !self.prg_ram.is_empty() && self.prg_ram_writable(address) => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            0x8000..=0xbfff => self.chr.map((address as usize - 0x8000) >> 11, value as isize),
+            0xc000..=0xdfff => {
+                let table = (address as usize - 0xc000) >> 11;
+                self.nametables[table] = value;
+                self.nametable_chr.map(table, value as isize);
+            },
+            // -SPPPPPP: sound disable, PRG bank
+            0xe000..=0xe7ff => {
+                self.sound_disabled = value & 0x40 != 0;
+                self.prg_rom.map(0, (value & 0x3f) as isize);
+            },
+            0xe800..=0xefff => self.prg_rom.map(1, (value & 0x3f) as isize),
+            0xf000..=0xf7ff => self.prg_rom.map(2, (value & 0x3f) as isize),
+            0xf800..=0xffff => {
+                self.ram_protect = value;
+                self.ram_address = value;
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        if !(0..4).all(|table| self.is_ciram(table)) {
+            return MirroringType::MapperControlled;
+        }
+        match self.nametables.iter().fold(0, |pages, &value| pages << 1 | (value & 0x01)) {
+            0b0101 => MirroringType::Vertical,
+            0b0011 => MirroringType::Horizontal,
+            0b0000 => MirroringType::SingleScreenA,
+            0b1111 => MirroringType::SingleScreenB,
+            _ => MirroringType::MapperControlled,
+        }
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        let table = (address as usize >> 10) & 0x03;
+        if self.is_ciram(table) {
+            None
+        } else {
+            Some(self.nametable_chr.read(table << 10 | (address as usize & 0x3ff)))
+        }
+    }
+
+    // CHR ROM nametables can't be written
+    fn nametable_write(&mut self, address: u16, _value: u8) -> bool {
+        !self.is_ciram((address as usize >> 10) & 0x03)
+    }
+
+    fn nametable_page(&self, table: usize) -> usize {
+        self.nametables[table] as usize & 0x01
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled { 0.0 } else { self.audio.output() }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // Counts up while enabled and stops at $7FFF
+    fn notify_cpu_cycle(&mut self) {
+        if !self.sound_disabled {
+            self.audio.tick();
+        }
+        if self.irq_counter & 0x8000 != 0 && self.irq_counter & 0x7fff != 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter & 0x7fff == 0x7fff {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    // Boards without PRG RAM keep their saves in the internal RAM
+    fn save_ram(&self) -> Option<&[u8]> {
+        match (self.battery, self.prg_ram.is_empty()) {
+            (false, _) => None,
+            (true, false) => Some(&self.prg_ram),
+            (true, true) => Some(&self.audio.ram),
+        }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        if self.prg_ram.is_empty() {
+            copy_save(&mut self.audio.ram, data);
+        } else {
+            copy_save(&mut self.prg_ram, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::n163::*;
+    use mapper::test::make_rom;
+
+    fn make_n163() -> N163 {
+        let mut rom = make_rom(19, 0, 0x2000, 16, 0x400, 32);
+        rom.header.flags_6 |= 0x02;
+        N163::new(&rom)
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut n163 = make_n163();
+        assert_eq!(n163.cpu_read(0xe000), Some(15));
+        n163.cpu_write(0xe000, 2);
+        n163.cpu_write(0xe800, 3);
+        n163.cpu_write(0xf000, 4);
+        assert_eq!(n163.cpu_read(0x8000), Some(2));
+        assert_eq!(n163.cpu_read(0xa000), Some(3));
+        assert_eq!(n163.cpu_read(0xc000), Some(4));
+
+        n163.cpu_write(0xb800, 9);
+        assert_eq!(n163.ppu_read(0x1c00), 9);
+
+        // PRG RAM is protected until $F800 allows it
+        n163.cpu_write(0x6000, 0x12);
+        assert_eq!(n163.cpu_read(0x6000), Some(0));
+        n163.cpu_write(0xf800, 0x40);
+        n163.cpu_write(0x6000, 0x12);
+        assert_eq!(n163.cpu_read(0x6000), Some(0x12));
+    }
+
+    #[test]
+    fn mapping_nametables() {
+        let mut n163 = make_n163();
+        assert_eq!(n163.mirroring(), MirroringType::Vertical);
+        n163.cpu_write(0xc800, 0xe0);
+        n163.cpu_write(0xd000, 0xe1);
+        assert_eq!(n163.mirroring(), MirroringType::Horizontal);
+        assert_eq!(n163.nametable_read(0x2400), None);
+
+        // CHR ROM bank 5 as the last nametable
+        n163.cpu_write(0xd800, 5);
+        assert_eq!(n163.mirroring(), MirroringType::MapperControlled);
+        assert_eq!(n163.nametable_read(0x2c00), Some(5));
+        assert!(n163.nametable_write(0x2c00, 0));
+    }
+
+    #[test]
+    fn accessing_internal_ram() {
+        let mut n163 = make_n163();
+        n163.cpu_write(0xf800, 0x80 | 0x10);
+        n163.cpu_write(0x4800, 0x11);
+        n163.cpu_write(0x4800, 0x22);
+        n163.cpu_write(0xf800, 0x10);
+        assert_eq!(n163.cpu_read(0x4800), Some(0x11));
+        assert_eq!(n163.cpu_read(0x4800), Some(0x11));
+        assert_eq!(n163.audio.ram[0x11], 0x22);
+    }
+
+    #[test]
+    fn counting_cycles() {
+        let mut n163 = make_n163();
+        n163.cpu_write(0x5000, 0xfd);
+        n163.cpu_write(0x5800, 0xff);
+        n163.notify_cpu_cycle();
+        assert!(!n163.irq());
+        n163.notify_cpu_cycle();
+        assert!(n163.irq());
+        // Stopped at $7FFF
+        n163.notify_cpu_cycle();
+        assert_eq!(n163.cpu_read(0x5000), Some(0xff));
+        n163.cpu_write(0x5800, 0x00);
+        assert!(!n163.irq());
+    }
+
+    #[test]
+    fn playing_waves() {
+        let mut n163 = make_n163();
+        // Channel 7: a 16-sample square wave at $00, volume 15, alone
+        n163.cpu_write(0xf800, 0x80);
+        for _ in 0..4 {
+            n163.cpu_write(0x4800, 0xff);
+        }
+        n163.cpu_write(0xf800, 0x80 | 0x78);
+        // One sample per update: frequency $10000, 16 samples long
+        for &value in [0x00, 0, 0x00, 0, 0xf1, 0, 0x00, 0x0f].iter() {
+            n163.cpu_write(0x4800, value);
+        }
+        let mut levels = vec![];
+        for _ in 0..15 * 16 {
+            n163.notify_cpu_cycle();
+            levels.push(n163.audio_output());
+        }
+        assert!(levels.iter().any(|&level| level > 0.0));
+        assert!(levels.iter().any(|&level| level < 0.0));
+
+        n163.cpu_write(0xe000, 0x40);
+        assert_eq!(n163.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,96 @@
+// Mapper 0: NROM-128 (16 KB PRG ROM mirrored at $C000) and NROM-256
+// https://wiki.nesdev.com/w/index.php/NROM
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct NROM {
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    chr: Banks,
+    mirroring: MirroringType,
+    battery: bool,
+}
+
+impl NROM {
+    pub fn new(rom: &ROM) -> NROM {
+        NROM {
+            prg_rom: Banks::new(rom.prg_rom.clone(), 0x8000, 0x4000, false),
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x2000),
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+        }
+    }
+}
+
+impl Mapper for NROM {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7fff = address {
+            if !self.prg_ram.is_empty() {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            }
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::nrom::*;
+    use rom::LoadOptions;
+
+    #[test]
+    fn mirroring_nrom_128() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 24 * 1024, 0);
+        data[16] = 0x78;
+        data[16 + 16 * 1024] = 0x55;
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        let mut nrom = NROM::new(&rom);
+
+        assert_eq!(nrom.cpu_read(0x8000), Some(0x78));
+        assert_eq!(nrom.cpu_read(0xc000), Some(0x78));
+        assert_eq!(nrom.cpu_read(0x5000), None);
+        assert_eq!(nrom.ppu_read(0x0000), 0x55);
+
+        nrom.cpu_write(0x6000, 0x12);
+        assert_eq!(nrom.cpu_read(0x6000), Some(0x12));
+        assert_eq!(nrom.save_ram().unwrap()[0], 0x12);
+        // PRG ROM isn't writable
+        nrom.cpu_write(0x8000, 0x00);
+        assert_eq!(nrom.cpu_read(0x8000), Some(0x78));
+    }
+}
@@ -0,0 +1,412 @@
+// Yamaha YM2413 (OPLL) derivative inside the VRC7: six two-operator FM
+// channels, one custom and fifteen built-in instruments
+// https://wiki.nesdev.com/w/index.php/VRC7_audio
+//
+// Registers, written through an address and a data port:
+//   $00-$07 custom instrument
+//   $10-$15 low 8 bits of the frequency
+//   $20-$25 --SKBBBF: sustain, key on, octave, high bit of the frequency
+//   $30-$35 IIIIVVVV: instrument, volume (3 dB steps)
+// The chip makes a sample every 72 of its 3.58 MHz cycles, 36 CPU cycles.
+// Levels are handled in dB and converted to amplitudes at the end.
+use std::f32::consts::PI;
+
+const CLOCK_DIVIDER: u8 = 36;
+const SAMPLE_RATE: f32 = 49_716.0;
+// Past this attenuation the envelope is silent
+const MAX_ATTENUATION: f32 = 48.0;
+
+// x0.5 to x15, some values repeat
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// Key scale level in dB at octave 7 for the top 4 bits of the frequency,
+// 6 dB less per lower octave
+const KSL_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+    36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+
+// Built-in instruments 1-15, in the layout of the custom registers:
+//   0, 1: AVESMMMM (tremolo, vibrato, sustained envelope, key scale rate, multiplier)
+//   2:    KKTTTTTT (modulator key scale level, total level)
+//   3:    KK-CMFFF (carrier key scale level, rectified carrier and modulator, feedback)
+//   4, 5: attack and decay rates
+//   6, 7: sustain level and release rate
+// Even bytes are the modulator, odd ones the carrier.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12],
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4],
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02],
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6],
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06],
+];
+
+// Tremolo: 3.7 Hz, 4.8 dB deep. Vibrato: 6.4 Hz, about 14 cents.
+const AM_RATE: f32 = 3.7;
+const AM_DEPTH: f32 = 4.8;
+const VIBRATO_RATE: f32 = 6.4;
+const VIBRATO_DEPTH: f32 = 14.0 / 1200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Envelope {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+// Parameters of one operator decoded from an instrument
+#[derive(Debug, Clone, Copy)]
+struct Patch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: f32,
+    release: u8,
+}
+
+impl Patch {
+    // `op` is 0 for the modulator, 1 for the carrier
+    fn decode(data: &[u8; 8], op: usize) -> Patch {
+        Patch {
+            tremolo: data[op] & 0x80 != 0,
+            vibrato: data[op] & 0x40 != 0,
+            sustained: data[op] & 0x20 != 0,
+            key_scale_rate: data[op] & 0x10 != 0,
+            multiplier: MULTIPLIERS[(data[op] & 0x0f) as usize],
+            key_scale_level: data[2 + op] >> 6,
+            rectified: data[3] & (0x08 << op) != 0,
+            attack: data[4 + op] >> 4,
+            decay: data[4 + op] & 0x0f,
+            sustain_level: (data[6 + op] >> 4) as f32 * 3.0,
+            release: data[6 + op] & 0x0f,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Operator {
+    // In cycles of the waveform
+    phase: f32,
+    // Envelope attenuation in dB
+    level: f32,
+    state: Envelope,
+}
+
+impl Default for Operator {
+    fn default() -> Operator {
+        Operator {
+            phase: 0.0,
+            level: MAX_ATTENUATION,
+            state: Envelope::Release,
+        }
+    }
+}
+
+// dB per sample of decay and release. Rate 1 takes 39 s over 96 dB,
+// each step of the effective rate (4 * rate + key scale) is 2^(1/4) faster.
+fn decay_step(rate: u8, key_scale: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+    let effective = (rate * 4 + key_scale).min(63) as f32;
+    96.0 / (39.28 * SAMPLE_RATE) * (effective / 4.0 - 1.0).exp2()
+}
+
+// Fraction of the remaining attenuation removed per sample in the
+// exponential attack, rate 1 takes 2.8 s
+fn attack_step(rate: u8, key_scale: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+    let effective = (rate * 4 + key_scale).min(63) as f32;
+    let samples = 2.826 * SAMPLE_RATE / (effective / 4.0 - 1.0).exp2();
+    ((MAX_ATTENUATION + 1.0).ln() / samples).min(1.0)
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = Envelope::Attack;
+    }
+
+    fn update_envelope(&mut self, patch: &Patch, key_scale: u8, release: u8) {
+        match self.state {
+            Envelope::Attack if patch.attack == 15 => self.level = 0.0,
+            Envelope::Attack => self.level -= (self.level + 1.0) * attack_step(patch.attack, key_scale),
+            Envelope::Decay => self.level += decay_step(patch.decay, key_scale),
+            // Percussive sounds keep decaying while the key is held
+            Envelope::Sustain if !patch.sustained => self.level += decay_step(patch.release, key_scale),
+            Envelope::Sustain => {},
+            Envelope::Release => self.level += decay_step(release, key_scale),
+        }
+        if self.state == Envelope::Attack && self.level <= 0.0 {
+            self.level = 0.0;
+            self.state = Envelope::Decay;
+        }
+        if self.state == Envelope::Decay && self.level >= patch.sustain_level {
+            self.level = patch.sustain_level;
+            self.state = Envelope::Sustain;
+        }
+        self.level = self.level.min(MAX_ATTENUATION);
+    }
+
+    // `attenuation` is everything but the envelope, in dB.
+    // `modulation` shifts the phase, in cycles.
+    fn output(&self, patch: &Patch, attenuation: f32, modulation: f32) -> f32 {
+        let level = self.level + attenuation;
+        if self.level >= MAX_ATTENUATION {
+            return 0.0;
+        }
+        let wave = (2.0 * PI * (self.phase + modulation)).sin();
+        if patch.rectified && wave < 0.0 {
+            0.0
+        } else {
+            wave * 10f32.powf(-level / 20.0)
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Channel {
+    frequency: u16,
+    octave: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    // Last two modulator outputs
+    feedback: [f32; 2],
+}
+
+impl Channel {
+    // Rate adjustment for higher notes, only the octave without the flag
+    fn key_scale(&self, patch: &Patch) -> u8 {
+        let scale = self.octave << 1 | (self.frequency >> 8) as u8;
+        if patch.key_scale_rate { scale } else { scale >> 2 }
+    }
+
+    fn key_scale_level(&self, patch: &Patch) -> f32 {
+        let level = KSL_LEVELS[(self.frequency >> 5) as usize] - 6.0 * (7 - self.octave) as f32;
+        match patch.key_scale_level {
+            0 => 0.0,
+            ksl => level.max(0.0) / (1 << (3 - ksl)) as f32,
+        }
+    }
+
+    // Release after key off: the sustain flag slows it down, percussive
+    // instruments fade at a fixed rate
+    fn release_rate(&self, patch: &Patch) -> u8 {
+        if self.sustain {
+            5
+        } else if patch.sustained {
+            patch.release
+        } else {
+            7
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Opll {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    divider: u8,
+    // LFO phases in cycles
+    am_phase: f32,
+    vibrato_phase: f32,
+    sample: f32,
+}
+
+impl Opll {
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        let register = self.address;
+        let index = (register & 0x0f) as usize;
+        match register {
+            0x00..=0x07 => self.custom[index] = value,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0x100) | value as u16;
+            },
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0x0ff) | (value as u16 & 0x01) << 8;
+                channel.octave = (value >> 1) & 0x07;
+                channel.sustain = value & 0x20 != 0;
+                let key_on = value & 0x10 != 0;
+                if key_on && !channel.key_on {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                } else if !key_on && channel.key_on {
+                    channel.modulator.state = Envelope::Release;
+                    channel.carrier.state = Envelope::Release;
+                }
+                channel.key_on = key_on;
+            },
+            0x30..=0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = value >> 4;
+                channel.volume = value & 0x0f;
+            },
+            _ => {},
+        }
+    }
+
+    // Silences every channel, as the VRC7 reset bit does
+    pub fn reset(&mut self) {
+        self.channels = [Channel::default(); 6];
+        self.sample = 0.0;
+    }
+
+    fn patch(&self, instrument: u8) -> [u8; 8] {
+        match instrument {
+            0 => self.custom,
+            i => PATCHES[i as usize - 1],
+        }
+    }
+
+    // Called on every CPU cycle
+    pub fn tick(&mut self) {
+        self.divider += 1;
+        if self.divider == CLOCK_DIVIDER {
+            self.divider = 0;
+            self.sample = self.generate();
+        }
+    }
+
+    fn generate(&mut self) -> f32 {
+        self.am_phase = (self.am_phase + AM_RATE / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / SAMPLE_RATE).fract();
+        let tremolo = AM_DEPTH * (1.0 - (2.0 * PI * self.am_phase).cos()) / 2.0;
+        let vibrato = (VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin()).exp2();
+
+        let mut output = 0.0;
+        for i in 0..self.channels.len() {
+            let data = self.patch(self.channels[i].instrument);
+            let modulator_patch = Patch::decode(&data, 0);
+            let carrier_patch = Patch::decode(&data, 1);
+            let channel = &mut self.channels[i];
+
+            // Cycles per sample at x1
+            let increment = channel.frequency as f32 * (channel.octave as f32).exp2() / 524_288.0;
+            let key_scale_level = channel.key_scale_level(&modulator_patch);
+            for (op, patch) in [(&mut channel.modulator, &modulator_patch),
+                                (&mut channel.carrier, &carrier_patch)].iter_mut() {
+                let factor = if patch.vibrato { vibrato } else { 1.0 };
+                op.phase = (op.phase + increment * patch.multiplier * factor).fract();
+            }
+
+            let modulator_key_scale = channel.key_scale(&modulator_patch);
+            let carrier_key_scale = channel.key_scale(&carrier_patch);
+            let modulator_release = channel.release_rate(&modulator_patch);
+            let carrier_release = channel.release_rate(&carrier_patch);
+            channel.modulator.update_envelope(&modulator_patch, modulator_key_scale, modulator_release);
+            channel.carrier.update_envelope(&carrier_patch, carrier_key_scale, carrier_release);
+
+            // Feedback of the average of the last two outputs, up to 4 pi
+            let feedback = match data[3] & 0x07 {
+                0 => 0.0,
+                fb => (channel.feedback[0] + channel.feedback[1]) / 2.0 * 2.0 / (1 << (7 - fb)) as f32,
+            };
+            let total_level = (data[2] & 0x3f) as f32 * 0.75;
+            let modulator_tremolo = if modulator_patch.tremolo { tremolo } else { 0.0 };
+            let modulation = channel.modulator.output(&modulator_patch, total_level + key_scale_level
+                                                      + modulator_tremolo, feedback);
+            channel.feedback = [channel.feedback[1], modulation];
+
+            // The modulator moves the carrier's phase by up to 8 pi
+            let carrier_ksl = channel.key_scale_level(&carrier_patch);
+            let carrier_tremolo = if carrier_patch.tremolo { tremolo } else { 0.0 };
+            let volume = channel.volume as f32 * 3.0;
+            output += channel.carrier.output(&carrier_patch, volume + carrier_ksl + carrier_tremolo,
+                                             modulation * 4.0);
+        }
+        output
+    }
+
+    // Sum of the channels, each within -1.0 to 1.0
+    pub fn output(&self) -> f32 {
+        self.sample
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::opll::*;
+
+    fn write(opll: &mut Opll, register: u8, value: u8) {
+        opll.write_address(register);
+        opll.write_data(value);
+    }
+
+    fn run(opll: &mut Opll, samples: usize) -> f32 {
+        let mut peak: f32 = 0.0;
+        for _ in 0..samples * CLOCK_DIVIDER as usize {
+            opll.tick();
+            peak = peak.max(opll.output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn playing_notes() {
+        let mut opll = Opll::default();
+        assert_eq!(run(&mut opll, 100), 0.0);
+
+        // Instrument 3 (piano) at full volume, A4 on channel 2
+        write(&mut opll, 0x32, 0x30);
+        write(&mut opll, 0x12, 0x20);
+        write(&mut opll, 0x22, 0x18 | 0x01);
+        assert!(run(&mut opll, 2000) > 0.3);
+
+        // Released it fades out
+        write(&mut opll, 0x22, 0x08 | 0x01);
+        run(&mut opll, 100_000);
+        assert_eq!(run(&mut opll, 100), 0.0);
+    }
+
+    #[test]
+    fn using_the_custom_instrument() {
+        let mut opll = Opll::default();
+        // Pure sine: silent modulator, instant attack, no decay
+        for (register, &value) in [0x20, 0x21, 0x3f, 0x00, 0xf0, 0xf0, 0x0f, 0x0f].iter().enumerate() {
+            write(&mut opll, register as u8, value);
+        }
+        write(&mut opll, 0x30, 0x00);
+        write(&mut opll, 0x10, 0xff);
+        write(&mut opll, 0x20, 0x1f);
+        let peak = run(&mut opll, 1000);
+        assert!(peak > 0.95 && peak <= 1.0);
+
+        // 15 volume steps of 3 dB
+        write(&mut opll, 0x30, 0x0f);
+        run(&mut opll, 1);
+        let peak = run(&mut opll, 1000);
+        assert!(peak > 0.0 && peak < 0.01);
+    }
+}
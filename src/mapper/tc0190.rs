@@ -0,0 +1,211 @@
+// Mappers 33 and 48: Taito TC0190 and TC0690
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_033
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_048
+//
+//   $8000, $8001 8 KB PRG banks at $8000 and $A000
+//   $8002, $8003 2 KB CHR banks   $A000-$A003 1 KB CHR banks at $1000
+// TC0690 moves the mirroring bit to $E000 and adds an MMC3-like scanline
+// counter at $C000-$C003.
+use mapper::banks::Banks;
+use mapper::{chr_banks, Mapper};
+use rom::{MirroringType, ROM};
+
+// A12 has to stay low this many CPU cycles before a rise clocks the counter
+const A12_LOW_CYCLES: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    TC0190,
+    TC0690,
+}
+
+#[derive(Debug)]
+pub struct TC0190 {
+    chip: Chip,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    // CPU cycles since power on, and since when A12 has been low
+    cycle: u64,
+    a12_low_since: Option<u64>,
+}
+
+impl TC0190 {
+    pub fn new(rom: &ROM) -> TC0190 {
+        let chip = if rom.mapper() == 48 { Chip::TC0690 } else { Chip::TC0190 };
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map_range(2, 2, -2);
+        TC0190 {
+            chip,
+            prg_rom,
+            chr: chr_banks(rom, 0x0400),
+            mirroring: rom.mirroring,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            cycle: 0,
+            a12_low_since: Some(0),
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    fn set_mirroring(&mut self, horizontal: bool) {
+        self.mirroring = if horizontal { MirroringType::Horizontal } else { MirroringType::Vertical };
+    }
+
+    fn clock_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for TC0190 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        let tc0690 = self.chip == Chip::TC0690;
+        match address & 0xe003 {
+            // -MPPPPPP: mirroring (TC0190 only), PRG bank
+            0x8000 => {
+                if !tc0690 {
+                    self.set_mirroring(value & 0x40 != 0);
+                }
+                self.prg_rom.map(0, (value & 0x3f) as isize);
+            },
+            0x8001 => self.prg_rom.map(1, (value & 0x3f) as isize),
+            0x8002 => self.chr.map_range(0, 2, value as isize * 2),
+            0x8003 => self.chr.map_range(2, 2, value as isize * 2),
+            register @ 0xa000..=0xa003 => self.chr.map(4 + (register & 0x03) as usize, value as isize),
+            // The counter counts up to $FF
+            0xc000 if tc0690 => self.irq_latch = value ^ 0xff,
+            0xc001 if tc0690 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            0xc002 if tc0690 => self.irq_enabled = true,
+            0xc003 if tc0690 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            0xe000 if tc0690 => self.set_mirroring(value & 0x40 != 0),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_ppu_address(&mut self, address: u16) {
+        if self.chip != Chip::TC0690 {
+            return;
+        }
+        let high = address & 0x1000 != 0;
+        match (high, self.a12_low_since) {
+            (true, Some(since)) => {
+                self.a12_low_since = None;
+                if self.cycle - since >= A12_LOW_CYCLES {
+                    self.clock_counter();
+                }
+            },
+            (false, None) => self.a12_low_since = Some(self.cycle),
+            _ => {},
+        }
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::tc0190::*;
+    use mapper::test;
+
+    fn make_rom(mapper: u16) -> ROM {
+        test::make_rom(mapper, 0, 0x2000, 16, 0x400, 32)
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut tc0190 = TC0190::new(&make_rom(33));
+        assert_eq!(tc0190.chip(), Chip::TC0190);
+        assert_eq!(tc0190.cpu_read(0xc000), Some(14));
+        tc0190.cpu_write(0x8000, 0x43);
+        tc0190.cpu_write(0x8001, 4);
+        assert_eq!(tc0190.cpu_read(0x8000), Some(3));
+        assert_eq!(tc0190.cpu_read(0xa000), Some(4));
+        assert_eq!(tc0190.mirroring(), MirroringType::Horizontal);
+
+        tc0190.cpu_write(0x8003, 5);
+        tc0190.cpu_write(0xa002, 20);
+        assert_eq!(tc0190.ppu_read(0x0800), 10);
+        assert_eq!(tc0190.ppu_read(0x0c00), 11);
+        assert_eq!(tc0190.ppu_read(0x1800), 20);
+    }
+
+    #[test]
+    fn counting_scanlines() {
+        let mut tc0690 = TC0190::new(&make_rom(48));
+        assert_eq!(tc0690.chip(), Chip::TC0690);
+        let mirroring = tc0690.mirroring();
+        tc0690.cpu_write(0x8000, 0x40);
+        assert_eq!(tc0690.mirroring(), mirroring);
+        tc0690.cpu_write(0xe000, 0x40);
+        assert_eq!(tc0690.mirroring(), MirroringType::Horizontal);
+
+        // Fires on the second scanline
+        tc0690.cpu_write(0xc000, 0xfe);
+        tc0690.cpu_write(0xc001, 0);
+        tc0690.cpu_write(0xc002, 0);
+        for line in 0..2 {
+            assert!(!tc0690.irq(), "line {}", line);
+            for _ in 0..4 {
+                tc0690.notify_cpu_cycle();
+            }
+            tc0690.notify_ppu_address(0x1000);
+            tc0690.notify_ppu_address(0x0000);
+        }
+        assert!(tc0690.irq());
+        tc0690.cpu_write(0xc003, 0);
+        assert!(!tc0690.irq());
+    }
+}
@@ -0,0 +1,118 @@
+// Mapper 75: Konami VRC1
+// https://wiki.nesdev.com/w/index.php/VRC1
+//
+//   $8000, $A000, $C000 8 KB PRG banks, the last one fixed at $E000
+//   $9000 mirroring and the high bits of the CHR banks
+//   $E000, $F000 4 KB CHR banks
+use mapper::banks::Banks;
+use mapper::{chr_banks, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct VRC1 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in two 4 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    four_screen: bool,
+    chr_banks: [u8; 2],
+}
+
+impl VRC1 {
+    pub fn new(rom: &ROM) -> VRC1 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        VRC1 {
+            prg_rom,
+            chr: chr_banks(rom, 0x1000),
+            mirroring: rom.mirroring,
+            four_screen: rom.mirroring == MirroringType::FourScreen,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn update_chr(&mut self) {
+        for (window, &bank) in self.chr_banks.iter().enumerate() {
+            self.chr.map(window, bank as isize);
+        }
+    }
+}
+
+impl Mapper for VRC1 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address & 0xf000 {
+            0x8000 => self.prg_rom.map(0, (value & 0x0f) as isize),
+            // -----BAM
+            //      ||+- Mirroring (0: vertical, 1: horizontal)
+            //      |+-- Bit 4 of CHR bank 0
+            //      +--- Bit 4 of CHR bank 1
+            0x9000 => {
+                if !self.four_screen {
+                    self.mirroring = if value & 0x01 == 0 {
+                        MirroringType::Vertical
+                    } else {
+                        MirroringType::Horizontal
+                    };
+                }
+                self.chr_banks[0] = (self.chr_banks[0] & 0x0f) | (value & 0x02) << 3;
+                self.chr_banks[1] = (self.chr_banks[1] & 0x0f) | (value & 0x04) << 2;
+                self.update_chr();
+            },
+            0xa000 => self.prg_rom.map(1, (value & 0x0f) as isize),
+            0xc000 => self.prg_rom.map(2, (value & 0x0f) as isize),
+            0xe000 | 0xf000 => {
+                let window = (address as usize >> 12) & 0x01;
+                self.chr_banks[window] = (self.chr_banks[window] & 0x10) | (value & 0x0f);
+                self.update_chr();
+            },
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc1::*;
+    use mapper::test::make_rom;
+
+    #[test]
+    fn switching_banks() {
+        let rom = make_rom(75, 0, 0x2000, 8, 0x1000, 32);
+        let mut vrc1 = VRC1::new(&rom);
+        assert_eq!(vrc1.cpu_read(0xe000), Some(7));
+
+        vrc1.cpu_write(0x8000, 2);
+        vrc1.cpu_write(0xa000, 3);
+        vrc1.cpu_write(0xc000, 4);
+        assert_eq!(vrc1.cpu_read(0x8000), Some(2));
+        assert_eq!(vrc1.cpu_read(0xa000), Some(3));
+        assert_eq!(vrc1.cpu_read(0xc000), Some(4));
+
+        vrc1.cpu_write(0xe000, 5);
+        vrc1.cpu_write(0xf000, 6);
+        vrc1.cpu_write(0x9000, 0x05);
+        assert_eq!(vrc1.ppu_read(0x0000), 5);
+        assert_eq!(vrc1.ppu_read(0x1000), 22);
+        assert_eq!(vrc1.mirroring(), MirroringType::Horizontal);
+    }
+}
@@ -0,0 +1,280 @@
+// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4
+// https://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+//
+// Each register block at $8000-$FFFF has four registers, selected by two
+// CPU address lines that differ between boards:
+//   21: VRC4a (A1, A2), VRC4c (A6, A7)
+//   22: VRC2a (A1, A0), CHR banks in 2 KB units
+//   23: VRC4f and VRC2b (A0, A1), VRC4e (A2, A3)
+//   25: VRC4b and VRC2c (A1, A0), VRC4d (A3, A2)
+// Without a submapper the boards sharing a mapper number are told apart by
+// listening to both pairs of lines.
+use mapper::banks::Banks;
+use mapper::vrc_irq::VrcIrq;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    // No IRQ, one mirroring bit, a 1-bit latch at $6000 without PRG RAM
+    VRC2,
+    VRC4,
+}
+
+#[derive(Debug)]
+pub struct VRC2 {
+    chip: Chip,
+    // Address bits wired to the chip's A0 and A1
+    a0_lines: u16,
+    a1_lines: u16,
+    // VRC2a drops the lowest CHR bank bit
+    chr_shift: u8,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    mirroring: MirroringType,
+    battery: bool,
+
+    prg_banks: [u8; 2],
+    // $8000 switchable and $C000 fixed, or the other way around
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    // VRC2 boards without PRG RAM
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl VRC2 {
+    pub fn new(rom: &ROM) -> VRC2 {
+        let submapper = rom.submapper();
+        let (chip, a0_lines, a1_lines) = match (rom.mapper(), submapper) {
+            (21, 1) => (Chip::VRC4, 0x02, 0x04),
+            (21, 2) => (Chip::VRC4, 0x40, 0x80),
+            (21, _) => (Chip::VRC4, 0x42, 0x84),
+            (22, _) => (Chip::VRC2, 0x02, 0x01),
+            (23, 1) => (Chip::VRC4, 0x01, 0x02),
+            (23, 2) => (Chip::VRC4, 0x04, 0x08),
+            (23, 3) => (Chip::VRC2, 0x01, 0x02),
+            (23, _) => (Chip::VRC4, 0x05, 0x0a),
+            (25, 1) => (Chip::VRC4, 0x02, 0x01),
+            (25, 2) => (Chip::VRC4, 0x08, 0x04),
+            (25, 3) => (Chip::VRC2, 0x02, 0x01),
+            (_, _) => (Chip::VRC4, 0x0a, 0x05),
+        };
+
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map_range(2, 2, -2);
+        VRC2 {
+            chip,
+            a0_lines,
+            a1_lines,
+            chr_shift: (rom.mapper() == 22) as u8,
+            prg_rom,
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x0400),
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            latch: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    // The switchable bank trades places with the second to last one
+    fn update_prg(&mut self) {
+        let (switchable, fixed) = if self.prg_swap { (2, 0) } else { (0, 2) };
+        self.prg_rom.map(switchable, self.prg_banks[0] as isize);
+        self.prg_rom.map(fixed, -2);
+        self.prg_rom.map(1, self.prg_banks[1] as isize);
+    }
+
+    fn update_chr(&mut self) {
+        for (window, &bank) in self.chr_banks.iter().enumerate() {
+            self.chr.map(window, (bank >> self.chr_shift) as isize);
+        }
+    }
+
+    // Register 0-3 in the block from the board's address lines
+    fn register(&self, address: u16) -> u16 {
+        (address & self.a0_lines != 0) as u16 | ((address & self.a1_lines != 0) as u16) << 1
+    }
+}
+
+impl Mapper for VRC2 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x6000..=0x6fff if self.chip == Chip::VRC2 => Some(self.latch),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        let register = self.register(address);
+        match (address & 0xf000, register) {
+            (0x6000..=0x7000, _) if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            (0x6000, _) if self.chip == Chip::VRC2 => self.latch = value & 0x01,
+            (0x8000, _) => {
+                self.prg_banks[0] = value & 0x1f;
+                self.update_prg();
+            },
+            (0x9000, 0..=1) if self.chip == Chip::VRC4 => {
+                self.mirroring = match value & 0x03 {
+                    0 => MirroringType::Vertical,
+                    1 => MirroringType::Horizontal,
+                    2 => MirroringType::SingleScreenA,
+                    _ => MirroringType::SingleScreenB,
+                };
+            },
+            // ------P-: PRG swap mode
+            (0x9000, 2) if self.chip == Chip::VRC4 => {
+                self.prg_swap = value & 0x02 != 0;
+                self.update_prg();
+            },
+            (0x9000, _) if self.chip == Chip::VRC2 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Vertical
+                } else {
+                    MirroringType::Horizontal
+                };
+            },
+            (0xa000, _) => {
+                self.prg_banks[1] = value & 0x1f;
+                self.update_prg();
+            },
+            // Two registers per bank, low and high nibble
+            (0xb000..=0xe000, _) => {
+                let bank = ((address as usize >> 12) - 0xb) * 2 + (register as usize >> 1);
+                let current = self.chr_banks[bank];
+                self.chr_banks[bank] = if register & 0x01 == 0 {
+                    (current & 0x1f0) | (value as u16 & 0x0f)
+                } else {
+                    (current & 0x00f) | (value as u16 & 0x1f) << 4
+                };
+                self.update_chr();
+            },
+            (0xf000, 0) if self.chip == Chip::VRC4 => self.irq.write_latch_low(value),
+            (0xf000, 1) if self.chip == Chip::VRC4 => self.irq.write_latch_high(value),
+            (0xf000, 2) if self.chip == Chip::VRC4 => self.irq.write_control(value),
+            (0xf000, 3) if self.chip == Chip::VRC4 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.irq.tick();
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc2::*;
+    use mapper::test;
+
+    fn make_rom(mapper: u16, submapper: u8) -> ROM {
+        test::make_rom(mapper, submapper, 0x2000, 16, 0x400, 128)
+    }
+
+    #[test]
+    fn switching_vrc4_banks() {
+        // VRC4e: registers on A2 and A3
+        let mut vrc4 = VRC2::new(&make_rom(23, 2));
+        assert_eq!(vrc4.chip(), Chip::VRC4);
+        assert_eq!(vrc4.cpu_read(0xc000), Some(14));
+        assert_eq!(vrc4.cpu_read(0xe000), Some(15));
+
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xa000, 4);
+        assert_eq!(vrc4.cpu_read(0x8000), Some(3));
+        assert_eq!(vrc4.cpu_read(0xa000), Some(4));
+        vrc4.cpu_write(0x9008, 0x02);
+        assert_eq!(vrc4.cpu_read(0x8000), Some(14));
+        assert_eq!(vrc4.cpu_read(0xc000), Some(3));
+
+        // CHR bank 1 = $45 through its low and high nibbles
+        vrc4.cpu_write(0xb008, 0x05);
+        vrc4.cpu_write(0xb00c, 0x04);
+        assert_eq!(vrc4.ppu_read(0x0400), 0x45);
+        vrc4.cpu_write(0x9000, 0x03);
+        assert_eq!(vrc4.mirroring(), MirroringType::SingleScreenB);
+    }
+
+    #[test]
+    fn decoding_without_submapper() {
+        // Mapper 21 listens to both VRC4a and VRC4c lines
+        let mut vrc4 = VRC2::new(&make_rom(21, 0));
+        vrc4.cpu_write(0xb004, 0x07);
+        vrc4.cpu_write(0xb0c0, 0x02);
+        assert_eq!(vrc4.ppu_read(0x0400), 0x27);
+    }
+
+    #[test]
+    fn using_vrc2() {
+        // VRC2a: CHR banks in 2 KB units
+        let mut vrc2 = VRC2::new(&make_rom(22, 0));
+        assert_eq!(vrc2.chip(), Chip::VRC2);
+        vrc2.cpu_write(0xb000, 0x06);
+        assert_eq!(vrc2.ppu_read(0x0000), 3);
+        vrc2.cpu_write(0x9003, 0x01);
+        assert_eq!(vrc2.mirroring(), MirroringType::Horizontal);
+        vrc2.cpu_write(0x9002, 0x02);
+        assert_eq!(vrc2.cpu_read(0x8000), Some(0));
+        // No IRQ
+        vrc2.cpu_write(0xf002, 0x07);
+        vrc2.notify_cpu_cycle();
+        assert!(!vrc2.irq());
+    }
+
+    #[test]
+    fn raising_irqs() {
+        let mut vrc4 = VRC2::new(&make_rom(25, 1));
+        // VRC4b: A1 is A0, A0 is A1
+        vrc4.cpu_write(0xf000, 0x0e);
+        vrc4.cpu_write(0xf002, 0x0f);
+        vrc4.cpu_write(0xf001, 0x07);
+        vrc4.notify_cpu_cycle();
+        vrc4.notify_cpu_cycle();
+        assert!(vrc4.irq());
+        vrc4.cpu_write(0xf003, 0);
+        assert!(!vrc4.irq());
+    }
+}
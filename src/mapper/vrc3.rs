@@ -0,0 +1,182 @@
+// Mapper 73: Konami VRC3 (Salamander)
+// https://wiki.nesdev.com/w/index.php/VRC3
+//
+//   $8000-$BFFF IRQ latch, a nibble per 4 KB
+//   $C000 IRQ control   $D000 IRQ acknowledge
+//   $F000 16 KB PRG bank at $8000, the last one fixed at $C000
+// CHR is always 8 KB of RAM.
+use mapper::banks::Banks;
+use mapper::{copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct VRC3 {
+    // $8000-$FFFF in two 16 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    chr_ram: Banks,
+    mirroring: MirroringType,
+    battery: bool,
+
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_enable_after_ack: bool,
+    irq_enabled: bool,
+    // Only the low 8 bits count
+    irq_8_bit: bool,
+    irq_pending: bool,
+}
+
+impl VRC3 {
+    pub fn new(rom: &ROM) -> VRC3 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x4000, false);
+        prg_rom.map(1, -1);
+        VRC3 {
+            prg_rom,
+            prg_ram: prg_ram(rom),
+            chr_ram: Banks::new(vec![0; 0x2000], 0x2000, 0x2000, true),
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enable_after_ack: false,
+            irq_enabled: false,
+            irq_8_bit: false,
+            irq_pending: false,
+        }
+    }
+}
+
+impl Mapper for VRC3 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            0x8000..=0xbfff => {
+                let shift = ((address - 0x8000) >> 12) * 4;
+                self.irq_latch = (self.irq_latch & !(0x0f << shift)) | ((value as u16 & 0x0f) << shift);
+            },
+            // -----MEA: 8-bit mode, enable, enable after acknowledge
+            0xc000..=0xcfff => {
+                self.irq_enable_after_ack = value & 0x01 != 0;
+                self.irq_enabled = value & 0x02 != 0;
+                self.irq_8_bit = value & 0x04 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_latch;
+                }
+                self.irq_pending = false;
+            },
+            0xd000..=0xdfff => {
+                self.irq_pending = false;
+                self.irq_enabled = self.irq_enable_after_ack;
+            },
+            0xf000..=0xffff => self.prg_rom.map(0, (value & 0x07) as isize),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_ram.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr_ram.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        // On overflow the counter is reloaded, in 8-bit mode only the low byte
+        let overflow = if self.irq_8_bit {
+            let (low, overflow) = (self.irq_counter as u8).overflowing_add(1);
+            let low = if overflow { self.irq_latch as u8 } else { low };
+            self.irq_counter = (self.irq_counter & 0xff00) | low as u16;
+            overflow
+        } else {
+            let (counter, overflow) = self.irq_counter.overflowing_add(1);
+            self.irq_counter = if overflow { self.irq_latch } else { counter };
+            overflow
+        };
+        if overflow {
+            self.irq_pending = true;
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc3::*;
+    use mapper::test::make_rom;
+
+    fn make_vrc3() -> VRC3 {
+        VRC3::new(&make_rom(73, 0, 0x4000, 8, 0, 0))
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut vrc3 = make_vrc3();
+        assert_eq!(vrc3.cpu_read(0xc000), Some(7));
+        vrc3.cpu_write(0xf000, 3);
+        assert_eq!(vrc3.cpu_read(0x8000), Some(3));
+        vrc3.ppu_write(0x0010, 0x55);
+        assert_eq!(vrc3.ppu_read(0x0010), 0x55);
+    }
+
+    #[test]
+    fn counting_cycles() {
+        let mut vrc3 = make_vrc3();
+        // Latch $FFFD
+        vrc3.cpu_write(0x8000, 0x0d);
+        vrc3.cpu_write(0x9000, 0x0f);
+        vrc3.cpu_write(0xa000, 0x0f);
+        vrc3.cpu_write(0xb000, 0x0f);
+        vrc3.cpu_write(0xc000, 0x02);
+        vrc3.notify_cpu_cycle();
+        vrc3.notify_cpu_cycle();
+        assert!(!vrc3.irq());
+        vrc3.notify_cpu_cycle();
+        assert!(vrc3.irq());
+        vrc3.cpu_write(0xd000, 0);
+        assert!(!vrc3.irq());
+
+        // 8-bit mode with latch $12FE ignores the high byte
+        vrc3.cpu_write(0x8000, 0x0e);
+        vrc3.cpu_write(0xb000, 0x01);
+        vrc3.cpu_write(0xa000, 0x02);
+        vrc3.cpu_write(0xc000, 0x06);
+        vrc3.notify_cpu_cycle();
+        assert!(!vrc3.irq());
+        vrc3.notify_cpu_cycle();
+        assert!(vrc3.irq());
+    }
+}
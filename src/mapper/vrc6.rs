@@ -0,0 +1,377 @@
+// Mappers 24 and 26: Konami VRC6a and VRC6b
+// https://wiki.nesdev.com/w/index.php/VRC6
+//
+//   $8000 16 KB PRG bank at $8000   $C000 8 KB PRG bank at $C000
+//   $9000-$B002 audio               $B003 CHR mode, mirroring, PRG RAM enable
+//   $D000-$E003 1 KB CHR banks      $F000-$F002 IRQ
+// VRC6b (26) swaps the A0 and A1 lines. The last 8 KB is fixed at $E000.
+use apu::APU;
+use mapper::banks::Banks;
+use mapper::vrc_irq::VrcIrq;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+// 76543210
+// --------
+// MDDDVVVV: mode (always on), duty, volume
+#[derive(Debug, Default)]
+pub struct VrcPulse {
+    volume: u8,
+    duty: u8,
+    ignore_duty: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl VrcPulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0x07;
+                self.volume = value & 0x0f;
+            },
+            1 => self.period = (self.period & 0xf00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x0ff) | (value as u16 & 0x0f) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            },
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0f;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+// Adds the rate to an accumulator every other step, resets after 7 additions
+#[derive(Debug, Default)]
+pub struct VrcSaw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl VrcSaw {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3f,
+            1 => self.period = (self.period & 0xf00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x0ff) | (value as u16 & 0x0f) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.accumulator = 0;
+                    self.step = 0;
+                }
+            },
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 0x01 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Audio {
+    pub pulse_1: VrcPulse,
+    pub pulse_2: VrcPulse,
+    pub saw: VrcSaw,
+    halted: bool,
+    // Periods are shifted right by 4 or 8 bits
+    shift: u8,
+}
+
+impl Audio {
+    // `block` is 0-2 for $9000, $A000 and $B000
+    fn write(&mut self, block: u16, register: u16, value: u8) {
+        match (block, register) {
+            (0, 3) => {
+                self.halted = value & 0x01 != 0;
+                self.shift = if value & 0x04 != 0 { 8 } else if value & 0x02 != 0 { 4 } else { 0 };
+            },
+            (0, _) => self.pulse_1.write(register, value),
+            (1, _) => self.pulse_2.write(register, value),
+            (_, _) => self.saw.write(register, value),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.halted {
+            return;
+        }
+        self.pulse_1.clock(self.shift);
+        self.pulse_2.clock(self.shift);
+        self.saw.clock(self.shift);
+    }
+
+    // Mixed linearly, a pulse at full volume is as loud as an APU pulse
+    fn output(&self) -> f32 {
+        let sum = self.pulse_1.output() + self.pulse_2.output() + self.saw.output();
+        sum as f32 * APU::pulse_level(15) / 15.0
+    }
+}
+
+#[derive(Debug)]
+pub struct VRC6 {
+    // VRC6b has A0 and A1 swapped
+    swapped_lines: bool,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    battery: bool,
+    pub audio: Audio,
+
+    chr_banks: [u8; 8],
+    // $B003
+    // 76543210
+    // --------
+    // W---MMDD
+    // |   ||++- CHR mode (0: 1 KB banks, 1: 2 KB banks, 2 and 3: mixed)
+    // |   ++--- Mirroring (0: vertical, 1: horizontal, 2: one-screen A, 3: one-screen B)
+    // +-------- PRG RAM enable
+    // Nametables from CHR ROM (bit 4) aren't supported.
+    control: u8,
+    irq: VrcIrq,
+}
+
+impl VRC6 {
+    pub fn new(rom: &ROM) -> VRC6 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        VRC6 {
+            swapped_lines: rom.mapper() == 26,
+            prg_rom,
+            prg_ram: prg_ram(rom),
+            chr: chr_banks(rom, 0x0400),
+            battery: rom.has_battery(),
+            audio: Audio::default(),
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn update_chr(&mut self) {
+        let r = |i: usize| self.chr_banks[i] as isize;
+        let banks = match self.control & 0x03 {
+            0 => [r(0), r(1), r(2), r(3), r(4), r(5), r(6), r(7)],
+            1 => [r(0) & !1, r(0) | 1, r(1) & !1, r(1) | 1, r(2) & !1, r(2) | 1, r(3) & !1, r(3) | 1],
+            _ => [r(0), r(1), r(2), r(3), r(4) & !1, r(4) | 1, r(5) & !1, r(5) | 1],
+        };
+        for (window, &bank) in banks.iter().enumerate() {
+            self.chr.map(window, bank);
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper for VRC6 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        let register = if self.swapped_lines {
+            (address & 0x01) << 1 | (address & 0x02) >> 1
+        } else {
+            address & 0x03
+        };
+        match (address & 0xf000, register) {
+            (0x6000..=0x7000, _) if self.prg_ram_enabled() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            (0x8000, _) => self.prg_rom.map_range(0, 2, (value & 0x0f) as isize * 2),
+            (0xb000, 3) => {
+                self.control = value;
+                self.update_chr();
+            },
+            (0x9000..=0xb000, _) => {
+                let block = (address >> 12) - 0x9;
+                self.audio.write(block, register, value);
+            },
+            (0xc000, _) => self.prg_rom.map(2, (value & 0x1f) as isize),
+            (0xd000..=0xe000, _) => {
+                let bank = ((address as usize >> 12) - 0xd) * 4 + register as usize;
+                self.chr_banks[bank] = value;
+                self.update_chr();
+            },
+            (0xf000, 0) => self.irq.latch = value,
+            (0xf000, 1) => self.irq.write_control(value),
+            (0xf000, 2) => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        match (self.control >> 2) & 0x03 {
+            0 => MirroringType::Vertical,
+            1 => MirroringType::Horizontal,
+            2 => MirroringType::SingleScreenA,
+            _ => MirroringType::SingleScreenB,
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.irq.tick();
+        self.audio.tick();
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc6::*;
+    use mapper::test::make_rom;
+
+    fn make_vrc6(mapper: u16) -> VRC6 {
+        VRC6::new(&make_rom(mapper, 0, 0x2000, 16, 0x400, 32))
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut vrc6 = make_vrc6(24);
+        assert_eq!(vrc6.cpu_read(0xe000), Some(15));
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xc000, 9);
+        assert_eq!(vrc6.cpu_read(0x8000), Some(4));
+        assert_eq!(vrc6.cpu_read(0xa000), Some(5));
+        assert_eq!(vrc6.cpu_read(0xc000), Some(9));
+
+        vrc6.cpu_write(0xd001, 7);
+        vrc6.cpu_write(0xe002, 12);
+        assert_eq!(vrc6.ppu_read(0x0400), 7);
+        assert_eq!(vrc6.ppu_read(0x1800), 12);
+
+        // 2 KB mode: R1 covers $0800-$0FFF
+        vrc6.cpu_write(0xb003, 0x85);
+        assert_eq!(vrc6.ppu_read(0x0800), 6);
+        assert_eq!(vrc6.ppu_read(0x0c00), 7);
+        assert_eq!(vrc6.mirroring(), MirroringType::Horizontal);
+
+        vrc6.cpu_write(0x6000, 0x12);
+        assert_eq!(vrc6.cpu_read(0x6000), Some(0x12));
+        vrc6.cpu_write(0xb003, 0x00);
+        assert_eq!(vrc6.cpu_read(0x6000), None);
+    }
+
+    #[test]
+    fn swapping_address_lines() {
+        let mut vrc6 = make_vrc6(26);
+        vrc6.cpu_write(0xd001, 7);
+        assert_eq!(vrc6.ppu_read(0x0800), 7);
+        vrc6.cpu_write(0xb002, 42);
+        assert_eq!(vrc6.mirroring(), MirroringType::Vertical);
+        vrc6.cpu_write(0xb003, 0x08);
+        assert_eq!(vrc6.mirroring(), MirroringType::SingleScreenA);
+    }
+
+    #[test]
+    fn playing_audio() {
+        let mut vrc6 = make_vrc6(24);
+        assert_eq!(vrc6.audio_output(), 0.0);
+
+        // Pulse at duty 8/16, volume 15
+        vrc6.cpu_write(0x9000, 0x7f);
+        vrc6.cpu_write(0x9001, 0x10);
+        vrc6.cpu_write(0x9002, 0x80);
+        let mut high = 0;
+        for _ in 0..17 * 16 {
+            vrc6.notify_cpu_cycle();
+            if vrc6.audio.pulse_1.output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 17 * 8);
+        assert!(vrc6.audio_output() >= 0.0);
+
+        // The saw climbs by the rate every other step, 6 * 42 >> 3 at the top
+        vrc6.cpu_write(0xb000, 42);
+        vrc6.cpu_write(0xb002, 0x80);
+        let mut peak = 0;
+        for _ in 0..14 {
+            vrc6.notify_cpu_cycle();
+            peak = peak.max(vrc6.audio.saw.output());
+        }
+        assert_eq!(peak, (6 * 42) >> 3);
+    }
+}
@@ -0,0 +1,220 @@
+// Mapper 85: Konami VRC7
+// https://wiki.nesdev.com/w/index.php/VRC7
+//
+//   $8000, $8010, $9000 8 KB PRG banks, the last one fixed at $E000
+//   $9010 audio address   $9030 audio data
+//   $A000-$D010 1 KB CHR banks
+//   $E000 mirroring, audio reset, PRG RAM enable
+//   $E010, $F000, $F010 IRQ latch, control and acknowledge
+// The second register of each pair sits on A4 for VRC7a (Lagrange Point)
+// and on A3 for VRC7b (Tiny Toon Adventures 2).
+use apu::APU;
+use mapper::banks::Banks;
+use mapper::opll::Opll;
+use mapper::vrc_irq::VrcIrq;
+use mapper::{chr_banks, copy_save, prg_ram, Mapper};
+use rom::{MirroringType, ROM};
+
+#[derive(Debug)]
+pub struct VRC7 {
+    // Address bits selecting the second register
+    register_lines: u16,
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    prg_ram: Vec<u8>,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    battery: bool,
+    pub opll: Opll,
+
+    // 76543210
+    // --------
+    // WS----MM
+    // ||    ++- Mirroring (0: vertical, 1: horizontal, 2: one-screen A, 3: one-screen B)
+    // |+------- Audio reset, silences the sound chip while set
+    // +-------- PRG RAM enable
+    control: u8,
+    irq: VrcIrq,
+}
+
+impl VRC7 {
+    pub fn new(rom: &ROM) -> VRC7 {
+        let register_lines = match rom.submapper() {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        VRC7 {
+            register_lines,
+            prg_rom,
+            prg_ram: prg_ram(rom),
+            // Lagrange Point has CHR RAM
+            chr: chr_banks(rom, 0x0400),
+            battery: rom.has_battery(),
+            opll: Opll::default(),
+            control: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper for VRC7 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                Some(self.prg_ram[index])
+            },
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        let second = address & self.register_lines != 0;
+        match (address & 0xf000, second) {
+            (0x6000..=0x7000, _) if self.prg_ram_enabled() => {
+                let index = (address as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = value;
+            },
+            _ if address & 0xf030 == 0x9010 => self.opll.write_address(value),
+            // Ignored while the sound chip is held in reset
+            _ if address & 0xf030 == 0x9030 && self.control & 0x40 == 0 => self.opll.write_data(value),
+            _ if address & 0xf030 == 0x9030 => {},
+            (0x8000, false) => self.prg_rom.map(0, (value & 0x3f) as isize),
+            (0x8000, true) => self.prg_rom.map(1, (value & 0x3f) as isize),
+            (0x9000, false) => self.prg_rom.map(2, (value & 0x3f) as isize),
+            (0xa000..=0xd000, _) => {
+                let window = ((address as usize >> 12) - 0xa) * 2 + second as usize;
+                self.chr.map(window, value as isize);
+            },
+            (0xe000, false) => {
+                self.control = value;
+                if value & 0x40 != 0 {
+                    self.opll.reset();
+                }
+            },
+            (0xe000, true) => self.irq.latch = value,
+            (0xf000, false) => self.irq.write_control(value),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        match self.control & 0x03 {
+            0 => MirroringType::Vertical,
+            1 => MirroringType::Horizontal,
+            2 => MirroringType::SingleScreenA,
+            _ => MirroringType::SingleScreenB,
+        }
+    }
+
+    // A channel at full level is about as loud as two APU pulses
+    fn audio_output(&self) -> f32 {
+        self.opll.output() * APU::pulse_level(30)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.irq()
+    }
+
+    fn notify_cpu_cycle(&mut self) {
+        self.irq.tick();
+        if self.control & 0x40 == 0 {
+            self.opll.tick();
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc7::*;
+    use mapper::test::make_rom;
+
+    fn make_vrc7(submapper: u8) -> VRC7 {
+        let mut rom = make_rom(85, submapper, 0x2000, 16, 0, 0);
+        rom.header.flags_6 |= 0x02;
+        VRC7::new(&rom)
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut vrc7 = make_vrc7(2);
+        assert_eq!(vrc7.cpu_read(0xe000), Some(15));
+        vrc7.cpu_write(0x8000, 3);
+        vrc7.cpu_write(0x8010, 4);
+        vrc7.cpu_write(0x9000, 5);
+        assert_eq!(vrc7.cpu_read(0x8000), Some(3));
+        assert_eq!(vrc7.cpu_read(0xa000), Some(4));
+        assert_eq!(vrc7.cpu_read(0xc000), Some(5));
+        // VRC7b would have taken this as $8010
+        vrc7.cpu_write(0x8008, 6);
+        assert_eq!(vrc7.cpu_read(0x8000), Some(6));
+
+        vrc7.ppu_write(0x0000, 0x42);
+        vrc7.cpu_write(0xd010, 0);
+        assert_eq!(vrc7.ppu_read(0x1c00), 0x42);
+
+        vrc7.cpu_write(0xe000, 0x82);
+        assert_eq!(vrc7.mirroring(), MirroringType::SingleScreenA);
+        vrc7.cpu_write(0x7000, 0x12);
+        assert_eq!(vrc7.cpu_read(0x7000), Some(0x12));
+        vrc7.cpu_write(0xe000, 0x00);
+        assert_eq!(vrc7.cpu_read(0x7000), None);
+    }
+
+    #[test]
+    fn playing_audio() {
+        let mut vrc7 = make_vrc7(1);
+        vrc7.cpu_write(0x9010, 0x30);
+        vrc7.cpu_write(0x9030, 0x10);
+        vrc7.cpu_write(0x9010, 0x10);
+        vrc7.cpu_write(0x9030, 0x80);
+        vrc7.cpu_write(0x9010, 0x20);
+        vrc7.cpu_write(0x9030, 0x17);
+        let mut playing = false;
+        for _ in 0..36 * 1000 {
+            vrc7.notify_cpu_cycle();
+            playing |= vrc7.audio_output() != 0.0;
+        }
+        assert!(playing);
+
+        vrc7.cpu_write(0xe000, 0x40);
+        vrc7.notify_cpu_cycle();
+        assert_eq!(vrc7.audio_output(), 0.0);
+    }
+
+    #[test]
+    fn raising_irqs() {
+        let mut vrc7 = make_vrc7(1);
+        vrc7.cpu_write(0xe008, 0xff);
+        vrc7.cpu_write(0xf000, 0x06);
+        vrc7.notify_cpu_cycle();
+        assert!(vrc7.irq());
+        vrc7.cpu_write(0xf008, 0);
+        assert!(!vrc7.irq());
+    }
+}
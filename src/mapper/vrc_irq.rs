@@ -0,0 +1,116 @@
+// IRQ counter shared by VRC4, VRC6 and VRC7
+// https://wiki.nesdev.com/w/index.php/VRC_IRQ
+//
+// An 8-bit counter counting up from the latch, it raises the IRQ when it
+// overflows. In scanline mode a prescaler divides the CPU clock by 113.667
+// (341 / 3) to approximate scanlines, in cycle mode it counts CPU cycles.
+
+const PRESCALER_PERIOD: i16 = 341;
+
+#[derive(Debug, Default)]
+pub struct VrcIrq {
+    pub latch: u8,
+    counter: u8,
+    prescaler: i16,
+    // Enabled again by the acknowledge
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xf0) | (value & 0x0f);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0f) | (value << 4);
+    }
+
+    // 76543210
+    // --------
+    // -----MEA
+    //      ||+- Enable after acknowledge
+    //      |+-- Enable
+    //      +--- Mode (0: scanline, 1: CPU cycle)
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn irq(&self) -> bool {
+        self.pending
+    }
+
+    // Called on every CPU cycle
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock();
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::vrc_irq::*;
+
+    #[test]
+    fn counting_cycles_and_scanlines() {
+        let mut irq = VrcIrq { latch: 0xfe, ..VrcIrq::default() };
+        irq.write_control(0x07);
+        irq.tick();
+        assert!(!irq.irq());
+        irq.tick();
+        assert!(irq.irq());
+        irq.acknowledge();
+        assert!(!irq.irq());
+
+        // Scanline mode: two lines from $FE, about 227 CPU cycles
+        irq.write_control(0x02);
+        for _ in 0..226 {
+            irq.tick();
+        }
+        assert!(!irq.irq());
+        for _ in 0..2 {
+            irq.tick();
+        }
+        assert!(irq.irq());
+
+        // Not enabled again after the acknowledge
+        irq.acknowledge();
+        for _ in 0..1000 {
+            irq.tick();
+        }
+        assert!(!irq.irq());
+    }
+}
@@ -0,0 +1,130 @@
+// Mapper 80: Taito X1-005
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_080
+//
+// Registers at $7EF0-$7EFF:
+//   $7EF0, $7EF1 2 KB CHR banks   $7EF2-$7EF5 1 KB CHR banks at $1000
+//   $7EF6 mirroring               $7EF8 RAM enable ($A3)
+//   $7EFA, $7EFC, $7EFE 8 KB PRG banks, the last one fixed at $E000
+// Odd addresses mirror the even ones above $7EF6. 128 bytes of RAM at
+// $7F00-$7FFF, often battery-backed.
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, Mapper};
+use rom::{MirroringType, ROM};
+
+const RAM_ENABLE: u8 = 0xa3;
+
+#[derive(Debug)]
+pub struct X1005 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    ram: [u8; 128],
+    mirroring: MirroringType,
+    battery: bool,
+    ram_enable: u8,
+}
+
+impl X1005 {
+    pub fn new(rom: &ROM) -> X1005 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        X1005 {
+            prg_rom,
+            chr: chr_banks(rom, 0x0400),
+            ram: [0; 128],
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            ram_enable: 0,
+        }
+    }
+}
+
+impl Mapper for X1005 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x7f00..=0x7fff if self.ram_enable == RAM_ENABLE => Some(self.ram[address as usize & 0x7f]),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            // 2 KB banks in 1 KB units, the low bit is ignored
+            0x7ef0 | 0x7ef1 => {
+                let window = (address as usize & 0x01) * 2;
+                self.chr.map_range(window, 2, (value & 0xfe) as isize);
+            },
+            0x7ef2..=0x7ef5 => self.chr.map(address as usize - 0x7ef2 + 4, value as isize),
+            // -------M: mirroring (0: horizontal, 1: vertical)
+            0x7ef6 | 0x7ef7 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Horizontal
+                } else {
+                    MirroringType::Vertical
+                };
+            },
+            0x7ef8 | 0x7ef9 => self.ram_enable = value,
+            0x7efa..=0x7eff => self.prg_rom.map((address as usize - 0x7efa) >> 1, value as isize),
+            0x7f00..=0x7fff if self.ram_enable == RAM_ENABLE => self.ram[address as usize & 0x7f] = value,
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::x1005::*;
+    use mapper::test::make_rom;
+
+    #[test]
+    fn switching_banks() {
+        let mut rom = make_rom(80, 0, 0x2000, 16, 0x400, 32);
+        rom.header.flags_6 |= 0x02;
+        let mut x1005 = X1005::new(&rom);
+        assert_eq!(x1005.cpu_read(0xe000), Some(15));
+        x1005.cpu_write(0x7efa, 3);
+        x1005.cpu_write(0x7efd, 4);
+        x1005.cpu_write(0x7efe, 5);
+        assert_eq!(x1005.cpu_read(0x8000), Some(3));
+        assert_eq!(x1005.cpu_read(0xa000), Some(4));
+        assert_eq!(x1005.cpu_read(0xc000), Some(5));
+
+        x1005.cpu_write(0x7ef1, 7);
+        x1005.cpu_write(0x7ef5, 9);
+        assert_eq!(x1005.ppu_read(0x0800), 6);
+        assert_eq!(x1005.ppu_read(0x0c00), 7);
+        assert_eq!(x1005.ppu_read(0x1c00), 9);
+        x1005.cpu_write(0x7ef6, 0x01);
+        assert_eq!(x1005.mirroring(), MirroringType::Vertical);
+
+        // RAM mirrored twice in $7F00-$7FFF, locked without the magic value
+        x1005.cpu_write(0x7f00, 0x12);
+        assert_eq!(x1005.cpu_read(0x7f00), None);
+        x1005.cpu_write(0x7ef8, 0xa3);
+        x1005.cpu_write(0x7f00, 0x12);
+        assert_eq!(x1005.cpu_read(0x7f80), Some(0x12));
+        assert_eq!(x1005.save_ram().unwrap()[0], 0x12);
+    }
+}
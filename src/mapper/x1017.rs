@@ -0,0 +1,158 @@
+// Mapper 82: Taito X1-017
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_082
+//
+// Registers at $7EF0-$7EFF:
+//   $7EF0, $7EF1 2 KB CHR banks   $7EF2-$7EF5 1 KB CHR banks
+//   $7EF6 mirroring, CHR layout   $7EF7-$7EF9 RAM enables ($CA, $69, $84)
+//   $7EFA-$7EFC 8 KB PRG banks, the last one fixed at $E000
+// 5 KB of RAM at $6000-$73FF in three parts with their own enables. The
+// IRQ registers at $7EFD-$7EFF are unused by the games and not emulated.
+use mapper::banks::Banks;
+use mapper::{chr_banks, copy_save, Mapper};
+use rom::{MirroringType, ROM};
+
+const RAM_SIZE: usize = 0x1400;
+// Magic values enabling $6000-$67FF, $6800-$6FFF and $7000-$73FF
+const RAM_ENABLES: [u8; 3] = [0xca, 0x69, 0x84];
+
+#[derive(Debug)]
+pub struct X1017 {
+    // $8000-$FFFF in four 8 KB windows
+    prg_rom: Banks,
+    // $0000-$1FFF in eight 1 KB windows
+    chr: Banks,
+    ram: Vec<u8>,
+    mirroring: MirroringType,
+    battery: bool,
+
+    // 2 KB banks then 1 KB banks
+    chr_banks: [u8; 6],
+    // The 1 KB banks at $0000 and the 2 KB banks at $1000
+    chr_swap: bool,
+    ram_enables: [u8; 3],
+}
+
+impl X1017 {
+    pub fn new(rom: &ROM) -> X1017 {
+        let mut prg_rom = Banks::new(rom.prg_rom.clone(), 0x8000, 0x2000, false);
+        prg_rom.map(3, -1);
+        X1017 {
+            prg_rom,
+            chr: chr_banks(rom, 0x0400),
+            ram: vec![0; RAM_SIZE],
+            mirroring: rom.mirroring,
+            battery: rom.has_battery(),
+            chr_banks: [0; 6],
+            chr_swap: false,
+            ram_enables: [0; 3],
+        }
+    }
+
+    fn update_chr(&mut self) {
+        let (two_kb, one_kb) = if self.chr_swap { (4, 0) } else { (0, 4) };
+        for i in 0..2 {
+            self.chr.map_range(two_kb + i * 2, 2, (self.chr_banks[i] & 0xfe) as isize);
+        }
+        for i in 0..4 {
+            self.chr.map(one_kb + i, self.chr_banks[2 + i] as isize);
+        }
+    }
+
+    fn ram_enabled(&self, address: u16) -> bool {
+        let part = (address as usize - 0x6000) >> 11;
+        self.ram_enables[part] == RAM_ENABLES[part]
+    }
+}
+
+impl Mapper for X1017 {
+    fn cpu_read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x73ff if self.ram_enabled(address) => Some(self.ram[address as usize - 0x6000]),
+            0x8000..=0xffff => Some(self.prg_rom.read(address as usize - 0x8000)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x73ff if self.ram_enabled(address) => self.ram[address as usize - 0x6000] = value,
+            0x7ef0..=0x7ef5 => {
+                self.chr_banks[address as usize - 0x7ef0] = value;
+                self.update_chr();
+            },
+            // ------CM
+            //       |+- Mirroring (0: horizontal, 1: vertical)
+            //       +-- CHR layout
+            0x7ef6 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    MirroringType::Horizontal
+                } else {
+                    MirroringType::Vertical
+                };
+                self.chr_swap = value & 0x02 != 0;
+                self.update_chr();
+            },
+            0x7ef7..=0x7ef9 => self.ram_enables[address as usize - 0x7ef7] = value,
+            // Bank numbers in bits 2-7
+            0x7efa..=0x7efc => self.prg_rom.map(address as usize - 0x7efa, (value >> 2) as isize),
+            _ => {},
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(address as usize)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(address as usize, value);
+    }
+
+    fn mirroring(&self) -> MirroringType {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::x1017::*;
+    use mapper::test::make_rom;
+
+    #[test]
+    fn switching_banks() {
+        let mut rom = make_rom(82, 0, 0x2000, 16, 0x400, 32);
+        rom.header.flags_6 |= 0x02;
+        let mut x1017 = X1017::new(&rom);
+        assert_eq!(x1017.cpu_read(0xe000), Some(15));
+        x1017.cpu_write(0x7efa, 3 << 2);
+        x1017.cpu_write(0x7efb, 4 << 2);
+        x1017.cpu_write(0x7efc, 5 << 2);
+        assert_eq!(x1017.cpu_read(0x8000), Some(3));
+        assert_eq!(x1017.cpu_read(0xa000), Some(4));
+        assert_eq!(x1017.cpu_read(0xc000), Some(5));
+
+        x1017.cpu_write(0x7ef0, 8);
+        x1017.cpu_write(0x7ef2, 20);
+        assert_eq!(x1017.ppu_read(0x0400), 9);
+        assert_eq!(x1017.ppu_read(0x1000), 20);
+        x1017.cpu_write(0x7ef6, 0x03);
+        assert_eq!(x1017.ppu_read(0x0000), 20);
+        assert_eq!(x1017.ppu_read(0x1400), 9);
+        assert_eq!(x1017.mirroring(), MirroringType::Vertical);
+
+        // Only the second part of the RAM enabled
+        x1017.cpu_write(0x7ef8, 0x69);
+        x1017.cpu_write(0x6800, 0x12);
+        x1017.cpu_write(0x6000, 0x34);
+        assert_eq!(x1017.cpu_read(0x6800), Some(0x12));
+        assert_eq!(x1017.cpu_read(0x6000), None);
+        assert_eq!(x1017.save_ram().unwrap().len(), 0x1400);
+    }
+}
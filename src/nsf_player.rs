@@ -0,0 +1,309 @@
+// Headless NSF player: runs the tune's INIT and PLAY routines on the CPU
+// with a minimal bus (RAM, APU, bankswitched PRG) and collects the APU output
+// https://wiki.nesdev.com/w/index.php/NSF#Initializing_a_tune
+use apu::APU;
+use cpu::bus::Bus;
+use cpu::cpu::CPU;
+use rom::nsf::NSF;
+use rom::Region;
+
+// INIT and PLAY return here, nothing is mapped at this address
+const RETURN_ADDRESS: u16 = 0x5000;
+const BANK_SIZE: usize = 4 * 1024;
+// INIT has to return within this many CPU cycles, about a second
+const INIT_BUDGET: u64 = 1_800_000;
+
+#[derive(Debug)]
+pub struct NSFBus {
+    pub ram: [u8; 0x800],
+    // $6000-$7FFF
+    pub sram: [u8; 0x2000],
+    pub apu: APU,
+    // PRG data split into 4KB banks
+    prg: Vec<u8>,
+    // Bank mapped into each 4KB slot of $8000-$FFFF
+    banks: [usize; 8],
+}
+
+impl NSFBus {
+    fn new(nsf: &NSF, apu: APU) -> NSFBus {
+        // Bankswitched tunes are padded so the load address lands at the
+        // same offset in the first bank, others are placed at the load address
+        let padding = if nsf.uses_bankswitching() {
+            nsf.load_address as usize & 0x0fff
+        } else {
+            (nsf.load_address as usize).saturating_sub(0x8000)
+        };
+        let mut prg = vec![0u8; padding];
+        prg.extend_from_slice(&nsf.data);
+        let size = prg.len().max(8 * BANK_SIZE).div_ceil(BANK_SIZE) * BANK_SIZE;
+        prg.resize(size, 0);
+
+        NSFBus {
+            ram: [0; 0x800],
+            sram: [0; 0x2000],
+            apu,
+            prg,
+            banks: [0, 1, 2, 3, 4, 5, 6, 7],
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        self.prg.len() / BANK_SIZE
+    }
+
+    fn switch_bank(&mut self, slot: usize, bank: u8) {
+        self.banks[slot] = bank as usize % self.bank_count();
+    }
+}
+
+impl Bus for NSFBus {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.ram[address as usize & 0x7ff],
+            0x4015 => self.apu.read_register(address).unwrap_or(0),
+            0x6000..=0x7fff => self.sram[address as usize - 0x6000],
+            0x8000..=0xffff => {
+                let offset = address as usize - 0x8000;
+                let bank = self.banks[offset / BANK_SIZE];
+                self.prg[bank * BANK_SIZE + offset % BANK_SIZE]
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram[address as usize & 0x7ff] = byte,
+            0x4000..=0x4017 => self.apu.write_register(address, byte),
+            0x5ff8..=0x5fff => self.switch_bank(address as usize - 0x5ff8, byte),
+            0x6000..=0x7fff => self.sram[address as usize - 0x6000] = byte,
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.apu.tick();
+            if let Some(address) = self.apu.dmc_request() {
+                let byte = self.read_byte(address);
+                self.apu.dmc_fill(byte);
+            }
+        }
+    }
+
+    fn irq(&mut self) -> bool {
+        self.apu.irq()
+    }
+}
+
+#[derive(Debug)]
+pub struct NSFPlayer {
+    pub nsf: NSF,
+    pub cpu: CPU<NSFBus>,
+    region: Region,
+    song: u8,
+    // CPU cycles between PLAY calls
+    play_period: i64,
+    // Counts down to the next PLAY call
+    play_timer: i64,
+    // Rendered but not yet returned samples
+    pending: Vec<f32>,
+}
+
+impl NSFPlayer {
+    // Dual region tunes are played as NTSC
+    pub fn new(nsf: NSF, sample_rate: u32) -> NSFPlayer {
+        let region = match nsf.region {
+            Region::PAL => Region::PAL,
+            _ => Region::NTSC,
+        };
+        NSFPlayer::with_region(nsf, region, sample_rate)
+    }
+
+    pub fn with_region(nsf: NSF, region: Region, sample_rate: u32) -> NSFPlayer {
+        let bus = NSFBus::new(&nsf, APU::new(region, sample_rate));
+        let play_period = nsf.play_speed(region) as i64 * region.cpu_clock() as i64 / 1_000_000;
+        let song = nsf.starting_song;
+        let mut player = NSFPlayer {
+            nsf,
+            cpu: CPU::new(bus),
+            region,
+            song,
+            play_period,
+            play_timer: play_period,
+            pending: Vec::new(),
+        };
+        player.start(song);
+        player
+    }
+
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cpu.bus.apu.sample_rate()
+    }
+
+    // Resets the machine and runs INIT for the zero based song number
+    pub fn start(&mut self, song: u8) {
+        self.song = song;
+        let sample_rate = self.sample_rate();
+        let bus = &mut self.cpu.bus;
+        bus.ram = [0; 0x800];
+        bus.sram = [0; 0x2000];
+        bus.apu = APU::new(self.region, sample_rate);
+        for address in 0x4000..0x4014 {
+            bus.write(address, 0x00);
+        }
+        bus.write(0x4015, 0x00);
+        bus.write(0x4015, 0x0f);
+        bus.write(0x4017, 0x40);
+        match self.nsf.bankswitch {
+            Some(banks) => for (slot, &bank) in banks.iter().enumerate() {
+                bus.switch_bank(slot, bank);
+            },
+            None => bus.banks = [0, 1, 2, 3, 4, 5, 6, 7],
+        }
+
+        self.cpu.a_reg = song;
+        self.cpu.x_reg = (self.region == Region::PAL) as u8;
+        self.cpu.y_reg = 0;
+        self.cpu.sp_reg = 0xfd;
+        self.cpu.p_reg.set_interrupt_flag(true);
+        self.pending.clear();
+
+        let init = self.nsf.init_address;
+        self.call(init);
+        let start = self.cpu.cycles;
+        while !self.is_idle() && self.cpu.cycles - start < INIT_BUDGET {
+            self.cpu.step();
+        }
+        // Sound produced by INIT is dropped, playback starts with PLAY
+        self.cpu.bus.apu.take_samples();
+        self.play_timer = 0;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.cpu.pc_reg == RETURN_ADDRESS
+    }
+
+    // Simulates a JSR from the return address
+    fn call(&mut self, address: u16) {
+        self.cpu.push_word(RETURN_ADDRESS.wrapping_sub(1));
+        self.cpu.pc_reg = address;
+    }
+
+    // Runs the tune until `count` samples are produced. The values are
+    // in -1.0..1.0 after the high-pass filter.
+    pub fn render(&mut self, count: usize) -> Vec<f32> {
+        while self.pending.len() < count {
+            let cycles = if self.is_idle() {
+                if self.play_timer <= 0 {
+                    self.play_timer += self.play_period;
+                    let play = self.nsf.play_address;
+                    self.call(play);
+                }
+                self.cpu.bus.tick(1);
+                self.cpu.cycles += 1;
+                1
+            } else {
+                self.cpu.step()
+            };
+            self.play_timer -= cycles as i64;
+            let samples = self.cpu.bus.apu.take_samples();
+            self.pending.extend(samples);
+        }
+        let rest = self.pending.split_off(count);
+        ::std::mem::replace(&mut self.pending, rest)
+    }
+
+    // Same as `render`, as signed 16-bit PCM
+    pub fn render_pcm(&mut self, count: usize) -> Vec<i16> {
+        self.render(count).iter()
+            .map(|&sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nsf_player::*;
+    use rom::nsf::test::make_nsf;
+
+    // INIT ($8000) sets up pulse 1 at ~440 Hz and counts its calls in $00,
+    // PLAY ($8020) counts its calls in $01
+    fn tone_nsf() -> NSF {
+        let mut program = vec![0xea; 0x30];
+        let init = [
+            0xe6, 0x00,       // INC $00
+            0x85, 0x02,       // STA $02
+            0xa9, 0xbf,       // LDA #$BF
+            0x8d, 0x00, 0x40, // STA $4000
+            0xa9, 0xfd,       // LDA #$FD
+            0x8d, 0x02, 0x40, // STA $4002
+            0xa9, 0x08,       // LDA #$08
+            0x8d, 0x03, 0x40, // STA $4003
+            0x60,             // RTS
+        ];
+        let play = [
+            0xe6, 0x01,       // INC $01
+            0x60,             // RTS
+        ];
+        program[..init.len()].copy_from_slice(&init);
+        program[0x20..0x20 + play.len()].copy_from_slice(&play);
+
+        let mut data = make_nsf(&program);
+        data[0x0c] = 0x20;
+        NSF::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn calling_init_and_play() {
+        let mut player = NSFPlayer::new(tone_nsf(), 44100);
+        assert_eq!(player.cpu.bus.ram[0], 1);
+        // The starting song is passed in A
+        assert_eq!(player.cpu.bus.ram[2], 1);
+
+        let samples = player.render(44100);
+        assert_eq!(samples.len(), 44100);
+        // About 60 PLAY calls a second
+        let plays = player.cpu.bus.ram[1];
+        assert!((59..=61).contains(&plays), "{} PLAY calls", plays);
+
+        player.start(2);
+        assert_eq!(player.song(), 2);
+        assert_eq!(player.cpu.bus.ram[1], 0);
+        assert_eq!(player.cpu.bus.ram[2], 2);
+    }
+
+    #[test]
+    fn rendering_a_tone() {
+        let mut player = NSFPlayer::new(tone_nsf(), 44100);
+        // Let the high-pass filter settle
+        player.render(4410);
+        let samples = player.render_pcm(4410);
+        assert!(samples.iter().any(|&s| s > 1000));
+
+        // Count rising zero crossings of the square wave: 440 Hz
+        let crossings = samples.windows(2).filter(|w| w[0] < 0 && w[1] >= 0).count();
+        assert!((42..=46).contains(&crossings), "{} crossings", crossings);
+    }
+
+    #[test]
+    fn switching_banks() {
+        let mut data = make_nsf(&[]);
+        data[0x70..0x78].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 1]);
+        data.extend(vec![0x11; BANK_SIZE]);
+        data.extend(vec![0x22; BANK_SIZE]);
+        let nsf = NSF::from_bytes(&data).unwrap();
+
+        let mut bus = NSFBus::new(&nsf, APU::new(Region::NTSC, 44100));
+        assert_eq!(bus.bank_count(), 8);
+        bus.switch_bank(7, 1);
+        assert_eq!(bus.read_byte(0xf000), 0x22);
+        bus.write(0x5fff, 0);
+        assert_eq!(bus.read_byte(0xffff), 0x11);
+    }
+}
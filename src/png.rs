@@ -0,0 +1,87 @@
+// Minimal PNG writer for screenshots: 8-bit RGBA, no filtering
+// https://www.w3.org/TR/png/
+//
+// A PNG file is the signature followed by chunks, each with its length, a
+// 4-letter type, the data and a CRC-32 of the type and data. IHDR gives the
+// size and pixel format, IDAT the zlib-compressed rows, every row starting
+// with its filter type, and IEND ends the file.
+use crc32fast::Hasher;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// `rgba` holds `width` * `height` pixels of 4 bytes. PNG has no empty
+// images, both must be at least 1.
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(width > 0 && height > 0, "empty {}x{} image", width, height);
+    assert_eq!(rgba.len(), width * height * 4);
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgba.chunks(width * 4) {
+        // Filter type 0, the bytes as they are
+        encoder.write_all(&[0]).unwrap();
+        encoder.write_all(row).unwrap();
+    }
+    write_chunk(&mut png, b"IDAT", &encoder.finish().unwrap());
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn write<P: AsRef<Path>>(path: P, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    fs::write(path, encode(width, height, rgba))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut hasher = Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    png.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use png::*;
+
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn encoding_images() {
+        let rgba = [0xff, 0x00, 0x00, 0xff, 0x00, 0xff, 0x00, 0x80,
+                    0x00, 0x00, 0xff, 0xff, 0x10, 0x20, 0x30, 0x40];
+        let png = encode(2, 2, &rgba);
+        assert_eq!(png[..8], SIGNATURE);
+        // IHDR
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+        assert_eq!(png[29..33], crc32fast::hash(&png[12..29]).to_be_bytes());
+
+        // IDAT: rows with their filter byte
+        let length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&png[41..41 + length]).read_to_end(&mut rows).unwrap();
+        assert_eq!(rows[0], 0);
+        assert_eq!(rows[1..9], rgba[..8]);
+        assert_eq!(rows[9], 0);
+        assert_eq!(rows[10..], rgba[8..]);
+
+        assert_eq!(png[png.len() - 12..png.len() - 4], [0, 0, 0, 0, b'I', b'E', b'N', b'D']);
+    }
+}
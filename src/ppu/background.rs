@@ -0,0 +1,263 @@
+// Background fetches and shift registers
+// https://wiki.nesdev.com/w/index.php/PPU_rendering
+//
+// Every 8 dots the PPU fetches a nametable byte, an attribute byte and the
+// two pattern bytes of the next tile, two dots each. The pattern bytes go
+// into the low half of two 16-bit shift registers at the start of the next
+// tile, the top bit (offset by fine X) is the pixel being drawn.
+//
+// Scrolling is the VRAM address `v` walking through the nametables: coarse
+// X goes up after every tile, Y after dot 256, dot 257 reloads the
+// horizontal bits from `t` and dots 280-304 of the pre-render line reload
+// the vertical ones.
+use mapper::Mapper;
+use ppu::PPU;
+
+#[derive(Debug, Default)]
+pub struct Background {
+    // Latches of the tile being fetched
+    nametable: u8,
+    attribute: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+    // Pattern bits of the current and the next tile, and their palette bits
+    // spread to one bit per pixel
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+}
+
+impl PPU {
+    // Fetches and scroll updates of one dot on the visible and pre-render lines
+    pub(super) fn fetch_background(&mut self, mapper: &mut dyn Mapper) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+            if dot % 8 == 1 {
+                self.reload_background();
+            }
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match (dot - 1) % 8 {
+                0 => {
+                    let address = 0x2000 | (self.v & 0x0fff);
+                    self.background.nametable = self.fetch(address, mapper);
+                },
+                2 => {
+                    let v = self.v;
+                    let address = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    // Each byte covers 4x4 tiles, 2 bits for every 2x2
+                    let shift = ((v >> 4) & 0x04) | (v & 0x02);
+                    self.background.attribute = (self.fetch(address, mapper) >> shift) & 0x03;
+                },
+                4 => {
+                    let address = self.pattern_address();
+                    self.background.pattern_low = self.fetch(address, mapper);
+                },
+                6 => {
+                    let address = self.pattern_address() + 8;
+                    self.background.pattern_high = self.fetch(address, mapper);
+                },
+                7 => self.increment_coarse_x(),
+                _ => {},
+            }
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            // t: ----F-- ---EDCBA -> v
+            257 => self.v = (self.v & !0x041f) | (self.t & 0x041f),
+            // t: GHIA.BC DEF----- -> v
+            280..=304 if self.scanline == self.pre_render_line() => {
+                self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+            },
+            // Two more nametable fetches nobody uses, MMC5 counts on them
+            337 | 339 => {
+                let address = 0x2000 | (self.v & 0x0fff);
+                self.fetch(address, mapper);
+            },
+            _ => {},
+        }
+    }
+
+    fn pattern_address(&self) -> u16 {
+        let table = (self.ctrl as u16 & 0x10) << 8;
+        let fine_y = (self.v >> 12) & 0x07;
+        table | (self.background.nametable as u16) << 4 | fine_y
+    }
+
+    fn shift_background(&mut self) {
+        let background = &mut self.background;
+        background.pattern_shift_low <<= 1;
+        background.pattern_shift_high <<= 1;
+        background.attribute_shift_low <<= 1;
+        background.attribute_shift_high <<= 1;
+    }
+
+    fn reload_background(&mut self) {
+        let background = &mut self.background;
+        background.pattern_shift_low = (background.pattern_shift_low & 0xff00) | background.pattern_low as u16;
+        background.pattern_shift_high = (background.pattern_shift_high & 0xff00) | background.pattern_high as u16;
+        let attribute = background.attribute;
+        let fill = |bit: u8| if attribute & bit != 0 { 0xff } else { 0x00 };
+        background.attribute_shift_low = (background.attribute_shift_low & 0xff00) | fill(0x01);
+        background.attribute_shift_high = (background.attribute_shift_high & 0xff00) | fill(0x02);
+    }
+
+    // Palette entry of the background at the current dot, 0 when transparent
+    pub(super) fn background_pixel(&self) -> u8 {
+        let x = self.dot - 1;
+        if self.mask & 0x08 == 0 || (x < 8 && self.mask & 0x02 == 0) {
+            return 0;
+        }
+        let background = &self.background;
+        let bit = 15 - self.x as u16;
+        let pixel = ((background.pattern_shift_high >> bit) & 0x01) << 1 |
+                    ((background.pattern_shift_low >> bit) & 0x01);
+        if pixel == 0 {
+            return 0;
+        }
+        let palette = ((background.attribute_shift_high >> bit) & 0x01) << 1 |
+                      ((background.attribute_shift_low >> bit) & 0x01);
+        (palette << 2 | pixel) as u8
+    }
+
+    // Coarse X wraps into the next horizontal nametable
+    pub(super) fn increment_coarse_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // Fine Y, then coarse Y which wraps into the next vertical nametable
+    // after row 29. Rows 30 and 31 (the attributes) wrap in the same one.
+    pub(super) fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03e0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03e0) | coarse_y << 5;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ppu::*;
+    use ppu::test::{make_mapper, run_to, write_vram as write};
+
+    fn scroll(ppu: &mut PPU, mapper: &mut dyn Mapper, x: u8, y: u8) {
+        ppu.write_register(0x2000, 0x00, mapper);
+        ppu.write_register(0x2005, x, mapper);
+        ppu.write_register(0x2005, y, mapper);
+    }
+
+    #[test]
+    fn drawing_tiles() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        // Tile 1: 4 pixels of color 1, then 4 of color 2
+        write(&mut ppu, &mut mapper, 0x0010, &[0xf0; 8]);
+        write(&mut ppu, &mut mapper, 0x0018, &[0x0f; 8]);
+        write(&mut ppu, &mut mapper, 0x2000, &[0x01; 32]);
+        // Palette 1 for the top right 2x2 tiles of the first 4x4
+        write(&mut ppu, &mut mapper, 0x23c0, &[0x04]);
+        write(&mut ppu, &mut mapper, 0x3f00, &[0x0f, 0x11, 0x22, 0x00, 0x00, 0x15, 0x26]);
+        scroll(&mut ppu, &mut mapper, 0, 0);
+        ppu.write_register(0x2001, 0x0a, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 261, 0);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        let frame = ppu.frame_buffer();
+        assert_eq!(frame[0..8], [0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x22, 0x22]);
+        assert_eq!(frame[16..20], [0x15; 4]);
+        // Tile 0 below is blank
+        assert_eq!(frame[8 * SCREEN_WIDTH], 0x0f);
+
+        // Fine X, the left 8 pixels hidden
+        scroll(&mut ppu, &mut mapper, 3, 0);
+        ppu.write_register(0x2001, 0x08, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 261, 0);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        let frame = ppu.frame_buffer();
+        assert_eq!(frame[0..8], [0x0f; 8]);
+        assert_eq!(frame[8..13], [0x11, 0x22, 0x22, 0x22, 0x22]);
+        // The third tile has palette 1
+        assert_eq!(frame[13..21], [0x15, 0x15, 0x15, 0x15, 0x26, 0x26, 0x26, 0x26]);
+    }
+
+    #[test]
+    fn copying_the_scroll() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        ppu.write_register(0x2001, 0x08, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 241, 0);
+        // X = 40, Y = 17 in the bottom right nametable
+        ppu.write_register(0x2000, 0x03, &mut mapper);
+        ppu.write_register(0x2005, 40, &mut mapper);
+        ppu.write_register(0x2005, 17, &mut mapper);
+        assert_eq!(ppu.t, 0x1c45);
+        run_to(&mut ppu, &mut mapper, 261, 281);
+        assert_eq!(ppu.v & 0x7be0, 0x1840);
+        // Coarse X of the first two tiles of the next line, fetched already
+        run_to(&mut ppu, &mut mapper, 0, 0);
+        assert_eq!(ppu.v, 0x1c47);
+        // 32 more tiles into the next nametable, fine Y goes up at dot 256
+        // and X comes back at 257
+        run_to(&mut ppu, &mut mapper, 0, 257);
+        assert_eq!(ppu.v, 0x2847);
+        run_to(&mut ppu, &mut mapper, 0, 258);
+        assert_eq!(ppu.v, 0x2c45);
+
+        // Row 29 wraps into the nametable below, the attribute rows don't
+        ppu.v = 0x73a0;
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0800);
+        ppu.v = 0x73e0;
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0000);
+        ppu.v = 0x041f;
+        ppu.increment_coarse_x();
+        assert_eq!(ppu.v, 0x0000);
+    }
+
+    #[test]
+    fn skipping_a_dot_on_odd_frames() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        let mut dots = [0; 3];
+        for frame in dots.iter_mut() {
+            let start = ppu.frame;
+            while ppu.frame == start {
+                ppu.step(&mut mapper);
+                *frame += 1;
+            }
+            ppu.write_register(0x2001, 0x08, &mut mapper);
+        }
+        assert_eq!(dots, [341 * 262, 341 * 262 - 1, 341 * 262]);
+
+        // Not on PAL
+        let mut ppu = PPU::new(Region::PAL);
+        ppu.write_register(0x2001, 0x08, &mut mapper);
+        ppu.frame = 1;
+        let mut count = 0;
+        while ppu.frame == 1 {
+            ppu.step(&mut mapper);
+            count += 1;
+        }
+        assert_eq!(count, 341 * 312);
+    }
+}
@@ -0,0 +1,579 @@
+// Picture processing unit (2C02), registers $2000-$2007 mirrored up to $3FFF
+// https://wiki.nesdev.com/w/index.php/PPU_registers
+//
+// Pattern tables and nametables are on the cartridge side of the PPU bus,
+// every access goes through the mapper.
+mod background;
+mod nametables;
+pub mod palette;
+mod sprites;
+
+use mapper::Mapper;
+use ppu::background::Background;
+use ppu::nametables::Nametables;
+use ppu::sprites::Sprites;
+use rom::Region;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// Frames an I/O latch bit keeps its value without being refreshed (~600 ms)
+const DECAY_FRAMES: u64 = 36;
+
+#[derive(Debug)]
+pub struct PPU {
+    // $2000
+    // 76543210
+    // ||||||||
+    // ||||||++- Base nametable address (0: $2000; 1: $2400; 2: $2800; 3: $2C00)
+    // |||||+--- VRAM address increment per CPU read/write of PPUDATA (0: add 1; 1: add 32)
+    // ||||+---- Sprite pattern table address for 8x8 sprites (0: $0000; 1: $1000)
+    // |||+----- Background pattern table address (0: $0000; 1: $1000)
+    // ||+------ Sprite size (0: 8x8; 1: 8x16)
+    // |+------- PPU master/slave select
+    // +-------- Generate an NMI at the start of vertical blanking
+    ctrl: u8,
+    // $2001
+    // 76543210
+    // ||||||||
+    // |||||||+- Greyscale
+    // ||||||+-- Show background in the leftmost 8 pixels
+    // |||||+--- Show sprites in the leftmost 8 pixels
+    // ||||+---- Show background
+    // |||+----- Show sprites
+    // +++------ Emphasize red, green, blue
+    mask: u8,
+    // $2002
+    // 76543210
+    // |||
+    // ||+------ Sprite overflow
+    // |+------- Sprite 0 hit
+    // +-------- Vertical blank has started
+    status: u8,
+    oam_addr: u8,
+    pub oam: [u8; 256],
+
+    // Current and temporary VRAM address, fine X scroll and the first/second
+    // write toggle shared by $2005 and $2006
+    // yyy NN YYYYY XXXXX
+    // ||| || ||||| +++++- coarse X scroll
+    // ||| || +++++------- coarse Y scroll
+    // ||| ++------------- nametable select
+    // +++---------------- fine Y scroll
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    // PPUDATA reads return the byte fetched by the previous read
+    read_buffer: u8,
+    // Value left on the PPU's data bus by the last register access, read
+    // back from write-only registers. Each bit fades to 0 unless refreshed.
+    io_latch: u8,
+    io_refreshed: [u64; 8],
+
+    nametables: Nametables,
+    // Backdrop and 3 colors of 4 background and 4 sprite palettes. The
+    // sprite palettes' backdrop entries are the background ones.
+    palette: [u8; 32],
+
+    background: Background,
+    sprites: Sprites,
+    // The picture, a row after another. Each pixel is a 6-bit color and the
+    // emphasis bits above it, red in bit 6, green in 7 and blue in 8.
+    frame_buffer: Vec<u16>,
+    // The 2C07 has red and green emphasis the other way round
+    swap_emphasis: bool,
+
+    // Position of the next dot
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    scanlines: u16,
+    // NTSC skips a dot of the pre-render line every other frame
+    skip_odd_dot: bool,
+    // Dots per CPU cycle, in fifths: 3 on NTSC, 3.2 on PAL
+    dot_fifths: u8,
+    dot_clock: u8,
+    nmi_pending: bool,
+    // The last visible line is done
+    frame_finished: bool,
+}
+
+impl PPU {
+    pub fn new(region: Region) -> PPU {
+        PPU {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            io_refreshed: [0; 8],
+            nametables: Nametables::default(),
+            palette: [0; 32],
+            background: Background::default(),
+            sprites: Sprites::default(),
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            swap_emphasis: region == Region::PAL || region == Region::Dendy,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            scanlines: match region {
+                Region::PAL | Region::Dendy => 312,
+                Region::NTSC | Region::Dual => 262,
+            },
+            skip_odd_dot: region == Region::NTSC || region == Region::Dual,
+            dot_fifths: if region == Region::PAL { 16 } else { 15 },
+            dot_clock: 0,
+            nmi_pending: false,
+            frame_finished: false,
+        }
+    }
+
+    fn pre_render_line(&self) -> u16 {
+        self.scanlines - 1
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & 0x18 != 0
+    }
+
+    // Visible lines and the pre-render line, when the PPU is fetching
+    fn rendering(&self) -> bool {
+        self.rendering_enabled() && (self.scanline < 240 || self.scanline == self.pre_render_line())
+    }
+
+    // The last picture, 256x240, see `palette::Palette` for the colors
+    pub fn frame_buffer(&self) -> &[u16] {
+        &self.frame_buffer
+    }
+
+    // `address` is $2000-$3FFF
+    pub fn read_register(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        self.decay_io_latch();
+        match address & 0x2007 {
+            // Only the top 3 bits are driven, the rest is the latch
+            0x2002 => {
+                let value = (self.status & 0xe0) | (self.io_latch & 0x1f);
+                self.set_vblank(false);
+                self.w = false;
+                self.refresh_io_latch(value, 0xe0);
+            },
+            0x2004 => {
+                let mut value = self.oam[self.oam_addr as usize];
+                // The attribute bytes have no bits 2-4
+                if self.oam_addr & 0x03 == 0x02 {
+                    value &= 0xe3;
+                }
+                self.refresh_io_latch(value, 0xff);
+            },
+            0x2007 => {
+                let address = self.v & 0x3fff;
+                if address >= 0x3f00 {
+                    // Palette reads skip the buffer, which gets the
+                    // nametable byte under the palette instead
+                    let value = self.read_palette(address);
+                    self.read_buffer = self.read(address - 0x1000, mapper);
+                    self.refresh_io_latch(value, 0x3f);
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = self.read(address, mapper);
+                    self.refresh_io_latch(value, 0xff);
+                }
+                self.increment_address(mapper);
+            },
+            _ => {},
+        }
+        self.io_latch
+    }
+
+    // `address` is $2000-$3FFF
+    pub fn write_register(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        self.refresh_io_latch(value, 0xff);
+        match address & 0x2007 {
+            0x2000 => {
+                let was_low = self.nmi_low();
+                self.ctrl = value;
+                self.t = (self.t & 0xf3ff) | (value as u16 & 0x03) << 10;
+                // Enabling NMI during vblank fires one right away
+                self.update_nmi(was_low);
+            },
+            0x2001 => self.mask = value,
+            0x2003 => self.oam_addr = value,
+            0x2004 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            // First write: XXXXXxxx, second write: YYYYYyyy
+            0x2005 => {
+                if !self.w {
+                    self.t = (self.t & 0xffe0) | (value as u16 >> 3);
+                    self.x = value & 0x07;
+                } else {
+                    self.t = (self.t & 0x8c1f) | (value as u16 & 0x07) << 12 | (value as u16 & 0xf8) << 2;
+                }
+                self.w = !self.w;
+            },
+            // First write: the high 6 bits, second write: the low byte
+            0x2006 => {
+                if !self.w {
+                    self.t = (self.t & 0x00ff) | (value as u16 & 0x3f) << 8;
+                } else {
+                    self.t = (self.t & 0xff00) | value as u16;
+                    self.v = self.t;
+                    mapper.notify_ppu_address(self.v & 0x3fff);
+                }
+                self.w = !self.w;
+            },
+            0x2007 => {
+                let address = self.v & 0x3fff;
+                self.write(address, value, mapper);
+                self.increment_address(mapper);
+            },
+            _ => {},
+        }
+    }
+
+    fn increment_address(&mut self, mapper: &mut dyn Mapper) {
+        // While rendering the address steps like the scroll does
+        if self.rendering() {
+            self.increment_coarse_x();
+            self.increment_y();
+            return;
+        }
+        let step = if self.ctrl & 0x04 != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7fff;
+        mapper.notify_ppu_address(self.v & 0x3fff);
+    }
+
+    // Bits driven by a register access take the new value
+    fn refresh_io_latch(&mut self, value: u8, driven: u8) {
+        self.io_latch = (self.io_latch & !driven) | (value & driven);
+        for (bit, refreshed) in self.io_refreshed.iter_mut().enumerate() {
+            if driven & (1 << bit) != 0 {
+                *refreshed = self.frame;
+            }
+        }
+    }
+
+    fn decay_io_latch(&mut self) {
+        for (bit, &refreshed) in self.io_refreshed.iter().enumerate() {
+            if self.frame - refreshed >= DECAY_FRAMES {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    // Rendering fetches, the mapper sees the address on the bus
+    fn fetch(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        mapper.notify_ppu_address(address);
+        self.read(address, mapper)
+    }
+
+    // PPU address space, $0000-$3FFF
+    fn read(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        match address {
+            0x0000..=0x1fff => mapper.ppu_read(address),
+            0x2000..=0x3eff => self.nametables.read(address, mapper),
+            _ => self.read_palette(address),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        match address {
+            0x0000..=0x1fff => mapper.ppu_write(address, value),
+            0x2000..=0x3eff => self.nametables.write(address, value, mapper),
+            _ => self.palette[palette_index(address)] = value & 0x3f,
+        }
+    }
+
+    // Greyscale keeps the column of grays, for $2007 reads too
+    fn read_palette(&self, address: u16) -> u8 {
+        let color = self.palette[palette_index(address)];
+        if self.mask & 0x01 != 0 { color & 0x30 } else { color }
+    }
+
+    // Bits 5-7 of the mask, red, green and blue
+    fn emphasis(&self) -> u16 {
+        let emphasis = self.mask as u16 >> 5;
+        if self.swap_emphasis {
+            (emphasis & 0x04) | (emphasis & 0x01) << 1 | (emphasis & 0x02) >> 1
+        } else {
+            emphasis
+        }
+    }
+
+    fn set_vblank(&mut self, vblank: bool) {
+        let was_low = self.nmi_low();
+        if vblank {
+            self.status |= 0x80;
+        } else {
+            self.status &= !0x80;
+        }
+        self.update_nmi(was_low);
+    }
+
+    // /NMI is low while both the vblank flag and the NMI enable are set
+    fn nmi_low(&self) -> bool {
+        self.status & 0x80 != 0 && self.ctrl & 0x80 != 0
+    }
+
+    // The CPU sees the falling edges
+    fn update_nmi(&mut self, was_low: bool) {
+        if self.nmi_low() && !was_low {
+            self.nmi_pending = true;
+        }
+    }
+
+    // True once per NMI
+    pub fn nmi(&mut self) -> bool {
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        nmi
+    }
+
+    fn draw_pixel(&mut self) {
+        // The background shifts before the pixel comes out
+        let pixel = if self.rendering_enabled() {
+            let background = self.background_pixel();
+            self.mix_sprites(background)
+        } else {
+            0
+        };
+        // With rendering off the backdrop shows, or the palette entry `v`
+        // points at
+        let address = if !self.rendering_enabled() && self.v & 0x3f00 == 0x3f00 {
+            self.v & 0x3fff
+        } else {
+            0x3f00 | pixel as u16
+        };
+        let x = self.dot as usize - 1;
+        let y = self.scanline as usize;
+        self.frame_buffer[y * SCREEN_WIDTH + x] = self.read_palette(address) as u16 | self.emphasis() << 6;
+    }
+
+    // True once per picture, as the PPU leaves the visible lines
+    pub fn frame_finished(&mut self) -> bool {
+        let finished = self.frame_finished;
+        self.frame_finished = false;
+        finished
+    }
+
+    // Advances the PPU by one CPU cycle
+    pub fn tick(&mut self, mapper: &mut dyn Mapper) {
+        self.dot_clock += self.dot_fifths;
+        while self.dot_clock >= 5 {
+            self.dot_clock -= 5;
+            self.step(mapper);
+        }
+    }
+
+    // One dot
+    fn step(&mut self, mapper: &mut dyn Mapper) {
+        if self.rendering() {
+            self.fetch_background(mapper);
+            self.fetch_sprites(mapper);
+            if self.dot == 260 {
+                mapper.notify_scanline();
+            }
+        }
+        if self.scanline < 240 && (1..=256).contains(&self.dot) {
+            self.draw_pixel();
+        }
+
+        if self.dot == 1 {
+            if self.scanline == 241 {
+                self.set_vblank(true);
+            } else if self.scanline == self.pre_render_line() {
+                self.set_vblank(false);
+                // Sprite 0 hit and overflow
+                self.status &= !0x60;
+            }
+        }
+
+        self.dot += 1;
+        // Dot 340 of the pre-render line is skipped on odd frames
+        let skip = self.skip_odd_dot && self.frame % 2 == 1 && self.rendering_enabled() &&
+                   self.scanline == self.pre_render_line() && self.dot == 340;
+        if self.dot == 341 || skip {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == 240 {
+                self.frame_finished = true;
+            }
+            if self.scanline == self.scanlines {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+}
+
+// $3F20-$3FFF mirror $3F00-$3F1F, and the backdrop entries of the sprite
+// palettes ($3F10/$3F14/$3F18/$3F1C) are those of the background
+fn palette_index(address: u16) -> usize {
+    let index = address as usize & 0x1f;
+    if index & 0x13 == 0x10 { index & 0x0f } else { index }
+}
+
+#[cfg(test)]
+pub mod test {
+    use ppu::*;
+    use mapper::nrom::NROM;
+    use rom::{LoadOptions, ROM};
+
+    // CHR RAM, vertical mirroring
+    pub fn make_mapper() -> NROM {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 16 * 1024, 0);
+        NROM::new(&ROM::from_bytes(&data, &LoadOptions::default()).unwrap())
+    }
+
+    // Through $2006/$2007
+    pub fn write_vram(ppu: &mut PPU, mapper: &mut NROM, address: u16, data: &[u8]) {
+        ppu.write_register(0x2006, (address >> 8) as u8, mapper);
+        ppu.write_register(0x2006, address as u8, mapper);
+        for &value in data {
+            ppu.write_register(0x2007, value, mapper);
+        }
+    }
+
+    pub fn run_to(ppu: &mut PPU, mapper: &mut NROM, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.step(mapper);
+        }
+    }
+
+    #[test]
+    fn reading_through_the_buffer() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        ppu.write_register(0x2006, 0x20, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.write_register(0x2007, 0x11, &mut mapper);
+        ppu.write_register(0x2007, 0x22, &mut mapper);
+        // Vertical mirroring, through the mirror at $3000
+        ppu.write_register(0x2006, 0x38, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper), 0x00);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper), 0x11);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper), 0x22);
+
+        // Palette reads come right away, the buffer gets the nametable byte
+        ppu.write_register(0x2006, 0x3f, &mut mapper);
+        ppu.write_register(0x2006, 0x01, &mut mapper);
+        ppu.write_register(0x2007, 0x2a, &mut mapper);
+        ppu.write_register(0x2006, 0x3f, &mut mapper);
+        ppu.write_register(0x2006, 0x01, &mut mapper);
+        // Mirrored at $3F21, the upper bits come from the latch
+        ppu.write_register(0x2000, 0xc0, &mut mapper);
+        assert_eq!(ppu.read_register(0x3f27, &mut mapper), 0xea);
+        ppu.write_register(0x2006, 0x28, &mut mapper);
+        ppu.write_register(0x2006, 0x01, &mut mapper);
+        ppu.read_register(0x2007, &mut mapper);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper), 0x22);
+
+        // Increments by 32
+        ppu.write_register(0x2000, 0x04, &mut mapper);
+        ppu.write_register(0x2006, 0x20, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.write_register(0x2007, 0x33, &mut mapper);
+        assert_eq!(ppu.v, 0x2020);
+    }
+
+    #[test]
+    fn sharing_the_write_toggle() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        ppu.write_register(0x2000, 0x03, &mut mapper);
+        // X = 125, Y = 94
+        ppu.write_register(0x2005, 0x7d, &mut mapper);
+        assert_eq!((ppu.t, ppu.x, ppu.w), (0x0c0f, 5, true));
+        ppu.write_register(0x2005, 0x5e, &mut mapper);
+        assert_eq!((ppu.t, ppu.w), (0x6d6f, false));
+
+        // Reading the status resets the toggle
+        ppu.write_register(0x2006, 0x04, &mut mapper);
+        ppu.read_register(0x2002, &mut mapper);
+        ppu.write_register(0x2006, 0x3d, &mut mapper);
+        ppu.write_register(0x2006, 0xf0, &mut mapper);
+        assert_eq!(ppu.v, 0x3df0);
+    }
+
+    #[test]
+    fn flagging_vblank() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        ppu.write_register(0x2000, 0x80, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 241, 2);
+        assert!(ppu.nmi());
+        assert!(!ppu.nmi());
+        assert_eq!(ppu.read_register(0x2002, &mut mapper) & 0x80, 0x80);
+        // Cleared by the read
+        assert_eq!(ppu.read_register(0x2002, &mut mapper) & 0x80, 0x00);
+
+        // Enabling NMI in vblank
+        run_to(&mut ppu, &mut mapper, 0, 0);
+        run_to(&mut ppu, &mut mapper, 250, 0);
+        assert!(ppu.nmi());
+        ppu.write_register(0x2000, 0x00, &mut mapper);
+        assert!(!ppu.nmi());
+        ppu.write_register(0x2000, 0x80, &mut mapper);
+        assert!(ppu.nmi());
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        assert_eq!(ppu.read_register(0x2002, &mut mapper) & 0x80, 0x00);
+        assert_eq!(ppu.frame, 1);
+    }
+
+    #[test]
+    fn keeping_the_io_latch() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        ppu.write_register(0x2003, 0x02, &mut mapper);
+        ppu.write_register(0x2004, 0xff, &mut mapper);
+        assert_eq!(ppu.read_register(0x2001, &mut mapper), 0xff);
+        ppu.write_register(0x2003, 0x02, &mut mapper);
+        assert_eq!(ppu.read_register(0x2004, &mut mapper), 0xe3);
+        // Write-only registers return the latch, through the mirrors too
+        assert_eq!(ppu.read_register(0x3ff8, &mut mapper), 0xe3);
+
+        // Fades out without refreshes
+        ppu.frame += DECAY_FRAMES;
+        assert_eq!(ppu.read_register(0x2000, &mut mapper), 0x00);
+    }
+
+    #[test]
+    fn mirroring_the_palette() {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        write_vram(&mut ppu, &mut mapper, 0x3f10, &[0x21, 0x22, 0x23, 0x24, 0x25]);
+        write_vram(&mut ppu, &mut mapper, 0x3f1c, &[0x2c]);
+        assert_eq!(ppu.palette[0x00], 0x21);
+        assert_eq!(ppu.palette[0x11], 0x22);
+        assert_eq!(ppu.palette[0x04], 0x25);
+        assert_eq!(ppu.palette[0x0c], 0x2c);
+        ppu.write_register(0x2006, 0x3f, &mut mapper);
+        ppu.write_register(0x2006, 0x30, &mut mapper);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper) & 0x3f, 0x21);
+        // Greyscale shows in reads as well
+        ppu.write_register(0x2001, 0x01, &mut mapper);
+        assert_eq!(ppu.read_register(0x2007, &mut mapper) & 0x3f, 0x20);
+
+        // Backdrop with red and blue emphasis, rendering off
+        ppu.write_register(0x2001, 0xa0, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 1, 0);
+        assert_eq!(ppu.frame_buffer()[0], 0x21 | 0x140);
+        // Red and green are swapped on PAL
+        let mut ppu = PPU::new(Region::PAL);
+        ppu.write_register(0x2001, 0x20, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 1, 0);
+        assert_eq!(ppu.frame_buffer()[0], 0x080);
+    }
+}
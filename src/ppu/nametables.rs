@@ -0,0 +1,177 @@
+// Nametable memory, $2000-$2FFF
+// https://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring
+//
+// The console has 2 KB of nametable RAM (CIRAM), enough for two of the four
+// nametables. The cartridge wires CIRAM's A10 to one of the PPU's address
+// lines, or holds it, which gives the mirroring:
+//   Horizontal       $2000 = $2400 (A), $2800 = $2C00 (B)
+//   Vertical         $2000 = $2800 (A), $2400 = $2C00 (B)
+//   Single-screen    all four are A, or all four B
+//   Four-screen      4 KB of VRAM on the cartridge, CIRAM isn't used
+// Boards with their own nametable memory (MMC5 ExRAM, N163 CHR ROM) take
+// single nametables over through `Mapper::nametable_read` and
+// `Mapper::nametable_write`, the others go to the CIRAM page the mapper
+// picks with `Mapper::nametable_page`.
+use mapper::Mapper;
+use rom::MirroringType;
+
+#[derive(Debug)]
+pub struct Nametables {
+    ciram: [u8; 0x800],
+    // Four-screen VRAM, allocated the first time the mapper asks for it
+    vram: Vec<u8>,
+}
+
+impl Default for Nametables {
+    fn default() -> Nametables {
+        Nametables {
+            ciram: [0; 0x800],
+            vram: Vec::new(),
+        }
+    }
+}
+
+impl Nametables {
+    // `address` is $2000-$3EFF, $3000-$3EFF mirrors $2000-$2EFF
+    pub fn read(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        let address = 0x2000 | (address & 0x0fff);
+        if let Some(value) = mapper.nametable_read(address) {
+            return value;
+        }
+        let index = self.index(address, mapper);
+        self.memory(mapper)[index]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        let address = 0x2000 | (address & 0x0fff);
+        if !mapper.nametable_write(address, value) {
+            let index = self.index(address, mapper);
+            self.memory(mapper)[index] = value;
+        }
+    }
+
+    // The mirroring is asked on every access, a mapper switching it
+    // changes the very next fetch
+    fn memory(&mut self, mapper: &dyn Mapper) -> &mut [u8] {
+        if mapper.mirroring() == MirroringType::FourScreen {
+            if self.vram.is_empty() {
+                self.vram = vec![0; 0x1000];
+            }
+            &mut self.vram
+        } else {
+            &mut self.ciram
+        }
+    }
+
+    fn index(&self, address: u16, mapper: &dyn Mapper) -> usize {
+        let table = (address as usize >> 10) & 0x03;
+        let offset = address as usize & 0x3ff;
+        if mapper.mirroring() == MirroringType::FourScreen {
+            table * 0x400 + offset
+        } else {
+            mapper.nametable_page(table) * 0x400 + offset
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ppu::nametables::*;
+
+    // Mirroring set by the test, nametable 3 from the board's own memory
+    struct Board {
+        mirroring: MirroringType,
+        own: [u8; 0x400],
+        own_enabled: bool,
+    }
+
+    impl Mapper for Board {
+        fn cpu_read(&mut self, _address: u16) -> Option<u8> {
+            None
+        }
+
+        fn cpu_write(&mut self, _address: u16, _value: u8) {}
+
+        fn ppu_read(&mut self, _address: u16) -> u8 {
+            0
+        }
+
+        fn ppu_write(&mut self, _address: u16, _value: u8) {}
+
+        fn mirroring(&self) -> MirroringType {
+            self.mirroring
+        }
+
+        fn nametable_read(&mut self, address: u16) -> Option<u8> {
+            if self.own_enabled && address >= 0x2c00 {
+                Some(self.own[address as usize & 0x3ff])
+            } else {
+                None
+            }
+        }
+
+        fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+            if self.own_enabled && address >= 0x2c00 {
+                self.own[address as usize & 0x3ff] = value;
+                return true;
+            }
+            false
+        }
+    }
+
+    // Writes the number of each nametable to its first byte, then reads
+    // them back
+    fn layout(nametables: &mut Nametables, board: &mut Board) -> [u8; 4] {
+        for table in 0..4 {
+            nametables.write(0x2000 + table * 0x400, table as u8, board);
+        }
+        let mut values = [0; 4];
+        for (table, value) in values.iter_mut().enumerate() {
+            *value = nametables.read(0x2000 + table as u16 * 0x400, board);
+        }
+        values
+    }
+
+    #[test]
+    fn mirroring_nametables() {
+        let mut nametables = Nametables::default();
+        let mut board = Board {
+            mirroring: MirroringType::Horizontal,
+            own: [0; 0x400],
+            own_enabled: false,
+        };
+        assert_eq!(layout(&mut nametables, &mut board), [1, 1, 3, 3]);
+        board.mirroring = MirroringType::Vertical;
+        assert_eq!(layout(&mut nametables, &mut board), [2, 3, 2, 3]);
+        board.mirroring = MirroringType::SingleScreenA;
+        assert_eq!(layout(&mut nametables, &mut board), [3, 3, 3, 3]);
+        board.mirroring = MirroringType::SingleScreenB;
+        nametables.write(0x2000, 0x55, &mut board);
+        board.mirroring = MirroringType::Horizontal;
+        // The switch shows on the next access, page B is $2800
+        assert_eq!(nametables.read(0x2800, &mut board), 0x55);
+        assert_eq!(nametables.read(0x3000, &mut board), 3);
+
+        board.mirroring = MirroringType::FourScreen;
+        assert_eq!(layout(&mut nametables, &mut board), [0, 1, 2, 3]);
+        assert_eq!(nametables.vram.len(), 0x1000);
+        // CIRAM as it was
+        board.mirroring = MirroringType::Vertical;
+        assert_eq!(nametables.read(0x2400, &mut board), 0x55);
+    }
+
+    #[test]
+    fn overriding_nametables() {
+        let mut nametables = Nametables::default();
+        let mut board = Board {
+            mirroring: MirroringType::Vertical,
+            own: [0; 0x400],
+            own_enabled: true,
+        };
+        assert_eq!(layout(&mut nametables, &mut board), [2, 1, 2, 3]);
+        assert_eq!(board.own[0], 3);
+        // CIRAM page B kept what was written through $2400
+        board.own_enabled = false;
+        assert_eq!(nametables.read(0x2c00, &mut board), 1);
+    }
+}
@@ -0,0 +1,206 @@
+// Colors of the PPU's 6-bit palette indexes
+// https://wiki.nesdev.com/w/index.php/PPU_palettes
+//
+// The NES PPU makes a composite video signal, the colors depend on the TV
+// and there is no single right palette. `.pal` files hold 64 RGB triplets
+// (192 bytes), or 8 sets of 64 for every combination of the emphasis bits
+// (1536 bytes). Without the emphasis sets, emphasis darkens the two other
+// channels.
+//
+// The RGB PPUs of the Vs. System (2C03, 2C05) have a fixed palette with 3
+// bits per channel, their emphasis bits turn a channel fully on.
+use rom::ROM;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Channels not emphasized keep this much
+const ATTENUATION: f32 = 0.816;
+
+// A widely used 2C02 palette
+const NTSC_COLORS: [u32; 64] = [
+    0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600, 0x561d00,
+    0x333500, 0x0b4800, 0x005200, 0x004f08, 0x00404d, 0x000000, 0x000000, 0x000000,
+    0xadadad, 0x155fd9, 0x4240ff, 0x7527fe, 0xa01acc, 0xb71e7b, 0xb53120, 0x994e00,
+    0x6b6d00, 0x388700, 0x0c9300, 0x008f32, 0x007c8d, 0x000000, 0x000000, 0x000000,
+    0xfffeff, 0x64b0ff, 0x9290ff, 0xc676ff, 0xf36aff, 0xfe6ecc, 0xfe8170, 0xea9e22,
+    0xbcbe00, 0x88d800, 0x5ce430, 0x45e082, 0x48cdde, 0x4f4f4f, 0x000000, 0x000000,
+    0xfffeff, 0xc0dfff, 0xd3d2ff, 0xe8c8ff, 0xfbc2ff, 0xfec4ea, 0xfeccc5, 0xf7d8a5,
+    0xe4e594, 0xcfef96, 0xbdf4ab, 0xb3f3cc, 0xb5ebf2, 0xb8b8b8, 0x000000, 0x000000,
+];
+
+// 2C03/2C05, red, green and blue levels 0-7
+const RGB_PPU_COLORS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    IoError(io::Error),
+    // Neither 192 nor 1536 bytes
+    SizeError(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for PaletteError {}
+
+impl From<io::Error> for PaletteError {
+    fn from(error: io::Error) -> PaletteError {
+        PaletteError::IoError(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Palette {
+    // 512 colors, indexed by the pixels of `PPU::frame_buffer`:
+    // emphasis bits (blue, green, red) and the 6-bit index
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        let colors: Vec<[u8; 3]> = NTSC_COLORS.iter()
+            .map(|&rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+            .collect();
+        Palette::with_emphasis(&colors)
+    }
+}
+
+impl Palette {
+    // The contents of a .pal file
+    pub fn from_bytes(data: &[u8]) -> Result<Palette, PaletteError> {
+        if data.len() != 192 && data.len() != 1536 {
+            return Err(PaletteError::SizeError(data.len()));
+        }
+        let colors: Vec<[u8; 3]> = data.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+        if colors.len() == 64 {
+            Ok(Palette::with_emphasis(&colors))
+        } else {
+            Ok(Palette { colors })
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, PaletteError> {
+        Palette::from_bytes(&fs::read(path)?)
+    }
+
+    // The Vs. System's RGB PPUs
+    pub fn rgb_ppu() -> Palette {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for &levels in RGB_PPU_COLORS.iter() {
+                let mut rgb = [0; 3];
+                for (channel, value) in rgb.iter_mut().enumerate() {
+                    let level = (levels >> ((2 - channel) * 3)) & 0x07;
+                    *value = if emphasis & (1 << channel) != 0 { 0xff } else { (level * 255 / 7) as u8 };
+                }
+                colors.push(rgb);
+            }
+        }
+        Palette { colors }
+    }
+
+    // The palette of the PPU the cartridge runs on. The 2C04s have their
+    // colors in a different order each, they and iNES 1.0 Vs. System games,
+    // whose PPU is unknown, get the composite palette.
+    pub fn for_rom(rom: &ROM) -> Palette {
+        match rom.header.vs_ppu() {
+            Some(0x00..=0x01) | Some(0x06..=0x0c) => Palette::rgb_ppu(),
+            _ => Palette::default(),
+        }
+    }
+
+    // Composite colors darkened for each combination of the emphasis bits
+    fn with_emphasis(colors: &[[u8; 3]]) -> Palette {
+        let mut emphasized = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for color in colors {
+                let mut rgb = *color;
+                for (channel, value) in rgb.iter_mut().enumerate() {
+                    if emphasis & !(1 << channel) != 0 {
+                        *value = (*value as f32 * ATTENUATION) as u8;
+                    }
+                }
+                emphasized.push(rgb);
+            }
+        }
+        Palette { colors: emphasized }
+    }
+
+    // A pixel of `PPU::frame_buffer`
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        self.colors[pixel as usize & 0x1ff]
+    }
+
+    // A whole picture, 4 bytes a pixel with an opaque alpha
+    pub fn to_rgba(&self, pixels: &[u16], rgba: &mut [u8]) {
+        for (&pixel, output) in pixels.iter().zip(rgba.chunks_mut(4)) {
+            let [red, green, blue] = self.rgb(pixel);
+            output.copy_from_slice(&[red, green, blue, 0xff]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mapper::test::make_rom;
+    use ppu::palette::*;
+
+    #[test]
+    fn loading_palettes() {
+        let mut data: Vec<u8> = (0..192).map(|i| i as u8).collect();
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.rgb(0x01), [3, 4, 5]);
+        // Red emphasis darkens green and blue, all three darken red too
+        assert_eq!(palette.rgb(0x41), [3, 3, 4]);
+        assert_eq!(palette.rgb(0x1ff), [154, 155, 155]);
+
+        data.extend((0..1344).map(|i| (i % 7) as u8));
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.rgb(0x41), [3, 4, 5]);
+        assert!(matches!(Palette::from_bytes(&data[..100]), Err(PaletteError::SizeError(100))));
+
+        let palette = Palette::default();
+        assert_eq!(palette.rgb(0x30), [0xff, 0xfe, 0xff]);
+        assert_eq!(palette.rgb(0x0f), [0, 0, 0]);
+
+        // 2C03: emphasis turns the channel on
+        let palette = Palette::rgb_ppu();
+        assert_eq!(palette.rgb(0x16), [0xff, 0, 0]);
+        assert_eq!(palette.rgb(0x21), [109, 182, 255]);
+        assert_eq!(palette.rgb(0x10f), [0, 0, 0xff]);
+    }
+
+    #[test]
+    fn choosing_palettes() {
+        let mut rom = make_rom(99, 0, 0x8000, 1, 0x2000, 1);
+        assert_eq!(Palette::for_rom(&rom).rgb(0x16), Palette::default().rgb(0x16));
+        rom.header.convert_to_nes2();
+        rom.header.flags_7 |= 0x01;
+        // RC2C05-01
+        rom.header.flags_13 = 0x08;
+        assert_eq!(Palette::for_rom(&rom).rgb(0x16), Palette::rgb_ppu().rgb(0x16));
+        // RP2C04-0001
+        rom.header.flags_13 = 0x02;
+        assert_eq!(Palette::for_rom(&rom).rgb(0x16), Palette::default().rgb(0x16));
+        // iNES 1.0 doesn't say which PPU
+        let mut rom = make_rom(99, 0, 0x8000, 1, 0x2000, 1);
+        rom.header.flags_7 |= 0x01;
+        assert_eq!(Palette::for_rom(&rom).rgb(0x16), Palette::default().rgb(0x16));
+    }
+}
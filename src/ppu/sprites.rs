@@ -0,0 +1,388 @@
+// Sprite evaluation and rendering
+// https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+//
+// During dots 65-256 the PPU looks through OAM for the sprites of the next
+// line and copies up to 8 of them into secondary OAM. Looking for a ninth
+// it goes wrong: after each sprite out of range it checks the next byte of
+// the next sprite instead of its Y, so the overflow flag is set for the
+// wrong sprites or missed. Dots 257-320 fetch the patterns of the 8 slots,
+// each with two nametable fetches nobody uses.
+//
+// OAM entries:
+//   0 Y of the top minus 1
+//   1 tile, for 8x16 sprites the pattern table is bit 0 and the top half
+//     the even tile
+//   2 attributes
+//     76543210
+//     |||   ||
+//     |||   ++- Palette (4 to 7)
+//     ||+------ Priority (0: in front of the background; 1: behind)
+//     |+------- Flip horizontally
+//     +-------- Flip vertically
+//   3 X of the left side
+use mapper::Mapper;
+use ppu::PPU;
+
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    x: u8,
+    attribute: u8,
+    // Flipped already, the leftmost pixel in bit 7
+    pattern_low: u8,
+    pattern_high: u8,
+    zero: bool,
+}
+
+#[derive(Debug)]
+pub struct Sprites {
+    // OAM indexes of the sprites on the next line, in secondary OAM order.
+    // More than 8 when the limit is off.
+    found: Vec<u8>,
+    // Sprite 0 among them
+    zero_found: bool,
+    next: Vec<Sprite>,
+    // Sprites of the line being drawn
+    line: Vec<Sprite>,
+    // Only 8 sprites on a line, as the PPU does
+    limit: bool,
+}
+
+impl Default for Sprites {
+    fn default() -> Sprites {
+        Sprites {
+            found: Vec::with_capacity(64),
+            zero_found: false,
+            next: Vec::with_capacity(64),
+            line: Vec::with_capacity(64),
+            limit: true,
+        }
+    }
+}
+
+impl PPU {
+    // With the limit off every sprite shows, less flicker in games
+    // cycling their sprites. The overflow flag works as with the limit.
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.sprites.limit = limit;
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & 0x20 != 0 { 16 } else { 8 }
+    }
+
+    // Sprite evaluation and fetches of one dot on the visible and
+    // pre-render lines
+    pub(super) fn fetch_sprites(&mut self, mapper: &mut dyn Mapper) {
+        let dot = self.dot;
+        match dot {
+            // Done at once, nothing sees it happening over dots 65-256
+            65 => self.evaluate_sprites(),
+            257..=320 => {
+                self.oam_addr = 0;
+                let slot = (dot - 257) as usize / 8;
+                match (dot - 257) % 8 {
+                    0 | 2 => {
+                        let address = 0x2000 | (self.v & 0x0fff);
+                        self.fetch(address, mapper);
+                    },
+                    4 => {
+                        let address = self.sprite_pattern_address(slot);
+                        let pattern = self.fetch(address, mapper);
+                        if let Some(sprite) = self.make_sprite(slot, pattern) {
+                            self.sprites.next.push(sprite);
+                        }
+                    },
+                    6 => {
+                        let address = self.sprite_pattern_address(slot) + 8;
+                        let pattern = self.fetch(address, mapper);
+                        if slot < self.sprites.found.len() {
+                            let flip = self.oam[self.sprites.found[slot] as usize * 4 + 2] & 0x40 != 0;
+                            let sprite = self.sprites.next.last_mut().unwrap();
+                            sprite.pattern_high = if flip { pattern.reverse_bits() } else { pattern };
+                        }
+                    },
+                    _ => {},
+                }
+                if dot == 320 {
+                    self.fetch_extra_sprites(mapper);
+                    ::std::mem::swap(&mut self.sprites.line, &mut self.sprites.next);
+                    self.sprites.next.clear();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn evaluate_sprites(&mut self) {
+        self.sprites.found.clear();
+        // No sprites on the first line
+        if self.scanline == self.pre_render_line() {
+            self.sprites.zero_found = false;
+            return;
+        }
+        let line = self.scanline;
+        let height = self.sprite_height();
+        let in_range = |y: u8| line >= y as u16 && line - (y as u16) < height;
+
+        let mut n = 0;
+        while n < 64 && self.sprites.found.len() < 8 {
+            if in_range(self.oam[n * 4]) {
+                self.sprites.found.push(n as u8);
+            }
+            n += 1;
+        }
+        self.sprites.zero_found = self.sprites.found.first() == Some(&0);
+
+        // Past the eighth the byte checked moves on with every sprite
+        let rest = n;
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.status |= 0x20;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0x03;
+        }
+
+        if !self.sprites.limit {
+            for n in rest..64 {
+                if in_range(self.oam[n * 4]) {
+                    self.sprites.found.push(n as u8);
+                }
+            }
+        }
+    }
+
+    // Empty slots fetch tile $FF
+    fn sprite_pattern_address(&self, slot: usize) -> u16 {
+        let (y, tile, attribute) = match self.sprites.found.get(slot) {
+            Some(&index) => {
+                let entry = &self.oam[index as usize * 4..index as usize * 4 + 3];
+                (entry[0], entry[1], entry[2])
+            },
+            None => return self.tile_address(0xff, 0),
+        };
+        let mut row = self.scanline.wrapping_sub(y as u16) & 0x0f;
+        if attribute & 0x80 != 0 {
+            row = self.sprite_height() - 1 - row;
+        }
+        self.tile_address(tile, row)
+    }
+
+    // Row 0-15 of a sprite's tile
+    fn tile_address(&self, tile: u8, row: u16) -> u16 {
+        if self.sprite_height() == 16 {
+            let table = (tile as u16 & 0x01) << 12;
+            let tile = (tile as u16 & 0xfe) + (row >> 3);
+            table | tile << 4 | (row & 0x07)
+        } else {
+            let table = (self.ctrl as u16 & 0x08) << 9;
+            table | (tile as u16) << 4 | (row & 0x07)
+        }
+    }
+
+    fn make_sprite(&self, slot: usize, pattern_low: u8) -> Option<Sprite> {
+        let index = *self.sprites.found.get(slot)? as usize;
+        let attribute = self.oam[index * 4 + 2];
+        let flip = attribute & 0x40 != 0;
+        Some(Sprite {
+            x: self.oam[index * 4 + 3],
+            attribute,
+            pattern_low: if flip { pattern_low.reverse_bits() } else { pattern_low },
+            pattern_high: 0,
+            zero: slot == 0 && self.sprites.zero_found,
+        })
+    }
+
+    // The sprites past the eighth when the limit is off, the real PPU has
+    // no time to fetch them
+    fn fetch_extra_sprites(&mut self, mapper: &mut dyn Mapper) {
+        for slot in 8..self.sprites.found.len() {
+            let address = self.sprite_pattern_address(slot);
+            let low = mapper.ppu_peek(address);
+            let high = mapper.ppu_peek(address + 8);
+            let mut sprite = self.make_sprite(slot, low).unwrap();
+            sprite.pattern_high = if sprite.attribute & 0x40 != 0 { high.reverse_bits() } else { high };
+            self.sprites.next.push(sprite);
+        }
+    }
+
+    // Palette entry at the current dot with the sprites over or under the
+    // background entry. The first opaque sprite wins even when it's behind
+    // the background, hiding the sprites after it.
+    pub(super) fn mix_sprites(&mut self, background: u8) -> u8 {
+        let x = self.dot - 1;
+        if self.mask & 0x10 == 0 || (x < 8 && self.mask & 0x04 == 0) {
+            return background;
+        }
+        let mut hit = false;
+        let mut pixel = background;
+        for sprite in &self.sprites.line {
+            let offset = x.wrapping_sub(sprite.x as u16);
+            if offset >= 8 {
+                continue;
+            }
+            let bit = 7 - offset;
+            let color = ((sprite.pattern_high >> bit) & 0x01) << 1 | ((sprite.pattern_low >> bit) & 0x01);
+            if color == 0 {
+                continue;
+            }
+            // Never on the last pixel
+            hit = sprite.zero && background != 0 && x != 255;
+            if sprite.attribute & 0x20 == 0 || background == 0 {
+                pixel = 0x10 | (sprite.attribute & 0x03) << 2 | color;
+            }
+            break;
+        }
+        if hit {
+            self.status |= 0x40;
+        }
+        pixel
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ppu::*;
+    use ppu::test::{make_mapper, run_to, write_vram};
+    use mapper::nrom::NROM;
+
+    // Tile 1 solid color 1, tile 2 with color 2 on the left half and tile 3
+    // solid color 3 in the background at columns 8-15 of row 1
+    fn setup() -> (PPU, NROM) {
+        let mut mapper = make_mapper();
+        let mut ppu = PPU::new(Region::NTSC);
+        write_vram(&mut ppu, &mut mapper, 0x0010, &[0xff; 8]);
+        write_vram(&mut ppu, &mut mapper, 0x0028, &[0xf0; 8]);
+        write_vram(&mut ppu, &mut mapper, 0x0030, &[0xff; 16]);
+        write_vram(&mut ppu, &mut mapper, 0x2028, &[0x03; 8]);
+        write_vram(&mut ppu, &mut mapper, 0x3f00, &[0x0f, 0x00, 0x00, 0x30]);
+        write_vram(&mut ppu, &mut mapper, 0x3f10, &[0x0f, 0x21, 0x22, 0x23, 0x0f, 0x25, 0x26]);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        (ppu, mapper)
+    }
+
+    fn set_sprite(ppu: &mut PPU, index: usize, entry: [u8; 4]) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&entry);
+    }
+
+    fn draw_frame(ppu: &mut PPU, mapper: &mut NROM) {
+        run_to(ppu, mapper, 261, 0);
+        run_to(ppu, mapper, 240, 0);
+    }
+
+    #[test]
+    fn drawing_sprites() {
+        let (mut ppu, mut mapper) = setup();
+        for index in 0..64 {
+            set_sprite(&mut ppu, index, [0xff; 4]);
+        }
+        // Flipped horizontally, over the next one
+        set_sprite(&mut ppu, 0, [9, 2, 0x40, 16]);
+        set_sprite(&mut ppu, 1, [9, 1, 0x01, 20]);
+        // Behind the background, in front of the backdrop
+        set_sprite(&mut ppu, 2, [9, 1, 0x20, 60]);
+        // Hidden by the left 8 pixels mask
+        set_sprite(&mut ppu, 3, [30, 1, 0x00, 4]);
+        ppu.write_register(0x2001, 0x1a, &mut mapper);
+        draw_frame(&mut ppu, &mut mapper);
+
+        let row = |y: usize| &ppu.frame_buffer()[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+        assert_eq!(row(9)[16..28], [0x0f; 12]);
+        assert_eq!(row(10)[16..28], [0x0f, 0x0f, 0x0f, 0x0f, 0x22, 0x22, 0x22, 0x22, 0x25, 0x25, 0x25, 0x25]);
+        assert_eq!(row(17)[20], 0x22);
+        assert_eq!(row(18)[20], 0x0f);
+        assert_eq!(row(10)[60..70], [0x21, 0x21, 0x21, 0x21, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30]);
+        assert_eq!(row(31)[0..12], [0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x21, 0x21, 0x21, 0x21]);
+
+        // 8x16: the pattern table from the tile number, tile 3 below tile 2
+        ppu.write_register(0x2000, 0x20, &mut mapper);
+        set_sprite(&mut ppu, 0, [99, 2, 0x00, 100]);
+        draw_frame(&mut ppu, &mut mapper);
+        let row = |y: usize| &ppu.frame_buffer()[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+        assert_eq!(row(100)[100..108], [0x22, 0x22, 0x22, 0x22, 0x0f, 0x0f, 0x0f, 0x0f]);
+        assert_eq!(row(115)[100..108], [0x23; 8]);
+    }
+
+    #[test]
+    fn overflowing() {
+        let (mut ppu, mut mapper) = setup();
+        for index in 0..64 {
+            set_sprite(&mut ppu, index, [0xf0; 4]);
+        }
+        // Nine sprites on lines 51-58
+        for index in 0..9 {
+            set_sprite(&mut ppu, index, [50, 1, 0x00, index as u8 * 8 + 8]);
+        }
+        ppu.write_register(0x2001, 0x18, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 261, 0);
+        run_to(&mut ppu, &mut mapper, 50, 0);
+        assert_eq!(ppu.status & 0x20, 0x00);
+        run_to(&mut ppu, &mut mapper, 51, 0);
+        assert_eq!(ppu.status & 0x20, 0x20);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        let row = &ppu.frame_buffer()[51 * SCREEN_WIDTH..52 * SCREEN_WIDTH];
+        assert_eq!(row[64..72], [0x21; 8]);
+        assert_eq!(row[72], 0x0f);
+        // Cleared on the pre-render line
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        assert_eq!(ppu.status & 0x20, 0x00);
+
+        // Without the limit the ninth shows, the flag is the same
+        ppu.set_sprite_limit(false);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        assert_eq!(ppu.status & 0x20, 0x20);
+        let row = &ppu.frame_buffer()[51 * SCREEN_WIDTH..52 * SCREEN_WIDTH];
+        assert_eq!(row[72..80], [0x21; 8]);
+
+        // Eight sprites, the ninth is off the line but the PPU checks the
+        // tile number of the tenth instead of its Y
+        set_sprite(&mut ppu, 8, [0xf0; 4]);
+        set_sprite(&mut ppu, 9, [0xf0, 50, 0xf0, 0xf0]);
+        ppu.set_sprite_limit(true);
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        assert_eq!(ppu.status & 0x20, 0x20);
+        // And misses a ninth sprite after that
+        set_sprite(&mut ppu, 9, [0xf0; 4]);
+        set_sprite(&mut ppu, 10, [50, 1, 0x00, 0]);
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        assert_eq!(ppu.status & 0x20, 0x00);
+    }
+
+    #[test]
+    fn hitting_sprite_0() {
+        let (mut ppu, mut mapper) = setup();
+        for index in 0..64 {
+            set_sprite(&mut ppu, index, [0xf0; 4]);
+        }
+        // Over the background tiles at x 64-127, y 8-15
+        set_sprite(&mut ppu, 0, [9, 1, 0x20, 100]);
+        ppu.write_register(0x2001, 0x18, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 261, 0);
+        // Pixel 100 comes out at dot 101
+        run_to(&mut ppu, &mut mapper, 10, 101);
+        assert_eq!(ppu.status & 0x40, 0x00);
+        run_to(&mut ppu, &mut mapper, 10, 102);
+        assert_eq!(ppu.status & 0x40, 0x40);
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        assert_eq!(ppu.status & 0x40, 0x00);
+
+        // Not with the background hidden under the sprite
+        ppu.write_register(0x2001, 0x10, &mut mapper);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        assert_eq!(ppu.status & 0x40, 0x00);
+        // Nor at x = 255
+        ppu.write_register(0x2001, 0x18, &mut mapper);
+        write_vram(&mut ppu, &mut mapper, 0x203f, &[0x03]);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        ppu.write_register(0x2006, 0x00, &mut mapper);
+        set_sprite(&mut ppu, 0, [9, 1, 0x00, 255]);
+        run_to(&mut ppu, &mut mapper, 261, 2);
+        run_to(&mut ppu, &mut mapper, 240, 0);
+        assert_eq!(ppu.status & 0x40, 0x00);
+    }
+}
@@ -1,3 +1,5 @@
+use cpu::bus::Bus;
+
 use std::fmt;
 
 pub struct RAM {
@@ -10,6 +12,12 @@ impl fmt::Debug for RAM {
     }
 }
 
+impl Default for RAM {
+    fn default() -> RAM {
+        RAM::new()
+    }
+}
+
 impl RAM {
     pub fn new() -> RAM {
         RAM {
@@ -17,16 +25,27 @@ impl RAM {
         }
     }
     pub fn read_byte(&self, address: u16) -> u8 {
-        self.data[address as usize] as u8
+        self.data[address as usize & 0x7ff]
     }
 
     pub fn read_word(&self, address: u16) -> u16 {
-        let upper_byte = self.data[address as usize + 1] as u16;
-        let lower_byte = self.data[address as usize] as u16;
-        (upper_byte << 8 | lower_byte) as u16
+        let upper_byte = self.read_byte(address.wrapping_add(1)) as u16;
+        let lower_byte = self.read_byte(address) as u16;
+        upper_byte << 8 | lower_byte
     }
     
     pub fn write(&mut self, address: u16, byte: u8) {
-        self.data[address as usize] = byte;
+        self.data[address as usize & 0x7ff] = byte;
+    }
+}
+
+// 2KB of internal RAM is mirrored over the whole address space
+impl Bus for RAM {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        RAM::read_byte(self, address)
+    }
+
+    fn write(&mut self, address: u16, byte: u8) {
+        RAM::write(self, address, byte);
     }
 }
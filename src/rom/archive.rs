@@ -0,0 +1,171 @@
+// ROM images stored in zip and gzip containers, detected by magic number
+// and decompressed in memory
+use rom::{read_bin, ROMReadError};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+// Entries with these extensions are picked from a zip when no name is given
+const ROM_EXTENSIONS: [&str; 6] = ["nes", "fds", "unf", "unif", "nsf", "nsfe"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Container {
+    Zip,
+    Gzip,
+}
+
+impl Container {
+    pub fn detect(data: &[u8]) -> Option<Container> {
+        if data.starts_with(&[0x50, 0x4b, 0x03, 0x04]) {
+            Some(Container::Zip)
+        } else if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Container::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Image {
+    pub data: Vec<u8>,
+    // Name of the file inside the archive, None for plain files
+    pub entry_name: Option<String>,
+}
+
+// Reads a file and unpacks it if it's an archive. `entry` selects a file
+// inside a zip, by default the first one with a ROM extension is used.
+pub fn read_image<P: AsRef<Path>>(path: P, entry: Option<&str>) -> Result<Image, ROMReadError> {
+    let data = read_bin(path)?;
+    unpack(data, entry)
+}
+
+pub fn unpack(data: Vec<u8>, entry: Option<&str>) -> Result<Image, ROMReadError> {
+    match Container::detect(&data) {
+        Some(Container::Zip) => unpack_zip(data, entry),
+        Some(Container::Gzip) => unpack_gzip(&data),
+        None => Ok(Image { data, entry_name: None }),
+    }
+}
+
+fn unpack_zip(data: Vec<u8>, entry: Option<&str>) -> Result<Image, ROMReadError> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(archive_error)?;
+
+    let mut names = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        names.push(archive.by_index_raw(i).map_err(archive_error)?.name().to_string());
+    }
+
+    let name = match entry {
+        Some(entry) => names.into_iter()
+            .find(|name| name == entry || file_name(name) == entry),
+        None => names.into_iter()
+            .find(|name| has_rom_extension(name)),
+    };
+    let name = match name {
+        Some(name) => name,
+        None => return Err(ROMReadError::ArchiveError("No ROM found in the archive".to_string())),
+    };
+
+    let mut file = archive.by_name(&name).map_err(archive_error)?;
+    // The size comes from the archive, it can't be trusted for reserving
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(Image {
+        data: buffer,
+        entry_name: Some(name),
+    })
+}
+
+fn unpack_gzip(data: &[u8]) -> Result<Image, ROMReadError> {
+    let mut decoder = GzDecoder::new(data);
+    let mut buffer = Vec::new();
+    decoder.read_to_end(&mut buffer)?;
+    let entry_name = decoder.header()
+        .and_then(|header| header.filename())
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+    Ok(Image {
+        data: buffer,
+        entry_name,
+    })
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn has_rom_extension(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((_, ext)) => ROM_EXTENSIONS.iter().any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext)),
+        None => false,
+    }
+}
+
+fn archive_error(error: zip::result::ZipError) -> ROMReadError {
+    ROMReadError::ArchiveError(error.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use rom::archive::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::{Compression, GzBuilder};
+    use zip::write::{FileOptions, ZipWriter};
+
+    use std::io::Write;
+
+    fn make_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for &(name, contents) in files {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn unpacking_zip() {
+        let data = make_zip(&[("readme.txt", b"hello"),
+                              ("Game (U).NES", b"NES\x1a first"),
+                              ("dir/Game (E).nes", b"NES\x1a second")]);
+
+        let image = unpack(data.clone(), None).unwrap();
+        assert_eq!(image.entry_name, Some("Game (U).NES".to_string()));
+        assert_eq!(image.data, b"NES\x1a first");
+
+        let image = unpack(data.clone(), Some("Game (E).nes")).unwrap();
+        assert_eq!(image.entry_name, Some("dir/Game (E).nes".to_string()));
+        assert_eq!(image.data, b"NES\x1a second");
+
+        assert!(unpack(data, Some("missing.nes")).is_err());
+        let data = make_zip(&[("readme.txt", b"hello")]);
+        assert!(unpack(data, None).is_err());
+    }
+
+    #[test]
+    fn unpacking_gzip() {
+        let mut encoder = GzBuilder::new()
+            .filename("Game.nes")
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(b"NES\x1a data").unwrap();
+        let image = unpack(encoder.finish().unwrap(), None).unwrap();
+        assert_eq!(image.entry_name, Some("Game.nes".to_string()));
+        assert_eq!(image.data, b"NES\x1a data");
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"NES\x1a").unwrap();
+        let image = unpack(encoder.finish().unwrap(), None).unwrap();
+        assert_eq!(image.entry_name, None);
+    }
+
+    #[test]
+    fn passing_plain_files_through() {
+        let image = unpack(b"NES\x1a".to_vec(), Some("ignored")).unwrap();
+        assert_eq!(image.data, b"NES\x1a");
+        assert!(image.entry_name.is_none());
+    }
+}
@@ -0,0 +1,332 @@
+// Game database for identifying ROM images by their hashes.
+// The format is a subset of the NES 2.0 XML database (nes20db.xml), so the
+// full database can be loaded in place of the embedded one, through
+// `LoadOptions::database`, or embedded by building with NES20DB set to it.
+use rom::{read_bin, Header, MirroringType, ROMReadError, Region};
+use utils;
+
+use crc32fast;
+use sha1_smol;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+lazy_static! {
+    static ref EMBEDDED: Database = Database::parse(include_str!(concat!(env!("OUT_DIR"), "/nes20db.xml")));
+}
+
+// Hashes of PRG ROM followed by CHR ROM, as used by the NES 2.0 database
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hashes {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl Hashes {
+    pub fn compute(prg_rom: &[u8], chr_rom: &[u8]) -> Hashes {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(prg_rom);
+        crc.update(chr_rom);
+
+        let mut sha1 = sha1_smol::Sha1::new();
+        sha1.update(prg_rom);
+        sha1.update(chr_rom);
+
+        Hashes {
+            crc32: crc.finalize(),
+            sha1: sha1.digest().bytes(),
+        }
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        self.sha1.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GameInfo {
+    pub title: String,
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    // None when the database leaves mirroring to the mapper
+    pub mirroring: Option<MirroringType>,
+    pub battery: bool,
+    pub region: Region,
+    pub board: Option<String>,
+}
+
+impl GameInfo {
+    // Rewrites the iNES header fields the database knows better.
+    // Returns true if anything has changed.
+    pub fn fix_header(&self, header: &mut Header) -> bool {
+        let old = (header.flags_6, header.flags_7, header.prg_ram_size,
+                   header.flags_9, header.flags_12);
+
+        header.set_mapper(self.mapper, self.submapper);
+        match self.mirroring {
+            Some(MirroringType::FourScreen) => utils::set_bit(&mut header.flags_6, 3, true),
+            Some(mirroring) => {
+                let vertical = mirroring == MirroringType::Vertical;
+                utils::set_bit(&mut header.flags_6, 0, vertical);
+                utils::set_bit(&mut header.flags_6, 3, false);
+            },
+            None => {},
+        }
+        utils::set_bit(&mut header.flags_6, 1, self.battery);
+        if header.is_nes2() {
+            header.flags_12 = (header.flags_12 & 0xfc) | self.region.timing();
+        } else {
+            // Only PAL gets its own bit in iNES 1.0
+            utils::set_bit(&mut header.flags_9, 0, self.region == Region::PAL);
+        }
+
+        old != (header.flags_6, header.flags_7, header.prg_ram_size,
+                header.flags_9, header.flags_12)
+    }
+
+    pub fn board_name(&self) -> String {
+        match self.board {
+            Some(ref board) => board.clone(),
+            None => format!("Mapper {}", self.mapper),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Database {
+    games: Vec<GameInfo>,
+    by_crc32: HashMap<u32, Vec<usize>>,
+}
+
+impl Database {
+    pub fn embedded() -> &'static Database {
+        &EMBEDDED
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Database, ROMReadError> {
+        let data = read_bin(path)?;
+        Ok(Database::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    // Games missing the <rom> hash are skipped, unknown tags are ignored
+    pub fn parse(xml: &str) -> Database {
+        let mut db = Database::default();
+        let mut rest = xml;
+        while let Some(start) = rest.find("<game>") {
+            let body = &rest[start + 6..];
+            let end = body.find("</game>").unwrap_or(body.len());
+            if let Some(game) = parse_game(&body[..end]) {
+                db.by_crc32.entry(game.crc32).or_default().push(db.games.len());
+                db.games.push(game);
+            }
+            rest = &body[end..];
+        }
+        db
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    // CRC32 picks the candidates, SHA-1 settles collisions when both sides have it
+    pub fn find(&self, hashes: &Hashes) -> Option<&GameInfo> {
+        let candidates = self.by_crc32.get(&hashes.crc32)?;
+        candidates.iter()
+            .map(|&i| &self.games[i])
+            .find(|game| game.sha1.is_none_or(|sha1| sha1 == hashes.sha1))
+    }
+}
+
+fn parse_game(xml: &str) -> Option<GameInfo> {
+    // nes20db keeps the file name in a comment: <!-- path/Title.nes -->
+    let title = xml.find("<!--").and_then(|start| {
+        let comment = &xml[start + 4..];
+        comment.find("-->").map(|end| comment[..end].trim())
+    }).map(|comment| {
+        let name = comment.rsplit(['/', '\\']).next().unwrap_or(comment);
+        name.trim_end_matches(".nes").to_string()
+    }).unwrap_or_default();
+
+    let rom = tag_attributes(xml, "rom")?;
+    let prg_rom = tag_attributes(xml, "prgrom").unwrap_or_default();
+    let chr_rom = tag_attributes(xml, "chrrom").unwrap_or_default();
+    let pcb = tag_attributes(xml, "pcb").unwrap_or_default();
+    let console = tag_attributes(xml, "console").unwrap_or_default();
+
+    let number = |attrs: &HashMap<&str, &str>, key| -> u32 {
+        attrs.get(key).and_then(|v| v.parse().ok()).unwrap_or(0)
+    };
+
+    let mirroring = match pcb.get("mirroring") {
+        Some(&"H") => Some(MirroringType::Horizontal),
+        Some(&"V") => Some(MirroringType::Vertical),
+        Some(&"4") => Some(MirroringType::FourScreen),
+        _ => None,
+    };
+
+    Some(GameInfo {
+        title,
+        crc32: u32::from_str_radix(rom.get("crc32")?, 16).ok()?,
+        sha1: rom.get("sha1").and_then(|hex| parse_sha1(hex)),
+        prg_rom_size: number(&prg_rom, "size") as usize,
+        chr_rom_size: number(&chr_rom, "size") as usize,
+        mapper: number(&pcb, "mapper") as u16,
+        submapper: number(&pcb, "submapper") as u8,
+        mirroring,
+        battery: number(&pcb, "battery") != 0,
+        region: Region::from_timing(number(&console, "region") as u8),
+        board: pcb.get("board").map(|board| board.to_string()),
+    })
+}
+
+// Attributes of the first <name .../> tag in the fragment
+fn tag_attributes<'a>(xml: &'a str, name: &str) -> Option<HashMap<&'a str, &'a str>> {
+    let open = format!("<{} ", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find('>')? + start;
+    let mut attrs = HashMap::new();
+    let mut rest = xml[start..end].trim_end_matches('/');
+    while let Some(eq) = rest.find("=\"") {
+        let key = rest[..eq].trim();
+        let value_start = eq + 2;
+        let value_end = rest[value_start..].find('"')? + value_start;
+        attrs.insert(key, &rest[value_start..value_end]);
+        rest = &rest[value_end + 1..];
+    }
+    Some(attrs)
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0u8; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(sha1)
+}
+
+#[cfg(test)]
+mod test {
+    use rom::database::*;
+    use rom::{LoadOptions, ROM};
+
+    use std::sync::Arc;
+
+    const XML: &str = r#"
+        <nes20db>
+        <game>
+            <!-- Licensed/Some Game (USA).nes -->
+            <rom size="24576" crc32="0000ABCD"/>
+            <pcb mapper="2" submapper="0" mirroring="V" battery="1"/>
+            <console type="0" region="2"/>
+        </game>
+        <game>
+            <!-- no hash, skipped -->
+            <pcb mapper="4"/>
+        </game>
+        </nes20db>
+    "#;
+
+    #[test]
+    fn hashing_prg_and_chr() {
+        let hashes = Hashes::compute(b"The quick brown fox ", b"jumps over the lazy dog");
+        assert_eq!(hashes.crc32, 0x414fa339);
+        assert_eq!(hashes.sha1_hex(), "2FD4E1C67A2D28FCED849EE1BB76E7391B93EB12");
+    }
+
+    #[test]
+    fn parsing_xml() {
+        let db = Database::parse(XML);
+        assert_eq!(db.len(), 1);
+
+        let hashes = Hashes { crc32: 0xabcd, sha1: [0; 20] };
+        let game = db.find(&hashes).unwrap();
+        assert_eq!(game.title, "Some Game (USA)");
+        assert_eq!(game.mapper, 2);
+        assert_eq!(game.mirroring, Some(MirroringType::Vertical));
+        assert!(game.battery);
+        assert_eq!(game.region, Region::Dual);
+        assert_eq!(game.board_name(), "Mapper 2");
+    }
+
+    #[test]
+    fn fixing_header() {
+        let db = Database::parse(XML);
+        let game = db.find(&Hashes { crc32: 0xabcd, sha1: [0; 20] }).unwrap();
+        let mut header = Header {
+            flags_6: 0b0001_0000,
+            flags_7: 0b1110_0000,
+            ..Default::default()
+        };
+
+        assert!(game.fix_header(&mut header));
+        assert_eq!(header.flags_6, 0b0010_0011);
+        assert_eq!(header.flags_7, 0b0000_0000);
+        assert!(!game.fix_header(&mut header));
+    }
+
+    #[test]
+    fn finding_embedded_games() {
+        let db = Database::embedded();
+        let hashes = Hashes { crc32: 0x9a2db086, sha1: [0; 20] };
+        // The SHA-1 doesn't match
+        assert!(db.find(&hashes).is_none());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/Super Mario Bros (E).nes");
+        let rom = ROM::load(path).unwrap();
+        let game = rom.game.unwrap();
+        assert_eq!(game.title, "Super Mario Bros. (Europe)");
+        assert_eq!(game.board_name(), "NES-NROM-256");
+        assert_eq!(rom.header.flags_9, 1);
+    }
+
+    #[test]
+    fn loading_with_own_database() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01];
+        data.resize(16 + 24 * 1024, 0);
+        let hashes = Hashes::compute(&data[16..16 + 16 * 1024], &data[16 + 16 * 1024..]);
+        let xml = format!(r#"<game>
+            <!-- Blank (USA) -->
+            <rom size="24576" crc32="{:08X}" sha1="{}"/>
+            <pcb mapper="3" submapper="0" mirroring="V" battery="0" board="NES-CNROM"/>
+            <console type="0" region="0"/>
+        </game>"#, hashes.crc32, hashes.sha1_hex());
+
+        assert!(ROM::from_bytes(&data, &LoadOptions::default()).unwrap().game.is_none());
+        let options = LoadOptions {
+            database: Some(Arc::new(Database::parse(&xml))),
+            ..Default::default()
+        };
+        let rom = ROM::from_bytes(&data, &options).unwrap();
+        assert_eq!(rom.game.as_ref().unwrap().title, "Blank (USA)");
+        assert_eq!(rom.mapper(), 3);
+        assert_eq!(rom.mirroring, MirroringType::Vertical);
+    }
+
+    #[test]
+    fn cleaning_diskdude_header() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x41];
+        data.extend_from_slice(b"DiskDude!");
+        data.resize(16 + 16 * 1024, 0);
+
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        assert!(rom.header_fixed);
+        assert_eq!(rom.header.flags_7, 0);
+        assert_eq!(rom.header.flags_6, 0x41);
+
+        let options = LoadOptions { header_overrides: false, ..Default::default() };
+        let rom = ROM::from_bytes(&data, &options).unwrap();
+        assert!(!rom.header_fixed);
+        assert_eq!(rom.header.flags_7, b'D');
+    }
+}
@@ -0,0 +1,432 @@
+// Famicom Disk System images (.fds)
+// Format description: https://wiki.nesdev.com/w/index.php/FDS_disk_format
+use rom::{archive, read_bin, ROMReadError, ROMType, ROM};
+
+use std::fmt;
+use std::path::Path;
+
+// Size of one disk side in an .fds file (block CRCs and gaps are omitted)
+pub const SIDE_SIZE: usize = 65500;
+// Size of the optional fwNES header
+const HEADER_SIZE: usize = 16;
+// The disksys.rom BIOS is mapped at $E000-$FFFF
+pub const BIOS_SIZE: usize = 8 * 1024;
+
+const DISK_INFO_SIZE: usize = 56;
+const FILE_AMOUNT_SIZE: usize = 2;
+const FILE_HEADER_SIZE: usize = 16;
+const VERIFICATION: &[u8] = b"*NINTENDO-HVC*";
+
+// Block codes
+const DISK_INFO_BLOCK: u8 = 0x01;
+const FILE_AMOUNT_BLOCK: u8 = 0x02;
+const FILE_HEADER_BLOCK: u8 = 0x03;
+const FILE_DATA_BLOCK: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    // Loaded into CPU memory
+    Program,
+    // Loaded into the pattern tables
+    Character,
+    // Loaded into the nametables
+    Nametable,
+    Unknown(u8),
+}
+
+impl From<u8> for FileKind {
+    fn from(byte: u8) -> FileKind {
+        match byte {
+            0 => FileKind::Program,
+            1 => FileKind::Character,
+            2 => FileKind::Nametable,
+            x => FileKind::Unknown(x),
+        }
+    }
+}
+
+// Block 1, the first block of every side
+#[derive(Debug, Default)]
+pub struct DiskInfo {
+    // Licensee code
+    pub manufacturer: u8,
+    // Three letter game code
+    pub game_name: [u8; 3],
+    // $20 " ": normal disk, $45 "E": event, $52 "R": reduction in price
+    pub game_type: u8,
+    pub revision: u8,
+    // 0: side A, 1: side B
+    pub side_number: u8,
+    pub disk_number: u8,
+    // 0: FMC ("normal card"), 1: FSC ("card with shutter")
+    pub disk_type: u8,
+    // Files with an ID less or equal to this are loaded at boot
+    pub boot_file: u8,
+    // BCD year (Showa era), month, day
+    pub manufacturing_date: [u8; 3],
+    pub country: u8,
+    pub rewrite_date: [u8; 3],
+    // BCD count of Disk Writer rewrites
+    pub rewrite_count: u8,
+    pub actual_side: u8,
+    pub price: u8,
+}
+
+impl DiskInfo {
+    fn parse(block: &[u8]) -> Result<DiskInfo, ROMReadError> {
+        if block.len() < DISK_INFO_SIZE || block[0] != DISK_INFO_BLOCK ||
+           &block[1..15] != VERIFICATION {
+            return Err(ROMReadError::FormatError);
+        }
+
+        let mut info = DiskInfo {
+            manufacturer: block[15],
+            game_type: block[19],
+            revision: block[20],
+            side_number: block[21],
+            disk_number: block[22],
+            disk_type: block[23],
+            boot_file: block[25],
+            country: block[34],
+            rewrite_count: block[52],
+            actual_side: block[53],
+            price: block[55],
+            ..Default::default()
+        };
+        info.game_name.copy_from_slice(&block[16..19]);
+        info.manufacturing_date.copy_from_slice(&block[31..34]);
+        info.rewrite_date.copy_from_slice(&block[44..47]);
+        Ok(info)
+    }
+
+    pub fn game_name(&self) -> String {
+        String::from_utf8_lossy(&self.game_name).into_owned()
+    }
+}
+
+// Block 3 with the contents of the following block 4
+#[derive(Debug)]
+pub struct DiskFile {
+    pub number: u8,
+    pub id: u8,
+    pub name: [u8; 8],
+    // Destination address in CPU or PPU memory
+    pub address: u16,
+    pub kind: FileKind,
+    pub data: Vec<u8>,
+}
+
+impl DiskFile {
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+}
+
+#[derive(Debug)]
+pub struct DiskSide {
+    pub info: DiskInfo,
+    // Value of block 2. Some games keep hidden files after the declared ones,
+    // so `files` may hold more entries than this.
+    pub file_amount: u8,
+    pub files: Vec<DiskFile>,
+    // Raw side data as stored in the image
+    pub data: Vec<u8>,
+}
+
+impl DiskSide {
+    pub fn parse(data: &[u8]) -> Result<DiskSide, ROMReadError> {
+        let info = DiskInfo::parse(data)?;
+
+        let mut pos = DISK_INFO_SIZE;
+        if data.len() < pos + FILE_AMOUNT_SIZE || data[pos] != FILE_AMOUNT_BLOCK {
+            return Err(ROMReadError::FormatError);
+        }
+        let file_amount = data[pos + 1];
+        pos += FILE_AMOUNT_SIZE;
+
+        let mut files = Vec::new();
+        loop {
+            match DiskSide::parse_file(&data[pos..]) {
+                Some((file, size)) => {
+                    files.push(file);
+                    pos += size;
+                },
+                // A declared file must be present, the rest of the side is
+                // scanned until the first gap.
+                None if files.len() < file_amount as usize => {
+                    return Err(ROMReadError::FormatError)
+                },
+                None => break,
+            }
+        }
+
+        Ok(DiskSide {
+            info,
+            file_amount,
+            files,
+            data: data.to_vec(),
+        })
+    }
+
+    // Returns the file and the number of bytes its two blocks take
+    fn parse_file(data: &[u8]) -> Option<(DiskFile, usize)> {
+        if data.len() < FILE_HEADER_SIZE + 1 || data[0] != FILE_HEADER_BLOCK {
+            return None;
+        }
+        let address = (data[12] as u16) << 8 | data[11] as u16;
+        let size = ((data[14] as usize) << 8) | data[13] as usize;
+        let data_start = FILE_HEADER_SIZE + 1;
+        let data_end = data_start + size;
+        if data[FILE_HEADER_SIZE] != FILE_DATA_BLOCK || data.len() < data_end {
+            return None;
+        }
+
+        let mut name = [0u8; 8];
+        name.copy_from_slice(&data[3..11]);
+        let file = DiskFile {
+            number: data[1],
+            id: data[2],
+            name,
+            address,
+            kind: FileKind::from(data[15]),
+            data: data[data_start..data_end].to_vec(),
+        };
+        Some((file, data_end))
+    }
+}
+
+#[derive(Debug)]
+pub struct DiskImage {
+    // Whether the image had the 16-byte fwNES header
+    pub has_header: bool,
+    pub sides: Vec<DiskSide>,
+}
+
+impl DiskImage {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DiskImage, ROMReadError> {
+        let image = archive::read_image(path, None)?;
+        DiskImage::from_bytes(&image.data)
+    }
+
+    pub fn from_bytes(raw_data: &[u8]) -> Result<DiskImage, ROMReadError> {
+        if raw_data.len() < 4 {
+            return Err(ROMReadError::FormatError);
+        }
+        match ROM::check_type(&raw_data[0..4]) {
+            Some(ROMType::FDS) => {},
+            Some(_) => return Err(ROMReadError::NotSupported),
+            None => return Err(ROMReadError::FormatError),
+        }
+
+        // The headerless variant starts right with the disk info block
+        let has_header = raw_data[0] != DISK_INFO_BLOCK;
+        let data = if has_header {
+            &raw_data[HEADER_SIZE.min(raw_data.len())..]
+        } else {
+            raw_data
+        };
+
+        // The side count in the header is often wrong, trust the file length
+        let side_count = data.len() / SIDE_SIZE;
+        if side_count == 0 {
+            return Err(ROMReadError::FormatError);
+        }
+
+        let mut sides = Vec::with_capacity(side_count);
+        for side in data.chunks(SIDE_SIZE).take(side_count) {
+            sides.push(DiskSide::parse(side)?);
+        }
+
+        Ok(DiskImage {
+            has_header,
+            sides,
+        })
+    }
+}
+
+impl fmt::Display for DiskImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, side) in self.sides.iter().enumerate() {
+            writeln!(f, "Side {}: game {}, disk {}, side {}, {} file(s)",
+                     i, side.info.game_name(), side.info.disk_number,
+                     if side.info.side_number == 0 { "A" } else { "B" },
+                     side.files.len())?;
+            for file in &side.files {
+                writeln!(f, "  #{} id ${:02X} \"{}\" {:?} ${:04X}, {} bytes",
+                         file.number, file.id, file.name(), file.kind,
+                         file.address, file.data.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Disk drive state: the BIOS and the currently inserted side
+#[derive(Debug)]
+pub struct DiskSystem {
+    pub bios: Vec<u8>,
+    pub image: DiskImage,
+    inserted: Option<usize>,
+}
+
+impl DiskSystem {
+    // The BIOS isn't distributed with the emulator, the user has to supply
+    // their own disksys.rom. Side A of the first disk is inserted at start.
+    pub fn new<P: AsRef<Path>>(image: DiskImage, bios_path: P)
+        -> Result<DiskSystem, ROMReadError> {
+        let bios = read_bin(bios_path)?;
+        DiskSystem::with_bios(image, bios)
+    }
+
+    pub fn with_bios(image: DiskImage, bios: Vec<u8>) -> Result<DiskSystem, ROMReadError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(ROMReadError::FormatError);
+        }
+        Ok(DiskSystem {
+            bios,
+            image,
+            inserted: Some(0),
+        })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.image.sides.len()
+    }
+
+    pub fn inserted_side(&self) -> Option<&DiskSide> {
+        self.inserted.map(|i| &self.image.sides[i])
+    }
+
+    pub fn inserted_index(&self) -> Option<usize> {
+        self.inserted
+    }
+
+    // Returns whether the image has the side, the drive is left as it is
+    // when it doesn't
+    pub fn insert(&mut self, side: usize) -> bool {
+        if side >= self.side_count() {
+            return false;
+        }
+        self.inserted = Some(side);
+        true
+    }
+
+    pub fn eject(&mut self) {
+        self.inserted = None;
+    }
+
+    // Turns the inserted disk over (side A <-> side B of the same disk).
+    // Single-sided disks stay as they are, an empty drive gets the first side.
+    pub fn flip(&mut self) {
+        self.inserted = match self.inserted {
+            None => Some(0),
+            Some(side) if side ^ 1 < self.side_count() => Some(side ^ 1),
+            Some(side) => Some(side),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rom::fds::*;
+
+    fn make_side(side_number: u8, files: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![DISK_INFO_BLOCK];
+        data.extend_from_slice(VERIFICATION);
+        data.extend_from_slice(&[0x01, b'S', b'M', b'B', b' ', 0x00, side_number]);
+        data.resize(DISK_INFO_SIZE, 0);
+        data.extend_from_slice(&[FILE_AMOUNT_BLOCK, files.len() as u8]);
+        for (i, &(kind, contents)) in files.iter().enumerate() {
+            data.extend_from_slice(&[FILE_HEADER_BLOCK, i as u8, i as u8]);
+            data.extend_from_slice(b"FILENAME");
+            data.extend_from_slice(&[0x00, 0x60,
+                                     contents.len() as u8, (contents.len() >> 8) as u8,
+                                     kind, FILE_DATA_BLOCK]);
+            data.extend_from_slice(contents);
+        }
+        data.resize(SIDE_SIZE, 0);
+        data
+    }
+
+    #[test]
+    fn parsing_headerless_image() {
+        let mut data = make_side(0, &[(0, &[1, 2, 3]), (1, &[4; 300])]);
+        data.extend(make_side(1, &[(2, &[5])]));
+        let image = DiskImage::from_bytes(&data).unwrap();
+
+        assert!(!image.has_header);
+        assert_eq!(image.sides.len(), 2);
+        assert_eq!(image.sides[0].info.game_name(), "SMB");
+        assert_eq!(image.sides[0].file_amount, 2);
+        assert_eq!(image.sides[0].files[1].kind, FileKind::Character);
+        assert_eq!(image.sides[0].files[1].data.len(), 300);
+        assert_eq!(image.sides[0].files[0].address, 0x6000);
+        assert_eq!(image.sides[0].files[0].name(), "FILENAME");
+        assert_eq!(image.sides[1].info.side_number, 1);
+        assert_eq!(image.sides[1].files[0].kind, FileKind::Nametable);
+    }
+
+    #[test]
+    fn parsing_image_with_header() {
+        let mut data = vec![0x46, 0x44, 0x53, 0x1a, 0x01];
+        data.resize(HEADER_SIZE, 0);
+        data.extend(make_side(0, &[(0, &[0xea; 16])]));
+        let image = DiskImage::from_bytes(&data).unwrap();
+
+        assert!(image.has_header);
+        assert_eq!(image.sides.len(), 1);
+        assert_eq!(image.sides[0].files[0].data, vec![0xea; 16]);
+    }
+
+    #[test]
+    fn finding_hidden_files() {
+        let mut data = make_side(0, &[(0, &[1]), (0, &[2])]);
+        // Declare one file only
+        data[DISK_INFO_SIZE + 1] = 1;
+        let image = DiskImage::from_bytes(&data).unwrap();
+
+        assert_eq!(image.sides[0].file_amount, 1);
+        assert_eq!(image.sides[0].files.len(), 2);
+    }
+
+    #[test]
+    fn rejecting_broken_images() {
+        let mut data = make_side(0, &[(0, &[1])]);
+        data[1] = b'?';
+        assert!(DiskImage::from_bytes(&data).is_err());
+
+        // File amount declares a missing file
+        let mut data = make_side(0, &[(0, &[1])]);
+        data[DISK_INFO_SIZE + 1] = 2;
+        assert!(DiskImage::from_bytes(&data).is_err());
+
+        let data = make_side(0, &[]);
+        assert!(DiskImage::from_bytes(&data[..1000]).is_err());
+    }
+
+    #[test]
+    fn swapping_sides() {
+        let mut data = make_side(0, &[]);
+        data.extend(make_side(1, &[]));
+        data.extend(make_side(0, &[]));
+        let image = DiskImage::from_bytes(&data).unwrap();
+        assert!(DiskSystem::with_bios(DiskImage::from_bytes(&data).unwrap(),
+                                      vec![0; 100]).is_err());
+        let mut system = DiskSystem::with_bios(image, vec![0; BIOS_SIZE]).unwrap();
+
+        assert_eq!(system.inserted_index(), Some(0));
+        system.flip();
+        assert_eq!(system.inserted_index(), Some(1));
+        system.flip();
+        assert_eq!(system.inserted_index(), Some(0));
+        assert!(!system.insert(3));
+        assert_eq!(system.inserted_index(), Some(0));
+        assert!(system.insert(2));
+        system.flip();
+        assert_eq!(system.inserted_index(), Some(2));
+        system.eject();
+        assert!(system.inserted_side().is_none());
+        system.flip();
+        assert_eq!(system.inserted_index(), Some(0));
+    }
+}
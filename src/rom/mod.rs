@@ -0,0 +1,872 @@
+pub mod archive;
+pub mod database;
+pub mod fds;
+pub mod nsf;
+pub mod patch;
+pub mod unif;
+pub mod validate;
+
+use utils;
+use rom::database::{Database, GameInfo, Hashes};
+use rom::patch::PatchError;
+
+use std::io;
+use std::io::Read;
+use std::convert::From;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const KB: u32 = 1024;
+
+#[derive(Debug)]
+pub enum ROMReadError {
+    // IO error while reading the ROM image
+    IoError(io::Error),
+    // The ROM image has an invalid format
+    FormatError,
+    // The ROM format not supported
+    NotSupported,
+    // A patch could not be applied to the ROM image
+    PatchError(PatchError),
+    // The zip or gzip container is broken or has no ROM in it
+    ArchiveError(String),
+}
+
+impl fmt::Display for ROMReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ROMReadError {
+    fn description(&self) -> &str {
+        match *self {
+            ROMReadError::IoError(_) => "Could not read the ROM file",
+            ROMReadError::FormatError => "Wrong ROM file format",
+            ROMReadError::NotSupported => "The ROM format not supported",
+            ROMReadError::PatchError(_) => "The patch could not be applied",
+            ROMReadError::ArchiveError(ref x) => x,
+        }
+    }
+}
+
+impl From<io::Error> for ROMReadError {
+    fn from(error: io::Error) -> ROMReadError {
+        ROMReadError::IoError(error)
+    }
+}
+
+impl From<PatchError> for ROMReadError {
+    fn from(error: PatchError) -> ROMReadError {
+        ROMReadError::PatchError(error)
+    }
+}
+
+#[derive(Debug)]
+enum ROMType {
+    INES,
+    UNIF,
+    FDS,
+    NSF,
+    NSFE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MirroringType {
+    Horizontal,
+    Vertical,
+    // 4 KB of VRAM on the cartridge, every nametable is unique
+    FourScreen,
+    // All four nametables point to the first (A) or second (B) CIRAM page
+    SingleScreenA,
+    SingleScreenB,
+    // The mapper switches mirroring at runtime, the header bit is meaningless
+    MapperControlled,
+}
+
+impl MirroringType {
+    pub fn name(self) -> &'static str {
+        match self {
+            MirroringType::Horizontal => "Horizontal",
+            MirroringType::Vertical => "Vertical", 
+            MirroringType::FourScreen => "Four-screen",
+            MirroringType::SingleScreenA => "Single-screen A",
+            MirroringType::SingleScreenB => "Single-screen B",
+            MirroringType::MapperControlled => "Mapper controlled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    NTSC,
+    PAL,
+    // Runs on both NTSC and PAL consoles
+    Dual,
+    // Famiclones with PAL frame rate and NTSC-like CPU/PPU ratio
+    Dendy,
+}
+
+impl Region {
+    // TV system as stored in NES 2.0 byte 12 and in the game database
+    pub fn from_timing(timing: u8) -> Region {
+        match timing & 0x03 {
+            0 => Region::NTSC,
+            1 => Region::PAL,
+            2 => Region::Dual,
+            _ => Region::Dendy,
+        }
+    }
+
+    pub fn timing(self) -> u8 {
+        match self {
+            Region::NTSC => 0,
+            Region::PAL => 1,
+            Region::Dual => 2,
+            Region::Dendy => 3,
+        }
+    }
+
+    // CPU clock rate in Hz
+    pub fn cpu_clock(self) -> u32 {
+        match self {
+            Region::NTSC | Region::Dual => 1_789_773,
+            Region::PAL => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+}
+
+// Mappers switching nametable mirroring with their own registers
+fn has_mapper_mirroring(mapper: u16) -> bool {
+    matches!(mapper, 1 | 4 | 5 | 7 | 9 | 10 | 15 | 16 | 18 | 19 | 21..=26 | 32 | 33 |
+                     48 | 59 | 65 | 67 | 68 | 69 | 75 | 80 | 82 | 85 | 118 | 159 | 225..=231 | 233)
+}
+
+// Common name of the boards using the mapper
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    let name = match mapper {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        13 => "CPROM",
+        15 => "100-in-1 Contra Function 16",
+        16 => "Bandai FCG",
+        19 => "Namco 163",
+        21 | 23 | 25 => "VRC2/VRC4",
+        22 => "VRC2a",
+        24 | 26 => "VRC6",
+        32 => "Irem G-101",
+        33 => "Taito TC0190",
+        34 => "BNROM/NINA-001",
+        48 => "Taito TC0690",
+        65 => "Irem H3001",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica BF909x",
+        73 => "VRC3",
+        75 => "VRC1",
+        80 => "Taito X1-005",
+        82 => "Taito X1-017",
+        85 => "VRC7",
+        159 => "Bandai LZ93D50",
+        59 | 225..=233 => "Multicart",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// iNES header 
+#[derive(Debug,Default)]
+pub struct Header {
+    // Constant $4E $45 $53 $1A ("NES" followed by MS-DOS end-of-file)
+    pub nes_constant: [u8; 4],
+    
+    // Size of PRG ROM in 16 KB units
+    pub prg_rom_size: u8,
+    
+    // Size of CHR ROM in 8 KB units (Value 0 means the board uses CHR RAM)
+    pub chr_rom_size: u8,
+    
+    // 76543210
+    // ||||||||
+    // |||||||+- Mirroring: 0: Horizontal (vertical arrangement) (CIRAM A10 = PPU A11)
+    // |||||||              1: vertical (Horizontal arrangement) (CIRAM A10 = PPU A10)
+    // ||||||+-- 1: Cartridge contains battery-backed PRG RAM ($6000-7FFF) or other persistent memory
+    // |||||+--- 1: 512-byte trainer at $7000-$71FF (stored before PRG data)
+    // ||||+---- 1: Ignore mirroring control or above mirroring bit; instead provide four-screen VRAM
+    // ++++----- Lower nybble of mapper number
+    pub flags_6: u8,
+
+    // 76543210
+    // ||||||||
+    // |||||||+- VS Unisystem
+    // ||||||+-- PlayChoice-10 (8KB of Hint Screen data stored after CHR data)
+    // ||||++--- If equal to 2, flags 8-15 are in NES 2.0 format
+    // ++++----- Upper nybble of mapper number
+    pub flags_7: u8,
+
+    // Size of PRG RAM in 8 KB units (Value 0 infers 8 KB for compatibility)
+    // NES 2.0:
+    // 76543210
+    // ||||||||
+    // ||||++++- Mapper number bits 8-11
+    // ++++----- Submapper number
+    pub prg_ram_size: u8,
+
+    // 76543210
+    // ||||||||
+    // |||||||+- TV system (0: NTSC; 1: PAL)
+    // +++++++-- Reserved, set to zero
+    pub flags_9: u8,
+
+    // 76543210
+    //   ||  ||
+    //   ||  ++- TV system (0: NTSC; 2: PAL; 1/3: dual compatible)
+    //   |+----- PRG RAM ($6000-$7FFF) (0: present; 1: not present)
+    //   +------ 0: Board has no bus conflicts; 1: Board has bus conflicts
+    // NES 2.0:
+    // 76543210
+    // ||||||||
+    // ||||++++- PRG RAM (volatile) shift count, size is 64 << shift
+    // ++++----- PRG NVRAM (battery-backed) shift count
+    pub flags_10: u8,
+
+    // The rest is used by NES 2.0 only and must be zero in iNES 1.0
+
+    // 76543210
+    // ||||||||
+    // ||||++++- CHR RAM size (volatile) shift count
+    // ++++----- CHR NVRAM size (battery-backed) shift count
+    pub flags_11: u8,
+
+    // 76543210
+    //       ||
+    //       ++- CPU/PPU timing (0: NTSC; 1: PAL; 2: multiple-region; 3: Dendy)
+    pub flags_12: u8,
+
+    // Vs. System PPU and hardware type, or extended console type
+    pub flags_13: u8,
+
+    // Number of miscellaneous ROMs present
+    pub flags_14: u8,
+
+    // Default expansion device
+    pub flags_15: u8,
+}
+
+impl Header {
+    pub fn new(rom_header: &[u8]) -> Result<Header, ROMReadError> {
+        let nes_const = &rom_header[0..4];
+        match ROM::check_type(nes_const) {
+            None => Err(ROMReadError::FormatError),
+            Some(ROMType::UNIF) | 
+            Some(ROMType::FDS) |
+            Some(ROMType::NSF) |
+            Some(ROMType::NSFE) => Err(ROMReadError::NotSupported),            
+            Some(ROMType::INES) => {
+                let mut new_nes_const: [u8; 4] = [0; 4];
+                new_nes_const.copy_from_slice(nes_const);
+
+                let header = Header { 
+                    nes_constant: new_nes_const,
+                    prg_rom_size: rom_header[4],
+                    chr_rom_size: rom_header[5],
+                    flags_6: rom_header[6],
+                    flags_7: rom_header[7],
+                    prg_ram_size: rom_header[8],
+                    flags_9: rom_header[9],
+                    flags_10: rom_header[10],
+                    flags_11: rom_header[11],
+                    flags_12: rom_header[12],
+                    flags_13: rom_header[13],
+                    flags_14: rom_header[14],
+                    flags_15: rom_header[15],
+                };
+                Ok(header)
+            },
+        } 
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[0..4].copy_from_slice(&self.nes_constant);
+        bytes[4] = self.prg_rom_size;
+        bytes[5] = self.chr_rom_size;
+        bytes[6] = self.flags_6;
+        bytes[7] = self.flags_7;
+        bytes[8] = self.prg_ram_size;
+        bytes[9] = self.flags_9;
+        bytes[10] = self.flags_10;
+        bytes[11] = self.flags_11;
+        bytes[12] = self.flags_12;
+        bytes[13] = self.flags_13;
+        bytes[14] = self.flags_14;
+        bytes[15] = self.flags_15;
+        bytes
+    }
+
+    // Old dumping tools wrote their name ("DiskDude!") into bytes 7-15.
+    // An iNES 1.0 header must have zeros in bytes 12-15, if it doesn't
+    // bytes 7-15 can't be trusted.
+    pub fn has_garbage(rom_header: &[u8]) -> bool {
+        let nes2 = rom_header[7] & 0x0c == 0x08;
+        !nes2 && rom_header[12..16].iter().any(|&byte| byte != 0)
+    }
+
+    pub fn clear_garbage(&mut self) {
+        self.flags_7 = 0;
+        self.prg_ram_size = 0;
+        self.flags_9 = 0;
+        self.flags_10 = 0;
+        self.flags_11 = 0;
+        self.flags_12 = 0;
+        self.flags_13 = 0;
+        self.flags_14 = 0;
+        self.flags_15 = 0;
+    }
+
+    pub fn is_nes2(&self) -> bool {
+        self.flags_7 & 0x0c == 0x08
+    }
+
+    // Rewrites an iNES 1.0 header as NES 2.0 keeping its meaning, so that
+    // the fields only NES 2.0 has (submapper, mapper bits 8-11) can be set
+    pub fn convert_to_nes2(&mut self) {
+        if self.is_nes2() {
+            return;
+        }
+        // 8 KB = 64 << 7
+        let prg_ram_shift = if self.has_battery() { 0x70 } else { 0x07 };
+        let chr_ram_shift = if self.chr_rom_size == 0 { 0x07 } else { 0x00 };
+        let timing = utils::get_bit(&self.flags_9, 0);
+
+        self.flags_7 = (self.flags_7 & 0xf3) | 0x08;
+        self.prg_ram_size = 0;
+        self.flags_9 = 0;
+        self.flags_10 = prg_ram_shift;
+        self.flags_11 = chr_ram_shift;
+        self.flags_12 = timing;
+        self.flags_13 = 0;
+        self.flags_14 = 0;
+        self.flags_15 = 0;
+    }
+
+    pub fn mapper(&self) -> u16 {
+        let mapper = ((self.flags_7 & 0xf0) | (self.flags_6 >> 4)) as u16;
+        if self.is_nes2() {
+            mapper | ((self.prg_ram_size & 0x0f) as u16) << 8
+        } else {
+            mapper
+        }
+    }
+
+    pub fn submapper(&self) -> u8 {
+        if self.is_nes2() { self.prg_ram_size >> 4 } else { 0 }
+    }
+
+    pub fn set_mapper(&mut self, mapper: u16, submapper: u8) {
+        if mapper > 0xff || submapper != 0 {
+            self.convert_to_nes2();
+        }
+        let low = mapper as u8;
+        self.flags_6 = (self.flags_6 & 0x0f) | (low << 4);
+        self.flags_7 = (self.flags_7 & 0x0f) | (low & 0xf0);
+        if self.is_nes2() {
+            self.prg_ram_size = (submapper << 4) | ((mapper >> 8) as u8 & 0x0f);
+        }
+    }
+
+    pub fn mirroring(&self) -> MirroringType {
+        if utils::get_bit(&self.flags_6, 3) == 1 {
+            MirroringType::FourScreen
+        } else if has_mapper_mirroring(self.mapper()) {
+            MirroringType::MapperControlled
+        } else if utils::get_bit(&self.flags_6, 0) == 1 {
+            MirroringType::Vertical
+        } else {
+            MirroringType::Horizontal
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        utils::get_bit(&self.flags_6, 1) == 1
+    }
+
+    // Vs. System arcade board
+    pub fn is_vs_system(&self) -> bool {
+        utils::get_bit(&self.flags_7, 0) == 1
+    }
+
+    // The PPU of a Vs. System board, only NES 2.0 headers have it
+    // (0-1, 6-7: 2C03, 2-5: 2C04, 8-C: 2C05)
+    pub fn vs_ppu(&self) -> Option<u8> {
+        if self.is_nes2() && self.is_vs_system() {
+            Some(self.flags_13 & 0x0f)
+        } else {
+            None
+        }
+    }
+
+    pub fn has_trainer(&self) -> bool {
+        utils::get_bit(&self.flags_6, 2) == 1
+    }
+
+    // iNES 1.0 keeps this in flags 10, NES 2.0 in the submapper of the
+    // discrete logic boards (1: no bus conflicts, 2: bus conflicts)
+    pub fn has_bus_conflicts(&self) -> bool {
+        if self.is_nes2() {
+            matches!(self.mapper(), 2 | 3 | 7) && self.submapper() == 2
+        } else {
+            utils::get_bit(&self.flags_10, 5) == 1
+        }
+    }
+
+    // PRG ROM size in bytes. NES 2.0 keeps the upper bits of the unit count
+    // in byte 9, MSB nybble $F switches to exponent-multiplier notation.
+    pub fn prg_rom_bytes(&self) -> usize {
+        rom_bytes(self.prg_rom_size, self.flags_9 & 0x0f, 16 * KB as usize, self.is_nes2())
+    }
+
+    pub fn chr_rom_bytes(&self) -> usize {
+        rom_bytes(self.chr_rom_size, self.flags_9 >> 4, 8 * KB as usize, self.is_nes2())
+    }
+
+    // Volatile PRG RAM at $6000-$7FFF in bytes
+    pub fn prg_ram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_bytes(self.flags_10 & 0x0f)
+        } else if self.has_battery() {
+            0
+        } else {
+            self.ines_prg_ram_bytes()
+        }
+    }
+
+    // Battery-backed PRG RAM (or EEPROM) in bytes
+    pub fn prg_nvram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_bytes(self.flags_10 >> 4)
+        } else if self.has_battery() {
+            self.ines_prg_ram_bytes()
+        } else {
+            0
+        }
+    }
+
+    // iNES 1.0 boards are assumed to have CHR RAM only without CHR ROM
+    pub fn chr_ram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_bytes(self.flags_11 & 0x0f)
+        } else if self.chr_rom_size == 0 {
+            8 * KB as usize
+        } else {
+            0
+        }
+    }
+
+    pub fn chr_nvram_bytes(&self) -> usize {
+        if self.is_nes2() { shift_bytes(self.flags_11 >> 4) } else { 0 }
+    }
+
+    fn ines_prg_ram_bytes(&self) -> usize {
+        self.prg_ram_size.max(1) as usize * 8 * KB as usize
+    }
+
+    pub fn region(&self) -> Region {
+        if self.is_nes2() {
+            return Region::from_timing(self.flags_12);
+        }
+        if utils::get_bit(&self.flags_9, 0) == 1 {
+            return Region::PAL;
+        }
+        match self.flags_10 & 0x03 {
+            1 | 3 => Region::Dual,
+            2 => Region::PAL,
+            _ => Region::NTSC,
+        }
+    }
+}
+
+// 0 means none, otherwise 64 << shift
+fn shift_bytes(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+// EEEE EEMM: 2^E * (MM * 2 + 1) bytes
+fn rom_bytes(lsb: u8, msb: u8, unit: usize, nes2: bool) -> usize {
+    if !nes2 {
+        lsb as usize * unit
+    } else if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+// File formats told apart by their first bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    INES,
+    NES2,
+    UNIF,
+    FDS,
+    NSF,
+    NSFE,
+}
+
+impl Format {
+    pub fn detect(raw_data: &[u8]) -> Option<Format> {
+        let format = match ROM::check_type(raw_data.get(0..4)?)? {
+            ROMType::INES if raw_data.len() >= 16 && raw_data[7] & 0x0c == 0x08 => Format::NES2,
+            ROMType::INES => Format::INES,
+            ROMType::UNIF => Format::UNIF,
+            ROMType::FDS => Format::FDS,
+            ROMType::NSF => Format::NSF,
+            ROMType::NSFE => Format::NSFE,
+        };
+        Some(format)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::INES => "iNES",
+            Format::NES2 => "NES 2.0",
+            Format::UNIF => "UNIF",
+            Format::FDS => "FDS",
+            Format::NSF => "NSF",
+            Format::NSFE => "NSFe",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    // Correct the header from the game database and drop garbage in bytes 7-15.
+    // Homebrew developers want their headers taken as they are.
+    pub header_overrides: bool,
+    // IPS/UPS/BPS patches applied in order before the header is parsed
+    pub patches: Vec<PathBuf>,
+    // Apply a patch with the ROM's file stem found next to it. Not looked
+    // for when `patches` are given, they may well include it.
+    pub auto_patch: bool,
+    // File to take from a zip archive instead of the first ROM in it
+    pub archive_entry: Option<String>,
+    // Database to identify the image with instead of the embedded one,
+    // e.g. the full NES 2.0 database read with `Database::load`
+    pub database: Option<Arc<Database>>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            header_overrides: true,
+            patches: Vec::new(),
+            auto_patch: true,
+            archive_entry: None,
+            database: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ROM {
+    // 16 bytes
+    pub header: Header,
+    // 512 bytes mapped to $7000-$71FF
+    pub trainer: Option<Vec<u8>>,
+    pub mirroring: MirroringType,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // Hashes of PRG ROM and CHR ROM
+    pub hashes: Hashes,
+    // Game database entry matching the hashes
+    pub game: Option<GameInfo>,
+    // Whether the header was corrected while loading
+    pub header_fixed: bool,
+    // Name of the file inside the zip or gzip archive the ROM came from
+    pub archive_entry: Option<String>,
+    // Board name of UNIF images
+    pub board: Option<String>,
+}
+
+impl ROM {
+    pub fn load(path: &str) -> Result<ROM, ROMReadError> {
+        ROM::load_with_options(path, &LoadOptions::default())
+    }
+
+    pub fn load_with_options(path: &str, options: &LoadOptions) -> Result<ROM, ROMReadError> {
+        let image = archive::read_image(Path::new(path), options.archive_entry.as_deref())?;
+        let mut raw_data = image.data;
+
+        let auto_patch = if options.auto_patch && options.patches.is_empty() {
+            patch::find_patch(path)
+        } else {
+            None
+        };
+        for patch_path in auto_patch.iter().chain(options.patches.iter()) {
+            raw_data = patch::apply_file(patch_path, &raw_data)?;
+        }
+        let mut rom = ROM::from_bytes(&raw_data, options)?;
+        rom.archive_entry = image.entry_name;
+        Ok(rom)
+    }
+
+    pub fn from_bytes(raw_data: &[u8], options: &LoadOptions) -> Result<ROM, ROMReadError> {
+        if raw_data.len() < 16 {
+            return Err(ROMReadError::FormatError);
+        }
+        if let Some(ROMType::UNIF) = ROM::check_type(&raw_data[0..4]) {
+            return unif::from_bytes(raw_data, options);
+        }
+        let mut header = Header::new(&raw_data[0..16])?;
+        let mut header_fixed = false;
+        if options.header_overrides && Header::has_garbage(&raw_data[0..16]) {
+            header.clear_garbage();
+            header_fixed = true;
+        }
+
+        let mut data_start: usize = 16;
+        let trainer = if header.has_trainer() {
+            data_start += 512;
+            raw_data.get(16..data_start).map(|trainer| trainer.to_vec())
+        } else {
+            None
+        };
+
+        // NES 2.0 exponent sizes go up to far more than any file holds
+        let prg_rom_end = data_start.checked_add(header.prg_rom_bytes());
+        let chr_rom_end = prg_rom_end.and_then(|end| end.checked_add(header.chr_rom_bytes()));
+        let (prg_rom_end, chr_rom_end) = match (prg_rom_end, chr_rom_end) {
+            (Some(prg_rom_end), Some(chr_rom_end)) if chr_rom_end <= raw_data.len() => (prg_rom_end, chr_rom_end),
+            _ => return Err(ROMReadError::FormatError),
+        };
+
+        let prg_rom = raw_data[data_start..prg_rom_end].to_vec();
+        let chr_rom = raw_data[prg_rom_end..chr_rom_end].to_vec();
+        Ok(ROM::assemble(header, trainer, prg_rom, chr_rom, header_fixed, options))
+    }
+
+    // Puts the parts of an image together and identifies it
+    fn assemble(header: Header, trainer: Option<Vec<u8>>, prg_rom: Vec<u8>, chr_rom: Vec<u8>,
+                header_fixed: bool, options: &LoadOptions) -> ROM {
+        let hashes = Hashes::compute(&prg_rom, &chr_rom);
+        let mut rom = ROM {
+            header,
+            trainer,
+            mirroring: MirroringType::Horizontal,
+            prg_rom,
+            chr_rom,
+            hashes,
+            game: None,
+            header_fixed,
+            archive_entry: None,
+            board: None,
+        };
+        let db = options.database.as_deref().unwrap_or_else(|| Database::embedded());
+        rom.identify(db, options.header_overrides);
+        rom
+    }
+
+    // The image as an .nes file: header, trainer, PRG ROM and CHR ROM
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let trainer = self.trainer.as_ref().map_or(0, |trainer| trainer.len());
+        let mut bytes = Vec::with_capacity(16 + trainer + self.prg_rom.len() + self.chr_rom.len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        if let Some(ref trainer) = self.trainer {
+            bytes.extend_from_slice(trainer);
+        }
+        bytes.extend_from_slice(&self.prg_rom);
+        bytes.extend_from_slice(&self.chr_rom);
+        bytes
+    }
+
+    // Looks the image up in the database and, if `fix_header` is set,
+    // replaces the header fields with the ones from the database
+    pub fn identify(&mut self, db: &Database, fix_header: bool) {
+        self.game = db.find(&self.hashes).cloned();
+        if fix_header {
+            if let Some(ref game) = self.game {
+                self.header_fixed |= game.fix_header(&mut self.header);
+            }
+        }
+
+        self.mirroring = self.header.mirroring();
+    }
+
+    pub fn mapper(&self) -> u16 {
+        self.header.mapper()
+    }
+
+    pub fn submapper(&self) -> u8 {
+        self.header.submapper()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery()
+    }
+
+    pub fn has_bus_conflicts(&self) -> bool {
+        self.header.has_bus_conflicts()
+    }
+
+    pub fn region(&self) -> Region {
+        self.header.region()
+    }
+
+    // Check ROM type for recognition file format
+    fn check_type(nes_constant: &[u8]) -> Option<ROMType> {
+        let ines_const = [0x4e, 0x45, 0x53, 0x1a];
+        let fds_const = [0x46, 0x44, 0x53, 0x1a];
+        // Headerless .fds dumps start with the disk info block: $01 "*NI..."
+        let fds_raw_const = [0x01, 0x2a, 0x4e, 0x49];
+        let unif_const = [0x55, 0x4e, 0x49, 0x46];
+        // "NESM" $1A and "NSFE"
+        let nsf_const = [0x4e, 0x45, 0x53, 0x4d];
+        let nsfe_const = [0x4e, 0x53, 0x46, 0x45];
+
+        if nes_constant == fds_const || nes_constant == fds_raw_const {
+            return Some(ROMType::FDS);
+        } else if nes_constant == ines_const {
+            return Some(ROMType::INES);
+        } else if nes_constant == unif_const {
+            return Some(ROMType::UNIF);
+        } else if nes_constant == nsf_const {
+            return Some(ROMType::NSF);
+        } else if nes_constant == nsfe_const {
+            return Some(ROMType::NSFE);
+        }
+        None
+    }
+}
+
+impl fmt::Display for ROM {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Header: {:?}, Mirroring type: {}, \
+               PRG ROM: {} KB, CHR ROM: {} KB",
+               self.header.nes_constant, self.mirroring.name(), 
+               self.prg_rom.len() / KB as usize, self.chr_rom.len() / KB as usize)?;
+        if let Some(ref entry) = self.archive_entry {
+            write!(f, ", File: {}", entry)?;
+        }
+        if let Some(ref game) = self.game {
+            write!(f, ", Game: {}", game.title)?;
+        }
+        Ok(())
+    }
+} 
+
+pub fn read_bin<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, ROMReadError> {
+    let mut file = File::open(path)?;
+    let mut buffer: Vec<u8> = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+
+#[cfg(test)]
+mod test {
+    use rom::*;
+
+    fn header(bytes: [u8; 12]) -> Header {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a];
+        data.extend_from_slice(&bytes);
+        Header::new(&data).unwrap()
+    }
+
+    #[test]
+    fn reading_mapper_number() {
+        let ines = header([2, 1, 0x41, 0x20, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(!ines.is_nes2());
+        assert_eq!(ines.mapper(), 0x24);
+        assert_eq!(ines.submapper(), 0);
+
+        let nes2 = header([2, 1, 0x41, 0x28, 0x31, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(nes2.is_nes2());
+        assert_eq!(nes2.mapper(), 0x124);
+        assert_eq!(nes2.submapper(), 3);
+    }
+
+    #[test]
+    fn reading_mirroring() {
+        assert_eq!(header([1, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0]).mirroring(),
+                   MirroringType::Horizontal);
+        assert_eq!(header([1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]).mirroring(),
+                   MirroringType::Vertical);
+        // MMC3 with four-screen VRAM
+        assert_eq!(header([1, 1, 0x48, 0, 0, 0, 0, 0, 0, 0, 0, 0]).mirroring(),
+                   MirroringType::FourScreen);
+        assert_eq!(header([1, 1, 0x41, 0, 0, 0, 0, 0, 0, 0, 0, 0]).mirroring(),
+                   MirroringType::MapperControlled);
+    }
+
+    #[test]
+    fn reading_flags() {
+        let ines = header([1, 1, 0x02, 0, 0, 1, 0x20, 0, 0, 0, 0, 0]);
+        assert!(ines.has_battery());
+        assert!(ines.has_bus_conflicts());
+        assert_eq!(ines.region(), Region::PAL);
+        assert_eq!(header([1, 1, 0, 0, 0, 0, 0x01, 0, 0, 0, 0, 0]).region(), Region::Dual);
+
+        // UxROM, NES 2.0 submapper 2
+        let nes2 = header([1, 1, 0x20, 0x08, 0x20, 0, 0, 0, 0x03, 0, 0, 0]);
+        assert!(nes2.has_bus_conflicts());
+        assert!(!nes2.has_battery());
+        assert_eq!(nes2.region(), Region::Dendy);
+    }
+
+    #[test]
+    fn reading_sizes() {
+        let ines = header([2, 0, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(ines.prg_rom_bytes(), 32 * 1024);
+        assert_eq!(ines.chr_rom_bytes(), 0);
+        assert_eq!(ines.chr_ram_bytes(), 8 * 1024);
+        assert_eq!(ines.prg_ram_bytes(), 0);
+        assert_eq!(ines.prg_nvram_bytes(), 8 * 1024);
+
+        // 4 MB PRG ROM in 256 + 0 units, CHR in exponent notation (2^10 * 3)
+        let nes2 = header([0x00, 0x29, 0, 0x08, 0, 0xf1, 0x07, 0x70, 0, 0, 0, 0]);
+        assert_eq!(nes2.prg_rom_bytes(), 4096 * 1024);
+        assert_eq!(nes2.chr_rom_bytes(), 3 * 1024);
+        assert_eq!(nes2.prg_ram_bytes(), 8 * 1024);
+        assert_eq!(nes2.prg_nvram_bytes(), 0);
+        assert_eq!(nes2.chr_nvram_bytes(), 8 * 1024);
+    }
+
+    #[test]
+    fn reading_oversized_roms() {
+        // PRG ROM of 2^63 * 7 bytes in exponent notation
+        let bytes = [0xff, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0];
+        assert_eq!(header(bytes).prg_rom_bytes(), usize::MAX);
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a];
+        data.extend_from_slice(&bytes);
+        data.resize(16 + 32 * 1024, 0);
+        assert!(matches!(ROM::from_bytes(&data, &LoadOptions::default()), Err(ROMReadError::FormatError)));
+    }
+
+    #[test]
+    fn converting_to_nes2() {
+        let mut header = header([2, 0, 0x12, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        header.set_mapper(0x101, 5);
+
+        assert!(header.is_nes2());
+        assert_eq!(header.mapper(), 0x101);
+        assert_eq!(header.submapper(), 5);
+        assert!(header.has_battery());
+        assert_eq!(header.region(), Region::PAL);
+        assert_eq!(header.flags_10, 0x70);
+        assert_eq!(header.flags_11, 0x07);
+    }
+}
@@ -0,0 +1,453 @@
+// NES Sound Format (.nsf) and its chunk based successor (.nsfe)
+// https://wiki.nesdev.com/w/index.php/NSF
+// https://wiki.nesdev.com/w/index.php/NSFe
+use rom::{archive, ROMReadError, ROMType, Region, ROM};
+
+use std::fmt;
+use std::path::Path;
+
+const HEADER_SIZE: usize = 0x80;
+// Default PLAY rates in microseconds (1/60.1 and 1/50.0 seconds)
+pub const NTSC_SPEED: u16 = 16639;
+pub const PAL_SPEED: u16 = 19997;
+
+// Expansion sound chips byte ($7B in NSF, INFO byte 7 in NSFe)
+// 7  bit  0
+// ---- ----
+// xVSN MFRC
+//  ||| ||||
+//  ||| |||+- Konami VRC6
+//  ||| ||+-- Konami VRC7
+//  ||| |+--- Famicom Disk System
+//  ||| +---- Nintendo MMC5
+//  ||+------ Namco 163
+//  |+------- Sunsoft 5B
+//  +-------- VT02+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpansionChips {
+    pub vrc6: bool,
+    pub vrc7: bool,
+    pub fds: bool,
+    pub mmc5: bool,
+    pub n163: bool,
+    pub sunsoft_5b: bool,
+    pub vt02: bool,
+}
+
+impl ExpansionChips {
+    pub fn from_flags(flags: u8) -> ExpansionChips {
+        ExpansionChips {
+            vrc6: flags & 0x01 != 0,
+            vrc7: flags & 0x02 != 0,
+            fds: flags & 0x04 != 0,
+            mmc5: flags & 0x08 != 0,
+            n163: flags & 0x10 != 0,
+            sunsoft_5b: flags & 0x20 != 0,
+            vt02: flags & 0x40 != 0,
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.vrc6 as u8 | (self.vrc7 as u8) << 1 | (self.fds as u8) << 2 |
+        (self.mmc5 as u8) << 3 | (self.n163 as u8) << 4 |
+        (self.sunsoft_5b as u8) << 5 | (self.vt02 as u8) << 6
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let chips = [(self.vrc6, "VRC6"), (self.vrc7, "VRC7"), (self.fds, "FDS"),
+                     (self.mmc5, "MMC5"), (self.n163, "N163"),
+                     (self.sunsoft_5b, "Sunsoft 5B"), (self.vt02, "VT02+")];
+        chips.iter().filter(|chip| chip.0).map(|chip| chip.1).collect()
+    }
+}
+
+// Per track metadata, only NSFe and NSF2 files carry it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub label: Option<String>,
+    // Length and fade out in milliseconds
+    pub time: Option<u32>,
+    pub fade: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct NSF {
+    // 0 for NSFe files
+    pub version: u8,
+    pub songs: u8,
+    // Zero based, unlike the NSF header
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: Option<String>,
+    // PLAY rates in microseconds
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // Initial values of $5FF8-$5FFF, None when the tune isn't bankswitched
+    pub bankswitch: Option<[u8; 8]>,
+    pub region: Region,
+    pub chips: ExpansionChips,
+    pub tracks: Vec<Track>,
+    // Order the songs should be played in
+    pub playlist: Option<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+impl NSF {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<NSF, ROMReadError> {
+        let image = archive::read_image(path, None)?;
+        NSF::from_bytes(&image.data)
+    }
+
+    pub fn from_bytes(raw_data: &[u8]) -> Result<NSF, ROMReadError> {
+        if raw_data.len() < 4 {
+            return Err(ROMReadError::FormatError);
+        }
+        match ROM::check_type(&raw_data[0..4]) {
+            Some(ROMType::NSF) => NSF::parse_nsf(raw_data),
+            Some(ROMType::NSFE) => NSF::parse_nsfe(raw_data),
+            Some(_) => Err(ROMReadError::NotSupported),
+            None => Err(ROMReadError::FormatError),
+        }
+    }
+
+    fn empty() -> NSF {
+        NSF {
+            version: 0,
+            songs: 1,
+            starting_song: 0,
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: None,
+            ntsc_speed: NTSC_SPEED,
+            pal_speed: PAL_SPEED,
+            bankswitch: None,
+            region: Region::NTSC,
+            chips: ExpansionChips::default(),
+            tracks: Vec::new(),
+            playlist: None,
+            data: Vec::new(),
+        }
+    }
+
+    fn parse_nsf(raw_data: &[u8]) -> Result<NSF, ROMReadError> {
+        if raw_data.len() < HEADER_SIZE || raw_data[4] != 0x1a {
+            return Err(ROMReadError::FormatError);
+        }
+        let header = &raw_data[..HEADER_SIZE];
+        let mut nsf = NSF::empty();
+        nsf.version = header[0x05];
+        nsf.songs = header[0x06];
+        nsf.starting_song = header[0x07].saturating_sub(1);
+        nsf.load_address = read_u16(header, 0x08);
+        nsf.init_address = read_u16(header, 0x0a);
+        nsf.play_address = read_u16(header, 0x0c);
+        nsf.title = read_string(&header[0x0e..0x2e]);
+        nsf.artist = read_string(&header[0x2e..0x4e]);
+        nsf.copyright = read_string(&header[0x4e..0x6e]);
+        nsf.ntsc_speed = read_u16(header, 0x6e);
+        nsf.bankswitch = bankswitch_values(&header[0x70..0x78]);
+        nsf.pal_speed = read_u16(header, 0x78);
+        nsf.region = region(header[0x7a]);
+        nsf.chips = ExpansionChips::from_flags(header[0x7b]);
+
+        // NSF2 may put NSFe metadata chunks right after the program data
+        let data_length = header[0x7d] as usize | (header[0x7e] as usize) << 8 |
+                          (header[0x7f] as usize) << 16;
+        let data = &raw_data[HEADER_SIZE..];
+        if nsf.version >= 2 && data_length > 0 && data_length <= data.len() {
+            nsf.data = data[..data_length].to_vec();
+            nsf.tracks = vec![Track::default(); nsf.songs as usize];
+            parse_chunks(&mut nsf, &data[data_length..], true)?;
+        } else {
+            nsf.data = data.to_vec();
+            nsf.tracks = vec![Track::default(); nsf.songs as usize];
+        }
+        Ok(nsf)
+    }
+
+    fn parse_nsfe(raw_data: &[u8]) -> Result<NSF, ROMReadError> {
+        let mut nsf = NSF::empty();
+        parse_chunks(&mut nsf, &raw_data[4..], false)?;
+        Ok(nsf)
+    }
+
+    pub fn uses_bankswitching(&self) -> bool {
+        self.bankswitch.is_some()
+    }
+
+    // PLAY period in microseconds for the given console region
+    pub fn play_speed(&self, region: Region) -> u16 {
+        match region {
+            Region::PAL | Region::Dendy => self.pal_speed,
+            Region::NTSC | Region::Dual => self.ntsc_speed,
+        }
+    }
+
+    pub fn track(&self, song: u8) -> Track {
+        self.tracks.get(song as usize).cloned().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for NSF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.version == 0 {
+            writeln!(f, "Format: NSFe")?;
+        } else {
+            writeln!(f, "Format: NSF version {}", self.version)?;
+        }
+        writeln!(f, "Title: {}\nArtist: {}\nCopyright: {}", self.title, self.artist, self.copyright)?;
+        writeln!(f, "Songs: {}, starting with {}", self.songs, self.starting_song + 1)?;
+        writeln!(f, "Load: ${:04X}, init: ${:04X}, play: ${:04X}",
+                 self.load_address, self.init_address, self.play_address)?;
+        writeln!(f, "Region: {:?}", self.region)?;
+        if let Some(ref banks) = self.bankswitch {
+            writeln!(f, "Banks: {:02X?}", banks)?;
+        }
+        let chips = self.chips.names();
+        if !chips.is_empty() {
+            writeln!(f, "Expansion audio: {}", chips.join(", "))?;
+        }
+        for (i, track) in self.tracks.iter().enumerate() {
+            if track.label.is_none() && track.time.is_none() {
+                continue;
+            }
+            write!(f, "  {:>3}. {}", i + 1, track.label.as_deref().unwrap_or(""))?;
+            if let Some(time) = track.time {
+                write!(f, " ({}:{:02})", time / 60_000, time / 1000 % 60)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// Chunks are "length (4 bytes) id (4 bytes) data". Chunks with an upper case
+// first letter in the id must be understood, the rest may be skipped.
+fn parse_chunks(nsf: &mut NSF, mut data: &[u8], metadata_only: bool) -> Result<(), ROMReadError> {
+    let mut has_info = metadata_only;
+    let mut has_data = metadata_only;
+    while data.len() >= 8 {
+        let length = data[0] as usize | (data[1] as usize) << 8 |
+                     (data[2] as usize) << 16 | (data[3] as usize) << 24;
+        let id = &data[4..8];
+        if data.len() - 8 < length {
+            return Err(ROMReadError::FormatError);
+        }
+        let chunk = &data[8..8 + length];
+        data = &data[8 + length..];
+
+        match id {
+            b"INFO" if !metadata_only => {
+                if chunk.len() < 8 {
+                    return Err(ROMReadError::FormatError);
+                }
+                nsf.load_address = read_u16(chunk, 0);
+                nsf.init_address = read_u16(chunk, 2);
+                nsf.play_address = read_u16(chunk, 4);
+                nsf.region = region(chunk[6]);
+                nsf.chips = ExpansionChips::from_flags(chunk[7]);
+                nsf.songs = chunk.get(8).cloned().unwrap_or(1);
+                nsf.starting_song = chunk.get(9).cloned().unwrap_or(0);
+                nsf.tracks = vec![Track::default(); nsf.songs as usize];
+                has_info = true;
+            },
+            b"DATA" if !metadata_only => {
+                nsf.data = chunk.to_vec();
+                has_data = true;
+            },
+            b"BANK" => {
+                let mut banks = [0u8; 8];
+                banks[..chunk.len().min(8)].copy_from_slice(&chunk[..chunk.len().min(8)]);
+                nsf.bankswitch = Some(banks);
+            },
+            b"RATE" => {
+                if chunk.len() >= 2 {
+                    nsf.ntsc_speed = read_u16(chunk, 0);
+                }
+                if chunk.len() >= 4 {
+                    nsf.pal_speed = read_u16(chunk, 2);
+                }
+            },
+            b"NEND" => break,
+            b"auth" => {
+                let mut strings = chunk.split(|&b| b == 0).map(read_string);
+                nsf.title = strings.next().unwrap_or_default();
+                nsf.artist = strings.next().unwrap_or_default();
+                nsf.copyright = strings.next().unwrap_or_default();
+                nsf.ripper = strings.next().filter(|ripper| !ripper.is_empty());
+            },
+            b"plst" => nsf.playlist = Some(chunk.to_vec()),
+            b"tlbl" => {
+                for (track, label) in nsf.tracks.iter_mut().zip(chunk.split(|&b| b == 0)) {
+                    track.label = Some(read_string(label));
+                }
+            },
+            b"time" | b"fade" => {
+                let fade = id == b"fade";
+                for (track, value) in nsf.tracks.iter_mut().zip(chunk.chunks(4)) {
+                    if value.len() < 4 {
+                        break;
+                    }
+                    let ms = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                    // Negative values mean "use the player's default"
+                    let ms = if ms < 0 { None } else { Some(ms as u32) };
+                    if fade {
+                        track.fade = ms;
+                    } else {
+                        track.time = ms;
+                    }
+                }
+            },
+            _ if id[0].is_ascii_uppercase() => return Err(ROMReadError::NotSupported),
+            _ => {},
+        }
+    }
+
+    if !has_info || !has_data {
+        return Err(ROMReadError::FormatError);
+    }
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+// Null terminated (or padded) string
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+// ---- --DP: P is PAL, D is dual NTSC/PAL
+fn region(flags: u8) -> Region {
+    if flags & 0x02 != 0 {
+        Region::Dual
+    } else if flags & 0x01 != 0 {
+        Region::PAL
+    } else {
+        Region::NTSC
+    }
+}
+
+fn bankswitch_values(banks: &[u8]) -> Option<[u8; 8]> {
+    if banks.iter().all(|&bank| bank == 0) {
+        return None;
+    }
+    let mut values = [0u8; 8];
+    values.copy_from_slice(banks);
+    Some(values)
+}
+
+#[cfg(test)]
+pub mod test {
+    use rom::nsf::*;
+
+    // A tune with INIT at $8000 and PLAY at $8001, both just return
+    pub fn make_nsf(program: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        data[..5].copy_from_slice(b"NESM\x1a");
+        data[0x05] = 1;
+        data[0x06] = 3;
+        data[0x07] = 2;
+        data[0x08..0x0e].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x01, 0x80]);
+        data[0x0e..0x13].copy_from_slice(b"Tune\0");
+        data[0x2e..0x35].copy_from_slice(b"Someone");
+        data[0x6e..0x70].copy_from_slice(&NTSC_SPEED.to_le_bytes());
+        data[0x78..0x7a].copy_from_slice(&PAL_SPEED.to_le_bytes());
+        data[0x7b] = 0b0010_0001;
+        data.extend_from_slice(program);
+        data
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn parsing_nsf() {
+        let nsf = NSF::from_bytes(&make_nsf(&[0x60, 0x60])).unwrap();
+        assert_eq!(nsf.version, 1);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.play_address, 0x8001);
+        assert_eq!(nsf.title, "Tune");
+        assert_eq!(nsf.artist, "Someone");
+        assert_eq!(nsf.region, Region::NTSC);
+        assert_eq!(nsf.chips.names(), vec!["VRC6", "Sunsoft 5B"]);
+        assert_eq!(nsf.chips.flags(), 0b0010_0001);
+        assert!(!nsf.uses_bankswitching());
+        assert_eq!(nsf.data, vec![0x60, 0x60]);
+        assert_eq!(nsf.tracks.len(), 3);
+
+        let mut data = make_nsf(&[]);
+        data[0x70] = 1;
+        let nsf = NSF::from_bytes(&data).unwrap();
+        assert_eq!(nsf.bankswitch, Some([1, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn parsing_nsfe() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x01, 0x80, 0x01, 0x02, 2, 1]));
+        data.extend(chunk(b"DATA", &[0x60, 0x60]));
+        data.extend(chunk(b"auth", b"Game\0Composer\0(c) Company\0Ripper\0"));
+        data.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+        let mut times = 90_000i32.to_le_bytes().to_vec();
+        times.extend_from_slice(&(-1i32).to_le_bytes());
+        data.extend(chunk(b"time", &times));
+        data.extend(chunk(b"fade", &1_000i32.to_le_bytes()));
+        data.extend(chunk(b"RATE", &[0x1a, 0x41]));
+        data.extend(chunk(b"xtra", b"skipped"));
+        data.extend(chunk(b"NEND", &[]));
+
+        let nsf = NSF::from_bytes(&data).unwrap();
+        assert_eq!(nsf.version, 0);
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.region, Region::PAL);
+        assert!(nsf.chips.vrc7);
+        assert_eq!(nsf.title, "Game");
+        assert_eq!(nsf.ripper, Some("Ripper".to_string()));
+        assert_eq!(nsf.ntsc_speed, 0x411a);
+        assert_eq!(nsf.track(0), Track {
+            label: Some("Intro".to_string()),
+            time: Some(90_000),
+            fade: Some(1_000),
+        });
+        assert_eq!(nsf.track(1).time, None);
+        assert_eq!(nsf.track(5), Track::default());
+
+        // Unknown required chunk
+        let mut broken = data[..data.len() - 8].to_vec();
+        broken.extend(chunk(b"XTRA", &[]));
+        assert!(NSF::from_bytes(&broken).is_err());
+        // No DATA
+        let mut broken = b"NSFE".to_vec();
+        broken.extend(chunk(b"INFO", &[0; 8]));
+        assert!(NSF::from_bytes(&broken).is_err());
+    }
+
+    #[test]
+    fn parsing_nsf2_metadata() {
+        let mut data = make_nsf(&[0x60, 0x60]);
+        data[0x05] = 2;
+        data[0x7d] = 2;
+        data.extend(chunk(b"tlbl", b"One\0Two\0Three\0"));
+        let nsf = NSF::from_bytes(&data).unwrap();
+        assert_eq!(nsf.data, vec![0x60, 0x60]);
+        assert_eq!(nsf.track(2).label, Some("Three".to_string()));
+    }
+}
@@ -0,0 +1,546 @@
+// Soft-patching of ROM images with IPS, UPS and BPS patches.
+// Patches are applied to the whole file (header included) in memory,
+// the file on disk is never touched.
+use rom::read_bin;
+
+use crc32fast;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Extensions looked for next to the ROM file, in order of preference
+const EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+// Largest image a UPS or BPS patch may make. The biggest NES ROMs are a
+// few MB, larger sizes come from broken patches and would exhaust memory.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum PatchError {
+    // IO error while reading the patch file
+    IoError(io::Error),
+    // Unknown magic number
+    UnknownFormat,
+    // The patch ended in the middle of a record
+    Truncated,
+    // The patch points outside of the source or target image
+    OutOfBounds,
+    // The source image isn't the one the patch was made for
+    SourceMismatch { expected: u32, actual: u32 },
+    // The source image has the checksum but not the size the patch expects
+    SourceSizeMismatch { expected: usize, actual: usize },
+    // The patched image doesn't match the checksum stored in the patch
+    TargetMismatch { expected: u32, actual: u32 },
+    // The patch file itself is damaged
+    PatchMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::IoError(ref err) => write!(f, "{}", err),
+            PatchError::SourceMismatch { expected, actual } =>
+                write!(f, "Source CRC32 mismatch: patch expects {:08X}, ROM has {:08X}",
+                       expected, actual),
+            PatchError::SourceSizeMismatch { expected, actual } =>
+                write!(f, "Source size mismatch: patch expects {} bytes, ROM has {}",
+                       expected, actual),
+            PatchError::TargetMismatch { expected, actual } =>
+                write!(f, "Target CRC32 mismatch: expected {:08X}, got {:08X}",
+                       expected, actual),
+            PatchError::PatchMismatch { expected, actual } =>
+                write!(f, "Patch CRC32 mismatch: expected {:08X}, got {:08X}",
+                       expected, actual),
+            ref other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(error: io::Error) -> PatchError {
+        PatchError::IoError(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatchFormat {
+    IPS,
+    UPS,
+    BPS,
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(b"PATCH") {
+            Some(PatchFormat::IPS)
+        } else if patch.starts_with(b"UPS1") {
+            Some(PatchFormat::UPS)
+        } else if patch.starts_with(b"BPS1") {
+            Some(PatchFormat::BPS)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn apply(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::IPS) => apply_ips(patch, source),
+        Some(PatchFormat::UPS) => apply_ups(patch, source),
+        Some(PatchFormat::BPS) => apply_bps(patch, source),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+pub fn apply_file<P: AsRef<Path>>(path: P, source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let patch = read_bin(path).map_err(|err| match err {
+        ::rom::ROMReadError::IoError(err) => PatchError::IoError(err),
+        _ => PatchError::UnknownFormat,
+    })?;
+    apply(&patch, source)
+}
+
+// Looks for `<stem>.bps`, `<stem>.ups` or `<stem>.ips` next to the ROM
+pub fn find_patch<P: AsRef<Path>>(rom_path: P) -> Option<PathBuf> {
+    let rom_path = rom_path.as_ref();
+    EXTENSIONS.iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file() && path != rom_path)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.data.get(self.pos).ok_or(PatchError::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(count).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(PatchError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn big_endian(&mut self, count: usize) -> Result<usize, PatchError> {
+        let bytes = self.bytes(count)?;
+        Ok(bytes.iter().fold(0, |acc, &byte| acc << 8 | byte as usize))
+    }
+
+    // Variable-length number shared by UPS and BPS. Numbers too big for a
+    // usize can't point anywhere in the images.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut data: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.byte()?;
+            data = ((x & 0x7f) as usize).checked_mul(shift)
+                .and_then(|value| data.checked_add(value))
+                .ok_or(PatchError::OutOfBounds)?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            data = data.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+}
+
+// IPS: "PATCH", records of 24-bit offset + 16-bit size (0 for RLE), "EOF",
+// optionally followed by a 24-bit truncation length
+fn apply_ips(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, 5);
+    loop {
+        if reader.data[reader.pos..].starts_with(b"EOF") {
+            reader.pos += 3;
+            if reader.data.len() - reader.pos == 3 {
+                let size = reader.big_endian(3)?;
+                target.truncate(size);
+            }
+            return Ok(target);
+        }
+
+        let offset = reader.big_endian(3)?;
+        let size = reader.big_endian(2)?;
+        if size == 0 {
+            let count = reader.big_endian(2)?;
+            let value = reader.byte()?;
+            if target.len() < offset + count {
+                target.resize(offset + count, 0);
+            }
+            for byte in &mut target[offset..offset + count] {
+                *byte = value;
+            }
+        } else {
+            let data = reader.bytes(size)?;
+            if target.len() < offset + size {
+                target.resize(offset + size, 0);
+            }
+            target[offset..offset + size].copy_from_slice(data);
+        }
+    }
+}
+
+// UPS and BPS end with CRC32s of the source, the target and the patch
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+fn read_footer(patch: &[u8]) -> Result<Footer, PatchError> {
+    if patch.len() < 16 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[patch.len() - 12..];
+    let le = |bytes: &[u8]| bytes.iter().rev().fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+
+    let expected = le(&footer[8..12]);
+    let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+    if expected != actual {
+        return Err(PatchError::PatchMismatch { expected, actual });
+    }
+    Ok(Footer {
+        source: le(&footer[0..4]),
+        target: le(&footer[4..8]),
+    })
+}
+
+fn check_source(footer: &Footer, source: &[u8]) -> Result<(), PatchError> {
+    let actual = crc32fast::hash(source);
+    if footer.source != actual {
+        return Err(PatchError::SourceMismatch { expected: footer.source, actual });
+    }
+    Ok(())
+}
+
+fn check_target(footer: &Footer, target: &[u8]) -> Result<(), PatchError> {
+    let actual = crc32fast::hash(target);
+    if footer.target != actual {
+        return Err(PatchError::TargetMismatch { expected: footer.target, actual });
+    }
+    Ok(())
+}
+
+// UPS: "UPS1", source size, target size, then hunks of a relative offset
+// followed by XOR data terminated with a zero byte
+fn apply_ups(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let footer = read_footer(patch)?;
+    check_source(&footer, source)?;
+
+    let mut reader = Reader::new(&patch[..patch.len() - 12], 4);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if source_size != source.len() {
+        return Err(PatchError::SourceSizeMismatch {
+            expected: source_size,
+            actual: source.len(),
+        });
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut offset: usize = 0;
+    while reader.pos < reader.data.len() {
+        offset = offset.checked_add(reader.number()?).ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let x = reader.byte()?;
+            if x == 0 {
+                // Past the target already if it saturates
+                offset = offset.saturating_add(1);
+                break;
+            }
+            *target.get_mut(offset).ok_or(PatchError::OutOfBounds)? ^= x;
+            offset += 1;
+        }
+    }
+
+    check_target(&footer, &target)?;
+    Ok(target)
+}
+
+// BPS: "BPS1", source size, target size, metadata, then commands copying
+// from the source, the patch or the already written target
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, PatchError> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let footer = read_footer(patch)?;
+    check_source(&footer, source)?;
+
+    let mut reader = Reader::new(&patch[..patch.len() - 12], 4);
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    // Commands can't write past the target size, this bounds TargetCopy too
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+
+    // The size isn't checked yet, don't reserve more than a sane patch makes
+    let mut target: Vec<u8> = Vec::with_capacity(target_size.min(source.len() + patch.len()));
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    // Signed offsets are stored as magnitude << 1 | sign
+    let relative = |base: usize, data: usize| -> Result<usize, PatchError> {
+        let delta = data >> 1;
+        let result = if data & 1 == 1 { base.checked_sub(delta) } else { base.checked_add(delta) };
+        result.ok_or(PatchError::OutOfBounds)
+    };
+
+    while reader.pos < reader.data.len() {
+        let data = reader.number()?;
+        let command = data & 3;
+        let length = (data >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+
+        match command {
+            SOURCE_READ => {
+                let start = target.len();
+                let end = start.checked_add(length).ok_or(PatchError::OutOfBounds)?;
+                let bytes = source.get(start..end).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(bytes);
+            },
+            TARGET_READ => {
+                target.extend_from_slice(reader.bytes(length)?);
+            },
+            SOURCE_COPY => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                let end = source_offset.checked_add(length).ok_or(PatchError::OutOfBounds)?;
+                let bytes = source.get(source_offset..end).ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            },
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                // The copy may overlap the bytes it writes, go one by one
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+    check_target(&footer, &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod test {
+    use rom::patch::*;
+    use rom::{LoadOptions, ROM};
+
+    use utils::test_dir;
+
+    use std::fs;
+
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let x = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | x);
+                return bytes;
+            }
+            bytes.push(x);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        for crc in &[crc32fast::hash(source), crc32fast::hash(target)] {
+            patch.extend_from_slice(&[*crc as u8, (crc >> 8) as u8,
+                                      (crc >> 16) as u8, (crc >> 24) as u8]);
+        }
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&[crc as u8, (crc >> 8) as u8, (crc >> 16) as u8, (crc >> 24) as u8]);
+        patch
+    }
+
+    #[test]
+    fn encoding_numbers() {
+        for &value in &[0, 1, 127, 128, 300, 16511, 16512, 1 << 20] {
+            let bytes = number(value);
+            assert_eq!(Reader::new(&bytes, 0).number().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn decoding_overlong_numbers() {
+        // The tenth byte goes past 64 bits
+        let bytes = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0x82];
+        assert!(matches!(Reader::new(&bytes, 0).number(), Err(PatchError::OutOfBounds)));
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&bytes);
+        patch.extend(number(0));
+        patch.extend(number(0));
+        let patch = with_footer(patch, b"abcd", b"");
+        assert!(matches!(apply(&patch, b"abcd"), Err(PatchError::OutOfBounds)));
+    }
+
+    #[test]
+    fn applying_ips() {
+        let source = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // Plain record
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        // RLE record growing the file
+        patch.extend_from_slice(&[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+
+        let target = apply(&patch, &source).unwrap();
+        assert_eq!(target, vec![0, 0xaa, 0xbb, 0, 0, 0, 0, 0xcc, 0xcc, 0xcc]);
+
+        // Truncation extension
+        patch.extend_from_slice(&[0x00, 0x00, 0x04]);
+        assert_eq!(apply(&patch, &source).unwrap(), vec![0, 0xaa, 0xbb, 0]);
+
+        assert!(apply(b"PATCH\x00\x00", &source).is_err());
+    }
+
+    #[test]
+    fn applying_ups() {
+        let source = b"Hello, World".to_vec();
+        let target = b"Hello, NES!!!".to_vec();
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(7));
+        for (i, byte) in target.iter().enumerate().skip(7) {
+            patch.push(source.get(i).cloned().unwrap_or(0) ^ byte);
+        }
+        patch.push(0);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+        match apply(&patch, b"Goodbye, World") {
+            Err(PatchError::SourceMismatch { .. }) => {},
+            other => panic!("Expected source mismatch, got {:?}", other),
+        }
+
+        // The right source CRC with a wrong size
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len() + 1));
+        patch.extend(number(target.len()));
+        let patch = with_footer(patch, &source, &target);
+        match apply(&patch, &source) {
+            Err(PatchError::SourceSizeMismatch { expected: 13, actual: 12 }) => {},
+            other => panic!("Expected source size mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejecting_huge_targets() {
+        let source = b"Hello, World".to_vec();
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(1 << 40));
+        let patch = with_footer(patch, &source, &source);
+        assert!(matches!(apply(&patch, &source), Err(PatchError::OutOfBounds)));
+
+        // TargetRead of one byte, then TargetCopy repeating it 1 GB long
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(1 << 40));
+        patch.extend(number(0));
+        patch.extend(number(1));
+        patch.push(b'!');
+        patch.extend(number(((1 << 30) - 1) << 2 | 3));
+        patch.extend(number(0));
+        let patch = with_footer(patch, &source, &source);
+        assert!(matches!(apply(&patch, &source), Err(PatchError::OutOfBounds)));
+    }
+
+    #[test]
+    fn applying_bps() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcdXYXYXYefgh".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        // SourceRead "abcd"
+        patch.extend(number((4 - 1) << 2));
+        // TargetRead "XY"
+        patch.extend(number((2 - 1) << 2 | 1));
+        patch.extend_from_slice(b"XY");
+        // TargetCopy 4 bytes from offset 4, overlapping itself
+        patch.extend(number((4 - 1) << 2 | 3));
+        patch.extend(number(4 << 1));
+        // SourceCopy "efgh" from offset 4
+        patch.extend(number((4 - 1) << 2 | 2));
+        patch.extend(number(4 << 1));
+        let mut patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+
+        patch[6] ^= 0xff;
+        match apply(&patch, &source) {
+            Err(PatchError::PatchMismatch { .. }) => {},
+            other => panic!("Expected patch mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finding_patch_next_to_rom() {
+        let dir = test_dir("finding_patch_next_to_rom");
+        let rom_path = dir.join("game.nes");
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01];
+        rom.resize(16 + 16 * 1024, 0);
+        fs::write(&rom_path, &rom).unwrap();
+        // Set the CHR ROM size byte, the CHR data lands past the end of the file
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x05, 0x00, 0x01, 0x01]);
+        patch.extend_from_slice(&[0x00, 0x40, 0x10, 0x00, 0x00, 0x20, 0x00, 0xff]);
+        patch.extend_from_slice(b"EOF");
+        fs::write(dir.join("game.ips"), &patch).unwrap();
+
+        assert_eq!(find_patch(&rom_path), Some(dir.join("game.ips")));
+        let loaded = ROM::load(rom_path.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.header.chr_rom_size, 1);
+        assert_eq!(loaded.chr_rom, vec![0xff; 8 * 1024]);
+        assert_eq!(fs::read(&rom_path).unwrap(), rom);
+
+        // Given patches replace the one found, the same file isn't applied twice
+        let mut other = b"PATCH".to_vec();
+        other.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x01, 0x42]);
+        other.extend_from_slice(b"EOF");
+        fs::write(dir.join("other.ips"), &other).unwrap();
+        let options = LoadOptions {
+            patches: vec![dir.join("other.ips")],
+            ..Default::default()
+        };
+        let loaded = ROM::load_with_options(rom_path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(loaded.header.chr_rom_size, 0);
+        assert_eq!(loaded.prg_rom[0], 0x42);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+// UNIF images (.unf): a 32-byte header followed by chunks of a four
+// character ID, a 32-bit little-endian length and the data
+// Format description: https://wiki.nesdev.com/w/index.php/UNIF
+//
+// The board name in the MAPR chunk takes the place of the mapper number.
+// Images are turned into a NES 2.0 header with the mapper of the board so
+// the rest of the emulator treats them like any other cartridge.
+use rom::{Header, LoadOptions, MirroringType, ROMReadError, ROM};
+
+const HEADER_SIZE: usize = 32;
+const KB: usize = 1024;
+
+// Board names without their NES-, HVC-, UNL-, BMC- or BTL- prefix, with
+// the mapper and submapper numbers of the same hardware
+const BOARDS: &[(&str, u16, u8)] = &[
+    ("NROM", 0, 0), ("NROM-128", 0, 0), ("NROM-256", 0, 0), ("RROM", 0, 0),
+    ("SAROM", 1, 0), ("SBROM", 1, 0), ("SCROM", 1, 0), ("SEROM", 1, 5), ("SGROM", 1, 0),
+    ("SKROM", 1, 0), ("SLROM", 1, 0), ("SNROM", 1, 0), ("SOROM", 1, 0), ("SUROM", 1, 0),
+    ("SXROM", 1, 0), ("UNROM", 2, 0), ("UOROM", 2, 0), ("CNROM", 3, 0),
+    ("TBROM", 4, 0), ("TEROM", 4, 0), ("TFROM", 4, 0), ("TGROM", 4, 0), ("TKROM", 4, 0),
+    ("TLROM", 4, 0), ("TNROM", 4, 0), ("TSROM", 4, 0), ("TVROM", 4, 0),
+    ("EKROM", 5, 0), ("ELROM", 5, 0), ("ETROM", 5, 0), ("EWROM", 5, 0),
+    ("AMROM", 7, 0), ("ANROM", 7, 0), ("AOROM", 7, 0), ("PNROM", 9, 0), ("PEEOROM", 9, 0),
+    ("CPROM", 13, 0), ("BNROM", 34, 0), ("GNROM", 66, 0), ("MHROM", 66, 0),
+    ("D1038", 59, 0), ("T3H53", 59, 0), ("Ghostbusters63in1", 226, 0),
+    ("CAMERICA-BF9096", 232, 0), ("CAMERICA-ALGQ", 232, 0), ("42in1ResetSwitch", 233, 0),
+];
+
+const PREFIXES: &[&str] = &["NES-", "HVC-", "UNL-", "BMC-", "BTL-"];
+
+// The board name without its prefix
+pub fn short_board_name(name: &str) -> &str {
+    PREFIXES.iter()
+        .find(|prefix| name.starts_with(*prefix))
+        .map_or(name, |prefix| &name[prefix.len()..])
+}
+
+// Mapper and submapper of a UNIF board
+pub fn board_mapper(name: &str) -> Option<(u16, u8)> {
+    let short_name = short_board_name(name);
+    BOARDS.iter()
+        .find(|&&(board, _, _)| board.eq_ignore_ascii_case(short_name))
+        .map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+// NES 2.0 size fields for a ROM size: a count of `unit`s, or
+// 2^E * (MM * 2 + 1) bytes when it isn't a whole number of them
+fn size_fields(bytes: usize, unit: usize) -> (u8, u8) {
+    let units = bytes / unit;
+    if bytes.is_multiple_of(unit) && units < 0xf00 {
+        return (units as u8, (units >> 8) as u8);
+    }
+    let exponent = bytes.trailing_zeros().min(63);
+    let multiplier = (bytes >> exponent).min(7) / 2;
+    ((exponent as u8) << 2 | multiplier as u8, 0x0f)
+}
+
+pub fn from_bytes(raw_data: &[u8], options: &LoadOptions) -> Result<ROM, ROMReadError> {
+    if raw_data.len() < HEADER_SIZE {
+        return Err(ROMReadError::FormatError);
+    }
+
+    let mut board = None;
+    let mut prg_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut chr_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut mirroring = None;
+    let mut battery = false;
+
+    let mut offset = HEADER_SIZE;
+    while offset + 8 <= raw_data.len() {
+        let id = &raw_data[offset..offset + 4];
+        let length = raw_data[offset + 4] as usize | (raw_data[offset + 5] as usize) << 8 |
+                     (raw_data[offset + 6] as usize) << 16 | (raw_data[offset + 7] as usize) << 24;
+        let start = offset + 8;
+        let data = raw_data.get(start..start + length).ok_or(ROMReadError::FormatError)?;
+        match id {
+            b"MAPR" => {
+                let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+                board = Some(String::from_utf8_lossy(&data[..end]).trim().to_string());
+            },
+            b"MIRR" => mirroring = data.first().cloned(),
+            b"BATR" => battery = data.first() != Some(&0),
+            _ if &id[..3] == b"PRG" || &id[..3] == b"CHR" => {
+                let index = (id[3] as char).to_digit(16).ok_or(ROMReadError::FormatError)? as usize;
+                let chunks = if &id[..3] == b"PRG" { &mut prg_chunks } else { &mut chr_chunks };
+                chunks[index] = Some(data);
+            },
+            _ => {},
+        }
+        offset = start + length;
+    }
+
+    let board = board.ok_or(ROMReadError::FormatError)?;
+    let prg_rom: Vec<u8> = prg_chunks.iter().flatten().flat_map(|chunk| chunk.iter().cloned()).collect();
+    let chr_rom: Vec<u8> = chr_chunks.iter().flatten().flat_map(|chunk| chunk.iter().cloned()).collect();
+    if prg_rom.is_empty() {
+        return Err(ROMReadError::FormatError);
+    }
+
+    let (prg_lsb, prg_msb) = size_fields(prg_rom.len(), 16 * KB);
+    let (chr_lsb, chr_msb) = size_fields(chr_rom.len(), 8 * KB);
+    // 8 KB of PRG RAM, and of CHR RAM without CHR ROM
+    let mut header = Header {
+        nes_constant: [0x4e, 0x45, 0x53, 0x1a],
+        prg_rom_size: prg_lsb,
+        chr_rom_size: chr_lsb,
+        flags_6: (battery as u8) << 1,
+        flags_7: 0x08,
+        flags_9: chr_msb << 4 | prg_msb,
+        flags_10: if battery { 0x70 } else { 0x07 },
+        flags_11: if chr_rom.is_empty() { 0x07 } else { 0x00 },
+        ..Default::default()
+    };
+    match mirroring {
+        Some(1) => header.flags_6 |= 0x01,
+        Some(4) => header.flags_6 |= 0x08,
+        _ => {},
+    }
+    if let Some((mapper, submapper)) = board_mapper(&board) {
+        header.set_mapper(mapper, submapper);
+    }
+
+    let mut rom = ROM::assemble(header, None, prg_rom, chr_rom, false, options);
+    rom.board = Some(board);
+    // Boards soldered for one-screen mirroring
+    match mirroring {
+        Some(2) => rom.mirroring = MirroringType::SingleScreenA,
+        Some(3) => rom.mirroring = MirroringType::SingleScreenB,
+        _ => {},
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+pub mod test {
+    use rom::unif::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        let length = data.len() as u32;
+        chunk.extend_from_slice(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    // A UNIF image with PRG and CHR split over two chunks each
+    pub fn make_unif(board: &str, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+        let mut data = b"UNIF".to_vec();
+        data.extend_from_slice(&[7, 0, 0, 0]);
+        data.resize(HEADER_SIZE, 0);
+        let mut name = board.as_bytes().to_vec();
+        name.push(0);
+        data.extend(chunk(b"MAPR", &name));
+        let (prg_0, prg_1) = prg.split_at(prg.len() / 2);
+        data.extend(chunk(b"PRG0", prg_0));
+        data.extend(chunk(b"PRG1", prg_1));
+        if !chr.is_empty() {
+            data.extend(chunk(b"CHR0", chr));
+        }
+        data.extend(chunk(b"MIRR", &[1]));
+        data
+    }
+
+    #[test]
+    fn reading_chunks() {
+        let mut prg = vec![0; 0x8000];
+        prg[0x4000] = 0x42;
+        let data = make_unif("NES-UNROM", &prg, &[]);
+        let rom = ROM::from_bytes(&data, &LoadOptions::default()).unwrap();
+        assert_eq!(rom.board, Some("NES-UNROM".to_string()));
+        assert_eq!(rom.mapper(), 2);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.prg_rom[0x4000], 0x42);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.header.chr_ram_bytes(), 0x2000);
+        assert_eq!(rom.mirroring, MirroringType::Vertical);
+
+        // Unknown boards keep their name only
+        let rom = ROM::from_bytes(&make_unif("BMC-Unknown", &prg, &[0; 0x2000]),
+                                  &LoadOptions::default()).unwrap();
+        assert_eq!(rom.mapper(), 0);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+
+        // Cut short
+        assert!(ROM::from_bytes(&data[..data.len() - 1], &LoadOptions::default()).is_err());
+    }
+
+    #[test]
+    fn naming_boards() {
+        assert_eq!(board_mapper("BMC-42in1ResetSwitch"), Some((233, 0)));
+        assert_eq!(board_mapper("HVC-SEROM"), Some((1, 5)));
+        assert_eq!(board_mapper("UNL-Whatever"), None);
+        assert_eq!(short_board_name("BMC-D1038"), "D1038");
+        assert_eq!(size_fields(0x2000, 0x4000), (13 << 2, 0x0f));
+        assert_eq!(size_fields(0x60000, 0x4000), (24, 0));
+    }
+}
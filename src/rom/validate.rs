@@ -0,0 +1,354 @@
+// Header lint: finds inconsistencies between an .nes header and the file
+// and suggests how to correct them
+use mapper;
+use rom::{Format, Header, LoadOptions, ROMReadError, ROM, KB};
+use utils;
+
+use std::fmt;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+// PlayChoice-10 hint screen stored after CHR ROM
+const PLAYCHOICE_SIZE: usize = 8 * KB as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    // Harmless, but worth knowing
+    Info,
+    // The ROM loads, but some of the header is likely wrong
+    Warning,
+    // The ROM doesn't load or can't run correctly
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fix {
+    // PRG ROM in 16 KB units and CHR ROM in 8 KB units
+    SetRomSizes { prg_rom_size: u8, chr_rom_size: u8 },
+    // Nothing to change in the header, the data after CHR ROM is dropped
+    DropTrailingData,
+    // Zero bytes 7-15 written by old dumping tools
+    ClearGarbage,
+    // Zero the reserved iNES 1.0 bits in bytes 9 and 11
+    ClearReserved,
+    // Read the header as iNES 1.0, dropping the NES 2.0 identifier
+    ConvertToINES,
+    // Declare 8 KB of CHR RAM
+    DeclareChrRam,
+}
+
+impl Fix {
+    pub fn apply(self, header: &mut Header) {
+        match self {
+            Fix::SetRomSizes { prg_rom_size, chr_rom_size } => {
+                header.prg_rom_size = prg_rom_size;
+                header.chr_rom_size = chr_rom_size;
+                if header.is_nes2() {
+                    header.flags_9 = 0;
+                }
+            },
+            Fix::DropTrailingData => {},
+            Fix::ClearGarbage => header.clear_garbage(),
+            Fix::ClearReserved => {
+                header.flags_9 &= 0x01;
+                header.flags_11 = 0;
+            },
+            Fix::ConvertToINES => {
+                header.flags_7 &= 0xf3;
+                header.prg_ram_size = 0;
+                header.flags_9 = 0;
+                header.flags_10 = 0;
+                header.flags_11 = 0;
+                header.flags_12 = 0;
+                header.flags_13 = 0;
+                header.flags_14 = 0;
+                header.flags_15 = 0;
+            },
+            Fix::DeclareChrRam => header.flags_11 = (header.flags_11 & 0xf0) | 0x07,
+        }
+    }
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fix::SetRomSizes { prg_rom_size, chr_rom_size } =>
+                write!(f, "set PRG ROM to {} KB and CHR ROM to {} KB",
+                       prg_rom_size as u32 * 16, chr_rom_size as u32 * 8),
+            Fix::DropTrailingData => write!(f, "drop the data after CHR ROM"),
+            Fix::ClearGarbage => write!(f, "zero bytes 7-15"),
+            Fix::ClearReserved => write!(f, "zero the reserved bits"),
+            Fix::ConvertToINES => write!(f, "rewrite the header as iNES 1.0"),
+            Fix::DeclareChrRam => write!(f, "declare 8 KB of CHR RAM"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<Fix>,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: String, fix: Option<Fix>) -> Finding {
+        Finding {
+            severity,
+            message,
+            fix,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        if let Some(fix) = self.fix {
+            write!(f, " (fix: {})", fix)?;
+        }
+        Ok(())
+    }
+}
+
+// Checks an .nes image. Later checks see the header with the fixes of the
+// earlier findings applied, so applying all fixes in order is consistent.
+pub fn validate(raw_data: &[u8]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    match Format::detect(raw_data) {
+        Some(Format::INES) | Some(Format::NES2) if raw_data.len() >= HEADER_SIZE => {},
+        Some(Format::INES) | Some(Format::NES2) | None => {
+            findings.push(Finding::new(Severity::Error, "Not an .nes file".to_string(), None));
+            return findings;
+        },
+        Some(format) => {
+            let message = format!("{} files have no iNES header to check", format.name());
+            findings.push(Finding::new(Severity::Error, message, None));
+            return findings;
+        },
+    }
+
+    let mut header = match Header::new(&raw_data[..HEADER_SIZE]) {
+        Ok(header) => header,
+        Err(_) => {
+            findings.push(Finding::new(Severity::Error, "Unreadable header".to_string(), None));
+            return findings;
+        },
+    };
+    let mut add = |header: &mut Header, severity, message, fix: Option<Fix>| {
+        if let Some(fix) = fix {
+            fix.apply(header);
+        }
+        findings.push(Finding::new(severity, message, fix));
+    };
+
+    if Header::has_garbage(&raw_data[..HEADER_SIZE]) {
+        add(&mut header, Severity::Warning,
+            "Bytes 12-15 of an iNES 1.0 header aren't zero, bytes 7-15 are likely garbage".to_string(),
+            Some(Fix::ClearGarbage));
+    } else if !header.is_nes2() && (header.flags_9 & 0xfe != 0 || header.flags_11 != 0) {
+        add(&mut header, Severity::Warning,
+            "Reserved bits of byte 9 or byte 11 are set in an iNES 1.0 header".to_string(),
+            Some(Fix::ClearReserved));
+    }
+
+    let trainer = if header.has_trainer() { TRAINER_SIZE } else { 0 };
+    let available = raw_data.len().saturating_sub(HEADER_SIZE + trainer);
+    if header.is_nes2() {
+        let declared = declared_rom(&header);
+        let ines = header.prg_rom_size as usize * 16 * KB as usize +
+                   header.chr_rom_size as usize * 8 * KB as usize;
+        if declared.is_none_or(|declared| declared > available) && ines <= available && ines > 0 {
+            add(&mut header, Severity::Error,
+                format!("NES 2.0 header declares {} of ROM, the file only fits the iNES 1.0 sizes",
+                        size_text(declared)),
+                Some(Fix::ConvertToINES));
+        }
+    }
+
+    let declared = declared_rom(&header);
+    if header.prg_rom_bytes() == 0 || declared.is_none_or(|declared| declared > available) {
+        let fix = fitting_sizes(&header, available);
+        add(&mut header, Severity::Error,
+            format!("Header declares {} of PRG and CHR ROM, the file has {}", size_text(declared), available),
+            fix);
+    }
+
+    // Without a fix the sizes may still overflow, there's nothing after them then
+    if let Some(declared) = declared_rom(&header) {
+        let playchoice = utils::get_bit(&header.flags_7, 1) == 1;
+        if playchoice && available.checked_sub(declared) == Some(PLAYCHOICE_SIZE) {
+            add(&mut header, Severity::Info,
+                "PlayChoice-10 hint screen after CHR ROM".to_string(), None);
+        } else if available > declared {
+            add(&mut header, Severity::Warning,
+                format!("{} bytes of trailing data after CHR ROM", available - declared),
+                Some(Fix::DropTrailingData));
+        }
+    }
+
+    if header.is_nes2() && header.chr_rom_bytes() == 0 &&
+       header.chr_ram_bytes() == 0 && header.chr_nvram_bytes() == 0 {
+        add(&mut header, Severity::Warning,
+            "No CHR ROM and no CHR RAM declared".to_string(),
+            Some(Fix::DeclareChrRam));
+    }
+
+    let number = header.mapper();
+    if !mapper::is_supported(number) {
+        add(&mut header, Severity::Warning,
+            format!("Mapper {} is not implemented", number), None);
+    }
+    findings
+}
+
+// PRG and CHR ROM together, None when NES 2.0 exponent sizes overflow
+fn declared_rom(header: &Header) -> Option<usize> {
+    header.prg_rom_bytes().checked_add(header.chr_rom_bytes())
+}
+
+fn size_text(bytes: Option<usize>) -> String {
+    match bytes {
+        Some(bytes) => format!("{} bytes", bytes),
+        None => "more bytes than fit in memory".to_string(),
+    }
+}
+
+// Sizes in iNES units that fit the data: PRG ROM as declared with CHR ROM
+// taking the rest, or the whole data as PRG ROM
+fn fitting_sizes(header: &Header, available: usize) -> Option<Fix> {
+    let prg_unit = 16 * KB as usize;
+    let chr_unit = 8 * KB as usize;
+    let prg_rom = header.prg_rom_bytes();
+    let (prg_rom_size, chr_rom_size) = if prg_rom > 0 && available >= prg_rom && prg_rom.is_multiple_of(prg_unit) {
+        (prg_rom / prg_unit, (available - prg_rom) / chr_unit)
+    } else {
+        (available / prg_unit, 0)
+    };
+    if prg_rom_size == 0 || prg_rom_size > 0xff || chr_rom_size > 0xff {
+        return None;
+    }
+    Some(Fix::SetRomSizes {
+        prg_rom_size: prg_rom_size as u8,
+        chr_rom_size: chr_rom_size as u8,
+    })
+}
+
+// Applies every suggested fix and loads the result, `ROM::to_bytes` gives
+// the corrected file
+pub fn fix(raw_data: &[u8]) -> Result<ROM, ROMReadError> {
+    if raw_data.len() < HEADER_SIZE {
+        return Err(ROMReadError::FormatError);
+    }
+    let mut header = Header::new(&raw_data[..HEADER_SIZE])?;
+    for finding in validate(raw_data) {
+        if let Some(fix) = finding.fix {
+            fix.apply(&mut header);
+        }
+    }
+
+    let mut data = header.to_bytes().to_vec();
+    data.extend_from_slice(&raw_data[HEADER_SIZE..]);
+    let options = LoadOptions {
+        header_overrides: false,
+        auto_patch: false,
+        ..Default::default()
+    };
+    ROM::from_bytes(&data, &options)
+}
+
+#[cfg(test)]
+mod test {
+    use rom::validate::*;
+
+    fn image(header: [u8; 12], data_size: usize) -> Vec<u8> {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a];
+        data.extend_from_slice(&header);
+        data.resize(HEADER_SIZE + data_size, 0xea);
+        data
+    }
+
+    fn fixes(findings: &[Finding]) -> Vec<Option<Fix>> {
+        findings.iter().map(|finding| finding.fix).collect()
+    }
+
+    #[test]
+    fn finding_size_mismatch() {
+        // 32 KB PRG + 8 KB CHR declared, only PRG present
+        let data = image([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 32 * 1024);
+        let findings = validate(&data);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].fix, Some(Fix::SetRomSizes { prg_rom_size: 2, chr_rom_size: 0 }));
+
+        let rom = fix(&data).unwrap();
+        assert_eq!(rom.header.chr_rom_size, 0);
+        assert_eq!(rom.to_bytes(), {
+            let mut fixed = data.clone();
+            fixed[5] = 0;
+            fixed
+        });
+    }
+
+    #[test]
+    fn finding_garbage_and_trailing_data() {
+        let mut data = image([1, 1, 0x01, b'D', b'i', b's', b'k', b'D', b'u', b'd', b'e', b'!'],
+                             24 * 1024 + 100);
+        let findings = validate(&data);
+        assert_eq!(fixes(&findings)[..2], [Some(Fix::ClearGarbage), Some(Fix::DropTrailingData)]);
+
+        let bytes = fix(&data).unwrap().to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE + 24 * 1024);
+        assert_eq!(bytes[7..16], [0; 9]);
+
+        // Reserved bits in a clean iNES 1.0 header
+        data = image([1, 1, 0, 0, 0, 0x02, 0, 0, 0, 0, 0, 0], 24 * 1024);
+        assert_eq!(fixes(&validate(&data))[0], Some(Fix::ClearReserved));
+    }
+
+    #[test]
+    fn finding_nes2_problems() {
+        // NES 2.0 identifier with garbage MSBs in byte 9
+        let data = image([2, 1, 0, 0x08, 0, 0x11, 0, 0, 0, 0, 0, 0], 40 * 1024);
+        let findings = validate(&data);
+        assert_eq!(findings[0].fix, Some(Fix::ConvertToINES));
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(!fix(&data).unwrap().header.is_nes2());
+
+        // No CHR ROM, no CHR RAM
+        let data = image([2, 0, 0, 0x08, 0, 0, 0, 0, 0, 0, 0, 0], 32 * 1024);
+        let findings = validate(&data);
+        assert_eq!(findings[0].fix, Some(Fix::DeclareChrRam));
+        assert_eq!(fix(&data).unwrap().header.chr_ram_bytes(), 8 * 1024);
+    }
+
+    #[test]
+    fn finding_oversized_roms() {
+        // NES 2.0 PRG and CHR ROM of 2^63 * 7 bytes each
+        let data = image([0xff, 0xff, 0, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0], 32 * 1024);
+        let findings = validate(&data);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].message,
+                   "Header declares more bytes than fit in memory of PRG and CHR ROM, the file has 32768");
+        assert_eq!(findings[0].fix, Some(Fix::SetRomSizes { prg_rom_size: 2, chr_rom_size: 0 }));
+        assert_eq!(fix(&data).unwrap().prg_rom.len(), 32 * 1024);
+    }
+
+    #[test]
+    fn reporting_mappers_and_formats() {
+        let data = image([2, 1, 0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0], 40 * 1024);
+        let findings = validate(&data);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].to_string(), "warning: Mapper 6 is not implemented");
+        let data = image([2, 1, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0], 40 * 1024);
+        assert!(validate(&data).is_empty());
+
+        let findings = validate(b"NESM\x1a");
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(validate(b"NES")[0].message, "Not an .nes file");
+    }
+}
@@ -0,0 +1,97 @@
+// Battery-backed cartridge memory kept in a .sav file next to the ROM.
+// The file holds the raw memory: PRG RAM, or the EEPROM of Bandai boards.
+//
+// Writes go to a temporary file which then replaces the save, a crash in
+// the middle leaves the previous save intact.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct SaveFile {
+    path: PathBuf,
+    // Contents of the file as last read or written
+    saved: Option<Vec<u8>>,
+}
+
+impl SaveFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> SaveFile {
+        SaveFile {
+            path: path.as_ref().to_path_buf(),
+            saved: None,
+        }
+    }
+
+    // <rom>.sav: the ROM path with its extension replaced. The games of
+    // an archive are told apart by their entry, their saves go next to the
+    // archive as <entry>.sav.
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P, archive_entry: Option<&str>) -> SaveFile {
+        let rom_path = rom_path.as_ref();
+        match archive_entry.and_then(|entry| Path::new(entry).file_name()) {
+            Some(name) => SaveFile::new(rom_path.with_file_name(name).with_extension("sav")),
+            None => SaveFile::new(rom_path.with_extension("sav")),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // None when there is no save yet
+    pub fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.saved = Some(data.clone());
+                Ok(Some(data))
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Writes the memory unless the file has it already, returns whether
+    // anything was written
+    pub fn write(&mut self, data: &[u8]) -> io::Result<bool> {
+        if self.saved.as_ref().is_some_and(|saved| saved[..] == data[..]) {
+            return Ok(false);
+        }
+        let mut temp_name = self.path.as_os_str().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+        self.saved = Some(data.to_vec());
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use save::*;
+    use utils::test_dir;
+
+    #[test]
+    fn writing_saves() {
+        let dir = test_dir("writing_saves");
+        let mut save = SaveFile::for_rom(dir.join("Zelda.nes"), None);
+        assert_eq!(save.path(), dir.join("Zelda.sav").as_path());
+        let entry = SaveFile::for_rom(dir.join("Nintendo.zip"), Some("Zelda/Zelda II.nes"));
+        assert_eq!(entry.path(), dir.join("Zelda II.sav").as_path());
+        assert_eq!(save.read().unwrap(), None);
+
+        assert!(save.write(&[1, 2, 3]).unwrap());
+        // Unchanged
+        assert!(!save.write(&[1, 2, 3]).unwrap());
+        assert!(save.write(&[4, 5, 6]).unwrap());
+        assert!(!dir.join("Zelda.sav.tmp").exists());
+
+        let mut save = SaveFile::for_rom(dir.join("Zelda.nes"), None);
+        assert_eq!(save.read().unwrap(), Some(vec![4, 5, 6]));
+        assert!(!save.write(&[4, 5, 6]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
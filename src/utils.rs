@@ -29,6 +29,18 @@ pub fn clear_bit(byte: &mut u8, i: u8) {
     set_bit(byte, i, false)
 }
 
+// An empty directory for the files of one test. The process id keeps
+// concurrent `cargo test` runs apart, the name the tests of one run.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> ::std::path::PathBuf {
+    use std::{env, fs, process};
+
+    let dir = env::temp_dir().join(format!("rusty_nes_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[cfg(test)]
 mod test {
     use utils::*;